@@ -0,0 +1,101 @@
+//! Size regression report.
+//!
+//! Prints the sizes of the driver types with the enabled features and
+//! exits with an error if a size exceeds the budget of the enabled
+//! features or, on 64-bit targets, if a component size differs from
+//! the documented size. Code size, including `core::fmt` code, is not
+//! checked.
+//!
+//! ```text
+//! cargo run --example footprint --no-default-features --features preset-tiny
+//! ```
+
+use pc_ps2_controller::footprint::{
+    COMPONENT_FOOTPRINT, COMPONENT_SIZES, FEATURE_BUDGET, FOOTPRINT,
+};
+
+use std::process::exit;
+
+fn main() {
+    let rows = [
+        (
+            "DevicesDisabled",
+            FOOTPRINT.devices_disabled,
+            FEATURE_BUDGET.devices_disabled,
+        ),
+        (
+            "EnabledDevices",
+            FOOTPRINT.enabled_devices,
+            FEATURE_BUDGET.enabled_devices,
+        ),
+        (
+            "KeyboardDriver",
+            FOOTPRINT.keyboard_driver,
+            FEATURE_BUDGET.keyboard_driver,
+        ),
+        (
+            "QueuedKeyboardDriver",
+            FOOTPRINT.queued_keyboard_driver,
+            FEATURE_BUDGET.queued_keyboard_driver,
+        ),
+        (
+            "MouseDriver",
+            FOOTPRINT.mouse_driver,
+            FEATURE_BUDGET.mouse_driver,
+        ),
+    ];
+
+    println!("{:<24}{:>8}{:>8}", "type", "size", "budget");
+    for (name, size, budget) in rows.iter() {
+        println!("{:<24}{:>8}{:>8}", name, size, budget);
+    }
+
+    let components = [
+        (
+            "translation table",
+            COMPONENT_FOOTPRINT.translation_table,
+            COMPONENT_SIZES.translation_table,
+        ),
+        (
+            "grab buffer",
+            COMPONENT_FOOTPRINT.grab_buffer,
+            COMPONENT_SIZES.grab_buffer,
+        ),
+        (
+            "deferred buffer",
+            COMPONENT_FOOTPRINT.deferred_buffer,
+            COMPONENT_SIZES.deferred_buffer,
+        ),
+        (
+            "transition log",
+            COMPONENT_FOOTPRINT.transition_log,
+            COMPONENT_SIZES.transition_log,
+        ),
+        (
+            "capture writer",
+            COMPONENT_FOOTPRINT.capture_writer,
+            COMPONENT_SIZES.capture_writer,
+        ),
+        (
+            "capture reader",
+            COMPONENT_FOOTPRINT.capture_reader,
+            COMPONENT_SIZES.capture_reader,
+        ),
+    ];
+
+    println!();
+    println!("{:<24}{:>8}{:>8}", "component", "size", "x86_64");
+    for (name, size, documented) in components.iter() {
+        println!("{:<24}{:>8}{:>8}", name, size, documented);
+    }
+
+    if !FOOTPRINT.fits(&FEATURE_BUDGET) {
+        eprintln!("driver types exceed the memory budget");
+        exit(1);
+    }
+
+    if cfg!(target_pointer_width = "64") && COMPONENT_FOOTPRINT != COMPONENT_SIZES {
+        eprintln!("component sizes differ from the documented sizes");
+        exit(1);
+    }
+}
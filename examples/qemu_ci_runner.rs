@@ -0,0 +1,53 @@
+//! QEMU CI runner for `qemu_ci` test kernels.
+//!
+//! Writes the monitor commands of `SMOKE_SCRIPT` to the QEMU monitor
+//! socket. Start QEMU with the test kernel and
+//!
+//! ```text
+//! -device isa-debug-exit,iobase=0xf4,iosize=0x04
+//! -monitor unix:monitor.sock,server,nowait
+//! ```
+//!
+//! and run this after the kernel has initialized the controller.
+//! Without a socket path the commands are printed to stdout.
+
+use pc_ps2_controller::qemu_ci::SMOKE_SCRIPT;
+
+use std::env;
+use std::io::{self, Write};
+use std::thread::sleep;
+use std::time::Duration;
+
+/// Delay between steps, so that the guest handles every input event
+/// before the next one.
+const STEP_DELAY: Duration = Duration::from_millis(100);
+
+fn main() -> io::Result<()> {
+    match env::args().nth(1) {
+        Some(path) => run(&mut connect(&path)?),
+        None => run(&mut io::stdout()),
+    }
+}
+
+fn run<W: Write>(monitor: &mut W) -> io::Result<()> {
+    for step in SMOKE_SCRIPT {
+        writeln!(monitor, "{}", step.monitor_command())?;
+        monitor.flush()?;
+        sleep(STEP_DELAY);
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn connect(path: &str) -> io::Result<std::os::unix::net::UnixStream> {
+    std::os::unix::net::UnixStream::connect(path)
+}
+
+#[cfg(not(unix))]
+fn connect(_: &str) -> io::Result<io::Stdout> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "monitor socket requires a Unix platform",
+    ))
+}
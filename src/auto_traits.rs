@@ -0,0 +1,98 @@
+//! Compile time checks for `Send` and `Sync`.
+//!
+//! Driver types contain only plain data, `PhantomData` markers of
+//! unit structs, the `PortIO` implementation and trace hook function
+//! pointers, so `Send` and `Sync` are derived automatically from the
+//! `PortIO` type. No `unsafe impl` is needed. These checks make sure
+//! that adding a field does not silently remove the auto traits.
+
+use crate::controller::driver::{
+    init_sequence::InitSequence,
+    marker::{Disabled, InterruptsEnabled},
+    AvailableDevices, DevicesDisabled, EnabledDevices, InitController,
+};
+use crate::controller::io::PortIO;
+use crate::device::command_queue::{CommandChecker, CommandQueue};
+use crate::device::keyboard::driver::{Keyboard, ScancodeDecoder};
+use crate::keyboard::KeyboardDriver;
+use crate::trace::Tracer;
+
+use pc_keyboard::{
+    layouts::{Uk105Key, Us104Key},
+    ScancodeSet2,
+};
+
+/// `PortIO` which is `Send` and `Sync`.
+struct SyncPortIO;
+
+impl PortIO for SyncPortIO {
+    type PortID = u16;
+
+    const DATA_PORT: Self::PortID = 0;
+    const STATUS_REGISTER: Self::PortID = 0;
+    const COMMAND_REGISTER: Self::PortID = 0;
+
+    fn read(&mut self, _: Self::PortID) -> u8 {
+        0
+    }
+
+    fn write(&mut self, _: Self::PortID, _: u8) {}
+}
+
+const fn assert_send_sync<T: Send + Sync>() {}
+
+const _: () = {
+    assert_send_sync::<InitController<SyncPortIO>>();
+    assert_send_sync::<DevicesDisabled<SyncPortIO>>();
+    assert_send_sync::<EnabledDevices<SyncPortIO, Disabled>>();
+    assert_send_sync::<EnabledDevices<SyncPortIO, InterruptsEnabled>>();
+    assert_send_sync::<AvailableDevices<SyncPortIO, InterruptsEnabled>>();
+    assert_send_sync::<InitSequence<SyncPortIO, InterruptsEnabled>>();
+    assert_send_sync::<Tracer>();
+    assert_send_sync::<CommandChecker>();
+    assert_send_sync::<CommandQueue<8>>();
+    assert_send_sync::<Keyboard<8>>();
+    assert_send_sync::<ScancodeDecoder>();
+    assert_send_sync::<Keyboard<8, 16, Uk105Key>>();
+    assert_send_sync::<KeyboardDriver<SyncPortIO, InterruptsEnabled, Us104Key, ScancodeSet2>>();
+};
+
+#[cfg(feature = "aux-device")]
+const _: () = {
+    use crate::controller::driver::split::{AuxiliaryPort, KeyboardPort};
+    use crate::device::mouse::packet::PacketDecoder;
+    use crate::mouse::MouseDriver;
+    use crate::passthrough::AuxPassthrough;
+
+    assert_send_sync::<KeyboardPort<SyncPortIO, InterruptsEnabled>>();
+    assert_send_sync::<AuxiliaryPort<SyncPortIO, InterruptsEnabled>>();
+    assert_send_sync::<PacketDecoder>();
+    assert_send_sync::<MouseDriver<SyncPortIO, InterruptsEnabled>>();
+    assert_send_sync::<AuxPassthrough<SyncPortIO, InterruptsEnabled>>();
+};
+
+#[cfg(all(feature = "x86-io", any(target_arch = "x86", target_arch = "x86_64")))]
+const _: () = {
+    assert_send_sync::<EnabledDevices<crate::controller::x86::X86PortIO, InterruptsEnabled>>();
+};
+
+#[cfg(feature = "gpio")]
+const _: () = {
+    use crate::gpio::{GpioPort, Ps2LineDriver};
+
+    struct SyncLine;
+
+    impl Ps2LineDriver for SyncLine {
+        fn clock(&mut self) -> bool {
+            true
+        }
+        fn data(&mut self) -> bool {
+            true
+        }
+        fn set_clock(&mut self, _: bool) {}
+        fn set_data(&mut self, _: bool) {}
+        fn delay_microseconds(&mut self, _: u32) {}
+    }
+
+    assert_send_sync::<GpioPort<SyncLine>>();
+};
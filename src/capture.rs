@@ -0,0 +1,325 @@
+//! Compact capture of keyboard input for bug reports.
+//!
+//! `KeyboardDriver::poll_captured` writes every received byte and the
+//! resulting event to a `Capture`. The bytes can be replayed with
+//! `testing::Simulator::press_keyboard_bytes` to reproduce the report.
+//!
+//! Format:
+//!
+//! * Header: `CAPTURE_MAGIC` and `CAPTURE_VERSION`.
+//! * Record: tag byte from `CaptureTag`, time since the previous
+//!   record as little endian `u16` in the caller's unit and the
+//!   payload. Longer time differences are split with `GAP` records.
+//!
+//! | Tag | Payload |
+//! |-----|---------|
+//! | `GAP` | None |
+//! | `BYTE` | Received byte |
+//! | `KEY` | `pc_keyboard::KeyCode` as `u8`, 1 if pressed |
+//! | `CHAR` | Decoded character as little endian `u32` |
+//! | `RAW_KEY` | Decoded `pc_keyboard::KeyCode` as `u8` |
+//! | `EVENT` | `CaptureEvent` as `u8` |
+//!
+//! Capture stops when the buffer is full. Records are not split.
+
+use crate::keyboard::KeyboardPollResult;
+
+use pc_keyboard::{DecodedKey, KeyState};
+
+pub const CAPTURE_MAGIC: [u8; 4] = *b"PS2C";
+pub const CAPTURE_VERSION: u8 = 1;
+
+/// Length of the header in bytes.
+pub const CAPTURE_HEADER_LENGTH: usize = CAPTURE_MAGIC.len() + 1;
+
+#[derive(Debug)]
+pub struct CaptureTag;
+
+impl CaptureTag {
+    pub const GAP: u8 = 0x00;
+    pub const BYTE: u8 = 0x01;
+    pub const KEY: u8 = 0x02;
+    pub const CHAR: u8 = 0x03;
+    pub const RAW_KEY: u8 = 0x04;
+    pub const EVENT: u8 = 0x05;
+}
+
+/// `KeyboardPollResult` variants which are not keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum CaptureEvent {
+    BATCompleted = 1,
+    BATFailure,
+    KeyDetectionError,
+    DecodingError,
+    TransmissionError,
+    PasswordUnlockPending,
+    PasswordUnlocked,
+}
+
+impl CaptureEvent {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        let event = match value {
+            1 => CaptureEvent::BATCompleted,
+            2 => CaptureEvent::BATFailure,
+            3 => CaptureEvent::KeyDetectionError,
+            4 => CaptureEvent::DecodingError,
+            5 => CaptureEvent::TransmissionError,
+            6 => CaptureEvent::PasswordUnlockPending,
+            7 => CaptureEvent::PasswordUnlocked,
+            _ => return None,
+        };
+        Some(event)
+    }
+}
+
+/// Capture writer for a caller provided buffer.
+#[derive(Debug)]
+pub struct Capture<'a> {
+    out: &'a mut [u8],
+    len: usize,
+    previous_timestamp: Option<u32>,
+    dropped: u32,
+}
+
+impl<'a> Capture<'a> {
+    /// Writes the header. If the buffer is too small for it, all
+    /// records are dropped.
+    pub fn new(out: &'a mut [u8]) -> Self {
+        let mut capture = Self {
+            out,
+            len: 0,
+            previous_timestamp: None,
+            dropped: 0,
+        };
+
+        if capture.out.len() >= CAPTURE_HEADER_LENGTH {
+            capture.out[..CAPTURE_MAGIC.len()].copy_from_slice(&CAPTURE_MAGIC);
+            capture.out[CAPTURE_MAGIC.len()] = CAPTURE_VERSION;
+            capture.len = CAPTURE_HEADER_LENGTH;
+        } else {
+            capture.len = capture.out.len();
+        }
+
+        capture
+    }
+
+    /// Header and records.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.out[..self.len]
+    }
+
+    /// Count of records which did not fit to the buffer.
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    /// Record a received byte. `timestamp` is in the caller's unit,
+    /// for example milliseconds.
+    pub fn byte(&mut self, timestamp: u32, byte: u8) {
+        self.record(timestamp, CaptureTag::BYTE, &[byte]);
+    }
+
+    pub fn poll_result(&mut self, timestamp: u32, result: &KeyboardPollResult) {
+        let event = match result {
+            KeyboardPollResult::Key { event, decoded } => {
+                let pressed = (event.state == KeyState::Down) as u8;
+                self.record(timestamp, CaptureTag::KEY, &[event.code as u8, pressed]);
+                match decoded {
+                    Some(DecodedKey::Unicode(c)) => {
+                        self.record(timestamp, CaptureTag::CHAR, &(*c as u32).to_le_bytes())
+                    }
+                    Some(DecodedKey::RawKey(code)) => {
+                        self.record(timestamp, CaptureTag::RAW_KEY, &[*code as u8])
+                    }
+                    None => (),
+                }
+                return;
+            }
+            KeyboardPollResult::BATCompleted => CaptureEvent::BATCompleted,
+            KeyboardPollResult::BATFailure => CaptureEvent::BATFailure,
+            KeyboardPollResult::KeyDetectionError => CaptureEvent::KeyDetectionError,
+            KeyboardPollResult::Error(_) => CaptureEvent::DecodingError,
+            KeyboardPollResult::TransmissionError(_) => CaptureEvent::TransmissionError,
+            KeyboardPollResult::PasswordUnlockPending => CaptureEvent::PasswordUnlockPending,
+            KeyboardPollResult::PasswordUnlocked => CaptureEvent::PasswordUnlocked,
+        };
+
+        self.record(timestamp, CaptureTag::EVENT, &[event as u8]);
+    }
+
+    fn record(&mut self, timestamp: u32, tag: u8, payload: &[u8]) {
+        let mut delta = match self.previous_timestamp {
+            Some(previous) => timestamp.wrapping_sub(previous),
+            None => 0,
+        };
+
+        let gaps = (delta / u32::from(u16::MAX)) as usize;
+        let length = gaps * 3 + 3 + payload.len();
+        if self.dropped > 0 || self.out.len() - self.len < length {
+            self.dropped = self.dropped.saturating_add(1);
+            return;
+        }
+
+        for _ in 0..gaps {
+            self.write(&[CaptureTag::GAP]);
+            self.write(&u16::MAX.to_le_bytes());
+            delta -= u32::from(u16::MAX);
+        }
+
+        self.write(&[tag]);
+        self.write(&(delta as u16).to_le_bytes());
+        self.write(payload);
+        self.previous_timestamp = Some(timestamp);
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.out[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+    }
+}
+
+/// Record read with `CaptureReader`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureRecord {
+    Byte { time: u32, byte: u8 },
+    Key { time: u32, code: u8, pressed: bool },
+    Char { time: u32, c: char },
+    RawKey { time: u32, code: u8 },
+    Event { time: u32, event: CaptureEvent },
+}
+
+#[derive(Debug)]
+pub struct InvalidCapture;
+
+/// Iterate records of a capture. Times are from the first record.
+#[derive(Debug)]
+pub struct CaptureReader<'a> {
+    bytes: &'a [u8],
+    time: u32,
+}
+
+impl<'a> CaptureReader<'a> {
+    pub fn new(capture: &'a [u8]) -> Result<Self, InvalidCapture> {
+        match capture.get(..CAPTURE_HEADER_LENGTH) {
+            Some(header)
+                if header[..CAPTURE_MAGIC.len()] == CAPTURE_MAGIC
+                    && header[CAPTURE_MAGIC.len()] == CAPTURE_VERSION =>
+            {
+                Ok(Self {
+                    bytes: &capture[CAPTURE_HEADER_LENGTH..],
+                    time: 0,
+                })
+            }
+            _ => Err(InvalidCapture),
+        }
+    }
+
+    /// Received bytes for `testing::Simulator::press_keyboard_bytes`.
+    pub fn bytes(self) -> impl Iterator<Item = u8> + 'a {
+        self.filter_map(|record| match record {
+            Ok(CaptureRecord::Byte { byte, .. }) => Some(byte),
+            _ => None,
+        })
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], InvalidCapture> {
+        if self.bytes.len() < count {
+            self.bytes = &[];
+            return Err(InvalidCapture);
+        }
+        let (data, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(data)
+    }
+
+    fn next_record(&mut self) -> Result<Option<CaptureRecord>, InvalidCapture> {
+        let header = self.take(3)?;
+        let tag = header[0];
+        self.time = self
+            .time
+            .wrapping_add(u32::from(u16::from_le_bytes([header[1], header[2]])));
+        let time = self.time;
+
+        let record = match tag {
+            CaptureTag::GAP => return Ok(None),
+            CaptureTag::BYTE => CaptureRecord::Byte {
+                time,
+                byte: self.take(1)?[0],
+            },
+            CaptureTag::KEY => {
+                let data = self.take(2)?;
+                CaptureRecord::Key {
+                    time,
+                    code: data[0],
+                    pressed: data[1] != 0,
+                }
+            }
+            CaptureTag::CHAR => {
+                let data = self.take(4)?;
+                let value = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+                CaptureRecord::Char {
+                    time,
+                    c: core::char::from_u32(value).ok_or(InvalidCapture)?,
+                }
+            }
+            CaptureTag::RAW_KEY => CaptureRecord::RawKey {
+                time,
+                code: self.take(1)?[0],
+            },
+            CaptureTag::EVENT => CaptureRecord::Event {
+                time,
+                event: CaptureEvent::from_u8(self.take(1)?[0]).ok_or(InvalidCapture)?,
+            },
+            _ => {
+                self.bytes = &[];
+                return Err(InvalidCapture);
+            }
+        };
+
+        Ok(Some(record))
+    }
+}
+
+impl Iterator for CaptureReader<'_> {
+    type Item = Result<CaptureRecord, InvalidCapture>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.bytes.is_empty() {
+            match self.next_record() {
+                Ok(Some(record)) => return Some(Ok(record)),
+                Ok(None) => (),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        None
+    }
+}
+
+/// One line per record for the `render` feature.
+///
+/// ```text
+/// 120 byte 0x1C
+/// 120 key code=30 pressed=1
+/// 120 char U+0061 'a'
+/// ```
+#[cfg(feature = "render")]
+#[cfg_attr(docsrs, doc(cfg(feature = "render")))]
+impl core::fmt::Display for CaptureRecord {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match *self {
+            CaptureRecord::Byte { time, byte } => write!(f, "{} byte 0x{:02X}", time, byte),
+            CaptureRecord::Key {
+                time,
+                code,
+                pressed,
+            } => write!(f, "{} key code={} pressed={}", time, code, pressed as u8),
+            CaptureRecord::Char { time, c } => {
+                write!(f, "{} char U+{:04X} {:?}", time, c as u32, c)
+            }
+            CaptureRecord::RawKey { time, code } => write!(f, "{} raw-key code={}", time, code),
+            CaptureRecord::Event { time, event } => write!(f, "{} event {:?}", time, event),
+        }
+    }
+}
@@ -2,3 +2,8 @@
 pub mod io;
 pub mod driver;
 pub mod raw;
+pub mod transaction;
+
+#[cfg(all(feature = "x86-io", any(target_arch = "x86", target_arch = "x86_64")))]
+#[cfg_attr(docsrs, doc(cfg(feature = "x86-io")))]
+pub mod x86;
@@ -1,12 +1,48 @@
+#[cfg(feature = "unstable")]
+#[cfg_attr(docsrs, doc(cfg(feature = "unstable")))]
+pub mod breadcrumbs;
+pub mod builder;
+pub mod config;
+#[cfg(feature = "conformance")]
+#[cfg_attr(docsrs, doc(cfg(feature = "conformance")))]
+pub mod conformance;
 pub mod debug;
+pub mod diagnostics;
+#[cfg(feature = "unstable")]
+#[cfg_attr(docsrs, doc(cfg(feature = "unstable")))]
+pub mod handoff;
+pub mod init_sequence;
 pub mod marker;
+pub mod output_port;
+pub mod quirks;
+pub mod ram;
+pub mod shutdown;
+#[cfg(feature = "aux-device")]
+#[cfg_attr(docsrs, doc(cfg(feature = "aux-device")))]
+pub mod split;
 pub mod status;
+pub mod transitions;
+pub mod unknown_bits;
 
+use builder::InitControllerBuilder;
+use config::{ConfigConflict, ControllerConfig};
 use marker::*;
-use status::{DataOwner, ReadStatus};
+use output_port::OutputPort;
+use quirks::Quirks;
+use ram::{ControllerRam, RamAddressOutOfRange, RamReadError};
+use status::{DataOrigin, DataOwner, ReadStatus};
+use transitions::{Transition, TransitionLog};
+use unknown_bits::{ParsingMode, UnknownBits, UnknownBitsSource};
 
+use super::transaction::{self, TransactionError};
 use super::{io::*, raw::*};
 
+#[cfg(feature = "aux-device")]
+use crate::device::identify::Device;
+use crate::device::io::{SendToDevice, TransmissionError, WouldBlock};
+use crate::device::keyboard::translation::TranslationMode;
+use crate::trace::{Subsystem, TraceEvent, TraceHook, TraceLevel, Tracer};
+
 use core::marker::PhantomData;
 
 #[derive(Debug)]
@@ -16,17 +52,36 @@ impl<T: PortIO> InitController<T> {
     /// You should disable interrupts before starting the initialization
     /// process.
     pub fn start_init(port_io: T) -> DevicesDisabled<T> {
-        let mut controller = DevicesDisabled(port_io);
+        Self::start_init_with_quirks(port_io, Quirks::empty())
+    }
+
+    /// Same as `start_init` but controller chipset workarounds are
+    /// used from the start.
+    pub fn start_init_with_quirks(port_io: T, quirks: Quirks) -> DevicesDisabled<T> {
+        Self::init(DevicesDisabled::new(port_io, None, quirks, Tracer::new()))
+    }
+
+    /// Collect all initialization options and then start the
+    /// initialization.
+    pub fn builder(port_io: T) -> InitControllerBuilder<T> {
+        InitControllerBuilder::new(port_io)
+    }
+
+    fn init(mut controller: DevicesDisabled<T>) -> DevicesDisabled<T> {
+        controller.transitions.record(Transition::Init);
+        Self::disable_devices_and_interrupts(controller)
+    }
 
+    fn disable_devices_and_interrupts(mut controller: DevicesDisabled<T>) -> DevicesDisabled<T> {
         controller.dangerous_disable_auxiliary_device_interface();
         controller.dangerous_disable_keyboard_interface();
 
-        let raw_command_byte = send_controller_command_and_wait_response(
-            &mut controller,
-            CommandReturnData::READ_CONTROLLER_COMMAND_BYTE,
+        // If the command byte can't be read, devices and interrupts are
+        // disabled with an otherwise cleared command byte.
+        let mut command_byte = controller.controller_command_byte().unwrap_or(
+            ControllerCommandByte::DISABLE_KEYBOARD
+                | ControllerCommandByte::DISABLE_AUXILIARY_DEVICE,
         );
-
-        let mut command_byte = ControllerCommandByte::from_bits_truncate(raw_command_byte);
         command_byte.set(ControllerCommandByte::ENABLE_AUXILIARY_INTERRUPT, false);
         command_byte.set(ControllerCommandByte::ENABLE_KEYBOARD_INTERRUPT, false);
 
@@ -34,31 +89,187 @@ impl<T: PortIO> InitController<T> {
 
         controller
     }
+
+    /// Start initialization without changing the controller command byte.
+    /// The command byte is written once when devices are enabled, using
+    /// `config` as the base value.
+    ///
+    /// Returns the original configuration, which can be restored
+    /// later with `DevicesDisabled::restore_controller_config`. It is
+    /// read before devices are disabled, so data which a device sends at
+    /// the same time may be read instead. The controller is returned
+    /// also if reading the configuration failed.
+    ///
+    /// Interrupts must be disabled until the devices are enabled, because
+    /// controller interrupts are not disabled.
+    pub fn start_init_with_config(
+        port_io: T,
+        config: ControllerConfig,
+    ) -> (
+        DevicesDisabled<T>,
+        Result<ControllerConfig, TransactionError>,
+    ) {
+        Self::init_with_config(DevicesDisabled::new(
+            port_io,
+            Some(config),
+            Quirks::empty(),
+            Tracer::new(),
+        ))
+    }
+
+    fn init_with_config(
+        mut controller: DevicesDisabled<T>,
+    ) -> (
+        DevicesDisabled<T>,
+        Result<ControllerConfig, TransactionError>,
+    ) {
+        controller.transitions.record(Transition::InitWithConfig);
+
+        let original_config = controller.controller_config();
+
+        controller.dangerous_disable_auxiliary_device_interface();
+        controller.dangerous_disable_keyboard_interface();
+
+        (controller, original_config)
+    }
 }
 
 #[derive(Debug)]
 pub enum InterfaceError {
     Keyboard(DeviceInterfaceError),
+    #[cfg(feature = "aux-device")]
     AuxiliaryDevice(DeviceInterfaceError),
+    /// Controller command byte couldn't be read after the interface
+    /// tests.
+    Transaction(TransactionError),
+}
+
+/// Returned from `DevicesDisabled::enable_available_devices`.
+#[derive(Debug)]
+pub struct AvailableDevices<T: PortIO, IRQ> {
+    pub controller: EnabledDevices<T, IRQ>,
+    /// Interface test failure of the device which was not enabled.
+    pub failure: Option<InterfaceError>,
 }
 
 #[derive(Debug)]
-pub struct DevicesDisabled<T: PortIO>(T);
+pub struct DevicesDisabled<T: PortIO> {
+    port_io: T,
+    /// Configuration which is written when devices are enabled.
+    config: Option<ControllerConfig>,
+    quirks: Quirks,
+    transitions: TransitionLog,
+    unknown_bits: UnknownBits,
+    tracer: Tracer,
+}
 
 impl<T: PortIO> DevicesDisabled<T> {
-    pub fn scancode_translation(&mut self, enabled: bool) {
-        let mut command_byte = self.controller_command_byte();
+    fn new(port_io: T, config: Option<ControllerConfig>, quirks: Quirks, tracer: Tracer) -> Self {
+        DevicesDisabled {
+            port_io,
+            config,
+            quirks,
+            transitions: TransitionLog::new(),
+            unknown_bits: UnknownBits::new(),
+            tracer,
+        }
+    }
+
+    /// Quirks are kept when the state changes.
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn transitions(&self) -> &TransitionLog {
+        &self.transitions
+    }
+
+    pub fn transitions_mut(&mut self) -> &mut TransitionLog {
+        &mut self.transitions
+    }
+
+    /// Parsing mode and records are kept when the state changes.
+    pub fn set_parsing_mode(&mut self, mode: ParsingMode) {
+        self.unknown_bits.set_mode(mode);
+    }
+
+    pub fn unknown_bits(&self) -> &UnknownBits {
+        &self.unknown_bits
+    }
+
+    /// Set hook for controller trace events. Hook and level are
+    /// kept when the state changes.
+    pub fn set_trace_hook(&mut self, hook: Option<TraceHook>) {
+        self.tracer.set_hook(hook);
+    }
+
+    /// Set trace level of `Subsystem::Controller`.
+    pub fn set_trace_level(&mut self, level: TraceLevel) {
+        self.tracer.set_level(Subsystem::Controller, level);
+    }
+
+    pub fn scancode_translation(&mut self, enabled: bool) -> Result<(), TransactionError> {
+        if let Some(config) = self.config {
+            self.config = Some(config.translation(enabled));
+            return Ok(());
+        }
+
+        let mut command_byte = self.controller_command_byte()?;
         command_byte.set(ControllerCommandByte::KEYBOARD_TRANSLATE_MODE, enabled);
         write_controller_command_byte(self, command_byte);
+        Ok(())
+    }
+
+    /// Enable controller translation for `TranslationMode::Hardware`
+    /// and disable it for `TranslationMode::Software`.
+    pub fn set_translation_mode(&mut self, mode: TranslationMode) -> Result<(), TransactionError> {
+        self.scancode_translation(mode.controller_translation())
+    }
+
+    /// Set configuration which is written when devices are enabled.
+    ///
+    /// Interrupt flags of the configuration are used only with
+    /// `enable_devices_and_interrupts` and only for enabled devices.
+    pub fn set_controller_config(&mut self, config: ControllerConfig) {
+        self.config = Some(config);
+    }
+
+    /// Same as `set_controller_config` but the configuration is
+    /// not set if `ControllerConfig::check` fails with the quirks of
+    /// this controller.
+    pub fn try_set_controller_config(
+        &mut self,
+        config: ControllerConfig,
+    ) -> Result<(), ConfigConflict> {
+        config.check(self.quirks)?;
+        self.config = Some(config);
+        Ok(())
+    }
+
+    /// Write the configuration as is and release the `PortIO`.
+    /// Use this to restore configuration on driver shutdown.
+    pub fn restore_controller_config(mut self, config: ControllerConfig) -> T {
+        send_controller_command_and_write_data(
+            &mut self,
+            CommandWaitData::WRITE_CONTROLLER_COMMAND_BYTE,
+            config.raw(),
+        );
+        self.port_io
     }
 
     pub fn enable_devices(
         mut self,
         devices: EnableDevice,
     ) -> Result<EnabledDevices<T, Disabled>, (Self, InterfaceError)> {
-        match self.test_devices(devices) {
-            Ok(()) => Ok(self.configure(devices, false)),
-            Err(e) => Err((self, e)),
+        let checked = self
+            .test_devices(devices)
+            .and_then(|()| self.interrupt_command_byte(false));
+        match checked {
+            Ok(command_byte) => Ok(self.configure(devices, false, command_byte)),
+            Err(e) => {
+                self.transitions.record(Transition::EnableFailed);
+                Err((self, e))
+            }
         }
     }
 
@@ -66,20 +277,107 @@ impl<T: PortIO> DevicesDisabled<T> {
         mut self,
         devices: EnableDevice,
     ) -> Result<EnabledDevices<T, InterruptsEnabled>, (Self, InterfaceError)> {
-        match self.test_devices(devices) {
-            Ok(()) => Ok(self.configure(devices, true)),
-            Err(e) => Err((self, e)),
+        let checked = self
+            .test_devices(devices)
+            .and_then(|()| self.interrupt_command_byte(true));
+        match checked {
+            Ok(command_byte) => Ok(self.configure(devices, true, command_byte)),
+            Err(e) => {
+                self.transitions.record(Transition::EnableFailed);
+                Err((self, e))
+            }
         }
     }
 
-    fn test_devices(&mut self, devices: EnableDevice) -> Result<(), InterfaceError> {
-        match &devices {
-            EnableDevice::Keyboard => self.test_keyboard(),
-            EnableDevice::AuxiliaryDevice => self.test_auxiliary_device(),
-            EnableDevice::KeyboardAndAuxiliaryDevice => self.test_keyboard_and_auxiliary_device(),
+    /// Same as `enable_devices` but if only one of two devices passes
+    /// its interface test, that device is enabled and the failure of
+    /// the other device is returned with the controller. Error is
+    /// returned if no device passed its test.
+    pub fn enable_available_devices(
+        mut self,
+        devices: EnableDevice,
+    ) -> Result<AvailableDevices<T, Disabled>, (Self, InterfaceError)> {
+        let checked = self.test_available_devices(devices).and_then(|available| {
+            self.interrupt_command_byte(false)
+                .map(|command_byte| (available, command_byte))
+        });
+        match checked {
+            Ok(((devices, failure), command_byte)) => Ok(AvailableDevices {
+                controller: self.configure(devices, false, command_byte),
+                failure,
+            }),
+            Err(e) => {
+                self.transitions.record(Transition::EnableFailed);
+                Err((self, e))
+            }
+        }
+    }
+
+    /// Same as `enable_devices_and_interrupts` but devices are
+    /// enabled like with `enable_available_devices`.
+    pub fn enable_available_devices_and_interrupts(
+        mut self,
+        devices: EnableDevice,
+    ) -> Result<AvailableDevices<T, InterruptsEnabled>, (Self, InterfaceError)> {
+        let checked = self.test_available_devices(devices).and_then(|available| {
+            self.interrupt_command_byte(true)
+                .map(|command_byte| (available, command_byte))
+        });
+        match checked {
+            Ok(((devices, failure), command_byte)) => Ok(AvailableDevices {
+                controller: self.configure(devices, true, command_byte),
+                failure,
+            }),
+            Err(e) => {
+                self.transitions.record(Transition::EnableFailed);
+                Err((self, e))
+            }
+        }
+    }
+
+    /// Returns devices which passed the interface test and the
+    /// failure of the other device.
+    fn test_available_devices(
+        &mut self,
+        devices: EnableDevice,
+    ) -> Result<(EnableDevice, Option<InterfaceError>), InterfaceError> {
+        #[cfg(feature = "aux-device")]
+        {
+            if let EnableDevice::KeyboardAndAuxiliaryDevice = devices {
+                let available = match (self.test_keyboard(), self.test_auxiliary_device()) {
+                    (Ok(()), Ok(())) => return Ok((devices, None)),
+                    (Ok(()), Err(e)) => (EnableDevice::Keyboard, e),
+                    (Err(e), Ok(())) => (EnableDevice::AuxiliaryDevice, e),
+                    (Err(e), Err(_)) => return Err(e),
+                };
+                self.transitions.record(Transition::EnablePartial);
+                return Ok((available.0, Some(available.1)));
+            }
         }
+
+        self.test_devices(devices).map(|()| (devices, None))
     }
 
+    fn test_devices(&mut self, devices: EnableDevice) -> Result<(), InterfaceError> {
+        let keyboard = if devices.keyboard() {
+            self.test_keyboard()
+        } else {
+            Ok(())
+        };
+
+        #[cfg(feature = "aux-device")]
+        let auxiliary_device = if devices.auxiliary_device() {
+            self.test_auxiliary_device()
+        } else {
+            Ok(())
+        };
+        #[cfg(not(feature = "aux-device"))]
+        let auxiliary_device = Ok(());
+
+        keyboard.and(auxiliary_device)
+    }
+
+    #[cfg(feature = "aux-device")]
     fn test_auxiliary_device(&mut self) -> Result<(), InterfaceError> {
         self.auxiliary_device_interface_test()
             .map_err(InterfaceError::AuxiliaryDevice)
@@ -90,32 +388,76 @@ impl<T: PortIO> DevicesDisabled<T> {
             .map_err(InterfaceError::Keyboard)
     }
 
-    fn test_keyboard_and_auxiliary_device(&mut self) -> Result<(), InterfaceError> {
-        self.test_keyboard().and(self.test_auxiliary_device())
+    /// Command byte which `configure` uses as the base value when
+    /// interrupts are enabled without a configuration. It is read
+    /// before devices are enabled, so that a failed read doesn't leave
+    /// devices enabled.
+    fn interrupt_command_byte(
+        &mut self,
+        interrupts: bool,
+    ) -> Result<Option<ControllerCommandByte>, InterfaceError> {
+        if interrupts && self.config.is_none() {
+            self.controller_command_byte()
+                .map(Some)
+                .map_err(InterfaceError::Transaction)
+        } else {
+            Ok(None)
+        }
     }
 
-    fn configure<IRQ>(mut self, devices: EnableDevice, interrupts: bool) -> EnabledDevices<T, IRQ> {
-        match &devices {
-            EnableDevice::Keyboard => self.dangerous_enable_keyboard_interface(),
-            EnableDevice::AuxiliaryDevice => self.dangerous_enable_auxiliary_device(),
-            EnableDevice::KeyboardAndAuxiliaryDevice => {
-                self.dangerous_enable_keyboard_interface();
+    fn configure<IRQ>(
+        mut self,
+        devices: EnableDevice,
+        interrupts: bool,
+        command_byte: Option<ControllerCommandByte>,
+    ) -> EnabledDevices<T, IRQ> {
+        if devices.keyboard() {
+            self.dangerous_enable_keyboard_interface();
+        }
+
+        #[cfg(feature = "aux-device")]
+        {
+            if devices.auxiliary_device() {
                 self.dangerous_enable_auxiliary_device();
             }
         }
 
-        if interrupts {
-            let mut command_byte = self.controller_command_byte();
+        if let Some(config) = self.config {
+            #[cfg(feature = "aux-device")]
+            let auxiliary_device = devices.auxiliary_device();
+            #[cfg(not(feature = "aux-device"))]
+            let auxiliary_device = false;
 
-            match &devices {
-                EnableDevice::Keyboard => {
-                    command_byte.set(ControllerCommandByte::ENABLE_KEYBOARD_INTERRUPT, true)
-                }
-                EnableDevice::AuxiliaryDevice => {
-                    command_byte.set(ControllerCommandByte::ENABLE_AUXILIARY_INTERRUPT, true)
-                }
-                EnableDevice::KeyboardAndAuxiliaryDevice => {
-                    command_byte.set(ControllerCommandByte::ENABLE_KEYBOARD_INTERRUPT, true);
+            let keyboard_interrupt =
+                config.enabled(ControllerCommandByte::ENABLE_KEYBOARD_INTERRUPT);
+            let auxiliary_interrupt =
+                config.enabled(ControllerCommandByte::ENABLE_AUXILIARY_INTERRUPT);
+
+            let config = config
+                .with(ControllerCommandByte::DISABLE_KEYBOARD, !devices.keyboard())
+                .with(
+                    ControllerCommandByte::DISABLE_AUXILIARY_DEVICE,
+                    !auxiliary_device,
+                )
+                .keyboard_interrupt(interrupts && devices.keyboard() && keyboard_interrupt)
+                .auxiliary_device_interrupt(interrupts && auxiliary_device && auxiliary_interrupt);
+
+            send_controller_command_and_write_data(
+                &mut self,
+                CommandWaitData::WRITE_CONTROLLER_COMMAND_BYTE,
+                config.raw(),
+            );
+        } else if let Some(mut command_byte) = command_byte {
+            // Command byte was read before the interfaces were enabled.
+            if devices.keyboard() {
+                command_byte.remove(ControllerCommandByte::DISABLE_KEYBOARD);
+                command_byte.set(ControllerCommandByte::ENABLE_KEYBOARD_INTERRUPT, true);
+            }
+
+            #[cfg(feature = "aux-device")]
+            {
+                if devices.auxiliary_device() {
+                    command_byte.remove(ControllerCommandByte::DISABLE_AUXILIARY_DEVICE);
                     command_byte.set(ControllerCommandByte::ENABLE_AUXILIARY_INTERRUPT, true);
                 }
             }
@@ -123,10 +465,25 @@ impl<T: PortIO> DevicesDisabled<T> {
             write_controller_command_byte(&mut self, command_byte);
         }
 
+        let mut transitions = self.transitions;
+        transitions.record(if interrupts {
+            Transition::EnableDevicesAndInterrupts
+        } else {
+            Transition::EnableDevices
+        });
+
         EnabledDevices {
-            port_io: self.0,
+            port_io: self.port_io,
             _marker: PhantomData,
             devices,
+            controller_response_pending: false,
+            auxiliary_device_data_pending: None,
+            tracer: self.tracer,
+            quirks: self.quirks,
+            transitions,
+            unknown_bits: self.unknown_bits,
+            #[cfg(feature = "aux-device")]
+            auxiliary_device_kind: None,
         }
     }
 }
@@ -134,13 +491,47 @@ impl<T: PortIO> DevicesDisabled<T> {
 #[derive(Debug, Copy, Clone)]
 pub enum EnableDevice {
     Keyboard,
+    #[cfg(feature = "aux-device")]
     AuxiliaryDevice,
+    #[cfg(feature = "aux-device")]
     KeyboardAndAuxiliaryDevice,
 }
 
+impl EnableDevice {
+    pub fn keyboard(&self) -> bool {
+        match self {
+            EnableDevice::Keyboard => true,
+            #[cfg(feature = "aux-device")]
+            EnableDevice::AuxiliaryDevice => false,
+            #[cfg(feature = "aux-device")]
+            EnableDevice::KeyboardAndAuxiliaryDevice => true,
+        }
+    }
+
+    #[cfg(feature = "aux-device")]
+    pub fn auxiliary_device(&self) -> bool {
+        match self {
+            EnableDevice::Keyboard => false,
+            EnableDevice::AuxiliaryDevice | EnableDevice::KeyboardAndAuxiliaryDevice => true,
+        }
+    }
+}
+
 impl_port_io_available!(<T: PortIO> DevicesDisabled<T>);
 
-impl<T: PortIO> ReadStatus<T> for DevicesDisabled<T> {}
+impl<T: PortIO> ReadStatus<T> for DevicesDisabled<T> {
+    fn tracer(&self) -> Option<&Tracer> {
+        Some(&self.tracer)
+    }
+
+    fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    fn unknown_bits_mut(&mut self) -> Option<&mut UnknownBits> {
+        Some(&mut self.unknown_bits)
+    }
+}
 impl<T: PortIO> DangerousDeviceCommands<T> for DevicesDisabled<T> {}
 impl<T: PortIO> InterruptsDisabled for DevicesDisabled<T> {}
 impl<T: PortIO> KeyboardDisabled for DevicesDisabled<T> {}
@@ -148,6 +539,11 @@ impl<T: PortIO> AuxiliaryDeviceDisabled for DevicesDisabled<T> {}
 impl<T: PortIO> ReadRAM<T> for DevicesDisabled<T> {}
 impl<T: PortIO> WriteRAM<T> for DevicesDisabled<T> {}
 impl<T: PortIO> Testing<T> for DevicesDisabled<T> {}
+impl<T: PortIO> Password<T> for DevicesDisabled<T> {}
+impl<T: PortIO> ReadInputs<T> for DevicesDisabled<T> {}
+impl<T: PortIO> WriteOutputBuffer<T> for DevicesDisabled<T> {}
+impl<T: PortIO> OutputPort<T> for DevicesDisabled<T> {}
+#[cfg(feature = "machine-reset")]
 impl<T: PortIO> ResetCPU<T> for DevicesDisabled<T> {}
 
 #[derive(Debug)]
@@ -155,40 +551,200 @@ pub struct EnabledDevices<T: PortIO, IRQ> {
     port_io: T,
     _marker: PhantomData<IRQ>,
     devices: EnableDevice,
+    controller_response_pending: bool,
+    auxiliary_device_data_pending: Option<u8>,
+    tracer: Tracer,
+    quirks: Quirks,
+    transitions: TransitionLog,
+    unknown_bits: UnknownBits,
+    #[cfg(feature = "aux-device")]
+    auxiliary_device_kind: Option<Device>,
+}
+
+#[derive(Debug)]
+pub struct DeviceNotEnabled;
+
+#[derive(Debug)]
+pub enum SendError {
+    DeviceNotEnabled,
+    /// Controller input buffer is full. Try again later.
+    WouldBlock,
+}
+
+/// `SendToDevice` implementation which sends to the keyboard.
+#[derive(Debug)]
+pub struct KeyboardSender<'a, T: PortIO, IRQ>(&'a mut EnabledDevices<T, IRQ>);
+
+impl<T: PortIO, IRQ> SendToDevice for KeyboardSender<'_, T, IRQ> {
+    fn send(&mut self, data: u8) {
+        // Keyboard is enabled, so sending can't fail.
+        let _ = self.0.send_to_keyboard(data);
+    }
+
+    fn try_send(&mut self, data: u8) -> Result<(), WouldBlock> {
+        self.0.try_send_to_keyboard(data).map_err(|_| WouldBlock)
+    }
 }
 
 impl<T: PortIO, IRQ> EnabledDevices<T, IRQ> {
-    pub fn send_to_auxiliary_device(&mut self, data: u8) -> Result<(), ()> {
-        match &self.devices {
-            EnableDevice::AuxiliaryDevice | EnableDevice::KeyboardAndAuxiliaryDevice => {
-                send_controller_command_and_write_data(
-                    self,
-                    CommandWaitData::WRITE_TO_AUXILIARY_DEVICE,
-                    data,
-                );
-                Ok(())
-            }
-            EnableDevice::Keyboard => Err(()),
+    pub fn transitions(&self) -> &TransitionLog {
+        &self.transitions
+    }
+
+    pub fn transitions_mut(&mut self) -> &mut TransitionLog {
+        &mut self.transitions
+    }
+
+    pub fn unknown_bits(&self) -> &UnknownBits {
+        &self.unknown_bits
+    }
+
+    /// Type of the auxiliary device. `None` until set with
+    /// `set_auxiliary_device_kind`. `mouse::MouseDriver` updates
+    /// this when the device is reconnected.
+    #[cfg(feature = "aux-device")]
+    pub fn auxiliary_device_kind(&self) -> Option<Device> {
+        self.auxiliary_device_kind
+    }
+
+    /// Set result of the auxiliary device identification. Ignored
+    /// if the auxiliary device is not enabled.
+    #[cfg(feature = "aux-device")]
+    pub fn set_auxiliary_device_kind(&mut self, kind: Option<Device>) {
+        if self.devices.auxiliary_device() {
+            self.auxiliary_device_kind = kind;
         }
     }
 
-    pub fn send_to_keyboard(&mut self, data: u8) -> Result<(), ()> {
-        match &self.devices {
-            EnableDevice::Keyboard | EnableDevice::KeyboardAndAuxiliaryDevice => {
-                while self.status().input_buffer_full() {}
-                self.port_io_mut().write(T::DATA_PORT, data);
-                Ok(())
+    /// Used by `mouse::MouseDriver` interrupt storm protection.
+    #[cfg(feature = "aux-device")]
+    pub(crate) fn set_auxiliary_device_interface(&mut self, enabled: bool) {
+        let command = if enabled {
+            Command::ENABLE_AUXILIARY_DEVICE_INTERFACE
+        } else {
+            Command::DISABLE_AUXILIARY_DEVICE_INTERFACE
+        };
+        while self.flush().is_err() {}
+        send_controller_command_and_wait_processing(self, command);
+    }
+
+    /// Sender for `device::keyboard::driver::Keyboard` commands.
+    pub fn keyboard_sender(&mut self) -> Result<KeyboardSender<'_, T, IRQ>, DeviceNotEnabled> {
+        if self.devices.keyboard() {
+            Ok(KeyboardSender(self))
+        } else {
+            Err(DeviceNotEnabled)
+        }
+    }
+
+    /// Set hook for controller trace events.
+    pub fn set_trace_hook(&mut self, hook: Option<TraceHook>) {
+        self.tracer.set_hook(hook);
+    }
+
+    /// Set trace level of `Subsystem::Controller`. Levels of other
+    /// subsystems are ignored.
+    pub fn set_trace_level(&mut self, level: TraceLevel) {
+        self.tracer.set_level(Subsystem::Controller, level);
+    }
+
+    #[cfg(feature = "aux-device")]
+    pub fn send_to_auxiliary_device(&mut self, data: u8) -> Result<(), DeviceNotEnabled> {
+        if self.devices.auxiliary_device() {
+            while self.flush().is_err() {}
+            send_controller_command_and_write_data(
+                self,
+                CommandWaitData::WRITE_TO_AUXILIARY_DEVICE,
+                data,
+            );
+            Ok(())
+        } else {
+            Err(DeviceNotEnabled)
+        }
+    }
+
+    pub fn send_to_keyboard(&mut self, data: u8) -> Result<(), DeviceNotEnabled> {
+        if self.devices.keyboard() {
+            while self.flush().is_err() {}
+            while self.status().input_buffer_full() {}
+            transaction::write_data(self, data);
+            Ok(())
+        } else {
+            Err(DeviceNotEnabled)
+        }
+    }
+
+    /// Send to keyboard without waiting for the controller
+    /// input buffer.
+    pub fn try_send_to_keyboard(&mut self, data: u8) -> Result<(), SendError> {
+        if !self.devices.keyboard() {
+            return Err(SendError::DeviceNotEnabled);
+        }
+
+        self.flush()?;
+
+        if self.status().input_buffer_full() {
+            return Err(SendError::WouldBlock);
+        }
+
+        transaction::write_data(self, data);
+        Ok(())
+    }
+
+    /// Send to auxiliary device without waiting for the controller
+    /// input buffer.
+    ///
+    /// Sending requires a controller command and a data write. If
+    /// the controller is still processing the command, the data byte is
+    /// stored and written later by `flush` or the next send.
+    #[cfg(feature = "aux-device")]
+    pub fn try_send_to_auxiliary_device(&mut self, data: u8) -> Result<(), SendError> {
+        if !self.devices.auxiliary_device() {
+            return Err(SendError::DeviceNotEnabled);
+        }
+
+        self.flush()?;
+
+        if self.status().input_buffer_full() {
+            return Err(SendError::WouldBlock);
+        }
+
+        transaction::write_command(self, CommandWaitData::WRITE_TO_AUXILIARY_DEVICE);
+        self.auxiliary_device_data_pending = Some(data);
+
+        // Ignore `WouldBlock`, because the data byte is now stored.
+        let _ = self.flush();
+        Ok(())
+    }
+
+    /// Write stored auxiliary device data byte if there is one.
+    pub fn flush(&mut self) -> Result<(), SendError> {
+        if let Some(data) = self.auxiliary_device_data_pending {
+            if self.status().input_buffer_full() {
+                return Err(SendError::WouldBlock);
             }
-            EnableDevice::AuxiliaryDevice => Err(()),
+
+            transaction::write_data(self, data);
+            self.auxiliary_device_data_pending = None;
         }
+
+        Ok(())
     }
 }
 
 impl<T: PortIO> EnabledDevices<T, InterruptsEnabled> {
     /// You should disable the interrupts before disabling
     /// the devices.
-    pub fn disable_devices(self) -> DevicesDisabled<T> {
-        InitController::start_init(self.port_io)
+    pub fn disable_devices(mut self) -> DevicesDisabled<T> {
+        self.transitions.record(Transition::DisableDevices);
+        InitController::disable_devices_and_interrupts(DevicesDisabled {
+            port_io: self.port_io,
+            config: None,
+            quirks: self.quirks,
+            transitions: self.transitions,
+            unknown_bits: self.unknown_bits,
+            tracer: self.tracer,
+        })
     }
 }
 
@@ -197,15 +753,45 @@ impl<T: PortIO> EnabledDevices<T, Disabled> {
         self.dangerous_disable_auxiliary_device_interface();
         self.dangerous_disable_keyboard_interface();
 
-        DevicesDisabled(self.port_io)
+        self.transitions.record(Transition::DisableDevices);
+        DevicesDisabled {
+            port_io: self.port_io,
+            config: None,
+            quirks: self.quirks,
+            transitions: self.transitions,
+            unknown_bits: self.unknown_bits,
+            tracer: self.tracer,
+        }
     }
 }
 
 impl_port_io_available!(<T: PortIO, IRQ> EnabledDevices<T, IRQ>);
 
-impl<T: PortIO, IRQ> ReadStatus<T> for EnabledDevices<T, IRQ> {}
+impl<T: PortIO, IRQ> ReadStatus<T> for EnabledDevices<T, IRQ> {
+    fn controller_response_pending(&self) -> bool {
+        self.controller_response_pending
+    }
+
+    fn set_controller_response_pending(&mut self, pending: bool) {
+        self.controller_response_pending = pending;
+    }
+
+    fn tracer(&self) -> Option<&Tracer> {
+        Some(&self.tracer)
+    }
+
+    fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    fn unknown_bits_mut(&mut self) -> Option<&mut UnknownBits> {
+        Some(&mut self.unknown_bits)
+    }
+}
 impl<T: PortIO, IRQ> ReadData<T> for EnabledDevices<T, IRQ> {}
+#[cfg(feature = "machine-reset")]
 impl<T: PortIO, IRQ> ResetCPU<T> for EnabledDevices<T, IRQ> {}
+impl<T: PortIO, IRQ> WriteOutputBuffer<T> for EnabledDevices<T, IRQ> {}
 
 impl<T: PortIO> DangerousDeviceCommands<T> for EnabledDevices<T, Disabled> {}
 
@@ -216,17 +802,19 @@ pub enum DeviceInterfaceError {
     DataLineLow,
     DataLineHigh,
     UnknownValue(u8),
+    /// Test command failed.
+    Transaction(TransactionError),
 }
 
 impl DeviceInterfaceError {
     fn from_test_result(value: u8) -> Result<(), DeviceInterfaceError> {
         use DeviceInterfaceError::*;
         let result = match value {
-            0 => return Ok(()),
-            1 => ClockLineLow,
-            2 => ClockLineHigh,
-            3 => DataLineLow,
-            4 => DataLineHigh,
+            InterfaceTestResult::PASSED => return Ok(()),
+            InterfaceTestResult::CLOCK_LINE_LOW => ClockLineLow,
+            InterfaceTestResult::CLOCK_LINE_HIGH => ClockLineHigh,
+            InterfaceTestResult::DATA_LINE_LOW => DataLineLow,
+            InterfaceTestResult::DATA_LINE_HIGH => DataLineHigh,
             _ => UnknownValue(value),
         };
 
@@ -234,20 +822,34 @@ impl DeviceInterfaceError {
     }
 }
 
-// TODO: The IBM reference (PDF page 344) says that there
-//       shouldn't be any writes to ports 0x60 and 0x64 when
-//       output buffer bit is set to 1. This is probably unnecessary
-//       when controller command doesn't use the output buffer?
-//       The current code checks that the buffer is empty only when using
-//       commands that return a value.
+pub(crate) fn trace<T: PortIO, U: ReadStatus<T>, F: FnOnce() -> TraceEvent>(
+    controller: &U,
+    level: TraceLevel,
+    event: F,
+) {
+    if let Some(tracer) = controller.tracer() {
+        tracer.trace(Subsystem::Controller, level, event);
+    }
+}
+
+fn check_unknown_bits<T: PortIO, U: ReadStatus<T>>(
+    controller: &mut U,
+    source: UnknownBitsSource,
+    raw: u8,
+    known: u8,
+) {
+    if let Some(unknown_bits) = controller.unknown_bits_mut() {
+        unknown_bits.check(source, raw, known);
+    }
+}
 
 fn send_controller_command_and_wait_processing<T: PortIO, U: ReadStatus<T>>(
     controller: &mut U,
     command: u8,
 ) {
-    while controller.status().input_buffer_full() {}
-    controller.port_io_mut().write(T::COMMAND_REGISTER, command);
-    while controller.status().input_buffer_full() {}
+    // Waits without a poll limit don't fail and GENERAL_TIMEOUT
+    // after the retries is reported with tracing.
+    let _ = transaction::send_command(controller, command, None);
 }
 
 fn send_controller_command_and_write_data<T: PortIO, U: ReadStatus<T>>(
@@ -255,8 +857,7 @@ fn send_controller_command_and_write_data<T: PortIO, U: ReadStatus<T>>(
     command: u8,
     data: u8,
 ) {
-    send_controller_command_and_wait_processing(controller, command);
-    controller.port_io_mut().write(T::DATA_PORT, data);
+    let _ = transaction::send_command_and_data(controller, command, data, None);
 }
 
 fn write_controller_command_byte<T: PortIO, U: ReadStatus<T>>(
@@ -276,47 +877,107 @@ fn send_controller_command_and_wait_response<
 >(
     controller: &mut U,
     command: u8,
-) -> u8 {
-    if controller.status().data_availability().is_some() {
-        controller.port_io_mut().read(T::DATA_PORT);
-    }
-
-    send_controller_command_and_wait_processing(controller, command);
-
-    loop {
-        if let Some(DataOwner::KeyboardOrCommandController) =
-            controller.status().data_availability()
-        {
-            return controller.port_io_mut().read(T::DATA_PORT);
-        }
-    }
+) -> Result<u8, TransactionError> {
+    transaction::send_command_and_read_response(controller, command, None)
 }
 
 pub trait ReadRAM<T: PortIO>:
     ReadStatus<T> + InterruptsDisabled + KeyboardDisabled + AuxiliaryDeviceDisabled + Sized
 {
-    fn controller_command_byte(&mut self) -> ControllerCommandByte {
+    fn controller_command_byte(&mut self) -> Result<ControllerCommandByte, TransactionError> {
+        self.controller_config().map(|config| config.command_byte())
+    }
+
+    /// Read the controller command byte including undefined bits.
+    fn controller_config(&mut self) -> Result<ControllerConfig, TransactionError> {
         let raw = send_controller_command_and_wait_response(
             self,
             CommandReturnData::READ_CONTROLLER_COMMAND_BYTE,
+        )?;
+        check_unknown_bits(
+            self,
+            UnknownBitsSource::CommandByte,
+            raw,
+            ControllerCommandByte::all().bits(),
         );
-        ControllerCommandByte::from_bits_truncate(raw)
+        Ok(ControllerConfig::from_raw(raw))
     }
 
-    fn ram(&mut self, data: &mut [u8; CONTROLLER_RAM_SIZE]) {
-        for (i, byte) in data.iter_mut().enumerate() {
-            let data = send_controller_command_and_wait_response(
+    /// Read the command byte and the rest of the controller RAM.
+    fn controller_ram(&mut self) -> Result<ControllerRam, TransactionError> {
+        let config = self.controller_config()?;
+        let mut bytes = [0; CONTROLLER_RAM_SIZE];
+
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = send_controller_command_and_wait_response(
                 self,
                 CommandReturnData::READ_RAM_START + i as u8,
-            );
-            *byte = data;
+            )?;
+        }
+
+        Ok(ControllerRam::new(config, bytes))
+    }
+
+    /// Read one byte of the controller RAM. Address 0 is the command
+    /// byte.
+    fn ram_byte(&mut self, address: u8) -> Result<u8, RamReadError> {
+        if address as usize >= ControllerRam::SIZE {
+            return Err(RamReadError::AddressOutOfRange);
         }
+
+        send_controller_command_and_wait_response(
+            self,
+            CommandReturnData::READ_CONTROLLER_COMMAND_BYTE + address,
+        )
+        .map_err(RamReadError::Transaction)
+    }
+
+    /// `data` is not changed if reading fails.
+    #[deprecated(note = "use `controller_ram`")]
+    fn ram(&mut self, data: &mut [u8; CONTROLLER_RAM_SIZE]) -> Result<(), TransactionError> {
+        *data = *self.controller_ram()?.bytes();
+        Ok(())
+    }
+}
+
+pub trait ReadInputs<T: PortIO>:
+    ReadStatus<T> + InterruptsDisabled + KeyboardDisabled + AuxiliaryDeviceDisabled + Sized
+{
+    /// Read controller input port. Some machines encode board
+    /// configuration to the bits which are not defined in `InputPortBits`,
+    /// so the raw value is also returned.
+    fn input_port(&mut self) -> Result<(InputPortBits, u8), TransactionError> {
+        let raw =
+            send_controller_command_and_wait_response(self, CommandReturnData::READ_INPUT_PORT)?;
+        check_unknown_bits(
+            self,
+            UnknownBitsSource::InputPort,
+            raw,
+            InputPortBits::all().bits(),
+        );
+        Ok((InputPortBits::from_bits_truncate(raw), raw))
+    }
+
+    /// Read controller test inputs T0 and T1.
+    fn test_inputs(&mut self) -> Result<TestInputBits, TransactionError> {
+        let raw =
+            send_controller_command_and_wait_response(self, CommandReturnData::READ_TEST_INPUTS)?;
+        check_unknown_bits(
+            self,
+            UnknownBitsSource::TestInputs,
+            raw,
+            TestInputBits::all().bits(),
+        );
+        Ok(TestInputBits::from_bits_truncate(raw))
     }
 }
 
 pub trait WriteRAM<T: PortIO>: ReadStatus<T> + Sized {
-    fn write_ram(&mut self, data: &mut [u8; CONTROLLER_RAM_SIZE]) {
-        for (i, byte) in data.iter().enumerate() {
+    /// Write controller RAM except the command byte. Command byte
+    /// is changed only with the initialization methods, because it
+    /// enables devices and interrupts.
+    fn write_controller_ram(&mut self, ram: &ControllerRam) {
+        for (i, byte) in ram.bytes().iter().enumerate() {
             send_controller_command_and_write_data(
                 self,
                 CommandWaitData::WRITE_RAM_START + i as u8,
@@ -324,6 +985,27 @@ pub trait WriteRAM<T: PortIO>: ReadStatus<T> + Sized {
             );
         }
     }
+
+    /// Write one byte of the controller RAM. Address 0, the command
+    /// byte, is out of range.
+    fn write_ram_byte(&mut self, address: u8, value: u8) -> Result<(), RamAddressOutOfRange> {
+        if address == ControllerRam::COMMAND_BYTE_ADDRESS || address as usize >= ControllerRam::SIZE
+        {
+            return Err(RamAddressOutOfRange);
+        }
+
+        send_controller_command_and_write_data(
+            self,
+            CommandWaitData::WRITE_CONTROLLER_COMMAND_BYTE + address,
+            value,
+        );
+        Ok(())
+    }
+
+    #[deprecated(note = "use `write_controller_ram`")]
+    fn write_ram(&mut self, data: &mut [u8; CONTROLLER_RAM_SIZE]) {
+        self.write_controller_ram(&ControllerRam::new(ControllerConfig::from_raw(0), *data));
+    }
 }
 
 /// Commands which may break invariants which are encoded
@@ -336,6 +1018,7 @@ trait DangerousDeviceCommands<T: PortIO>: ReadStatus<T> + Sized {
         );
     }
 
+    #[cfg(feature = "aux-device")]
     fn dangerous_enable_auxiliary_device(&mut self) {
         send_controller_command_and_wait_processing(
             self,
@@ -352,31 +1035,42 @@ trait DangerousDeviceCommands<T: PortIO>: ReadStatus<T> + Sized {
     }
 }
 
+#[derive(Debug)]
+pub enum SelfTestError {
+    /// Self test result which is not `SelfTestResult::PASSED`.
+    Failed(u8),
+    Transaction(TransactionError),
+}
+
 pub trait Testing<T: PortIO>:
     ReadStatus<T> + ReadRAM<T> + InterruptsDisabled + KeyboardDisabled + AuxiliaryDeviceDisabled + Sized
 {
+    #[cfg(feature = "aux-device")]
     fn auxiliary_device_interface_test(&mut self) -> Result<(), DeviceInterfaceError> {
         let test_result = send_controller_command_and_wait_response(
             self,
             CommandReturnData::AUXILIARY_DEVICE_INTERFACE_TEST,
-        );
+        )
+        .map_err(DeviceInterfaceError::Transaction)?;
         DeviceInterfaceError::from_test_result(test_result)
     }
 
-    fn self_test(&mut self) -> Result<(), u8> {
+    fn self_test(&mut self) -> Result<(), SelfTestError> {
         // According to the OSDev Wiki the controller self test
         // may reset the controller, so lets save
         // the controller command byte and restore it
         // after the self test.
 
-        let command_byte = self.controller_command_byte();
+        let command_byte = self
+            .controller_command_byte()
+            .map_err(SelfTestError::Transaction)?;
         let result = send_controller_command_and_wait_response(self, CommandReturnData::SELF_TEST);
         write_controller_command_byte(self, command_byte);
 
-        if result == 0x55 {
-            Ok(())
-        } else {
-            Err(result)
+        match result {
+            Ok(SelfTestResult::PASSED) => Ok(()),
+            Ok(value) => Err(SelfTestError::Failed(value)),
+            Err(e) => Err(SelfTestError::Transaction(e)),
         }
     }
 
@@ -384,34 +1078,272 @@ pub trait Testing<T: PortIO>:
         let test_result = send_controller_command_and_wait_response(
             self,
             CommandReturnData::KEYBOARD_INTERFACE_TEST,
-        );
+        )
+        .map_err(DeviceInterfaceError::Transaction)?;
         DeviceInterfaceError::from_test_result(test_result)
     }
 }
 
+#[derive(Debug)]
+pub enum PasswordError {
+    NotInstalled,
+    /// Password must contain at least one byte before
+    /// the null terminator.
+    EmptyPassword,
+    UnknownTestResult(u8),
+    Transaction(TransactionError),
+}
+
+pub trait Password<T: PortIO>:
+    ReadStatus<T> + InterruptsDisabled + KeyboardDisabled + AuxiliaryDeviceDisabled + Sized
+{
+    fn password_installed(&mut self) -> Result<bool, PasswordError> {
+        let result = send_controller_command_and_wait_response(
+            self,
+            CommandReturnData::TEST_PASSWORD_INSTALLED,
+        )
+        .map_err(PasswordError::Transaction)?;
+
+        match result {
+            PasswordTestResult::INSTALLED => Ok(true),
+            PasswordTestResult::NOT_INSTALLED => Ok(false),
+            value => Err(PasswordError::UnknownTestResult(value)),
+        }
+    }
+
+    /// Load password to the controller.
+    ///
+    /// Password bytes are keyboard scancodes. Bytes after the first null
+    /// byte are ignored and the null terminator is written automatically.
+    fn load_password(&mut self, password: &[u8]) -> Result<(), PasswordError> {
+        let length = password
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(password.len());
+
+        if length == 0 {
+            return Err(PasswordError::EmptyPassword);
+        }
+
+        send_controller_command_and_wait_processing(self, CommandWaitData::LOAD_PASSWORD);
+
+        for byte in password[..length].iter().chain(&[0]) {
+            while self.status().input_buffer_full() {}
+            self.port_io_mut().write(T::DATA_PORT, *byte);
+        }
+
+        while self.status().input_buffer_full() {}
+
+        Ok(())
+    }
+
+    /// Enable password security. Keyboard and auxiliary device
+    /// communication is blocked until the user types the password.
+    fn enable_password(&mut self) -> Result<(), PasswordError> {
+        if !self.password_installed()? {
+            return Err(PasswordError::NotInstalled);
+        }
+
+        send_controller_command_and_wait_processing(self, Command::ENABLE_PASSWORD);
+
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub enum DeviceData {
     Keyboard(u8),
+    #[cfg(feature = "aux-device")]
     AuxiliaryDevice(u8),
+    /// Response to a controller command which was sent
+    /// without waiting for the response.
+    ControllerCommandResponse(u8),
+    /// Corrupted byte from keyboard was discarded. Request
+    /// resend from the keyboard.
+    KeyboardTransmissionError(TransmissionError),
+    /// Corrupted byte from auxiliary device was discarded. Request
+    /// resend from the auxiliary device.
+    #[cfg(feature = "aux-device")]
+    AuxiliaryDeviceTransmissionError(TransmissionError),
+}
+
+/// Byte from the data port with the status register state which
+/// was used to route it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RoutedByte {
+    pub channel: DataOwner,
+    /// Byte as it was read, also when it is corrupted.
+    pub byte: u8,
+    /// Byte is the response to a controller command.
+    pub attributed_to_command: bool,
+    pub error: Option<TransmissionError>,
+}
+
+impl RoutedByte {
+    /// Convert to the `ReadData::read_data` representation. `None`
+    /// for auxiliary device data if auxiliary device support is
+    /// disabled.
+    pub fn device_data(self) -> Option<DeviceData> {
+        if self.attributed_to_command {
+            return Some(DeviceData::ControllerCommandResponse(self.byte));
+        }
+
+        match (self.channel, self.error) {
+            (DataOwner::KeyboardOrCommandController, Some(e)) => {
+                Some(DeviceData::KeyboardTransmissionError(e))
+            }
+            (DataOwner::KeyboardOrCommandController, None) => Some(DeviceData::Keyboard(self.byte)),
+            #[cfg(feature = "aux-device")]
+            (DataOwner::AuxiliaryDevice, Some(e)) => {
+                Some(DeviceData::AuxiliaryDeviceTransmissionError(e))
+            }
+            #[cfg(feature = "aux-device")]
+            (DataOwner::AuxiliaryDevice, None) => Some(DeviceData::AuxiliaryDevice(self.byte)),
+            #[cfg(not(feature = "aux-device"))]
+            (DataOwner::AuxiliaryDevice, _) => None,
+        }
+    }
+}
+
+impl DeviceData {
+    /// Channel which the byte came from. Controller command
+    /// responses share the keyboard channel.
+    pub fn channel(&self) -> DataOwner {
+        match self {
+            DeviceData::Keyboard(_)
+            | DeviceData::ControllerCommandResponse(_)
+            | DeviceData::KeyboardTransmissionError(_) => DataOwner::KeyboardOrCommandController,
+            #[cfg(feature = "aux-device")]
+            DeviceData::AuxiliaryDevice(_) | DeviceData::AuxiliaryDeviceTransmissionError(_) => {
+                DataOwner::AuxiliaryDevice
+            }
+        }
+    }
+
+    /// `None` if the byte was corrupted.
+    pub fn byte(&self) -> Option<u8> {
+        match *self {
+            DeviceData::Keyboard(data) | DeviceData::ControllerCommandResponse(data) => Some(data),
+            #[cfg(feature = "aux-device")]
+            DeviceData::AuxiliaryDevice(data) => Some(data),
+            _ => None,
+        }
+    }
 }
 
 pub trait ReadData<T: PortIO>: ReadStatus<T> + Sized {
+    /// If auxiliary device support is disabled, auxiliary device
+    /// data is discarded.
     fn read_data(&mut self) -> Option<DeviceData> {
-        self.status().data_availability().map(|data_owner| {
-            let data = self.port_io_mut().read(T::DATA_PORT);
-            match data_owner {
-                DataOwner::KeyboardOrCommandController => DeviceData::Keyboard(data),
-                DataOwner::AuxiliaryDevice => DeviceData::AuxiliaryDevice(data),
+        self.read_routed()?.device_data()
+    }
+
+    /// Same as `read_data` but the byte is returned with its routing
+    /// information, also when it is corrupted or auxiliary device
+    /// support is disabled.
+    fn read_routed(&mut self) -> Option<RoutedByte> {
+        let status = self.status();
+        let data_origin = status.data_origin()?;
+        let byte = self.port_io_mut().read(T::DATA_PORT);
+        trace(self, TraceLevel::Verbose, || TraceEvent::DataRead(byte));
+
+        let (channel, attributed_to_command) = match data_origin {
+            DataOrigin::Keyboard => (DataOwner::KeyboardOrCommandController, false),
+            DataOrigin::AuxiliaryDevice => (DataOwner::AuxiliaryDevice, false),
+            DataOrigin::ControllerCommandResponse => {
+                self.set_controller_response_pending(false);
+                (DataOwner::KeyboardOrCommandController, true)
             }
+        };
+
+        Some(RoutedByte {
+            channel,
+            byte,
+            attributed_to_command,
+            error: status.transmission_error(),
         })
     }
+
+    /// Owner of the byte in the output buffer. Only the status
+    /// register is read, so the byte stays in the buffer.
+    fn peek_available(&mut self) -> Option<DataOwner> {
+        self.status().data_availability()
+    }
 }
 
-pub trait ResetCPU<T: PortIO>: ReadStatus<T> + Sized {
-    fn reset_cpu(&mut self) {
-        send_controller_command_and_wait_processing(
+/// Loopback writes to the controller output buffer.
+///
+/// Written byte is returned from the data port like it was sent
+/// from the device, including the device interrupt if it is enabled.
+/// This is useful for testing device drivers and interrupt handlers
+/// without physical input.
+pub trait WriteOutputBuffer<T: PortIO>: ReadStatus<T> + Sized {
+    fn write_keyboard_output_buffer(&mut self, data: u8) {
+        send_controller_command_and_write_data(
             self,
-            Command::PULSE_OUTPUT_PORT_START | 0b0000_1110,
+            CommandWaitData::WRITE_KEYBOARD_OUTPUT_BUFFER,
+            data,
         );
     }
+
+    #[cfg(feature = "aux-device")]
+    fn write_auxiliary_device_output_buffer(&mut self, data: u8) {
+        send_controller_command_and_write_data(
+            self,
+            CommandWaitData::WRITE_AUXILIARY_DEVICE_OUTPUT_BUFFER,
+            data,
+        );
+    }
+}
+
+/// Status register reads before `ResetCPU::try_reset_cpu` writes
+/// to a full input buffer or moves to the next reset method.
+#[cfg(feature = "machine-reset")]
+#[cfg_attr(docsrs, doc(cfg(feature = "machine-reset")))]
+pub const RESET_CPU_POLLS: u32 = 10_000;
+
+/// Reset the machine with the controller reset line.
+#[cfg(feature = "machine-reset")]
+#[cfg_attr(docsrs, doc(cfg(feature = "machine-reset")))]
+pub trait ResetCPU<T: PortIO>: ReadStatus<T> + Sized {
+    fn reset_cpu(&mut self) {
+        send_controller_command_and_wait_processing(self, Command::PULSE_OUTPUT_PORT_RESET);
+    }
+
+    /// Best effort reset for panic and reboot paths. Waits are bounded
+    /// with `RESET_CPU_POLLS` and stored auxiliary device data is not
+    /// written. The reset line is pulsed first. If the CPU is still
+    /// running after that, the output port is written with the reset
+    /// bit cleared. Returns if neither method worked, so the caller
+    /// can try other reset methods.
+    fn try_reset_cpu(&mut self) {
+        bounded_wait_input_buffer(self);
+        transaction::write_command(self, Command::PULSE_OUTPUT_PORT_RESET);
+        bounded_wait_input_buffer(self);
+        bounded_wait_reset(self);
+
+        transaction::write_command(self, CommandWaitData::WRITE_OUTPUT_PORT);
+        bounded_wait_input_buffer(self);
+        let output_port = OutputPortBits::all() - OutputPortBits::RESET_MICROPROCESSOR;
+        transaction::write_data(self, output_port.bits());
+        bounded_wait_input_buffer(self);
+        bounded_wait_reset(self);
+    }
+}
+
+#[cfg(feature = "machine-reset")]
+fn bounded_wait_input_buffer<T: PortIO, U: ReadStatus<T>>(controller: &mut U) {
+    for _ in 0..RESET_CPU_POLLS {
+        if !controller.status().input_buffer_full() {
+            return;
+        }
+    }
+}
+
+/// Give the reset time to take effect.
+#[cfg(feature = "machine-reset")]
+fn bounded_wait_reset<T: PortIO, U: ReadStatus<T>>(controller: &mut U) {
+    for _ in 0..RESET_CPU_POLLS {
+        controller.port_io_mut().read(T::STATUS_REGISTER);
+    }
 }
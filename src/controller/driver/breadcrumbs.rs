@@ -0,0 +1,191 @@
+//! Early boot breadcrumbs in unused controller RAM.
+//!
+//! Controller RAM keeps its contents over a CPU reset, so a kernel
+//! which ends up in a reset loop can store a few bytes of progress
+//! information there and read them on the next boot. Only the command
+//! byte has a standard meaning, but some controllers use the other
+//! bytes too, so the usable addresses are configured with
+//! `BreadcrumbSlots`. Nothing is written unless the caller enables
+//! the slots.
+//!
+//! ```rust,ignore
+//! let slots = BreadcrumbSlots::for_quirks(controller.quirks(), true);
+//! let mut previous = [0; 4];
+//! let len = slots.load(&mut controller, &mut previous)?;
+//! slots.store(&mut controller, &[BOOT_STAGE_MEMORY])?;
+//! ```
+
+use super::{
+    quirks::Quirks,
+    ram::{ControllerRam, RamReadError},
+    ReadRAM, WriteRAM,
+};
+use crate::controller::io::PortIO;
+use crate::controller::transaction::TransactionError;
+
+/// Last four bytes of the controller RAM. They are unused on most
+/// controllers without the `RAM_IN_USE` quirk, but some firmware may
+/// store data there.
+pub const DEFAULT_BREADCRUMB_ADDRESSES: [u8; 4] = [0x1C, 0x1D, 0x1E, 0x1F];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreadcrumbError {
+    /// Address is the command byte or outside of the controller RAM.
+    InvalidAddress(u8),
+    /// Data is longer than the number of slots.
+    NotEnoughSlots,
+    /// Byte read back from the controller differs from the written
+    /// byte. Controller doesn't have RAM at `address` or uses it.
+    VerifyFailed { address: u8, written: u8, read: u8 },
+    /// Reading `address` failed.
+    Transaction {
+        address: u8,
+        error: TransactionError,
+    },
+}
+
+/// Controller RAM addresses which are safe to overwrite.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BreadcrumbSlots {
+    addresses: &'static [u8],
+}
+
+impl BreadcrumbError {
+    fn from_read_error(address: u8, error: RamReadError) -> Self {
+        match error {
+            RamReadError::AddressOutOfRange => BreadcrumbError::InvalidAddress(address),
+            RamReadError::Transaction(error) => BreadcrumbError::Transaction { address, error },
+        }
+    }
+}
+
+/// No slots.
+impl Default for BreadcrumbSlots {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+
+impl BreadcrumbSlots {
+    /// Slots which don't overwrite anything.
+    pub const NONE: Self = Self { addresses: &[] };
+
+    pub const fn new(addresses: &'static [u8]) -> Result<Self, BreadcrumbError> {
+        let mut i = 0;
+        while i < addresses.len() {
+            let address = addresses[i];
+            if address == ControllerRam::COMMAND_BYTE_ADDRESS
+                || address as usize >= ControllerRam::SIZE
+            {
+                return Err(BreadcrumbError::InvalidAddress(address));
+            }
+            i += 1;
+        }
+
+        Ok(Self { addresses })
+    }
+
+    /// `DEFAULT_BREADCRUMB_ADDRESSES` if `enabled` is true and the
+    /// controller doesn't have the `RAM_IN_USE` quirk, otherwise no
+    /// slots.
+    pub fn for_quirks(quirks: Quirks, enabled: bool) -> Self {
+        if !enabled || quirks.contains(Quirks::RAM_IN_USE) {
+            Self::NONE
+        } else {
+            Self {
+                addresses: &DEFAULT_BREADCRUMB_ADDRESSES,
+            }
+        }
+    }
+
+    pub fn addresses(&self) -> &'static [u8] {
+        self.addresses
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.addresses.len()
+    }
+
+    /// Write `data` to the first slots and read every byte back.
+    /// Writing stops at the first byte which doesn't match.
+    pub fn store<T: PortIO, U: ReadRAM<T> + WriteRAM<T>>(
+        &self,
+        controller: &mut U,
+        data: &[u8],
+    ) -> Result<(), BreadcrumbError> {
+        if data.len() > self.addresses.len() {
+            return Err(BreadcrumbError::NotEnoughSlots);
+        }
+
+        for (&address, &written) in self.addresses.iter().zip(data) {
+            controller
+                .write_ram_byte(address, written)
+                .map_err(|_| BreadcrumbError::InvalidAddress(address))?;
+            let read = controller
+                .ram_byte(address)
+                .map_err(|e| BreadcrumbError::from_read_error(address, e))?;
+            if read != written {
+                return Err(BreadcrumbError::VerifyFailed {
+                    address,
+                    written,
+                    read,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read slots to `data`. Returns the number of bytes read.
+    pub fn load<T: PortIO, U: ReadRAM<T>>(
+        &self,
+        controller: &mut U,
+        data: &mut [u8],
+    ) -> Result<usize, BreadcrumbError> {
+        let mut len = 0;
+        for (&address, byte) in self.addresses.iter().zip(data.iter_mut()) {
+            *byte = controller
+                .ram_byte(address)
+                .map_err(|e| BreadcrumbError::from_read_error(address, e))?;
+            len += 1;
+        }
+
+        Ok(len)
+    }
+
+    /// Write zero to every slot.
+    pub fn clear<T: PortIO, U: ReadRAM<T> + WriteRAM<T>>(
+        &self,
+        controller: &mut U,
+    ) -> Result<(), BreadcrumbError> {
+        for &address in self.addresses {
+            controller
+                .write_ram_byte(address, 0)
+                .map_err(|_| BreadcrumbError::InvalidAddress(address))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slots_are_disabled_by_default() {
+        assert_eq!(BreadcrumbSlots::default(), BreadcrumbSlots::NONE);
+        assert_eq!(
+            BreadcrumbSlots::for_quirks(Quirks::empty(), false),
+            BreadcrumbSlots::NONE
+        );
+        assert_eq!(
+            BreadcrumbSlots::for_quirks(Quirks::RAM_IN_USE, true),
+            BreadcrumbSlots::NONE
+        );
+        assert_eq!(
+            BreadcrumbSlots::for_quirks(Quirks::empty(), true).addresses(),
+            &DEFAULT_BREADCRUMB_ADDRESSES
+        );
+    }
+}
@@ -0,0 +1,102 @@
+//! All initialization options in one place.
+//!
+//! ```rust,ignore
+//! let (controller, result) = InitController::builder(port_io)
+//!     .quirks(Quirks::DOUBLE_RESPONSE_READ)
+//!     .translation_mode(TranslationMode::Software)
+//!     .trace_hook(trace, TraceLevel::Info)
+//!     .start_init();
+//! ```
+//!
+//! Options which are set when the devices are used, like end of
+//! interrupt hooks, are set on the device drivers.
+
+use super::unknown_bits::ParsingMode;
+use super::*;
+
+/// Created with `InitController::builder`. Options which are not
+/// set keep the `InitController::start_init` defaults.
+#[derive(Debug)]
+#[must_use]
+pub struct InitControllerBuilder<T: PortIO> {
+    port_io: T,
+    config: Option<ControllerConfig>,
+    quirks: Quirks,
+    parsing_mode: ParsingMode,
+    translation_mode: Option<TranslationMode>,
+    tracer: Tracer,
+}
+
+impl<T: PortIO> InitControllerBuilder<T> {
+    pub(super) fn new(port_io: T) -> Self {
+        Self {
+            port_io,
+            config: None,
+            quirks: Quirks::empty(),
+            parsing_mode: ParsingMode::Truncate,
+            translation_mode: None,
+            tracer: Tracer::new(),
+        }
+    }
+
+    /// Start with `InitController::start_init_with_config`. The
+    /// original configuration is returned from `start_init`.
+    pub fn controller_config(mut self, config: ControllerConfig) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    pub fn quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = quirks;
+        self
+    }
+
+    pub fn parsing_mode(mut self, mode: ParsingMode) -> Self {
+        self.parsing_mode = mode;
+        self
+    }
+
+    /// Default is to keep the current controller translation setting.
+    pub fn translation_mode(mut self, mode: TranslationMode) -> Self {
+        self.translation_mode = Some(mode);
+        self
+    }
+
+    /// Hook receives `Subsystem::Controller` events, starting from
+    /// the initialization commands.
+    pub fn trace_hook(mut self, hook: TraceHook, level: TraceLevel) -> Self {
+        self.tracer.set_hook(Some(hook));
+        self.tracer.set_level(Subsystem::Controller, level);
+        self
+    }
+
+    /// Returns the controller and the original configuration if
+    /// `controller_config` was set. The controller is returned also if
+    /// reading the original configuration or setting the translation
+    /// mode failed.
+    pub fn start_init(
+        self,
+    ) -> (
+        DevicesDisabled<T>,
+        Result<Option<ControllerConfig>, TransactionError>,
+    ) {
+        let mut controller =
+            DevicesDisabled::new(self.port_io, self.config, self.quirks, self.tracer);
+        controller.set_parsing_mode(self.parsing_mode);
+
+        let (mut controller, original_config) = if self.config.is_some() {
+            let (controller, original) = InitController::init_with_config(controller);
+            (controller, original.map(Some))
+        } else {
+            (InitController::init(controller), Ok(None))
+        };
+
+        let result = match self.translation_mode {
+            Some(mode) => original_config
+                .and_then(|config| controller.set_translation_mode(mode).map(|()| config)),
+            None => original_config,
+        };
+
+        (controller, result)
+    }
+}
@@ -0,0 +1,109 @@
+//! Controller command byte configuration.
+
+use super::quirks::Quirks;
+use crate::controller::raw::ControllerCommandByte;
+
+/// Snapshot of the controller command byte.
+///
+/// Bits which `ControllerCommandByte` does not define are preserved,
+/// so a configuration read from the hardware can be restored as is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ControllerConfig(u8);
+
+/// Configuration which corrupts input with the active quirks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigConflict {
+    /// Translation and auxiliary device interrupt are enabled and
+    /// the controller has the `TRANSLATES_AUXILIARY_DATA` quirk.
+    TranslatedAuxiliaryData,
+    /// Translation and keyboard inhibit override are enabled and
+    /// the controller has the `INHIBIT_OVERRIDE_DISABLES_TRANSLATION`
+    /// quirk.
+    UntranslatedKeyboardData,
+}
+
+impl Default for ControllerConfig {
+    /// Translation and system flag enabled. Interrupts disabled.
+    fn default() -> Self {
+        Self::from_raw(
+            (ControllerCommandByte::KEYBOARD_TRANSLATE_MODE | ControllerCommandByte::SYSTEM_FLAG)
+                .bits(),
+        )
+    }
+}
+
+impl ControllerConfig {
+    /// Command byte bit 3. `ControllerCommandByte` doesn't define it,
+    /// because most controllers ignore it.
+    pub const INHIBIT_OVERRIDE: u8 = 0b0000_1000;
+
+    pub const fn from_raw(command_byte: u8) -> Self {
+        Self(command_byte)
+    }
+
+    pub fn raw(&self) -> u8 {
+        self.0
+    }
+
+    pub fn command_byte(&self) -> ControllerCommandByte {
+        ControllerCommandByte::from_bits_truncate(self.0)
+    }
+
+    pub fn translation(self, enabled: bool) -> Self {
+        self.with(ControllerCommandByte::KEYBOARD_TRANSLATE_MODE, enabled)
+    }
+
+    pub fn keyboard_interrupt(self, enabled: bool) -> Self {
+        self.with(ControllerCommandByte::ENABLE_KEYBOARD_INTERRUPT, enabled)
+    }
+
+    pub fn auxiliary_device_interrupt(self, enabled: bool) -> Self {
+        self.with(ControllerCommandByte::ENABLE_AUXILIARY_INTERRUPT, enabled)
+    }
+
+    pub fn system_flag(self, enabled: bool) -> Self {
+        self.with(ControllerCommandByte::SYSTEM_FLAG, enabled)
+    }
+
+    pub fn inhibit_override(self, enabled: bool) -> Self {
+        if enabled {
+            Self(self.0 | Self::INHIBIT_OVERRIDE)
+        } else {
+            Self(self.0 & !Self::INHIBIT_OVERRIDE)
+        }
+    }
+
+    /// Check the translation bit against the bits which are known to
+    /// break it with `quirks`.
+    pub fn check(&self, quirks: Quirks) -> Result<(), ConfigConflict> {
+        if !self.enabled(ControllerCommandByte::KEYBOARD_TRANSLATE_MODE) {
+            return Ok(());
+        }
+
+        if quirks.contains(Quirks::TRANSLATES_AUXILIARY_DATA)
+            && self.enabled(ControllerCommandByte::ENABLE_AUXILIARY_INTERRUPT)
+        {
+            return Err(ConfigConflict::TranslatedAuxiliaryData);
+        }
+
+        if quirks.contains(Quirks::INHIBIT_OVERRIDE_DISABLES_TRANSLATION)
+            && self.0 & Self::INHIBIT_OVERRIDE != 0
+        {
+            return Err(ConfigConflict::UntranslatedKeyboardData);
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn with(self, bits: ControllerCommandByte, enabled: bool) -> Self {
+        if enabled {
+            Self(self.0 | bits.bits())
+        } else {
+            Self(self.0 & !bits.bits())
+        }
+    }
+
+    pub(crate) fn enabled(&self, bits: ControllerCommandByte) -> bool {
+        self.command_byte().contains(bits)
+    }
+}
@@ -0,0 +1,646 @@
+//! Run supported commands against real hardware and
+//! collect a report of the results.
+//!
+//! Timing is measured as status register polls, because there is
+//! no time source available.
+//!
+//! Tested commands:
+//!
+//! * Controller: the read-only commands, self test, output port write
+//!   with the value which was read and the interface enable and
+//!   disable commands. Password, RAM write, output port pulse and
+//!   output buffer write commands are not tested.
+//! * Keyboard: `ECHO`, `READ_ID`, scancode set query, `RESET`,
+//!   `SET_STATUS_INDICATORS`, `SET_TYPEMATIC_RATE`, `DEFAULT_DISABLE`,
+//!   `SET_DEFAULT` and `ENABLE`. Scancode set selection and the
+//!   set 3 key type commands are not tested.
+//! * Auxiliary device: every `MouseCommand` except `RESET`,
+//!   `RESEND` and the remote and wrap mode commands.
+//!
+//! `ConformanceReport::to_bytes` format: `REPORT_MAGIC`,
+//! `REPORT_VERSION`, result count and the results. A result is
+//! target (0 controller, 1 keyboard, 2 auxiliary device), command,
+//! support (0 supported, 1 unsupported, 2 quirky), response length,
+//! the response bytes and polls as little endian `u32`.
+
+use super::*;
+
+use crate::device::keyboard::raw::{
+    CommandReturnData as KeyboardCommand, FromKeyboard, ScancodeSetNumber, StatusIndicators,
+};
+#[cfg(feature = "aux-device")]
+use crate::device::mouse::raw::MouseCommand;
+
+/// Maximum number of results in `ConformanceReport`.
+pub const MAX_REPORT_RESULTS: usize = 32;
+
+/// Maximum number of response bytes stored for a command.
+pub const MAX_RESPONSE_LENGTH: usize = 4;
+
+pub const REPORT_MAGIC: [u8; 4] = *b"PS2R";
+pub const REPORT_VERSION: u8 = 1;
+
+/// Maximum length of `ConformanceReport::to_bytes` output.
+pub const MAX_REPORT_LENGTH: usize =
+    REPORT_MAGIC.len() + 2 + MAX_REPORT_RESULTS * (8 + MAX_RESPONSE_LENGTH);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Controller,
+    Keyboard,
+    AuxiliaryDevice,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Support {
+    /// Response was the expected one.
+    Supported,
+    /// There was no response or the device requested resend.
+    Unsupported,
+    /// Response was incomplete or unexpected.
+    Quirky,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CommandResult {
+    pub target: Target,
+    pub command: u8,
+    pub support: Support,
+    pub response: [u8; MAX_RESPONSE_LENGTH],
+    pub response_len: usize,
+    /// Status register polls until the last response byte.
+    pub polls: u32,
+}
+
+impl CommandResult {
+    pub fn response(&self) -> &[u8] {
+        &self.response[..self.response_len]
+    }
+}
+
+#[derive(Debug)]
+pub struct ConformanceReport {
+    results: [Option<CommandResult>; MAX_REPORT_RESULTS],
+    len: usize,
+}
+
+impl Default for ConformanceReport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConformanceReport {
+    pub fn new() -> Self {
+        Self {
+            results: [None; MAX_REPORT_RESULTS],
+            len: 0,
+        }
+    }
+
+    pub fn results(&self) -> impl Iterator<Item = &CommandResult> {
+        self.results[..self.len].iter().flatten()
+    }
+
+    pub fn count(&self, support: Support) -> usize {
+        self.results().filter(|r| r.support == support).count()
+    }
+
+    /// Largest poll count of the supported commands.
+    pub fn max_polls(&self) -> Option<u32> {
+        self.supported_polls().max()
+    }
+
+    /// Smallest poll count of the supported commands.
+    pub fn min_polls(&self) -> Option<u32> {
+        self.supported_polls().min()
+    }
+
+    fn supported_polls(&self) -> impl Iterator<Item = u32> + '_ {
+        self.results()
+            .filter(|r| r.support == Support::Supported)
+            .map(|r| r.polls)
+    }
+
+    /// Write the report in the format described in the module
+    /// documentation. Returns the number of written bytes or `None`
+    /// if `out` is too short. `MAX_REPORT_LENGTH` is always enough.
+    pub fn to_bytes(&self, out: &mut [u8]) -> Option<usize> {
+        let mut len = 0;
+        let mut write = |bytes: &[u8]| {
+            let end = len + bytes.len();
+            out.get_mut(len..end)?.copy_from_slice(bytes);
+            len = end;
+            Some(())
+        };
+
+        write(&REPORT_MAGIC)?;
+        write(&[REPORT_VERSION, self.len as u8])?;
+        for result in self.results() {
+            let target = match result.target {
+                Target::Controller => 0,
+                Target::Keyboard => 1,
+                Target::AuxiliaryDevice => 2,
+            };
+            let support = match result.support {
+                Support::Supported => 0,
+                Support::Unsupported => 1,
+                Support::Quirky => 2,
+            };
+            write(&[target, result.command, support, result.response_len as u8])?;
+            write(result.response())?;
+            write(&result.polls.to_le_bytes())?;
+        }
+
+        Some(len)
+    }
+
+    fn push(&mut self, result: CommandResult) {
+        if let Some(slot) = self.results.get_mut(self.len) {
+            *slot = Some(result);
+            self.len += 1;
+        }
+    }
+}
+
+/// Expected response byte. `None` matches any byte.
+type Expected = Option<u8>;
+
+/// Bytes to send and the number of response bytes
+/// to read after every byte.
+type DeviceTest = (&'static [(u8, usize)], &'static [Expected]);
+
+const ACK: Expected = Some(FromKeyboard::ACK);
+const ANY: Expected = None;
+
+const LEDS_OFF: u8 = StatusIndicators::empty().bits();
+/// 500 ms delay and 10.9 characters per second.
+const DEFAULT_TYPEMATIC: u8 = 0b0010_1011;
+#[cfg(feature = "aux-device")]
+const DEFAULT_SAMPLE_RATE: u8 = 100;
+/// 4 counts per millimeter.
+#[cfg(feature = "aux-device")]
+const DEFAULT_RESOLUTION: u8 = 2;
+
+struct Response {
+    bytes: [u8; MAX_RESPONSE_LENGTH],
+    len: usize,
+    polls: u32,
+}
+
+impl Response {
+    fn new() -> Self {
+        Self {
+            bytes: [0; MAX_RESPONSE_LENGTH],
+            len: 0,
+            polls: 0,
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        if let Some(slot) = self.bytes.get_mut(self.len) {
+            *slot = byte;
+            self.len += 1;
+        }
+    }
+
+    fn into_result(self, target: Target, command: u8, expected: &[Expected]) -> CommandResult {
+        let response = &self.bytes[..self.len];
+
+        let support = if response.is_empty() || response[0] == FromKeyboard::RESEND {
+            Support::Unsupported
+        } else if response.len() == expected.len()
+            && response
+                .iter()
+                .zip(expected)
+                .all(|(byte, expected)| expected.is_none() || *expected == Some(*byte))
+        {
+            Support::Supported
+        } else {
+            Support::Quirky
+        };
+
+        CommandResult {
+            target,
+            command,
+            support,
+            response: self.bytes,
+            response_len: self.len,
+            polls: self.polls,
+        }
+    }
+}
+
+/// Test controller commands which do not change controller state.
+///
+/// The controller command byte is restored after the self test.
+/// Output port is written only if the reset bit of the value which
+/// was read is set. Interface enable commands are followed by
+/// the disable command, so device bytes may be received between
+/// them and they are discarded.
+pub fn test_controller<T: PortIO>(
+    controller: &mut DevicesDisabled<T>,
+    max_polls: u32,
+    report: &mut ConformanceReport,
+) {
+    let tests: [(u8, Expected); 8] = [
+        (CommandReturnData::READ_CONTROLLER_COMMAND_BYTE, ANY),
+        (CommandReturnData::READ_RAM_START, ANY),
+        (CommandReturnData::TEST_PASSWORD_INSTALLED, ANY),
+        (
+            CommandReturnData::KEYBOARD_INTERFACE_TEST,
+            Some(InterfaceTestResult::PASSED),
+        ),
+        (
+            CommandReturnData::AUXILIARY_DEVICE_INTERFACE_TEST,
+            Some(InterfaceTestResult::PASSED),
+        ),
+        (CommandReturnData::READ_INPUT_PORT, ANY),
+        (CommandReturnData::READ_OUTPUT_PORT, ANY),
+        (CommandReturnData::READ_TEST_INPUTS, ANY),
+    ];
+
+    for &(command, expected) in tests.iter() {
+        let response = controller_command(controller, command, max_polls);
+        let mut result = response.into_result(Target::Controller, command, &[expected]);
+
+        if command == CommandReturnData::TEST_PASSWORD_INSTALLED
+            && result.support == Support::Supported
+            && result.response[0] != PasswordTestResult::INSTALLED
+            && result.response[0] != PasswordTestResult::NOT_INSTALLED
+        {
+            result.support = Support::Quirky;
+        }
+
+        report.push(result);
+    }
+
+    let command_byte = controller_command(
+        controller,
+        CommandReturnData::READ_CONTROLLER_COMMAND_BYTE,
+        max_polls,
+    );
+    let response = controller_command(controller, CommandReturnData::SELF_TEST, max_polls);
+    if command_byte.len == 1 {
+        send_controller_command_and_write_data(
+            controller,
+            CommandWaitData::WRITE_CONTROLLER_COMMAND_BYTE,
+            command_byte.bytes[0],
+        );
+    }
+    report.push(response.into_result(
+        Target::Controller,
+        CommandReturnData::SELF_TEST,
+        &[Some(SelfTestResult::PASSED)],
+    ));
+
+    report.push(write_output_port(controller, max_polls));
+
+    let interfaces = [
+        (
+            Command::ENABLE_KEYBOARD_INTERFACE,
+            Command::DISABLE_KEYBOARD_INTERFACE,
+            ControllerCommandByte::DISABLE_KEYBOARD,
+        ),
+        (
+            Command::ENABLE_AUXILIARY_DEVICE_INTERFACE,
+            Command::DISABLE_AUXILIARY_DEVICE_INTERFACE,
+            ControllerCommandByte::DISABLE_AUXILIARY_DEVICE,
+        ),
+    ];
+
+    for &(enable, disable, disabled_bit) in interfaces.iter() {
+        report.push(interface_command(
+            controller,
+            enable,
+            disabled_bit,
+            false,
+            max_polls,
+        ));
+        report.push(interface_command(
+            controller,
+            disable,
+            disabled_bit,
+            true,
+            max_polls,
+        ));
+    }
+}
+
+/// Test keyboard commands. Keyboard is reset, LEDs are turned off,
+/// typematic settings are set to defaults and scanning is enabled.
+///
+/// `max_polls` must cover the keyboard self test after `RESET`,
+/// which can take hundreds of milliseconds.
+pub fn test_keyboard<T: PortIO>(
+    controller: &mut EnabledDevices<T, Disabled>,
+    max_polls: u32,
+    report: &mut ConformanceReport,
+) {
+    let tests: [DeviceTest; 9] = [
+        (&[(KeyboardCommand::ECHO, 1)], &[Some(FromKeyboard::ECHO)]),
+        (
+            &[(KeyboardCommand::READ_ID, 3)],
+            &[ACK, Some(FromKeyboard::ID_FIRST_BYTE), ANY],
+        ),
+        (
+            &[
+                (KeyboardCommand::SELECT_ALTERNATE_SCANCODES, 1),
+                (ScancodeSetNumber::GET_CURRENT, 2),
+            ],
+            &[ACK, ACK, ANY],
+        ),
+        (
+            &[(KeyboardCommand::RESET, 2)],
+            &[ACK, Some(FromKeyboard::BAT_COMPLETION_CODE)],
+        ),
+        (
+            &[(KeyboardCommand::SET_STATUS_INDICATORS, 1), (LEDS_OFF, 1)],
+            &[ACK, ACK],
+        ),
+        (
+            &[
+                (KeyboardCommand::SET_TYPEMATIC_RATE, 1),
+                (DEFAULT_TYPEMATIC, 1),
+            ],
+            &[ACK, ACK],
+        ),
+        (&[(KeyboardCommand::DEFAULT_DISABLE, 1)], &[ACK]),
+        (&[(KeyboardCommand::SET_DEFAULT, 1)], &[ACK]),
+        (&[(KeyboardCommand::ENABLE, 1)], &[ACK]),
+    ];
+
+    for &(steps, expected) in tests.iter() {
+        let response = device_command(controller, Target::Keyboard, steps, max_polls);
+        report.push(response.into_result(Target::Keyboard, steps[0].0, expected));
+    }
+}
+
+/// Test auxiliary device commands. Device settings are reset to
+/// defaults and data reporting is disabled.
+///
+/// Data reporting is enabled between `ENABLE_DATA_REPORTING` and
+/// `DISABLE_DATA_REPORTING`, so `DISABLE_DATA_REPORTING` is reported
+/// as quirky if the device sends a movement packet between them.
+#[cfg(feature = "aux-device")]
+pub fn test_auxiliary_device<T: PortIO>(
+    controller: &mut EnabledDevices<T, Disabled>,
+    max_polls: u32,
+    report: &mut ConformanceReport,
+) {
+    let tests: [DeviceTest; 9] = [
+        (&[(MouseCommand::GET_DEVICE_ID, 2)], &[ACK, ANY]),
+        (&[(MouseCommand::STATUS_REQUEST, 4)], &[ACK, ANY, ANY, ANY]),
+        (&[(MouseCommand::SET_SCALING_2_1, 1)], &[ACK]),
+        (&[(MouseCommand::SET_SCALING_1_1, 1)], &[ACK]),
+        (
+            &[(MouseCommand::SET_RESOLUTION, 1), (DEFAULT_RESOLUTION, 1)],
+            &[ACK, ACK],
+        ),
+        (
+            &[(MouseCommand::SET_SAMPLE_RATE, 1), (DEFAULT_SAMPLE_RATE, 1)],
+            &[ACK, ACK],
+        ),
+        (&[(MouseCommand::ENABLE_DATA_REPORTING, 1)], &[ACK]),
+        (&[(MouseCommand::DISABLE_DATA_REPORTING, 1)], &[ACK]),
+        (&[(MouseCommand::SET_DEFAULTS, 1)], &[ACK]),
+    ];
+
+    for &(steps, expected) in tests.iter() {
+        let response = device_command(controller, Target::AuxiliaryDevice, steps, max_polls);
+        report.push(response.into_result(Target::AuxiliaryDevice, steps[0].0, expected));
+    }
+}
+
+/// Write the output port value which was read. The result
+/// response is the value which was read after the write.
+fn write_output_port<T: PortIO>(
+    controller: &mut DevicesDisabled<T>,
+    max_polls: u32,
+) -> CommandResult {
+    let command = CommandWaitData::WRITE_OUTPUT_PORT;
+    let before = controller_command(controller, CommandReturnData::READ_OUTPUT_PORT, max_polls);
+    let value = match before.bytes[..before.len] {
+        [value] if value & OutputPortBits::RESET_MICROPROCESSOR.bits() != 0 => value,
+        _ => return Response::new().into_result(Target::Controller, command, &[]),
+    };
+
+    send_controller_command_and_write_data(controller, command, value);
+
+    let mut after = controller_command(controller, CommandReturnData::READ_OUTPUT_PORT, max_polls);
+    after.polls += before.polls;
+    after.into_result(Target::Controller, command, &[Some(value)])
+}
+
+/// Send interface enable or disable command and check
+/// the interface disable bit of the controller command byte.
+/// The result response is the command byte.
+fn interface_command<T: PortIO>(
+    controller: &mut DevicesDisabled<T>,
+    command: u8,
+    disabled_bit: ControllerCommandByte,
+    disabled: bool,
+    max_polls: u32,
+) -> CommandResult {
+    let mut response = Response::new();
+    if !write_command(controller, command, max_polls, &mut response) {
+        return response.into_result(Target::Controller, command, &[]);
+    }
+
+    let command_byte = controller_command(
+        controller,
+        CommandReturnData::READ_CONTROLLER_COMMAND_BYTE,
+        max_polls,
+    );
+    response.polls += command_byte.polls;
+
+    let mut result = command_byte.into_result(Target::Controller, command, &[ANY]);
+    result.polls = response.polls;
+    if result.support == Support::Supported
+        && (result.response[0] & disabled_bit.bits() != 0) != disabled
+    {
+        result.support = Support::Quirky;
+    }
+    result
+}
+
+fn controller_command<T: PortIO>(
+    controller: &mut DevicesDisabled<T>,
+    command: u8,
+    max_polls: u32,
+) -> Response {
+    let mut response = Response::new();
+    if !write_command(controller, command, max_polls, &mut response) {
+        return response;
+    }
+
+    for _ in 0..max_polls {
+        response.polls += 1;
+        if controller.status().data_availability().is_some() {
+            response.push(controller.port_io_mut().read(T::DATA_PORT));
+            controller.set_controller_response_pending(false);
+            break;
+        }
+    }
+
+    response
+}
+
+/// Discard an available byte and write `command` when the controller
+/// input buffer is empty. Returns false if the input buffer
+/// stayed full for `max_polls` polls.
+fn write_command<T: PortIO>(
+    controller: &mut DevicesDisabled<T>,
+    command: u8,
+    max_polls: u32,
+    response: &mut Response,
+) -> bool {
+    if controller.status().data_availability().is_some() {
+        controller.port_io_mut().read(T::DATA_PORT);
+    }
+
+    while controller.status().input_buffer_full() {
+        response.polls += 1;
+        if response.polls >= max_polls {
+            return false;
+        }
+    }
+
+    transaction::write_command(controller, command);
+    true
+}
+
+/// Send bytes of `steps` and read the given amount of response bytes
+/// after every byte.
+fn device_command<T: PortIO>(
+    controller: &mut EnabledDevices<T, Disabled>,
+    target: Target,
+    steps: &[(u8, usize)],
+    max_polls: u32,
+) -> Response {
+    let mut response = Response::new();
+
+    while controller.read_data().is_some() {}
+
+    for &(byte, response_count) in steps {
+        let sent = match target {
+            Target::Keyboard => controller.send_to_keyboard(byte),
+            #[cfg(feature = "aux-device")]
+            Target::AuxiliaryDevice => controller.send_to_auxiliary_device(byte),
+            _ => Err(DeviceNotEnabled),
+        };
+
+        if sent.is_err() {
+            return response;
+        }
+
+        let mut received = 0;
+        let mut polls = 0;
+        while received < response_count && polls < max_polls {
+            polls += 1;
+
+            let byte = match (target, controller.read_data()) {
+                (Target::Keyboard, Some(DeviceData::Keyboard(byte))) => byte,
+                #[cfg(feature = "aux-device")]
+                (Target::AuxiliaryDevice, Some(DeviceData::AuxiliaryDevice(byte))) => byte,
+                _ => continue,
+            };
+
+            response.push(byte);
+            received += 1;
+
+            if byte == FromKeyboard::RESEND {
+                response.polls += polls;
+                return response;
+            }
+        }
+
+        response.polls += polls;
+
+        if received < response_count {
+            break;
+        }
+    }
+
+    response
+}
+
+#[cfg(all(test, feature = "testing", feature = "aux-device"))]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use crate::controller::io::PortIOAvailable;
+    use crate::testing::Simulator;
+
+    const MAX_POLLS: u32 = 64;
+
+    fn report() -> (ConformanceReport, EnabledDevices<Simulator, Disabled>) {
+        let mut report = ConformanceReport::new();
+        let mut controller = InitController::start_init(Simulator::new());
+        let output_port = controller.port_io_mut().output_port();
+        test_controller(&mut controller, MAX_POLLS, &mut report);
+
+        let simulator = controller.port_io_mut();
+        let disabled = ControllerCommandByte::DISABLE_KEYBOARD
+            | ControllerCommandByte::DISABLE_AUXILIARY_DEVICE;
+        assert!(simulator.command_byte().contains(disabled));
+        assert_eq!(simulator.output_port(), output_port);
+        assert!(!simulator.cpu_reset_requested());
+
+        let mut controller = controller
+            .enable_devices(EnableDevice::KeyboardAndAuxiliaryDevice)
+            .unwrap();
+        test_keyboard(&mut controller, MAX_POLLS, &mut report);
+        test_auxiliary_device(&mut controller, MAX_POLLS, &mut report);
+        (report, controller)
+    }
+
+    #[test]
+    fn simulator_supports_every_tested_command() {
+        let (report, mut controller) = report();
+
+        assert_eq!(report.count(Support::Supported), MAX_REPORT_RESULTS);
+        for target in [
+            Target::Controller,
+            Target::Keyboard,
+            Target::AuxiliaryDevice,
+        ]
+        .iter()
+        {
+            assert!(report.results().any(|r| r.target == *target));
+        }
+
+        let simulator = controller.port_io_mut();
+        assert_eq!(simulator.keyboard().typematic(), DEFAULT_TYPEMATIC);
+        assert!(simulator.keyboard().scanning());
+        assert!(!simulator.mouse().reporting());
+    }
+
+    #[test]
+    fn report_bytes_contain_every_result() {
+        let (report, _) = report();
+
+        let mut bytes = [0; MAX_REPORT_LENGTH];
+        let len = report.to_bytes(&mut bytes).unwrap();
+        assert_eq!(bytes[..4], REPORT_MAGIC);
+        assert_eq!(bytes[4], REPORT_VERSION);
+        assert_eq!(bytes[5] as usize, MAX_REPORT_RESULTS);
+
+        let mut offset = 6;
+        for result in report.results() {
+            let record = &bytes[offset..];
+            assert_eq!(record[1], result.command);
+            assert_eq!(record[2], 0);
+            let response_len = record[3] as usize;
+            assert_eq!(&record[4..4 + response_len], result.response());
+            let mut polls = [0; 4];
+            polls.copy_from_slice(&record[4 + response_len..8 + response_len]);
+            assert_eq!(u32::from_le_bytes(polls), result.polls);
+            offset += 8 + response_len;
+        }
+        assert_eq!(offset, len);
+
+        assert_eq!(report.to_bytes(&mut bytes[..len - 1]), None);
+    }
+}
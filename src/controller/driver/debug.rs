@@ -1,16 +1,14 @@
 use core::marker::PhantomData;
 
 use crate::controller::{
-    driver::status::ReadStatus,
-    driver::*,
-    io::{PortIO, PortIOAvailable},
+    driver::output_port::OutputPort, driver::status::ReadStatus, driver::*, io::PortIO,
 };
 #[derive(Debug)]
 /// Bypass state machine encoded to the types. This should be used
 /// only for debugging purposes.
-pub struct DebugMode<'a, T: PortIO, U: PortIOAvailable<T>>(PhantomData<T>, &'a mut U);
+pub struct DebugMode<'a, T: PortIO, U: ReadStatus<T>>(PhantomData<T>, &'a mut U);
 
-impl<'a, T: PortIO, U: PortIOAvailable<T>> DebugMode<'a, T, U> {
+impl<'a, T: PortIO, U: ReadStatus<T>> DebugMode<'a, T, U> {
     pub fn new(controller: &'a mut U) -> Self {
         DebugMode(PhantomData, controller)
     }
@@ -27,18 +25,46 @@ impl<'a, T: PortIO, U: PortIOAvailable<T>> DebugMode<'a, T, U> {
         write_controller_command_byte(self, data);
     }
 
-    pub fn send_controller_command_and_wait_response(&mut self, command: u8) -> u8 {
+    pub fn send_controller_command_and_wait_response(
+        &mut self,
+        command: u8,
+    ) -> Result<u8, TransactionError> {
         send_controller_command_and_wait_response(self, command)
     }
 }
 
-impl_port_io_available!(<T: PortIO, U: PortIOAvailable<T>> DebugMode<'_, T, U>);
+impl_port_io_available!(<T: PortIO, U: ReadStatus<T>> DebugMode<'_, T, U>);
 
-impl<T: PortIO, U: PortIOAvailable<T>> ReadStatus<T> for DebugMode<'_, T, U> {}
-impl<T: PortIO, U: PortIOAvailable<T>> InterruptsDisabled for DebugMode<'_, T, U> {}
-impl<T: PortIO, U: PortIOAvailable<T>> KeyboardDisabled for DebugMode<'_, T, U> {}
-impl<T: PortIO, U: PortIOAvailable<T>> AuxiliaryDeviceDisabled for DebugMode<'_, T, U> {}
-impl<T: PortIO, U: PortIOAvailable<T>> ReadRAM<T> for DebugMode<'_, T, U> {}
-impl<T: PortIO, U: PortIOAvailable<T>> WriteRAM<T> for DebugMode<'_, T, U> {}
-impl<T: PortIO, U: PortIOAvailable<T>> Testing<T> for DebugMode<'_, T, U> {}
-impl<T: PortIO, U: PortIOAvailable<T>> ResetCPU<T> for DebugMode<'_, T, U> {}
+impl<T: PortIO, U: ReadStatus<T>> ReadStatus<T> for DebugMode<'_, T, U> {
+    fn controller_response_pending(&self) -> bool {
+        self.1.controller_response_pending()
+    }
+
+    fn set_controller_response_pending(&mut self, pending: bool) {
+        self.1.set_controller_response_pending(pending)
+    }
+
+    fn tracer(&self) -> Option<&Tracer> {
+        self.1.tracer()
+    }
+
+    fn quirks(&self) -> Quirks {
+        self.1.quirks()
+    }
+
+    fn unknown_bits_mut(&mut self) -> Option<&mut UnknownBits> {
+        self.1.unknown_bits_mut()
+    }
+}
+impl<T: PortIO, U: ReadStatus<T>> InterruptsDisabled for DebugMode<'_, T, U> {}
+impl<T: PortIO, U: ReadStatus<T>> KeyboardDisabled for DebugMode<'_, T, U> {}
+impl<T: PortIO, U: ReadStatus<T>> AuxiliaryDeviceDisabled for DebugMode<'_, T, U> {}
+impl<T: PortIO, U: ReadStatus<T>> ReadRAM<T> for DebugMode<'_, T, U> {}
+impl<T: PortIO, U: ReadStatus<T>> WriteRAM<T> for DebugMode<'_, T, U> {}
+impl<T: PortIO, U: ReadStatus<T>> Testing<T> for DebugMode<'_, T, U> {}
+impl<T: PortIO, U: ReadStatus<T>> Password<T> for DebugMode<'_, T, U> {}
+impl<T: PortIO, U: ReadStatus<T>> ReadInputs<T> for DebugMode<'_, T, U> {}
+impl<T: PortIO, U: ReadStatus<T>> WriteOutputBuffer<T> for DebugMode<'_, T, U> {}
+impl<T: PortIO, U: ReadStatus<T>> OutputPort<T> for DebugMode<'_, T, U> {}
+#[cfg(feature = "machine-reset")]
+impl<T: PortIO, U: ReadStatus<T>> ResetCPU<T> for DebugMode<'_, T, U> {}
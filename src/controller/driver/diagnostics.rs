@@ -0,0 +1,262 @@
+//! Machine readable controller diagnostics.
+//!
+//! Exported format is a header followed by TLV entries:
+//!
+//! * Header: `DIAGNOSTICS_MAGIC` and `DIAGNOSTICS_VERSION`.
+//! * Entry: tag byte from `DiagnosticTag`, value length byte and
+//!   the value. Counters are little endian `u32` values.
+//!
+//! Entries which do not fit to the output buffer and entries of
+//! failed controller commands are not written.
+//! Parsers should skip entries with unknown tags.
+
+use super::*;
+
+use super::transitions::TRANSITION_LOG_LENGTH;
+use super::unknown_bits::{UnknownBits, UnknownBitsSource};
+
+use crate::device::keyboard::driver::{DecoderLayout, Keyboard};
+
+pub const DIAGNOSTICS_MAGIC: [u8; 4] = *b"PS2D";
+pub const DIAGNOSTICS_VERSION: u8 = 1;
+
+/// Length of the header in bytes.
+pub const DIAGNOSTICS_HEADER_LENGTH: usize = DIAGNOSTICS_MAGIC.len() + 1;
+
+#[derive(Debug)]
+pub struct DiagnosticTag;
+
+impl DiagnosticTag {
+    pub const STATUS: u8 = 0x01;
+    pub const COMMAND_BYTE: u8 = 0x02;
+    pub const INPUT_PORT: u8 = 0x03;
+    pub const OUTPUT_PORT: u8 = 0x04;
+    pub const TEST_INPUTS: u8 = 0x05;
+    pub const SELF_TEST: u8 = 0x10;
+    pub const KEYBOARD_INTERFACE_TEST: u8 = 0x11;
+    pub const AUXILIARY_DEVICE_INTERFACE_TEST: u8 = 0x12;
+    pub const KEYBOARD_TRANSMISSION_ERRORS: u8 = 0x20;
+    pub const KEYBOARD_UNEXPLAINED_BYTES: u8 = 0x21;
+    pub const KEYBOARD_DISCARDED_GRABBED_EVENTS: u8 = 0x22;
+    /// `UnknownBitsRecord` count as little endian `u32` followed by
+    /// the latest raw value.
+    pub const UNKNOWN_BITS_COMMAND_BYTE: u8 = 0x23;
+    pub const UNKNOWN_BITS_INPUT_PORT: u8 = 0x24;
+    pub const UNKNOWN_BITS_TEST_INPUTS: u8 = 0x25;
+    /// `Transition` values, oldest first.
+    pub const TRANSITIONS: u8 = 0x30;
+}
+
+/// Counters from the device drivers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiagnosticCounters {
+    pub keyboard_transmission_errors: u32,
+    pub keyboard_unexplained_bytes: u32,
+    pub keyboard_discarded_grabbed_events: u32,
+}
+
+impl DiagnosticCounters {
+    pub fn from_keyboard<const N: usize, const G: usize, K: DecoderLayout>(
+        keyboard: &Keyboard<N, G, K>,
+    ) -> Self {
+        Self {
+            keyboard_transmission_errors: keyboard.transmission_errors(),
+            keyboard_unexplained_bytes: keyboard.unexplained_bytes(),
+            keyboard_discarded_grabbed_events: keyboard.discarded_grabbed_events(),
+        }
+    }
+}
+
+/// Raw register values and test results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Diagnostics {
+    pub status: u8,
+    /// `None` if the controller command failed. This applies to all
+    /// controller command results.
+    pub command_byte: Option<u8>,
+    pub input_port: Option<u8>,
+    pub output_port: Option<u8>,
+    pub test_inputs: Option<u8>,
+    pub self_test: Option<u8>,
+    pub keyboard_interface_test: Option<u8>,
+    /// `None` also if `aux-device` feature is disabled.
+    pub auxiliary_device_interface_test: Option<u8>,
+    pub counters: DiagnosticCounters,
+    /// `Transition` values, oldest first.
+    pub transitions: [u8; TRANSITION_LOG_LENGTH],
+    pub transitions_len: usize,
+    /// Records are exported only if the count is not zero.
+    pub unknown_bits: UnknownBits,
+}
+
+impl Diagnostics {
+    /// Read registers and run the controller tests. The controller
+    /// command byte is restored after the self test if it was read.
+    pub fn collect<T: PortIO>(controller: &mut DevicesDisabled<T>) -> Self {
+        let status = controller.port_io_mut().read(T::STATUS_REGISTER);
+
+        let mut transitions = [0; TRANSITION_LOG_LENGTH];
+        let mut transitions_len = 0;
+        for (slot, record) in transitions.iter_mut().zip(controller.transitions().iter()) {
+            *slot = record.transition as u8;
+            transitions_len += 1;
+        }
+
+        let unknown_bits = *controller.unknown_bits();
+
+        let mut read =
+            |command| send_controller_command_and_wait_response(controller, command).ok();
+
+        let command_byte = read(CommandReturnData::READ_CONTROLLER_COMMAND_BYTE);
+        let input_port = read(CommandReturnData::READ_INPUT_PORT);
+        let output_port = read(CommandReturnData::READ_OUTPUT_PORT);
+        let test_inputs = read(CommandReturnData::READ_TEST_INPUTS);
+        let keyboard_interface_test = read(CommandReturnData::KEYBOARD_INTERFACE_TEST);
+
+        let auxiliary_device_interface_test = if cfg!(feature = "aux-device") {
+            read(CommandReturnData::AUXILIARY_DEVICE_INTERFACE_TEST)
+        } else {
+            None
+        };
+
+        // Self test may reset the controller.
+        let self_test = read(CommandReturnData::SELF_TEST);
+        if let Some(command_byte) = command_byte {
+            send_controller_command_and_write_data(
+                controller,
+                CommandWaitData::WRITE_CONTROLLER_COMMAND_BYTE,
+                command_byte,
+            );
+        }
+
+        Self {
+            status,
+            command_byte,
+            input_port,
+            output_port,
+            test_inputs,
+            self_test,
+            keyboard_interface_test,
+            auxiliary_device_interface_test,
+            counters: DiagnosticCounters::default(),
+            transitions,
+            transitions_len,
+            unknown_bits,
+        }
+    }
+
+    /// Write the diagnostics to `out`. Returns the number of
+    /// bytes written.
+    pub fn export(&self, out: &mut [u8]) -> usize {
+        let mut writer = Writer { out, len: 0 };
+
+        if !writer.write(&DIAGNOSTICS_MAGIC) || !writer.write(&[DIAGNOSTICS_VERSION]) {
+            return 0;
+        }
+
+        writer.entry(DiagnosticTag::STATUS, &[self.status]);
+
+        let results = [
+            (DiagnosticTag::COMMAND_BYTE, self.command_byte),
+            (DiagnosticTag::INPUT_PORT, self.input_port),
+            (DiagnosticTag::OUTPUT_PORT, self.output_port),
+            (DiagnosticTag::TEST_INPUTS, self.test_inputs),
+            (DiagnosticTag::SELF_TEST, self.self_test),
+            (
+                DiagnosticTag::KEYBOARD_INTERFACE_TEST,
+                self.keyboard_interface_test,
+            ),
+            (
+                DiagnosticTag::AUXILIARY_DEVICE_INTERFACE_TEST,
+                self.auxiliary_device_interface_test,
+            ),
+        ];
+        for &(tag, result) in results.iter() {
+            if let Some(result) = result {
+                writer.entry(tag, &[result]);
+            }
+        }
+
+        let counters = [
+            (
+                DiagnosticTag::KEYBOARD_TRANSMISSION_ERRORS,
+                self.counters.keyboard_transmission_errors,
+            ),
+            (
+                DiagnosticTag::KEYBOARD_UNEXPLAINED_BYTES,
+                self.counters.keyboard_unexplained_bytes,
+            ),
+            (
+                DiagnosticTag::KEYBOARD_DISCARDED_GRABBED_EVENTS,
+                self.counters.keyboard_discarded_grabbed_events,
+            ),
+        ];
+        for &(tag, value) in counters.iter() {
+            writer.entry(tag, &value.to_le_bytes());
+        }
+
+        let unknown_bits = [
+            (
+                DiagnosticTag::UNKNOWN_BITS_COMMAND_BYTE,
+                UnknownBitsSource::CommandByte,
+            ),
+            (
+                DiagnosticTag::UNKNOWN_BITS_INPUT_PORT,
+                UnknownBitsSource::InputPort,
+            ),
+            (
+                DiagnosticTag::UNKNOWN_BITS_TEST_INPUTS,
+                UnknownBitsSource::TestInputs,
+            ),
+        ];
+        for &(tag, source) in unknown_bits.iter() {
+            let record = self.unknown_bits.get(source);
+            if record.count != 0 {
+                let count = u32::from(record.count).to_le_bytes();
+                writer.entry(tag, &[count[0], count[1], count[2], count[3], record.last]);
+            }
+        }
+
+        writer.entry(
+            DiagnosticTag::TRANSITIONS,
+            &self.transitions[..self.transitions_len],
+        );
+
+        writer.len
+    }
+}
+
+struct Writer<'a> {
+    out: &'a mut [u8],
+    len: usize,
+}
+
+impl Writer<'_> {
+    /// Returns `false` if there is not enough space.
+    fn write(&mut self, bytes: &[u8]) -> bool {
+        match self.out.get_mut(self.len..self.len + bytes.len()) {
+            Some(slot) => {
+                slot.copy_from_slice(bytes);
+                self.len += bytes.len();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn entry(&mut self, tag: u8, value: &[u8]) {
+        if self.out.len() - self.len >= 2 + value.len() {
+            self.write(&[tag, value.len() as u8]);
+            self.write(value);
+        }
+    }
+}
+
+impl<T: PortIO> DevicesDisabled<T> {
+    /// Collect `Diagnostics` and export them to `out`. Returns
+    /// the number of bytes written. Counters are zero, use
+    /// `Diagnostics` directly to include driver counters.
+    pub fn export_diagnostics(&mut self, out: &mut [u8]) -> usize {
+        Diagnostics::collect(self).export(out)
+    }
+}
@@ -0,0 +1,266 @@
+//! Controller state handoff between boot stages.
+//!
+//! A bootloader which uses this crate calls `EnabledDevices::handoff`
+//! and passes `Handoff::to_bytes` to the kernel. The kernel calls
+//! `InitController::resume_from_handoff`, which skips the controller
+//! initialization and the interface tests if the controller
+//! configuration has not changed.
+//!
+//! Format: `HANDOFF_MAGIC`, `HANDOFF_VERSION`, controller command
+//! byte, enabled device flags, quirks, scancode set number or 0,
+//! keyboard kind and auxiliary device kind. A device kind is three
+//! bytes: 0 for unknown, 1 keyboard, 2 standard mouse, 3 wheel mouse,
+//! 4 five button mouse or 5 unknown ID, followed by the ID bytes of
+//! an unknown ID.
+
+use super::*;
+
+use crate::device::identify::{Device, MouseKind};
+use crate::device::keyboard::driver::KeyboardScancodeSetting;
+use crate::device::keyboard::raw::ScancodeSetNumber;
+
+pub const HANDOFF_MAGIC: [u8; 4] = *b"PS2H";
+pub const HANDOFF_VERSION: u8 = 1;
+pub const HANDOFF_LENGTH: usize = 15;
+
+/// Maximum number of bytes which `EnabledDevices::handoff` discards
+/// after disabling the device interfaces.
+pub const HANDOFF_DRAIN_POLLS: u32 = 16;
+
+const FLAG_KEYBOARD: u8 = 1 << 0;
+#[cfg(feature = "aux-device")]
+const FLAG_AUXILIARY_DEVICE: u8 = 1 << 1;
+
+/// Controller state from a previous boot stage.
+#[derive(Debug, Clone, Copy)]
+pub struct Handoff {
+    /// Configuration with the device interfaces disabled.
+    pub config: ControllerConfig,
+    pub devices: EnableDevice,
+    pub quirks: Quirks,
+    /// Scancode set of the keyboard driver. See
+    /// `QueuedKeyboardDriver::handoff`.
+    pub scancode_set: Option<KeyboardScancodeSetting>,
+    /// Keyboard identification result of the keyboard driver. See
+    /// `QueuedKeyboardDriver::handoff`.
+    pub keyboard_kind: Option<Device>,
+    pub auxiliary_device_kind: Option<Device>,
+}
+
+#[derive(Debug)]
+pub struct InvalidHandoff;
+
+/// Controller configuration was different from `Handoff::config`
+/// or it couldn't be read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HandoffMismatch {
+    pub config: Result<ControllerConfig, TransactionError>,
+}
+
+impl Handoff {
+    pub fn to_bytes(&self) -> [u8; HANDOFF_LENGTH] {
+        let mut flags = 0;
+        if self.devices.keyboard() {
+            flags |= FLAG_KEYBOARD;
+        }
+        #[cfg(feature = "aux-device")]
+        {
+            if self.devices.auxiliary_device() {
+                flags |= FLAG_AUXILIARY_DEVICE;
+            }
+        }
+
+        let mut bytes = [0; HANDOFF_LENGTH];
+        bytes[..4].copy_from_slice(&HANDOFF_MAGIC);
+        bytes[4] = HANDOFF_VERSION;
+        bytes[5] = self.config.raw();
+        bytes[6] = flags;
+        bytes[7] = self.quirks.bits();
+        bytes[8] = self.scancode_set.map_or(0, |set| set as u8);
+        bytes[9..12].copy_from_slice(&device_to_bytes(self.keyboard_kind));
+        bytes[12..15].copy_from_slice(&device_to_bytes(self.auxiliary_device_kind));
+        bytes
+    }
+
+    /// Unknown quirks are ignored. Auxiliary device is ignored if
+    /// the `aux-device` feature is disabled.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, InvalidHandoff> {
+        if bytes.len() < HANDOFF_LENGTH
+            || bytes[..4] != HANDOFF_MAGIC
+            || bytes[4] != HANDOFF_VERSION
+        {
+            return Err(InvalidHandoff);
+        }
+
+        let flags = bytes[6];
+        let keyboard = flags & FLAG_KEYBOARD != 0;
+        #[cfg(feature = "aux-device")]
+        let devices = match (keyboard, flags & FLAG_AUXILIARY_DEVICE != 0) {
+            (true, true) => EnableDevice::KeyboardAndAuxiliaryDevice,
+            (true, false) => EnableDevice::Keyboard,
+            (false, true) => EnableDevice::AuxiliaryDevice,
+            (false, false) => return Err(InvalidHandoff),
+        };
+        #[cfg(not(feature = "aux-device"))]
+        let devices = if keyboard {
+            EnableDevice::Keyboard
+        } else {
+            return Err(InvalidHandoff);
+        };
+
+        let scancode_set = match bytes[8] {
+            0 => None,
+            ScancodeSetNumber::SET_1 => Some(KeyboardScancodeSetting::Set1),
+            ScancodeSetNumber::SET_2 => Some(KeyboardScancodeSetting::Set2),
+            ScancodeSetNumber::SET_3 => Some(KeyboardScancodeSetting::Set3),
+            _ => return Err(InvalidHandoff),
+        };
+
+        Ok(Handoff {
+            config: ControllerConfig::from_raw(bytes[5]),
+            devices,
+            quirks: Quirks::from_bits_truncate(bytes[7]),
+            scancode_set,
+            keyboard_kind: device_from_bytes(&bytes[9..12])?,
+            auxiliary_device_kind: device_from_bytes(&bytes[12..15])?,
+        })
+    }
+}
+
+fn device_to_bytes(device: Option<Device>) -> [u8; 3] {
+    match device {
+        None => [0, 0, 0],
+        Some(Device::Keyboard) => [1, 0, 0],
+        Some(Device::Mouse(MouseKind::Standard)) => [2, 0, 0],
+        Some(Device::Mouse(MouseKind::Wheel)) => [3, 0, 0],
+        Some(Device::Mouse(MouseKind::FiveButton)) => [4, 0, 0],
+        Some(Device::UnknownID {
+            first_byte,
+            second_byte,
+        }) => [5, first_byte, second_byte],
+    }
+}
+
+fn device_from_bytes(bytes: &[u8]) -> Result<Option<Device>, InvalidHandoff> {
+    let device = match bytes[0] {
+        0 => return Ok(None),
+        1 => Device::Keyboard,
+        2 => Device::Mouse(MouseKind::Standard),
+        3 => Device::Mouse(MouseKind::Wheel),
+        4 => Device::Mouse(MouseKind::FiveButton),
+        5 => Device::UnknownID {
+            first_byte: bytes[1],
+            second_byte: bytes[2],
+        },
+        _ => return Err(InvalidHandoff),
+    };
+    Ok(Some(device))
+}
+
+impl<T: PortIO, IRQ> EnabledDevices<T, IRQ> {
+    /// Disable the device interfaces without changing interrupt
+    /// settings and release the `PortIO` for the next boot stage.
+    ///
+    /// Interrupts must be disabled until the next boot stage has
+    /// resumed, because the controller command byte is read.
+    ///
+    /// The controller doesn't know the keyboard state, so
+    /// `Handoff::scancode_set` and `Handoff::keyboard_kind` are
+    /// `None`. `QueuedKeyboardDriver::handoff` sets them from the
+    /// keyboard driver.
+    ///
+    /// The `PortIO` is returned also if the configuration couldn't be
+    /// read.
+    pub fn handoff(mut self) -> (T, Result<Handoff, TransactionError>) {
+        self.transitions.record(Transition::Handoff);
+
+        let devices = self.devices;
+        #[cfg(feature = "aux-device")]
+        let auxiliary_device_kind = self.auxiliary_device_kind;
+        #[cfg(not(feature = "aux-device"))]
+        let auxiliary_device_kind = None;
+
+        let mut controller = DevicesDisabled {
+            port_io: self.port_io,
+            config: None,
+            quirks: self.quirks,
+            transitions: self.transitions,
+            unknown_bits: self.unknown_bits,
+            tracer: self.tracer,
+        };
+        controller.dangerous_disable_auxiliary_device_interface();
+        controller.dangerous_disable_keyboard_interface();
+
+        for _ in 0..HANDOFF_DRAIN_POLLS {
+            if controller.status().data_origin().is_none() {
+                break;
+            }
+            controller.port_io_mut().read(T::DATA_PORT);
+        }
+
+        let handoff = controller.controller_config().map(|config| Handoff {
+            config,
+            devices,
+            quirks: controller.quirks,
+            scancode_set: None,
+            keyboard_kind: None,
+            auxiliary_device_kind,
+        });
+
+        (controller.port_io, handoff)
+    }
+}
+
+impl<T: PortIO> InitController<T> {
+    /// Enable the devices of `handoff` without interface tests or
+    /// controller initialization. Interrupts are disabled.
+    ///
+    /// If the controller configuration has changed since
+    /// `EnabledDevices::handoff`, the controller is initialized like
+    /// with `start_init_with_quirks` and returned with the error.
+    pub fn resume_from_handoff(
+        port_io: T,
+        handoff: &Handoff,
+    ) -> Result<EnabledDevices<T, Disabled>, (DevicesDisabled<T>, HandoffMismatch)> {
+        let controller = Self::check_handoff(port_io, handoff)?;
+        Ok(controller.resume(handoff, false))
+    }
+
+    /// Same as `resume_from_handoff` but interrupts are enabled for
+    /// the devices which had them enabled in `Handoff::config`.
+    pub fn resume_from_handoff_with_interrupts(
+        port_io: T,
+        handoff: &Handoff,
+    ) -> Result<EnabledDevices<T, InterruptsEnabled>, (DevicesDisabled<T>, HandoffMismatch)> {
+        let controller = Self::check_handoff(port_io, handoff)?;
+        Ok(controller.resume(handoff, true))
+    }
+
+    fn check_handoff(
+        port_io: T,
+        handoff: &Handoff,
+    ) -> Result<DevicesDisabled<T>, (DevicesDisabled<T>, HandoffMismatch)> {
+        let mut controller =
+            DevicesDisabled::new(port_io, Some(handoff.config), handoff.quirks, Tracer::new());
+        let config = controller.controller_config();
+
+        if config != Ok(handoff.config) {
+            controller.config = None;
+            return Err((Self::init(controller), HandoffMismatch { config }));
+        }
+
+        Ok(controller)
+    }
+}
+
+impl<T: PortIO> DevicesDisabled<T> {
+    fn resume<IRQ>(mut self, handoff: &Handoff, interrupts: bool) -> EnabledDevices<T, IRQ> {
+        self.transitions.record(Transition::ResumeFromHandoff);
+        #[allow(unused_mut)]
+        // Configuration is set, so the command byte is not read.
+        let mut controller = self.configure(handoff.devices, interrupts, None);
+        #[cfg(feature = "aux-device")]
+        controller.set_auxiliary_device_kind(handoff.auxiliary_device_kind);
+        controller
+    }
+}
@@ -0,0 +1,355 @@
+//! Cooperative controller initialization.
+//!
+//! `InitController::start_init` and `DevicesDisabled::enable_devices`
+//! wait for every controller command. `InitSequence` runs the same
+//! initialization, with the controller self test added, but
+//! `InitSequence::poll` doesn't wait for the controller. Every call
+//! writes at most one byte or reads at most one response, so boot
+//! code can do other work between the calls.
+//!
+//! The call which returns `InitPoll::Ready` enables the devices like
+//! `enable_devices` and waits until the controller has handled those
+//! commands. Device reset and identification are done after this with
+//! the device drivers, which don't block either.
+//!
+//! ```rust,ignore
+//! let mut init = InitSequence::new(port_io, EnableDevice::Keyboard);
+//! let controller = loop {
+//!     init = match init.poll() {
+//!         InitPoll::Pending(init) => init,
+//!         InitPoll::Ready(controller) => break controller,
+//!         InitPoll::Failed(controller, e) => return Err(e),
+//!     };
+//!     other_boot_work();
+//! };
+//! ```
+
+use super::{
+    check_unknown_bits,
+    marker::{Disabled, InterruptsEnabled},
+    quirks::Quirks,
+    status::{DataOwner, ReadStatus},
+    trace,
+    transitions::Transition,
+    unknown_bits::UnknownBitsSource,
+    DeviceInterfaceError, DevicesDisabled, EnableDevice, EnabledDevices, InterfaceError,
+};
+use crate::controller::io::{PortIO, PortIOAvailable};
+use crate::controller::raw::{
+    Command, CommandReturnData, CommandWaitData, ControllerCommandByte, SelfTestResult,
+};
+use crate::controller::transaction::{
+    command_returns_data, write_command, write_data, TRANSACTION_POLLS, TRANSACTION_RETRIES,
+};
+use crate::trace::{TraceEvent, TraceLevel, Tracer};
+
+use core::marker::PhantomData;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InitStep {
+    DisableAuxiliaryDevice,
+    DisableKeyboard,
+    ReadCommandByte,
+    /// Write the command byte with interrupts disabled.
+    WriteCommandByte,
+    SelfTest,
+    /// Write the command byte again, because the self test may
+    /// reset the controller.
+    RestoreCommandByte,
+    KeyboardInterfaceTest,
+    #[cfg(feature = "aux-device")]
+    AuxiliaryDeviceInterfaceTest,
+    EnableDevices,
+}
+
+#[derive(Debug)]
+pub enum InitSequenceError {
+    /// Controller didn't handle the command or respond within the
+    /// poll limit.
+    Timeout(InitStep),
+    /// Controller set `GENERAL_TIMEOUT` on every retry.
+    GeneralTimeout(InitStep),
+    /// Self test result. Controller may have been reset.
+    SelfTest(u8),
+    Interface(InterfaceError),
+}
+
+#[derive(Debug)]
+pub enum InitPoll<T: PortIO, IRQ> {
+    Pending(InitSequence<T, IRQ>),
+    Ready(EnabledDevices<T, IRQ>),
+    Failed(DevicesDisabled<T>, InitSequenceError),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Wait {
+    /// Command of the current step is not written.
+    Command,
+    /// Command is written and its data byte waits for an empty
+    /// input buffer.
+    Data(u8),
+    Response,
+}
+
+/// Resumable controller initialization. `IRQ` is the interrupt
+/// marker of the controller which the sequence returns.
+#[derive(Debug)]
+pub struct InitSequence<T: PortIO, IRQ> {
+    controller: DevicesDisabled<T>,
+    _marker: PhantomData<IRQ>,
+    devices: EnableDevice,
+    interrupts: bool,
+    self_test: bool,
+    step: InitStep,
+    wait: Wait,
+    command_byte: ControllerCommandByte,
+    polls: Option<u32>,
+    remaining: Option<u32>,
+    retries: u32,
+}
+
+impl<T: PortIO> InitSequence<T, Disabled> {
+    /// Same as `start_init` and `enable_devices`. You should disable
+    /// interrupts before the first poll.
+    pub fn new(port_io: T, devices: EnableDevice) -> Self {
+        Self::create(port_io, devices, false)
+    }
+}
+
+impl<T: PortIO> InitSequence<T, InterruptsEnabled> {
+    /// Same as `start_init` and `enable_devices_and_interrupts`.
+    pub fn new_with_interrupts(port_io: T, devices: EnableDevice) -> Self {
+        Self::create(port_io, devices, true)
+    }
+}
+
+impl<T: PortIO, IRQ> InitSequence<T, IRQ> {
+    fn create(port_io: T, devices: EnableDevice, interrupts: bool) -> Self {
+        let mut controller = DevicesDisabled::new(port_io, None, Quirks::empty(), Tracer::new());
+        controller.transitions.record(Transition::Init);
+
+        Self {
+            controller,
+            _marker: PhantomData,
+            devices,
+            interrupts,
+            self_test: true,
+            step: InitStep::DisableAuxiliaryDevice,
+            wait: Wait::Command,
+            command_byte: ControllerCommandByte::empty(),
+            polls: Some(TRANSACTION_POLLS),
+            remaining: Some(TRANSACTION_POLLS),
+            retries: 0,
+        }
+    }
+
+    /// Set quirks, tracing and the controller configuration before
+    /// the first poll. Methods which send commands wait for the
+    /// controller.
+    pub fn controller_mut(&mut self) -> &mut DevicesDisabled<T> {
+        &mut self.controller
+    }
+
+    /// Self test is enabled by default.
+    pub fn set_self_test(&mut self, enabled: bool) {
+        self.self_test = enabled;
+    }
+
+    /// Number of polls which one step waits for the controller.
+    /// `None` waits forever.
+    pub fn set_poll_limit(&mut self, polls: Option<u32>) {
+        self.polls = polls;
+        self.remaining = polls;
+    }
+
+    /// Step which the next poll continues.
+    pub fn step(&self) -> InitStep {
+        self.step
+    }
+
+    pub fn poll(mut self) -> InitPoll<T, IRQ> {
+        if let Err(e) = self.advance() {
+            if let InitSequenceError::Interface(_) = e {
+                self.controller.transitions.record(Transition::EnableFailed);
+            }
+            return InitPoll::Failed(self.controller, e);
+        }
+
+        if self.step == InitStep::EnableDevices {
+            // Command byte was written by the sequence, so it is not
+            // read again.
+            let command_byte = if self.interrupts {
+                Some(self.command_byte)
+            } else {
+                None
+            };
+            InitPoll::Ready(
+                self.controller
+                    .configure(self.devices, self.interrupts, command_byte),
+            )
+        } else {
+            InitPoll::Pending(self)
+        }
+    }
+
+    fn advance(&mut self) -> Result<(), InitSequenceError> {
+        let status = self.controller.status();
+
+        match self.wait {
+            Wait::Response => {
+                if let Some(DataOwner::KeyboardOrCommandController) = status.data_availability() {
+                    let mut response = self.controller.port_io_mut().read(T::DATA_PORT);
+                    if self
+                        .controller
+                        .quirks
+                        .contains(Quirks::DOUBLE_RESPONSE_READ)
+                    {
+                        response = self.controller.port_io_mut().read(T::DATA_PORT);
+                    }
+                    self.controller.set_controller_response_pending(false);
+                    trace(&self.controller, TraceLevel::Verbose, || {
+                        TraceEvent::DataRead(response)
+                    });
+
+                    if status.general_timeout_error() {
+                        return self.retry();
+                    }
+
+                    return self.handle_response(response);
+                }
+            }
+            _ if status.input_buffer_full() => (),
+            Wait::Data(data) => {
+                write_data(&mut self.controller, data);
+                self.next_step();
+                return Ok(());
+            }
+            Wait::Command => {
+                let command = self.command();
+                if command_returns_data(command) && status.data_availability().is_some() {
+                    // Stale device data would be read as the response.
+                    self.controller.port_io_mut().read(T::DATA_PORT);
+                    return Ok(());
+                }
+
+                write_command(&mut self.controller, command);
+                match self.step {
+                    InitStep::DisableAuxiliaryDevice | InitStep::DisableKeyboard => {
+                        self.next_step()
+                    }
+                    InitStep::WriteCommandByte | InitStep::RestoreCommandByte => {
+                        self.wait = Wait::Data(self.command_byte.bits())
+                    }
+                    _ => self.wait = Wait::Response,
+                }
+                return Ok(());
+            }
+        }
+
+        match &mut self.remaining {
+            Some(0) => Err(InitSequenceError::Timeout(self.step)),
+            Some(polls) => {
+                *polls -= 1;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn handle_response(&mut self, response: u8) -> Result<(), InitSequenceError> {
+        match self.step {
+            InitStep::ReadCommandByte => {
+                check_unknown_bits(
+                    &mut self.controller,
+                    UnknownBitsSource::CommandByte,
+                    response,
+                    ControllerCommandByte::all().bits(),
+                );
+                self.command_byte = ControllerCommandByte::from_bits_truncate(response);
+                self.command_byte.remove(
+                    ControllerCommandByte::ENABLE_AUXILIARY_INTERRUPT
+                        | ControllerCommandByte::ENABLE_KEYBOARD_INTERRUPT,
+                );
+            }
+            InitStep::SelfTest if response != SelfTestResult::PASSED => {
+                return Err(InitSequenceError::SelfTest(response));
+            }
+            InitStep::KeyboardInterfaceTest => {
+                DeviceInterfaceError::from_test_result(response)
+                    .map_err(|e| InitSequenceError::Interface(InterfaceError::Keyboard(e)))?;
+            }
+            #[cfg(feature = "aux-device")]
+            InitStep::AuxiliaryDeviceInterfaceTest => {
+                DeviceInterfaceError::from_test_result(response).map_err(|e| {
+                    InitSequenceError::Interface(InterfaceError::AuxiliaryDevice(e))
+                })?;
+            }
+            _ => (),
+        }
+
+        self.next_step();
+        Ok(())
+    }
+
+    fn retry(&mut self) -> Result<(), InitSequenceError> {
+        let command = self.command();
+        trace(&self.controller, TraceLevel::Info, || {
+            TraceEvent::GeneralTimeout(command)
+        });
+
+        if self.retries < TRANSACTION_RETRIES {
+            self.retries += 1;
+            self.wait = Wait::Command;
+            Ok(())
+        } else {
+            Err(InitSequenceError::GeneralTimeout(self.step))
+        }
+    }
+
+    fn command(&self) -> u8 {
+        match self.step {
+            InitStep::DisableAuxiliaryDevice => Command::DISABLE_AUXILIARY_DEVICE_INTERFACE,
+            InitStep::DisableKeyboard => Command::DISABLE_KEYBOARD_INTERFACE,
+            InitStep::ReadCommandByte => CommandReturnData::READ_CONTROLLER_COMMAND_BYTE,
+            InitStep::WriteCommandByte | InitStep::RestoreCommandByte => {
+                CommandWaitData::WRITE_CONTROLLER_COMMAND_BYTE
+            }
+            InitStep::SelfTest => CommandReturnData::SELF_TEST,
+            InitStep::KeyboardInterfaceTest => CommandReturnData::KEYBOARD_INTERFACE_TEST,
+            #[cfg(feature = "aux-device")]
+            InitStep::AuxiliaryDeviceInterfaceTest => {
+                CommandReturnData::AUXILIARY_DEVICE_INTERFACE_TEST
+            }
+            // Not sent. Devices are enabled with `configure`.
+            InitStep::EnableDevices => Command::ENABLE_KEYBOARD_INTERFACE,
+        }
+    }
+
+    fn next_step(&mut self) {
+        self.step = match self.step {
+            InitStep::DisableAuxiliaryDevice => InitStep::DisableKeyboard,
+            InitStep::DisableKeyboard => InitStep::ReadCommandByte,
+            InitStep::ReadCommandByte => InitStep::WriteCommandByte,
+            InitStep::WriteCommandByte if self.self_test => InitStep::SelfTest,
+            InitStep::SelfTest => InitStep::RestoreCommandByte,
+            InitStep::WriteCommandByte | InitStep::RestoreCommandByte
+                if self.devices.keyboard() =>
+            {
+                InitStep::KeyboardInterfaceTest
+            }
+            #[cfg(feature = "aux-device")]
+            InitStep::WriteCommandByte
+            | InitStep::RestoreCommandByte
+            | InitStep::KeyboardInterfaceTest
+                if self.devices.auxiliary_device() =>
+            {
+                InitStep::AuxiliaryDeviceInterfaceTest
+            }
+            _ => InitStep::EnableDevices,
+        };
+
+        self.wait = Wait::Command;
+        self.remaining = self.polls;
+        self.retries = 0;
+    }
+}
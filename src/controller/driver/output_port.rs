@@ -0,0 +1,33 @@
+//! Output port access which can't reset the machine.
+//!
+//! Output port bit `RESET_MICROPROCESSOR` is the active low CPU reset
+//! line, so `OutputPort` always writes it set. The device clock and
+//! data lines and the interrupt bits are driven by the controller and
+//! are written as read. Resetting the machine requires `ResetCPU`.
+
+use super::*;
+
+pub trait OutputPort<T: PortIO>:
+    ReadStatus<T> + InterruptsDisabled + KeyboardDisabled + AuxiliaryDeviceDisabled + Sized
+{
+    fn output_port(&mut self) -> Result<OutputPortBits, TransactionError> {
+        let raw =
+            send_controller_command_and_wait_response(self, CommandReturnData::READ_OUTPUT_PORT)?;
+        Ok(OutputPortBits::from_bits_truncate(raw))
+    }
+
+    /// Set the A20 gate. Disabling the gate breaks access to memory
+    /// above 1 MiB on machines which use this gate. Output port is not
+    /// written if it can't be read.
+    fn set_address_line_20(&mut self, enabled: bool) -> Result<(), TransactionError> {
+        let mut bits = self.output_port()?;
+        bits.set(OutputPortBits::GATE_ADDRESS_LINE_20, enabled);
+        bits.insert(OutputPortBits::RESET_MICROPROCESSOR);
+        send_controller_command_and_write_data(
+            self,
+            CommandWaitData::WRITE_OUTPUT_PORT,
+            bits.bits(),
+        );
+        Ok(())
+    }
+}
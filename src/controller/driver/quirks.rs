@@ -0,0 +1,34 @@
+//! Workarounds for controller chipset bugs.
+//!
+//! Quirks are applied by the functions which write commands and read
+//! command responses, so the driver logic stays the same with all
+//! quirks profiles.
+
+use bitflags::bitflags;
+
+bitflags! {
+    pub struct Quirks: u8 {
+        /// Read status register once after every controller
+        /// command write before polling it.
+        const DUMMY_STATUS_READ_AFTER_COMMAND = 0b0000_0001;
+        /// Read controller command response twice and use the
+        /// second value. First read returns a stale value.
+        const DOUBLE_RESPONSE_READ = 0b0000_0010;
+        /// Controller firmware stores its own state in the RAM after
+        /// the command byte, so no bytes are free for breadcrumbs.
+        const RAM_IN_USE = 0b0000_0100;
+        /// Translation is applied to auxiliary device bytes too, so
+        /// auxiliary device packets are corrupted when translation
+        /// is enabled.
+        const TRANSLATES_AUXILIARY_DATA = 0b0000_1000;
+        /// Keyboard bytes are not translated when command byte bit
+        /// 3, keyboard inhibit override, is set.
+        const INHIBIT_OVERRIDE_DISABLES_TRANSLATION = 0b0001_0000;
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::empty()
+    }
+}
@@ -0,0 +1,85 @@
+//! Controller RAM contents.
+
+use super::config::ControllerConfig;
+use crate::controller::raw::{ControllerCommandByte, CONTROLLER_RAM_SIZE};
+use crate::controller::transaction::TransactionError;
+
+/// Copy of the controller RAM.
+///
+/// Address 0 is the controller command byte. Only the command byte has
+/// a standard meaning. Other bytes are controller specific, and some
+/// models store for example password or security state there. Use
+/// `get` and `set` to access them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ControllerRam {
+    config: ControllerConfig,
+    /// Addresses from 1 to `CONTROLLER_RAM_SIZE`.
+    bytes: [u8; CONTROLLER_RAM_SIZE],
+}
+
+#[derive(Debug)]
+pub struct RamAddressOutOfRange;
+
+/// Error from `ReadRAM::ram_byte`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RamReadError {
+    AddressOutOfRange,
+    Transaction(TransactionError),
+}
+
+impl ControllerRam {
+    pub const COMMAND_BYTE_ADDRESS: u8 = 0;
+    /// Size including the command byte.
+    pub const SIZE: usize = CONTROLLER_RAM_SIZE + 1;
+
+    pub const fn new(config: ControllerConfig, bytes: [u8; CONTROLLER_RAM_SIZE]) -> Self {
+        Self { config, bytes }
+    }
+
+    pub fn config(&self) -> ControllerConfig {
+        self.config
+    }
+
+    pub fn set_config(&mut self, config: ControllerConfig) {
+        self.config = config;
+    }
+
+    pub fn command_byte(&self) -> ControllerCommandByte {
+        self.config.command_byte()
+    }
+
+    /// Bytes after the command byte.
+    pub fn bytes(&self) -> &[u8; CONTROLLER_RAM_SIZE] {
+        &self.bytes
+    }
+
+    pub fn bytes_mut(&mut self) -> &mut [u8; CONTROLLER_RAM_SIZE] {
+        &mut self.bytes
+    }
+
+    pub fn get(&self, address: u8) -> Option<u8> {
+        match address {
+            Self::COMMAND_BYTE_ADDRESS => Some(self.config.raw()),
+            _ => self.bytes.get(address as usize - 1).copied(),
+        }
+    }
+
+    pub fn set(&mut self, address: u8, value: u8) -> Result<(), RamAddressOutOfRange> {
+        match address {
+            Self::COMMAND_BYTE_ADDRESS => self.config = ControllerConfig::from_raw(value),
+            _ => {
+                *self
+                    .bytes
+                    .get_mut(address as usize - 1)
+                    .ok_or(RamAddressOutOfRange)? = value
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Iterate `(address, value)` pairs starting from the command byte.
+    pub fn iter(&self) -> impl Iterator<Item = (u8, u8)> + '_ {
+        (0..Self::SIZE as u8).filter_map(move |address| Some((address, self.get(address)?)))
+    }
+}
@@ -0,0 +1,130 @@
+//! Handing the controller over to firmware, another driver or a
+//! virtual machine.
+
+use super::*;
+
+use crate::device::keyboard::raw::{CommandReturnData as KeyboardCommand, FromKeyboard};
+#[cfg(feature = "aux-device")]
+use crate::device::mouse::raw::{FromMouse, MouseCommand};
+
+/// Maximum number of data reads while waiting for a device to
+/// acknowledge the disable command.
+pub const SHUTDOWN_ACK_POLLS: u32 = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceShutdown {
+    NotEnabled,
+    /// Device acknowledged the disable command.
+    Disabled,
+    /// Device did not acknowledge the disable command. It may still
+    /// be sending data.
+    NoAck,
+}
+
+/// State which `EnabledDevices::shutdown` left the hardware in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShutdownReport {
+    pub keyboard: DeviceShutdown,
+    pub auxiliary_device: DeviceShutdown,
+    /// Bytes which devices sent during the shutdown.
+    pub discarded_bytes: u32,
+    /// Configuration read back after it was written.
+    pub config: Result<ControllerConfig, TransactionError>,
+}
+
+impl<T: PortIO, IRQ> EnabledDevices<T, IRQ> {
+    /// Disable keyboard scanning and auxiliary device data reporting,
+    /// disable device interfaces and interrupts, write `config` as is
+    /// and release the `PortIO`.
+    pub fn shutdown(mut self, config: ControllerConfig) -> (T, ShutdownReport) {
+        let mut discarded_bytes = 0;
+
+        let keyboard = if self
+            .send_to_keyboard(KeyboardCommand::DEFAULT_DISABLE)
+            .is_ok()
+        {
+            self.wait_shutdown_ack(DataOrigin::Keyboard, &mut discarded_bytes)
+        } else {
+            DeviceShutdown::NotEnabled
+        };
+
+        #[cfg(feature = "aux-device")]
+        let auxiliary_device = if self
+            .send_to_auxiliary_device(MouseCommand::DISABLE_DATA_REPORTING)
+            .is_ok()
+        {
+            self.wait_shutdown_ack(DataOrigin::AuxiliaryDevice, &mut discarded_bytes)
+        } else {
+            DeviceShutdown::NotEnabled
+        };
+        #[cfg(not(feature = "aux-device"))]
+        let auxiliary_device = DeviceShutdown::NotEnabled;
+
+        self.transitions.record(Transition::Shutdown);
+        let mut controller = InitController::disable_devices_and_interrupts(DevicesDisabled {
+            port_io: self.port_io,
+            config: None,
+            quirks: self.quirks,
+            transitions: self.transitions,
+            unknown_bits: self.unknown_bits,
+            tracer: self.tracer,
+        });
+
+        // Data which was sent before the interfaces were disabled.
+        for _ in 0..SHUTDOWN_ACK_POLLS {
+            if controller.status().data_origin().is_none() {
+                break;
+            }
+            controller.port_io_mut().read(T::DATA_PORT);
+            discarded_bytes += 1;
+        }
+
+        send_controller_command_and_write_data(
+            &mut controller,
+            CommandWaitData::WRITE_CONTROLLER_COMMAND_BYTE,
+            config.raw(),
+        );
+        let config = controller.controller_config();
+
+        let report = ShutdownReport {
+            keyboard,
+            auxiliary_device,
+            discarded_bytes,
+            config,
+        };
+
+        (controller.port_io, report)
+    }
+
+    fn wait_shutdown_ack(
+        &mut self,
+        device: DataOrigin,
+        discarded_bytes: &mut u32,
+    ) -> DeviceShutdown {
+        for _ in 0..SHUTDOWN_ACK_POLLS {
+            let data = match self.read_data() {
+                Some(data) => data,
+                None => continue,
+            };
+
+            let ack = match data {
+                DeviceData::Keyboard(byte) => {
+                    matches!(device, DataOrigin::Keyboard) && byte == FromKeyboard::ACK
+                }
+                #[cfg(feature = "aux-device")]
+                DeviceData::AuxiliaryDevice(byte) => {
+                    matches!(device, DataOrigin::AuxiliaryDevice) && byte == FromMouse::ACK
+                }
+                _ => false,
+            };
+
+            if ack {
+                return DeviceShutdown::Disabled;
+            }
+
+            *discarded_bytes += 1;
+        }
+
+        DeviceShutdown::NoAck
+    }
+}
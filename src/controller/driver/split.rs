@@ -0,0 +1,237 @@
+//! Independent keyboard and auxiliary device port handles.
+//!
+//! Handles can be given to different interrupt handlers. Both handles
+//! access the same controller, so sending from both handles at the
+//! same time is not supported. Sending to the auxiliary device requires
+//! two writes, and a keyboard write between them would be sent to the
+//! auxiliary device. Disable interrupts when sending if the other
+//! handle might be used from an interrupt handler.
+//!
+//! Send methods don't wait for the controller. They return
+//! `WouldBlock` if the controller input buffer is full.
+//!
+//! Both handles trace with the tracer of the split controller. The
+//! keyboard handle keeps the transition log and unknown bit records,
+//! and `EnabledDevices::join` takes them back.
+
+use super::status::{DataOrigin, ReadStatus};
+use super::*;
+
+use crate::controller::io::SplitPortIO;
+use crate::device::io::WouldBlock;
+
+#[derive(Debug)]
+pub struct KeyboardPort<T: PortIO, IRQ> {
+    port_io: T,
+    _marker: PhantomData<IRQ>,
+    quirks: Quirks,
+    tracer: Tracer,
+    transitions: TransitionLog,
+    unknown_bits: UnknownBits,
+}
+
+impl<T: PortIO, IRQ> KeyboardPort<T, IRQ> {
+    pub fn send_to_keyboard(&mut self, data: u8) -> Result<(), WouldBlock> {
+        if self.status().input_buffer_full() {
+            return Err(WouldBlock);
+        }
+
+        self.port_io_mut().write(T::DATA_PORT, data);
+        Ok(())
+    }
+
+    /// Read data if the output buffer contains data from the keyboard.
+    pub fn read_keyboard_data(&mut self) -> Option<u8> {
+        match self.status().data_origin()? {
+            DataOrigin::Keyboard => Some(self.port_io_mut().read(T::DATA_PORT)),
+            DataOrigin::AuxiliaryDevice | DataOrigin::ControllerCommandResponse => None,
+        }
+    }
+}
+
+impl_port_io_available!(<T: PortIO, IRQ> KeyboardPort<T, IRQ>);
+
+impl<T: PortIO, IRQ> ReadStatus<T> for KeyboardPort<T, IRQ> {
+    fn tracer(&self) -> Option<&Tracer> {
+        Some(&self.tracer)
+    }
+
+    fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    fn unknown_bits_mut(&mut self) -> Option<&mut UnknownBits> {
+        Some(&mut self.unknown_bits)
+    }
+}
+
+#[derive(Debug)]
+pub struct AuxiliaryPort<T: PortIO, IRQ> {
+    port_io: T,
+    _marker: PhantomData<IRQ>,
+    quirks: Quirks,
+    tracer: Tracer,
+    kind: Option<Device>,
+    data_pending: Option<u8>,
+}
+
+impl<T: PortIO, IRQ> AuxiliaryPort<T, IRQ> {
+    /// Sending requires a controller command and a data write. If
+    /// the controller is still processing the command, the data byte
+    /// is stored and written later by `flush` or the next send.
+    pub fn send_to_auxiliary_device(&mut self, data: u8) -> Result<(), WouldBlock> {
+        self.flush()?;
+
+        if self.status().input_buffer_full() {
+            return Err(WouldBlock);
+        }
+
+        transaction::write_command(self, CommandWaitData::WRITE_TO_AUXILIARY_DEVICE);
+        self.data_pending = Some(data);
+
+        // Ignore `WouldBlock`, because the data byte is now stored.
+        let _ = self.flush();
+        Ok(())
+    }
+
+    /// Write stored data byte if there is one.
+    pub fn flush(&mut self) -> Result<(), WouldBlock> {
+        if let Some(data) = self.data_pending {
+            if self.status().input_buffer_full() {
+                return Err(WouldBlock);
+            }
+
+            transaction::write_data(self, data);
+            self.data_pending = None;
+        }
+
+        Ok(())
+    }
+
+    /// Read data if the output buffer contains data from the
+    /// auxiliary device.
+    pub fn read_auxiliary_device_data(&mut self) -> Option<u8> {
+        match self.status().data_origin()? {
+            DataOrigin::AuxiliaryDevice => Some(self.port_io_mut().read(T::DATA_PORT)),
+            DataOrigin::Keyboard | DataOrigin::ControllerCommandResponse => None,
+        }
+    }
+
+    /// Value of `EnabledDevices::auxiliary_device_kind` when the
+    /// handles were split.
+    pub fn kind(&self) -> Option<Device> {
+        self.kind
+    }
+
+    pub fn set_kind(&mut self, kind: Option<Device>) {
+        self.kind = kind;
+    }
+}
+
+impl_port_io_available!(<T: PortIO, IRQ> AuxiliaryPort<T, IRQ>);
+
+impl<T: PortIO, IRQ> ReadStatus<T> for AuxiliaryPort<T, IRQ> {
+    fn tracer(&self) -> Option<&Tracer> {
+        Some(&self.tracer)
+    }
+
+    fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+}
+
+pub type SplitPorts<T, IRQ> = (KeyboardPort<T, IRQ>, AuxiliaryPort<T, IRQ>);
+
+impl<T: SplitPortIO, IRQ> EnabledDevices<T, IRQ> {
+    /// Split to independent port handles. Both devices must be enabled.
+    ///
+    /// Returns the controller if only one device is enabled or if
+    /// `flush` returns `WouldBlock`.
+    pub fn split(mut self) -> Result<SplitPorts<T, IRQ>, Self> {
+        if self.flush().is_err() {
+            return Err(self);
+        }
+
+        match self.devices {
+            EnableDevice::KeyboardAndAuxiliaryDevice => {
+                let auxiliary_port_io = self.port_io.split_port_io();
+                let keyboard = KeyboardPort {
+                    port_io: self.port_io,
+                    _marker: PhantomData,
+                    quirks: self.quirks,
+                    tracer: self.tracer,
+                    transitions: self.transitions,
+                    unknown_bits: self.unknown_bits,
+                };
+                let auxiliary_device = AuxiliaryPort {
+                    port_io: auxiliary_port_io,
+                    _marker: PhantomData,
+                    quirks: self.quirks,
+                    tracer: self.tracer,
+                    kind: self.auxiliary_device_kind,
+                    data_pending: None,
+                };
+                Ok((keyboard, auxiliary_device))
+            }
+            EnableDevice::Keyboard | EnableDevice::AuxiliaryDevice => Err(self),
+        }
+    }
+}
+
+impl<T: PortIO, IRQ> EnabledDevices<T, IRQ> {
+    /// Join port handles created with `split`. Tracer, transition
+    /// log and unknown bit records are taken from the keyboard port
+    /// handle and auxiliary device kind from the auxiliary port
+    /// handle. A data byte which the auxiliary port handle has
+    /// stored is written by the next send or `flush`.
+    /// `Transition::Join` is recorded.
+    pub fn join(keyboard: KeyboardPort<T, IRQ>, auxiliary_device: AuxiliaryPort<T, IRQ>) -> Self {
+        let auxiliary_device_kind = auxiliary_device.kind;
+        let auxiliary_device_data_pending = auxiliary_device.data_pending;
+        drop(auxiliary_device);
+
+        let mut transitions = keyboard.transitions;
+        transitions.record(Transition::Join);
+
+        EnabledDevices {
+            port_io: keyboard.port_io,
+            _marker: PhantomData,
+            devices: EnableDevice::KeyboardAndAuxiliaryDevice,
+            controller_response_pending: false,
+            auxiliary_device_data_pending,
+            tracer: keyboard.tracer,
+            quirks: keyboard.quirks,
+            transitions,
+            unknown_bits: keyboard.unknown_bits,
+            auxiliary_device_kind,
+        }
+    }
+}
+
+impl<T: PortIO> DevicesDisabled<T> {
+    /// Join port handles created with `EnabledDevices::split` and
+    /// disable the devices and interrupts. A stored auxiliary device
+    /// data byte is discarded. `Transition::Join` and
+    /// `Transition::DisableDevices` are recorded.
+    ///
+    /// You should disable the interrupts before calling this.
+    pub fn join<IRQ>(
+        keyboard: KeyboardPort<T, IRQ>,
+        auxiliary_device: AuxiliaryPort<T, IRQ>,
+    ) -> Self {
+        drop(auxiliary_device);
+
+        let mut transitions = keyboard.transitions;
+        transitions.record(Transition::Join);
+        transitions.record(Transition::DisableDevices);
+
+        InitController::disable_devices_and_interrupts(DevicesDisabled {
+            port_io: keyboard.port_io,
+            config: None,
+            quirks: keyboard.quirks,
+            transitions,
+            unknown_bits: keyboard.unknown_bits,
+            tracer: keyboard.tracer,
+        })
+    }
+}
@@ -4,10 +4,16 @@ use crate::controller::{
     io::{PortIO, PortIOAvailable},
     raw::StatusRegister,
 };
+use crate::device::io::TransmissionError;
+use crate::trace::Tracer;
+
+use super::quirks::Quirks;
+use super::unknown_bits::UnknownBits;
 
 #[derive(Debug)]
 pub struct StatusInfo {
     register: StatusRegister,
+    controller_response_pending: bool,
 }
 
 #[derive(Debug)]
@@ -16,12 +22,30 @@ pub struct OddParity;
 #[derive(Debug)]
 pub struct EvenParity;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DataOwner {
     KeyboardOrCommandController,
     AuxiliaryDevice,
 }
 
+/// Data source attribution which also takes
+/// controller command responses into account.
+#[derive(Debug)]
+pub enum DataOrigin {
+    ControllerCommandResponse,
+    Keyboard,
+    AuxiliaryDevice,
+}
+
+/// Target of the last write to the controller input buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputBufferWrite {
+    /// Last write was to the command register.
+    ControllerCommand,
+    /// Last write was to the data port.
+    Data,
+}
+
 #[derive(Debug)]
 pub enum PasswordState {
     Active,
@@ -45,6 +69,17 @@ impl StatusInfo {
         self.register.contains(StatusRegister::GENERAL_TIMEOUT)
     }
 
+    /// Error which corrupted the byte in the output buffer.
+    pub fn transmission_error(&self) -> Option<TransmissionError> {
+        if self.general_timeout_error() {
+            Some(TransmissionError::Timeout)
+        } else if self.keyboard_data_parity().is_err() {
+            Some(TransmissionError::Parity)
+        } else {
+            None
+        }
+    }
+
     /// If `Some(_)` there is new data available to read from the controller.
     pub fn data_availability(&self) -> Option<DataOwner> {
         if self.register.contains(
@@ -59,11 +94,46 @@ impl StatusInfo {
         }
     }
 
+    /// If `Some(_)` there is new data available to read from the controller.
+    ///
+    /// Unlike `data_availability` this separates controller command
+    /// responses from keyboard data by using the `COMMAND_OR_DATA` bit
+    /// and the driver's pending controller command tracking.
+    pub fn data_origin(&self) -> Option<DataOrigin> {
+        match self.data_availability()? {
+            DataOwner::AuxiliaryDevice => Some(DataOrigin::AuxiliaryDevice),
+            DataOwner::KeyboardOrCommandController => {
+                if self.controller_response_pending
+                    && self.last_input_buffer_write() == InputBufferWrite::ControllerCommand
+                {
+                    Some(DataOrigin::ControllerCommandResponse)
+                } else {
+                    Some(DataOrigin::Keyboard)
+                }
+            }
+        }
+    }
+
+    /// If `true` then the driver has sent a controller command which
+    /// returns data and the response is not read yet.
+    pub fn controller_response_pending(&self) -> bool {
+        self.controller_response_pending
+    }
+
+    pub fn last_input_buffer_write(&self) -> InputBufferWrite {
+        if self.register.contains(StatusRegister::COMMAND_OR_DATA) {
+            InputBufferWrite::ControllerCommand
+        } else {
+            InputBufferWrite::Data
+        }
+    }
+
+    /// `INHIBIT_SWITCH` is set when the keyboard is not inhibited.
     pub fn password_state(&self) -> PasswordState {
         if self.register.contains(StatusRegister::INHIBIT_SWITCH) {
-            PasswordState::Active
-        } else {
             PasswordState::Inactive
+        } else {
+            PasswordState::Active
         }
     }
 
@@ -87,6 +157,31 @@ pub trait ReadStatus<T: PortIO>: PortIOAvailable<T> {
 
         StatusInfo {
             register: StatusRegister::from_bits_truncate(raw),
+            controller_response_pending: self.controller_response_pending(),
         }
     }
+
+    /// If `true` then there is a controller command response
+    /// which is not read yet from the data port.
+    fn controller_response_pending(&self) -> bool {
+        false
+    }
+
+    /// Update controller command response tracking.
+    fn set_controller_response_pending(&mut self, _pending: bool) {}
+
+    /// Tracer for controller events.
+    fn tracer(&self) -> Option<&Tracer> {
+        None
+    }
+
+    /// Controller chipset workarounds.
+    fn quirks(&self) -> Quirks {
+        Quirks::empty()
+    }
+
+    /// Records of unknown register bits.
+    fn unknown_bits_mut(&mut self) -> Option<&mut UnknownBits> {
+        None
+    }
 }
@@ -0,0 +1,1363 @@
+//! History of controller typestate transitions.
+//!
+//! `TRANSITION_GRAPH` lists every public method which changes the
+//! controller type. A method which moves the controller between
+//! states must have an edge in the table, and every `Transition`
+//! must be recorded by at least one edge. The second rule is checked
+//! at compile time. Tests exercise every edge and search the source
+//! for public methods which consume a controller type without an
+//! edge.
+//!
+//! The graph requires the `unstable` feature.
+
+use crate::ring_buffer::{FullPolicy, RingBuffer};
+
+#[cfg(feature = "unstable")]
+use ControllerState::*;
+
+/// Number of transitions which `TransitionLog` stores.
+pub const TRANSITION_LOG_LENGTH: usize = 8;
+
+/// Returns current time in user chosen unit. Timestamps are
+/// 32-bit to keep the controller types small.
+pub type TransitionClock = fn() -> u32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Transition {
+    /// `InitController::start_init`, `start_init_with_quirks` or
+    /// `InitSequence::new`.
+    Init = 1,
+    /// `InitController::start_init_with_config`.
+    InitWithConfig,
+    EnableDevices,
+    EnableDevicesAndInterrupts,
+    /// Interface test failed and devices were not enabled.
+    EnableFailed,
+    DisableDevices,
+    /// `EnabledDevices::join` or `DevicesDisabled::join`.
+    Join,
+    /// `EnabledDevices::shutdown`.
+    Shutdown,
+    /// `EnabledDevices::handoff`. Requires the `unstable` feature.
+    Handoff,
+    /// `InitController::resume_from_handoff` or
+    /// `resume_from_handoff_with_interrupts`. Requires the `unstable`
+    /// feature.
+    ResumeFromHandoff,
+    /// Interface test of one device failed and only the other
+    /// device is enabled.
+    EnablePartial,
+}
+
+impl Transition {
+    /// Every transition in declaration order.
+    pub const ALL: [Transition; 11] = [
+        Transition::Init,
+        Transition::InitWithConfig,
+        Transition::EnableDevices,
+        Transition::EnableDevicesAndInterrupts,
+        Transition::EnableFailed,
+        Transition::DisableDevices,
+        Transition::Join,
+        Transition::Shutdown,
+        Transition::Handoff,
+        Transition::ResumeFromHandoff,
+        Transition::EnablePartial,
+    ];
+
+    /// Edges which record this transition.
+    #[cfg(feature = "unstable")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "unstable")))]
+    pub fn edges(self) -> impl Iterator<Item = &'static TransitionEdge> {
+        TRANSITION_GRAPH
+            .iter()
+            .filter(move |edge| edge.records.contains(&self))
+    }
+
+    /// Match is exhaustive, so a new variant fails to compile here.
+    /// Add it to `ALL` and `TRANSITION_GRAPH` at the same time.
+    #[cfg(feature = "unstable")]
+    const fn index(self) -> usize {
+        match self {
+            Transition::Init
+            | Transition::InitWithConfig
+            | Transition::EnableDevices
+            | Transition::EnableDevicesAndInterrupts
+            | Transition::EnableFailed
+            | Transition::DisableDevices
+            | Transition::Join
+            | Transition::Shutdown
+            | Transition::Handoff
+            | Transition::ResumeFromHandoff
+            | Transition::EnablePartial => self as usize - 1,
+        }
+    }
+}
+
+#[cfg(feature = "unstable")]
+#[cfg_attr(docsrs, doc(cfg(feature = "unstable")))]
+/// Controller state in the typestate graph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControllerState {
+    /// Plain `PortIO`. Controller state is unknown to the driver.
+    PortIO,
+    /// `DevicesDisabled`.
+    DevicesDisabled,
+    /// `EnabledDevices<T, Disabled>` or `EnabledDevices<T, InterruptsEnabled>`.
+    EnabledDevices { interrupts: bool },
+    /// `KeyboardPort` and `AuxiliaryPort` from `EnabledDevices::split`.
+    Split { interrupts: bool },
+    /// `InitSequence<T, Disabled>` or `InitSequence<T, InterruptsEnabled>`.
+    InitSequence { interrupts: bool },
+}
+
+#[cfg(feature = "unstable")]
+#[cfg_attr(docsrs, doc(cfg(feature = "unstable")))]
+/// Public method which changes the controller state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransitionEdge {
+    pub method: &'static str,
+    pub from: ControllerState,
+    pub to: ControllerState,
+    /// Transitions which the method records in the `TransitionLog`.
+    pub records: &'static [Transition],
+    /// What must be true before the method is called.
+    pub precondition: &'static str,
+}
+
+#[cfg(feature = "unstable")]
+impl TransitionEdge {
+    const fn new(
+        method: &'static str,
+        from: ControllerState,
+        to: ControllerState,
+        records: &'static [Transition],
+        precondition: &'static str,
+    ) -> Self {
+        Self {
+            method,
+            from,
+            to,
+            records,
+            precondition,
+        }
+    }
+}
+
+#[cfg(feature = "unstable")]
+const ENABLED: ControllerState = EnabledDevices { interrupts: false };
+#[cfg(feature = "unstable")]
+const ENABLED_IRQ: ControllerState = EnabledDevices { interrupts: true };
+#[cfg(feature = "unstable")]
+const SPLIT: ControllerState = Split { interrupts: false };
+#[cfg(feature = "unstable")]
+const SPLIT_IRQ: ControllerState = Split { interrupts: true };
+#[cfg(feature = "unstable")]
+const INIT_SEQUENCE: ControllerState = InitSequence { interrupts: false };
+#[cfg(feature = "unstable")]
+const INIT_SEQUENCE_IRQ: ControllerState = InitSequence { interrupts: true };
+
+#[cfg(feature = "unstable")]
+const CPU_INTERRUPTS_DISABLED: &str = "CPU interrupts from the controller are disabled.";
+#[cfg(feature = "unstable")]
+const DEVICES_ENABLED: &str = "Devices are enabled.";
+#[cfg(feature = "unstable")]
+const INIT_SEQUENCE_PENDING: &str = "Controller has not finished the current step.";
+#[cfg(feature = "unstable")]
+const INIT_SEQUENCE_FAILED: &str =
+    "A step failed. Only interface test failures record `EnableFailed`.";
+
+#[cfg(feature = "unstable")]
+#[cfg_attr(docsrs, doc(cfg(feature = "unstable")))]
+/// Typestate transition graph. Methods which are generic over the
+/// interrupt marker have an edge for both markers.
+pub const TRANSITION_GRAPH: &[TransitionEdge] = &[
+    TransitionEdge::new(
+        "InitController::start_init",
+        PortIO,
+        DevicesDisabled,
+        &[Transition::Init],
+        CPU_INTERRUPTS_DISABLED,
+    ),
+    TransitionEdge::new(
+        "InitController::start_init_with_quirks",
+        PortIO,
+        DevicesDisabled,
+        &[Transition::Init],
+        CPU_INTERRUPTS_DISABLED,
+    ),
+    TransitionEdge::new(
+        "InitController::start_init_with_config",
+        PortIO,
+        DevicesDisabled,
+        &[Transition::InitWithConfig],
+        "CPU interrupts from the controller are disabled until devices are enabled.",
+    ),
+    TransitionEdge::new(
+        "InitControllerBuilder::start_init",
+        PortIO,
+        DevicesDisabled,
+        &[Transition::Init, Transition::InitWithConfig],
+        CPU_INTERRUPTS_DISABLED,
+    ),
+    TransitionEdge::new(
+        "InitSequence::new",
+        PortIO,
+        INIT_SEQUENCE,
+        &[Transition::Init],
+        CPU_INTERRUPTS_DISABLED,
+    ),
+    TransitionEdge::new(
+        "InitSequence::new_with_interrupts",
+        PortIO,
+        INIT_SEQUENCE_IRQ,
+        &[Transition::Init],
+        CPU_INTERRUPTS_DISABLED,
+    ),
+    TransitionEdge::new(
+        "InitSequence::poll",
+        INIT_SEQUENCE,
+        INIT_SEQUENCE,
+        &[],
+        INIT_SEQUENCE_PENDING,
+    ),
+    TransitionEdge::new(
+        "InitSequence::poll",
+        INIT_SEQUENCE_IRQ,
+        INIT_SEQUENCE_IRQ,
+        &[],
+        INIT_SEQUENCE_PENDING,
+    ),
+    TransitionEdge::new(
+        "InitSequence::poll",
+        INIT_SEQUENCE,
+        ENABLED,
+        &[Transition::EnableDevices],
+        "Every step finished.",
+    ),
+    TransitionEdge::new(
+        "InitSequence::poll",
+        INIT_SEQUENCE_IRQ,
+        ENABLED_IRQ,
+        &[Transition::EnableDevicesAndInterrupts],
+        "Every step finished.",
+    ),
+    TransitionEdge::new(
+        "InitSequence::poll",
+        INIT_SEQUENCE,
+        DevicesDisabled,
+        &[Transition::EnableFailed],
+        INIT_SEQUENCE_FAILED,
+    ),
+    TransitionEdge::new(
+        "InitSequence::poll",
+        INIT_SEQUENCE_IRQ,
+        DevicesDisabled,
+        &[Transition::EnableFailed],
+        INIT_SEQUENCE_FAILED,
+    ),
+    TransitionEdge::new(
+        "InitController::resume_from_handoff",
+        PortIO,
+        ENABLED,
+        &[Transition::ResumeFromHandoff, Transition::EnableDevices],
+        "Controller configuration matches `Handoff::config`.",
+    ),
+    TransitionEdge::new(
+        "InitController::resume_from_handoff_with_interrupts",
+        PortIO,
+        ENABLED_IRQ,
+        &[
+            Transition::ResumeFromHandoff,
+            Transition::EnableDevicesAndInterrupts,
+        ],
+        "Controller configuration matches `Handoff::config`.",
+    ),
+    TransitionEdge::new(
+        "InitController::resume_from_handoff",
+        PortIO,
+        DevicesDisabled,
+        &[Transition::Init],
+        "Controller configuration doesn't match `Handoff::config`.",
+    ),
+    TransitionEdge::new(
+        "InitController::resume_from_handoff_with_interrupts",
+        PortIO,
+        DevicesDisabled,
+        &[Transition::Init],
+        "Controller configuration doesn't match `Handoff::config`.",
+    ),
+    TransitionEdge::new(
+        "DevicesDisabled::enable_devices",
+        DevicesDisabled,
+        ENABLED,
+        &[Transition::EnableDevices],
+        "Interface tests of the devices passed.",
+    ),
+    TransitionEdge::new(
+        "DevicesDisabled::enable_devices_and_interrupts",
+        DevicesDisabled,
+        ENABLED_IRQ,
+        &[Transition::EnableDevicesAndInterrupts],
+        "Interface tests of the devices passed.",
+    ),
+    TransitionEdge::new(
+        "DevicesDisabled::enable_available_devices",
+        DevicesDisabled,
+        ENABLED,
+        &[Transition::EnablePartial, Transition::EnableDevices],
+        "Interface test of at least one device passed.",
+    ),
+    TransitionEdge::new(
+        "DevicesDisabled::enable_available_devices_and_interrupts",
+        DevicesDisabled,
+        ENABLED_IRQ,
+        &[
+            Transition::EnablePartial,
+            Transition::EnableDevicesAndInterrupts,
+        ],
+        "Interface test of at least one device passed.",
+    ),
+    TransitionEdge::new(
+        "DevicesDisabled::enable_devices",
+        DevicesDisabled,
+        DevicesDisabled,
+        &[Transition::EnableFailed],
+        "Interface test failed.",
+    ),
+    TransitionEdge::new(
+        "DevicesDisabled::enable_devices_and_interrupts",
+        DevicesDisabled,
+        DevicesDisabled,
+        &[Transition::EnableFailed],
+        "Interface test failed.",
+    ),
+    TransitionEdge::new(
+        "DevicesDisabled::enable_available_devices",
+        DevicesDisabled,
+        DevicesDisabled,
+        &[Transition::EnableFailed],
+        "Interface tests of all devices failed.",
+    ),
+    TransitionEdge::new(
+        "DevicesDisabled::enable_available_devices_and_interrupts",
+        DevicesDisabled,
+        DevicesDisabled,
+        &[Transition::EnableFailed],
+        "Interface tests of all devices failed.",
+    ),
+    TransitionEdge::new(
+        "DevicesDisabled::restore_controller_config",
+        DevicesDisabled,
+        PortIO,
+        &[],
+        "",
+    ),
+    TransitionEdge::new(
+        "EnabledDevices::disable_devices",
+        ENABLED,
+        DevicesDisabled,
+        &[Transition::DisableDevices],
+        "",
+    ),
+    TransitionEdge::new(
+        "EnabledDevices::disable_devices",
+        ENABLED_IRQ,
+        DevicesDisabled,
+        &[Transition::DisableDevices],
+        "CPU interrupts from the controller are disabled after the call.",
+    ),
+    TransitionEdge::new(
+        "EnabledDevices::split",
+        ENABLED,
+        SPLIT,
+        &[],
+        "Both devices are enabled and a stored auxiliary device data byte was written.",
+    ),
+    TransitionEdge::new(
+        "EnabledDevices::split",
+        ENABLED_IRQ,
+        SPLIT_IRQ,
+        &[],
+        "Both devices are enabled and a stored auxiliary device data byte was written.",
+    ),
+    TransitionEdge::new(
+        "EnabledDevices::join",
+        SPLIT,
+        ENABLED,
+        &[Transition::Join],
+        "",
+    ),
+    TransitionEdge::new(
+        "EnabledDevices::join",
+        SPLIT_IRQ,
+        ENABLED_IRQ,
+        &[Transition::Join],
+        "",
+    ),
+    TransitionEdge::new(
+        "DevicesDisabled::join",
+        SPLIT,
+        DevicesDisabled,
+        &[Transition::Join, Transition::DisableDevices],
+        "",
+    ),
+    TransitionEdge::new(
+        "DevicesDisabled::join",
+        SPLIT_IRQ,
+        DevicesDisabled,
+        &[Transition::Join, Transition::DisableDevices],
+        "CPU interrupts from the controller are disabled after the call.",
+    ),
+    TransitionEdge::new(
+        "EnabledDevices::shutdown",
+        ENABLED,
+        PortIO,
+        &[Transition::Shutdown],
+        DEVICES_ENABLED,
+    ),
+    TransitionEdge::new(
+        "EnabledDevices::shutdown",
+        ENABLED_IRQ,
+        PortIO,
+        &[Transition::Shutdown],
+        DEVICES_ENABLED,
+    ),
+    TransitionEdge::new(
+        "EnabledDevices::handoff",
+        ENABLED,
+        PortIO,
+        &[Transition::Handoff],
+        DEVICES_ENABLED,
+    ),
+    TransitionEdge::new(
+        "EnabledDevices::handoff",
+        ENABLED_IRQ,
+        PortIO,
+        &[Transition::Handoff],
+        DEVICES_ENABLED,
+    ),
+];
+
+#[cfg(feature = "unstable")]
+#[cfg_attr(docsrs, doc(cfg(feature = "unstable")))]
+/// Returns `true` if some method moves the controller from `from`
+/// to `to`.
+pub fn transition_allowed(from: ControllerState, to: ControllerState) -> bool {
+    TRANSITION_GRAPH
+        .iter()
+        .any(|edge| edge.from == from && edge.to == to)
+}
+
+#[cfg(feature = "unstable")]
+const fn graph_records_every_transition() -> bool {
+    let mut i = 0;
+    while i < Transition::ALL.len() {
+        let transition = Transition::ALL[i];
+        if transition.index() != i {
+            return false;
+        }
+
+        let mut found = false;
+        let mut j = 0;
+        while j < TRANSITION_GRAPH.len() {
+            let records = TRANSITION_GRAPH[j].records;
+            let mut k = 0;
+            while k < records.len() {
+                found |= records[k] as u8 == transition as u8;
+                k += 1;
+            }
+            j += 1;
+        }
+
+        if !found {
+            return false;
+        }
+        i += 1;
+    }
+
+    true
+}
+
+#[cfg(feature = "unstable")]
+const _: () = assert!(graph_records_every_transition());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransitionRecord {
+    pub transition: Transition,
+    /// Zero if there was no clock.
+    pub timestamp: u32,
+}
+
+/// Latest `TRANSITION_LOG_LENGTH` transitions. The log is kept
+/// when the controller state changes.
+#[derive(Debug)]
+pub struct TransitionLog {
+    records: RingBuffer<TransitionRecord, TRANSITION_LOG_LENGTH>,
+    clock: Option<TransitionClock>,
+}
+
+impl Default for TransitionLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TransitionLog {
+    pub fn new() -> Self {
+        Self {
+            records: RingBuffer::new(),
+            clock: None,
+        }
+    }
+
+    pub fn set_clock(&mut self, clock: Option<TransitionClock>) {
+        self.clock = clock;
+    }
+
+    /// Oldest transition first.
+    pub fn iter(&self) -> impl Iterator<Item = &TransitionRecord> {
+        self.records.iter()
+    }
+
+    pub fn last(&self) -> Option<&TransitionRecord> {
+        self.records.iter().last()
+    }
+
+    pub(crate) fn record(&mut self, transition: Transition) {
+        let record = TransitionRecord {
+            transition,
+            timestamp: self.clock.map_or(0, |clock| clock()),
+        };
+        let _ = self
+            .records
+            .push_back_with_policy(record, FullPolicy::OverwriteOldest);
+    }
+}
+
+#[cfg(all(test, feature = "testing", feature = "unstable"))]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    extern crate std;
+
+    use super::*;
+    use crate::controller::driver::{
+        config::ControllerConfig,
+        init_sequence::{InitPoll, InitSequence, InitSequenceError},
+        quirks::Quirks,
+        DevicesDisabled, EnableDevice, EnabledDevices, InitController,
+    };
+    use crate::controller::io::PortIO;
+    use crate::controller::raw::{
+        CommandReturnData, ControllerCommandByte, InterfaceTestResult, StatusRegister,
+    };
+    use crate::testing::Simulator;
+
+    use std::{cell::RefCell, rc::Rc, vec::Vec};
+
+    /// `Simulator` which implements `SplitPortIO` by cloning.
+    /// Interface tests in `failing_tests` report a low clock line.
+    /// Input buffer is full while `busy` is set. `busy_after_command`
+    /// sets `busy` when the next controller command is written.
+    #[derive(Debug, Clone)]
+    struct SharedSimulator {
+        simulator: Rc<RefCell<Simulator>>,
+        failing_tests: &'static [u8],
+        failing_response: Rc<RefCell<bool>>,
+        busy: Rc<RefCell<bool>>,
+        busy_after_command: Rc<RefCell<bool>>,
+    }
+
+    impl SharedSimulator {
+        fn new(failing_tests: &'static [u8]) -> Self {
+            Self {
+                simulator: Rc::new(RefCell::new(Simulator::new())),
+                failing_tests,
+                failing_response: Rc::new(RefCell::new(false)),
+                busy: Rc::new(RefCell::new(false)),
+                busy_after_command: Rc::new(RefCell::new(false)),
+            }
+        }
+
+        fn set_busy(&self, busy: bool) {
+            *self.busy.borrow_mut() = busy;
+        }
+
+        fn set_busy_after_command(&self) {
+            *self.busy_after_command.borrow_mut() = true;
+        }
+    }
+
+    impl PortIO for SharedSimulator {
+        type PortID = u16;
+
+        const DATA_PORT: Self::PortID = Simulator::DATA_PORT;
+        const STATUS_REGISTER: Self::PortID = Simulator::STATUS_REGISTER;
+        const COMMAND_REGISTER: Self::PortID = Simulator::COMMAND_REGISTER;
+
+        fn read(&mut self, port: Self::PortID) -> u8 {
+            let value = self.simulator.borrow_mut().read(port);
+            if port == Self::DATA_PORT && self.failing_response.replace(false) {
+                InterfaceTestResult::CLOCK_LINE_LOW
+            } else if port == Self::STATUS_REGISTER && *self.busy.borrow() {
+                value | StatusRegister::INPUT_BUFFER_FULL.bits()
+            } else {
+                value
+            }
+        }
+
+        fn write(&mut self, port: Self::PortID, data: u8) {
+            if port == Self::COMMAND_REGISTER {
+                *self.failing_response.borrow_mut() = self.failing_tests.contains(&data);
+                if self.busy_after_command.replace(false) {
+                    self.set_busy(true);
+                }
+            }
+            self.simulator.borrow_mut().write(port, data)
+        }
+    }
+
+    const KEYBOARD_TEST: &[u8] = &[CommandReturnData::KEYBOARD_INTERFACE_TEST];
+    const AUXILIARY_DEVICE_TEST: &[u8] = &[CommandReturnData::AUXILIARY_DEVICE_INTERFACE_TEST];
+    const BOTH_TESTS: &[u8] = &[
+        CommandReturnData::KEYBOARD_INTERFACE_TEST,
+        CommandReturnData::AUXILIARY_DEVICE_INTERFACE_TEST,
+    ];
+
+    fn transitions(log: &TransitionLog) -> Vec<Transition> {
+        log.iter().map(|record| record.transition).collect()
+    }
+
+    /// Edges which a scenario has exercised.
+    type Checked = Vec<&'static TransitionEdge>;
+
+    /// Mark the edge of `method` from `from` to `to` as exercised
+    /// when its records can't be read after the call.
+    fn exercised(
+        checked: &mut Checked,
+        method: &str,
+        from: ControllerState,
+        to: ControllerState,
+    ) -> &'static TransitionEdge {
+        let edge = TRANSITION_GRAPH
+            .iter()
+            .find(|edge| edge.method == method && edge.from == from && edge.to == to)
+            .unwrap_or_else(|| panic!("no edge {} from {:?} to {:?}", method, from, to));
+        checked.push(edge);
+        edge
+    }
+
+    /// Transitions recorded after the first `before` records must
+    /// be recorded by the edge of `method` from `from` to `to`.
+    fn check_edge(
+        checked: &mut Checked,
+        method: &str,
+        from: ControllerState,
+        to: ControllerState,
+        log: &TransitionLog,
+        before: usize,
+    ) {
+        let edge = exercised(checked, method, from, to);
+
+        let recorded = &transitions(log)[before..];
+        assert_eq!(recorded.is_empty(), edge.records.is_empty(), "{}", method);
+        for transition in recorded {
+            assert!(
+                edge.records.contains(transition),
+                "{} recorded {:?}",
+                method,
+                transition
+            );
+        }
+    }
+
+    fn init_methods(checked: &mut Checked) {
+        let controller = InitController::start_init(Simulator::new());
+        check_edge(
+            checked,
+            "InitController::start_init",
+            PortIO,
+            DevicesDisabled,
+            controller.transitions(),
+            0,
+        );
+
+        let controller = InitController::start_init_with_quirks(Simulator::new(), Quirks::empty());
+        check_edge(
+            checked,
+            "InitController::start_init_with_quirks",
+            PortIO,
+            DevicesDisabled,
+            controller.transitions(),
+            0,
+        );
+
+        let (controller, _) =
+            InitController::start_init_with_config(Simulator::new(), ControllerConfig::from_raw(0));
+        check_edge(
+            checked,
+            "InitController::start_init_with_config",
+            PortIO,
+            DevicesDisabled,
+            controller.transitions(),
+            0,
+        );
+
+        for &config in &[None, Some(ControllerConfig::from_raw(0))] {
+            let mut builder = InitController::builder(Simulator::new());
+            if let Some(config) = config {
+                builder = builder.controller_config(config);
+            }
+            let (controller, _) = builder.start_init();
+            check_edge(
+                checked,
+                "InitControllerBuilder::start_init",
+                PortIO,
+                DevicesDisabled,
+                controller.transitions(),
+                0,
+            );
+        }
+    }
+
+    fn enable_and_disable(checked: &mut Checked) {
+        let controller = InitController::start_init(Simulator::new());
+        let controller = controller.enable_devices(EnableDevice::Keyboard).unwrap();
+        check_edge(
+            checked,
+            "DevicesDisabled::enable_devices",
+            DevicesDisabled,
+            ENABLED,
+            controller.transitions(),
+            1,
+        );
+
+        let controller = controller.disable_devices();
+        check_edge(
+            checked,
+            "EnabledDevices::disable_devices",
+            ENABLED,
+            DevicesDisabled,
+            controller.transitions(),
+            2,
+        );
+
+        let controller = controller
+            .enable_devices_and_interrupts(EnableDevice::Keyboard)
+            .unwrap();
+        check_edge(
+            checked,
+            "DevicesDisabled::enable_devices_and_interrupts",
+            DevicesDisabled,
+            ENABLED_IRQ,
+            controller.transitions(),
+            3,
+        );
+
+        let controller = controller.disable_devices();
+        check_edge(
+            checked,
+            "EnabledDevices::disable_devices",
+            ENABLED_IRQ,
+            DevicesDisabled,
+            controller.transitions(),
+            4,
+        );
+    }
+
+    fn failed_interface_tests(checked: &mut Checked) {
+        let controller = InitController::start_init(SharedSimulator::new(KEYBOARD_TEST));
+        let (controller, _) = controller
+            .enable_devices(EnableDevice::Keyboard)
+            .unwrap_err();
+        check_edge(
+            checked,
+            "DevicesDisabled::enable_devices",
+            DevicesDisabled,
+            DevicesDisabled,
+            controller.transitions(),
+            1,
+        );
+
+        let (controller, _) = controller
+            .enable_devices_and_interrupts(EnableDevice::Keyboard)
+            .unwrap_err();
+        check_edge(
+            checked,
+            "DevicesDisabled::enable_devices_and_interrupts",
+            DevicesDisabled,
+            DevicesDisabled,
+            controller.transitions(),
+            2,
+        );
+
+        let controller = InitController::start_init(SharedSimulator::new(BOTH_TESTS));
+        let (controller, _) = controller
+            .enable_available_devices(EnableDevice::KeyboardAndAuxiliaryDevice)
+            .unwrap_err();
+        check_edge(
+            checked,
+            "DevicesDisabled::enable_available_devices",
+            DevicesDisabled,
+            DevicesDisabled,
+            controller.transitions(),
+            1,
+        );
+
+        let (controller, _) = controller
+            .enable_available_devices_and_interrupts(EnableDevice::KeyboardAndAuxiliaryDevice)
+            .unwrap_err();
+        check_edge(
+            checked,
+            "DevicesDisabled::enable_available_devices_and_interrupts",
+            DevicesDisabled,
+            DevicesDisabled,
+            controller.transitions(),
+            2,
+        );
+    }
+
+    fn partial_enable(checked: &mut Checked) {
+        let controller = InitController::start_init(SharedSimulator::new(AUXILIARY_DEVICE_TEST));
+        let available = controller
+            .enable_available_devices(EnableDevice::KeyboardAndAuxiliaryDevice)
+            .unwrap();
+        assert!(available.failure.is_some());
+        check_edge(
+            checked,
+            "DevicesDisabled::enable_available_devices",
+            DevicesDisabled,
+            ENABLED,
+            available.controller.transitions(),
+            1,
+        );
+        assert_eq!(
+            transitions(available.controller.transitions()),
+            [
+                Transition::Init,
+                Transition::EnablePartial,
+                Transition::EnableDevices
+            ]
+        );
+
+        let controller = available.controller.disable_devices();
+        let available = controller
+            .enable_available_devices_and_interrupts(EnableDevice::KeyboardAndAuxiliaryDevice)
+            .unwrap();
+        check_edge(
+            checked,
+            "DevicesDisabled::enable_available_devices_and_interrupts",
+            DevicesDisabled,
+            ENABLED_IRQ,
+            available.controller.transitions(),
+            4,
+        );
+    }
+
+    fn split_and_join(checked: &mut Checked) {
+        let controller = InitController::start_init(SharedSimulator::new(&[]))
+            .enable_devices(EnableDevice::KeyboardAndAuxiliaryDevice)
+            .unwrap();
+        let controller = split_and_join_with(controller, ENABLED, SPLIT, checked);
+
+        let controller = controller
+            .disable_devices()
+            .enable_devices_and_interrupts(EnableDevice::KeyboardAndAuxiliaryDevice)
+            .unwrap();
+        split_and_join_with(controller, ENABLED_IRQ, SPLIT_IRQ, checked);
+    }
+
+    fn join_to_devices_disabled(checked: &mut Checked) {
+        let controller = InitController::start_init(SharedSimulator::new(&[]))
+            .enable_devices(EnableDevice::KeyboardAndAuxiliaryDevice)
+            .unwrap();
+        let before = transitions(controller.transitions()).len();
+        let (keyboard, auxiliary_device) = controller.split().unwrap();
+        let controller = DevicesDisabled::join(keyboard, auxiliary_device);
+        check_edge(
+            checked,
+            "DevicesDisabled::join",
+            SPLIT,
+            DevicesDisabled,
+            controller.transitions(),
+            before,
+        );
+
+        let controller = controller
+            .enable_devices_and_interrupts(EnableDevice::KeyboardAndAuxiliaryDevice)
+            .unwrap();
+        let before = transitions(controller.transitions()).len();
+        let (keyboard, auxiliary_device) = controller.split().unwrap();
+        let controller = DevicesDisabled::join(keyboard, auxiliary_device);
+        check_edge(
+            checked,
+            "DevicesDisabled::join",
+            SPLIT_IRQ,
+            DevicesDisabled,
+            controller.transitions(),
+            before,
+        );
+    }
+
+    /// Split fails without waiting while the auxiliary device data
+    /// byte can't be written and the handles don't wait for
+    /// the controller.
+    #[test]
+    fn split_ports_do_not_block() {
+        let simulator = SharedSimulator::new(&[]);
+        let mut controller = InitController::start_init(simulator.clone())
+            .enable_devices(EnableDevice::KeyboardAndAuxiliaryDevice)
+            .unwrap();
+
+        // The command is written, but the data byte is stored.
+        simulator.set_busy_after_command();
+        controller.try_send_to_auxiliary_device(0xF4).unwrap();
+        controller.flush().unwrap_err();
+        let mut controller = controller.split().unwrap_err();
+        simulator.set_busy(false);
+        controller.flush().unwrap();
+
+        let (mut keyboard, mut auxiliary_device) = controller.split().unwrap();
+        simulator.set_busy(true);
+        keyboard.send_to_keyboard(0xEE).unwrap_err();
+        auxiliary_device.send_to_auxiliary_device(0xF4).unwrap_err();
+        simulator.set_busy(false);
+        keyboard.send_to_keyboard(0xEE).unwrap();
+        auxiliary_device.send_to_auxiliary_device(0xF4).unwrap();
+    }
+
+    fn split_and_join_with<IRQ: core::fmt::Debug>(
+        controller: EnabledDevices<SharedSimulator, IRQ>,
+        enabled: ControllerState,
+        split: ControllerState,
+        checked: &mut Checked,
+    ) -> EnabledDevices<SharedSimulator, IRQ> {
+        let before = transitions(controller.transitions()).len();
+
+        // Split ports don't expose the log, so the records of both
+        // edges are checked after the join.
+        let (keyboard, auxiliary_device) = controller.split().unwrap();
+        let controller = EnabledDevices::join(keyboard, auxiliary_device);
+        assert_eq!(
+            transitions(controller.transitions())[before..],
+            [Transition::Join]
+        );
+        check_edge(
+            checked,
+            "EnabledDevices::split",
+            enabled,
+            split,
+            controller.transitions(),
+            before + 1,
+        );
+        check_edge(
+            checked,
+            "EnabledDevices::join",
+            split,
+            enabled,
+            controller.transitions(),
+            before,
+        );
+        controller
+    }
+
+    fn resume_from_handoff(checked: &mut Checked) {
+        // Handoff and shutdown release the `PortIO`, so their records
+        // can't be read afterwards.
+        let controller = InitController::start_init(Simulator::new())
+            .enable_devices(EnableDevice::Keyboard)
+            .unwrap();
+        let (port_io, handoff) = controller.handoff();
+        exercised(checked, "EnabledDevices::handoff", ENABLED, PortIO);
+        let handoff = handoff.unwrap();
+
+        let controller = InitController::resume_from_handoff(port_io, &handoff).unwrap();
+        check_edge(
+            checked,
+            "InitController::resume_from_handoff",
+            PortIO,
+            ENABLED,
+            controller.transitions(),
+            0,
+        );
+
+        let (port_io, handoff) = controller.handoff();
+        exercised(checked, "EnabledDevices::handoff", ENABLED, PortIO);
+        let handoff = handoff.unwrap();
+        let controller =
+            InitController::resume_from_handoff_with_interrupts(port_io, &handoff).unwrap();
+        check_edge(
+            checked,
+            "InitController::resume_from_handoff_with_interrupts",
+            PortIO,
+            ENABLED_IRQ,
+            controller.transitions(),
+            0,
+        );
+
+        let (port_io, handoff) = controller.handoff();
+        exercised(checked, "EnabledDevices::handoff", ENABLED_IRQ, PortIO);
+        let mut handoff = handoff.unwrap();
+        let translation = handoff
+            .config
+            .enabled(ControllerCommandByte::KEYBOARD_TRANSLATE_MODE);
+        handoff.config = handoff.config.translation(!translation);
+        let (controller, _) = InitController::resume_from_handoff(port_io, &handoff).unwrap_err();
+        check_edge(
+            checked,
+            "InitController::resume_from_handoff",
+            PortIO,
+            DevicesDisabled,
+            controller.transitions(),
+            0,
+        );
+
+        let port_io = controller.restore_controller_config(ControllerConfig::from_raw(0));
+        exercised(
+            checked,
+            "DevicesDisabled::restore_controller_config",
+            DevicesDisabled,
+            PortIO,
+        );
+        let (controller, _) =
+            InitController::resume_from_handoff_with_interrupts(port_io, &handoff).unwrap_err();
+        check_edge(
+            checked,
+            "InitController::resume_from_handoff_with_interrupts",
+            PortIO,
+            DevicesDisabled,
+            controller.transitions(),
+            0,
+        );
+    }
+
+    fn shutdown(checked: &mut Checked) {
+        let controller = InitController::start_init(Simulator::new())
+            .enable_devices(EnableDevice::Keyboard)
+            .unwrap();
+        let (port_io, _) = controller.shutdown(ControllerConfig::from_raw(0));
+        exercised(checked, "EnabledDevices::shutdown", ENABLED, PortIO);
+
+        let controller = InitController::start_init(port_io)
+            .enable_devices_and_interrupts(EnableDevice::Keyboard)
+            .unwrap();
+        let _ = controller.shutdown(ControllerConfig::from_raw(0));
+        exercised(checked, "EnabledDevices::shutdown", ENABLED_IRQ, PortIO);
+    }
+
+    fn init_sequence(checked: &mut Checked) {
+        let mut sequence = InitSequence::new(Simulator::new(), EnableDevice::Keyboard);
+        check_edge(
+            checked,
+            "InitSequence::new",
+            PortIO,
+            INIT_SEQUENCE,
+            sequence.controller_mut().transitions(),
+            0,
+        );
+        let controller = loop {
+            match sequence.poll() {
+                InitPoll::Pending(mut pending) => {
+                    check_edge(
+                        checked,
+                        "InitSequence::poll",
+                        INIT_SEQUENCE,
+                        INIT_SEQUENCE,
+                        pending.controller_mut().transitions(),
+                        1,
+                    );
+                    sequence = pending;
+                }
+                InitPoll::Ready(controller) => break controller,
+                InitPoll::Failed(_, e) => panic!("{:?}", e),
+            }
+        };
+        check_edge(
+            checked,
+            "InitSequence::poll",
+            INIT_SEQUENCE,
+            ENABLED,
+            controller.transitions(),
+            1,
+        );
+
+        let mut sequence =
+            InitSequence::new_with_interrupts(Simulator::new(), EnableDevice::Keyboard);
+        check_edge(
+            checked,
+            "InitSequence::new_with_interrupts",
+            PortIO,
+            INIT_SEQUENCE_IRQ,
+            sequence.controller_mut().transitions(),
+            0,
+        );
+        let controller = loop {
+            match sequence.poll() {
+                InitPoll::Pending(mut pending) => {
+                    check_edge(
+                        checked,
+                        "InitSequence::poll",
+                        INIT_SEQUENCE_IRQ,
+                        INIT_SEQUENCE_IRQ,
+                        pending.controller_mut().transitions(),
+                        1,
+                    );
+                    sequence = pending;
+                }
+                InitPoll::Ready(controller) => break controller,
+                InitPoll::Failed(_, e) => panic!("{:?}", e),
+            }
+        };
+        check_edge(
+            checked,
+            "InitSequence::poll",
+            INIT_SEQUENCE_IRQ,
+            ENABLED_IRQ,
+            controller.transitions(),
+            1,
+        );
+
+        let sequence =
+            InitSequence::new(SharedSimulator::new(KEYBOARD_TEST), EnableDevice::Keyboard);
+        let controller = init_sequence_failure(sequence);
+        check_edge(
+            checked,
+            "InitSequence::poll",
+            INIT_SEQUENCE,
+            DevicesDisabled,
+            controller.transitions(),
+            1,
+        );
+
+        let sequence = InitSequence::new_with_interrupts(
+            SharedSimulator::new(KEYBOARD_TEST),
+            EnableDevice::Keyboard,
+        );
+        let controller = init_sequence_failure(sequence);
+        check_edge(
+            checked,
+            "InitSequence::poll",
+            INIT_SEQUENCE_IRQ,
+            DevicesDisabled,
+            controller.transitions(),
+            1,
+        );
+    }
+
+    fn init_sequence_failure<IRQ: core::fmt::Debug>(
+        mut sequence: InitSequence<SharedSimulator, IRQ>,
+    ) -> DevicesDisabled<SharedSimulator> {
+        loop {
+            match sequence.poll() {
+                InitPoll::Pending(pending) => sequence = pending,
+                InitPoll::Ready(_) => panic!("interface test passed"),
+                InitPoll::Failed(controller, e) => {
+                    assert!(matches!(e, InitSequenceError::Interface(_)), "{:?}", e);
+                    break controller;
+                }
+            }
+        }
+    }
+
+    const SCENARIOS: &[fn(&mut Checked)] = &[
+        init_methods,
+        enable_and_disable,
+        failed_interface_tests,
+        partial_enable,
+        split_and_join,
+        join_to_devices_disabled,
+        resume_from_handoff,
+        shutdown,
+        init_sequence,
+    ];
+
+    #[test]
+    fn init_methods_record_their_edges() {
+        init_methods(&mut Vec::new());
+    }
+
+    #[test]
+    fn enable_and_disable_record_their_edges() {
+        enable_and_disable(&mut Vec::new());
+    }
+
+    #[test]
+    fn failed_interface_tests_record_their_edges() {
+        failed_interface_tests(&mut Vec::new());
+    }
+
+    #[test]
+    fn partial_enable_records_its_edges() {
+        partial_enable(&mut Vec::new());
+    }
+
+    #[test]
+    fn split_and_join_record_their_edges() {
+        split_and_join(&mut Vec::new());
+    }
+
+    #[test]
+    fn join_to_devices_disabled_records_its_edges() {
+        join_to_devices_disabled(&mut Vec::new());
+    }
+
+    #[test]
+    fn resume_from_handoff_records_its_edges() {
+        resume_from_handoff(&mut Vec::new());
+    }
+
+    #[test]
+    fn shutdown_exercises_its_edges() {
+        shutdown(&mut Vec::new());
+    }
+
+    #[test]
+    fn init_sequence_records_its_edges() {
+        init_sequence(&mut Vec::new());
+    }
+
+    #[test]
+    fn every_edge_is_exercised() {
+        let mut checked = Vec::new();
+        for scenario in SCENARIOS {
+            scenario(&mut checked);
+        }
+
+        for edge in TRANSITION_GRAPH {
+            assert!(
+                checked.contains(&edge),
+                "no scenario exercises {} from {:?} to {:?}",
+                edge.method,
+                edge.from,
+                edge.to
+            );
+        }
+    }
+
+    /// Files with inherent impls of `TYPESTATE_TYPES`.
+    const SOURCES: &[&str] = &[
+        include_str!("../driver.rs"),
+        include_str!("builder.rs"),
+        include_str!("diagnostics.rs"),
+        include_str!("handoff.rs"),
+        include_str!("init_sequence.rs"),
+        include_str!("shutdown.rs"),
+        include_str!("split.rs"),
+    ];
+
+    const TYPESTATE_TYPES: &[&str] = &[
+        "InitController",
+        "InitControllerBuilder",
+        "InitSequence",
+        "DevicesDisabled",
+        "EnabledDevices",
+        "KeyboardPort",
+        "AuxiliaryPort",
+    ];
+
+    /// Methods which take the controller or `PortIO` by value but
+    /// don't return another controller state.
+    const NOT_TRANSITIONS: &[&str] = &[
+        "InitController::builder",
+        "InitControllerBuilder::controller_config",
+        "InitControllerBuilder::quirks",
+        "InitControllerBuilder::parsing_mode",
+        "InitControllerBuilder::translation_mode",
+        "InitControllerBuilder::trace_hook",
+    ];
+
+    /// Public methods of `TYPESTATE_TYPES` which take `self`, a
+    /// `PortIO` or split ports by value. This is a text search, so
+    /// a new file with impls of these types must be added to
+    /// `SOURCES`.
+    fn consuming_methods() -> Vec<std::string::String> {
+        let mut methods = Vec::new();
+        for source in SOURCES {
+            let mut impl_type = None;
+            let mut lines = source.lines();
+            while let Some(line) = lines.next() {
+                if line.starts_with("impl<") || line.starts_with("impl ") {
+                    impl_type = impl_type_name(line)
+                        .filter(|name| TYPESTATE_TYPES.contains(name))
+                        .filter(|_| !line.contains(" for "));
+                } else if line.starts_with('}') {
+                    impl_type = None;
+                }
+
+                let (impl_type, signature) = match (impl_type, line.strip_prefix("    pub fn ")) {
+                    (Some(impl_type), Some(signature)) => (impl_type, signature),
+                    _ => continue,
+                };
+                let mut signature = std::string::String::from(signature);
+                while !signature.contains('{') && !signature.ends_with(';') {
+                    signature.push_str(lines.next().unwrap().trim());
+                }
+
+                let consumes = signature.contains("(self")
+                    || signature.contains("(mut self")
+                    || signature.contains(": T,")
+                    || signature.contains(": T)")
+                    || signature.contains(": KeyboardPort<");
+                if consumes {
+                    let name = signature.split(['(', '<']).next().unwrap();
+                    methods.push(std::format!("{}::{}", impl_type, name));
+                }
+            }
+        }
+        methods
+    }
+
+    /// Type name of an impl block header such as
+    /// `impl<T: PortIO, IRQ> EnabledDevices<T, IRQ> {`.
+    fn impl_type_name(line: &str) -> Option<&str> {
+        let mut rest = line.strip_prefix("impl")?;
+        if rest.starts_with('<') {
+            let mut depth = 0;
+            let end = rest.char_indices().find_map(|(i, c)| {
+                match c {
+                    '<' => depth += 1,
+                    '>' => depth -= 1,
+                    _ => (),
+                }
+                if depth == 0 {
+                    Some(i)
+                } else {
+                    None
+                }
+            })?;
+            rest = &rest[end + 1..];
+        }
+        rest.trim_start()
+            .split(|c: char| !c.is_alphanumeric() && c != '_')
+            .next()
+    }
+
+    #[test]
+    fn every_consuming_method_has_an_edge() {
+        let methods = consuming_methods();
+
+        for method in &methods {
+            assert!(
+                TRANSITION_GRAPH.iter().any(|edge| edge.method == method)
+                    || NOT_TRANSITIONS.contains(&method.as_str()),
+                "{} has no edge in TRANSITION_GRAPH",
+                method
+            );
+        }
+
+        // Keep the lists in sync with the source.
+        for method in TRANSITION_GRAPH
+            .iter()
+            .map(|edge| edge.method)
+            .chain(NOT_TRANSITIONS.iter().copied())
+        {
+            assert!(
+                methods.iter().any(|found| found == method),
+                "{} was not found in the source",
+                method
+            );
+        }
+    }
+}
@@ -0,0 +1,90 @@
+//! Tracking of register bits which the crate does not define.
+//!
+//! Register values are converted to bitflags with
+//! `from_bits_truncate`, which drops unknown bits. Unknown bits may
+//! reveal chipset specific features or a misread port, so with
+//! `ParsingMode::Strict` they are counted per register.
+
+/// Default is `Truncate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ParsingMode {
+    /// Drop unknown bits.
+    #[default]
+    Truncate,
+    /// Drop unknown bits and record them in `UnknownBits`.
+    Strict,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnknownBitsSource {
+    CommandByte,
+    InputPort,
+    TestInputs,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct UnknownBitsRecord {
+    /// Count of reads which had unknown bits. Saturates at
+    /// `u16::MAX`.
+    pub count: u16,
+    /// Raw value of the latest read which had unknown bits.
+    pub last: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct UnknownBits {
+    mode: ParsingMode,
+    command_byte: UnknownBitsRecord,
+    input_port: UnknownBitsRecord,
+    test_inputs: UnknownBitsRecord,
+}
+
+impl UnknownBits {
+    pub const fn new() -> Self {
+        Self {
+            mode: ParsingMode::Truncate,
+            command_byte: UnknownBitsRecord { count: 0, last: 0 },
+            input_port: UnknownBitsRecord { count: 0, last: 0 },
+            test_inputs: UnknownBitsRecord { count: 0, last: 0 },
+        }
+    }
+
+    pub fn mode(&self) -> ParsingMode {
+        self.mode
+    }
+
+    /// Records are kept when the mode changes.
+    pub fn set_mode(&mut self, mode: ParsingMode) {
+        self.mode = mode;
+    }
+
+    pub fn get(&self, source: UnknownBitsSource) -> UnknownBitsRecord {
+        match source {
+            UnknownBitsSource::CommandByte => self.command_byte,
+            UnknownBitsSource::InputPort => self.input_port,
+            UnknownBitsSource::TestInputs => self.test_inputs,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self {
+            mode: self.mode,
+            ..Self::new()
+        };
+    }
+
+    /// Record `raw` if mode is strict and it has bits outside `known`.
+    pub(crate) fn check(&mut self, source: UnknownBitsSource, raw: u8, known: u8) {
+        if self.mode != ParsingMode::Strict || raw & !known == 0 {
+            return;
+        }
+
+        let record = match source {
+            UnknownBitsSource::CommandByte => &mut self.command_byte,
+            UnknownBitsSource::InputPort => &mut self.input_port,
+            UnknownBitsSource::TestInputs => &mut self.test_inputs,
+        };
+        record.count = record.count.saturating_add(1);
+        record.last = raw;
+    }
+}
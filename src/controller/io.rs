@@ -14,6 +14,23 @@ pub trait PortIO {
     fn write(&mut self, port: Self::PortID, data: u8);
 }
 
+/// Port access for the second port handle of `EnabledDevices::split`.
+///
+/// Both handles access the same ports. `EnabledDevices::join` drops
+/// the access which `split_port_io` created. Types which implement
+/// `Clone` implement this by cloning.
+#[cfg(feature = "aux-device")]
+pub trait SplitPortIO: PortIO + Sized {
+    fn split_port_io(&mut self) -> Self;
+}
+
+#[cfg(feature = "aux-device")]
+impl<T: PortIO + Clone> SplitPortIO for T {
+    fn split_port_io(&mut self) -> Self {
+        self.clone()
+    }
+}
+
 pub trait PortIOAvailable<T: PortIO> {
     fn port_io_mut(&mut self) -> &mut T;
 }
@@ -22,7 +39,7 @@ macro_rules! impl_port_io_available {
     (<T: PortIO> $type:ty) => {
         impl<T: PortIO> crate::controller::io::PortIOAvailable<T> for $type {
             fn port_io_mut(&mut self) -> &mut T {
-                &mut self.0
+                &mut self.port_io
             }
         }
     };
@@ -33,8 +50,10 @@ macro_rules! impl_port_io_available {
             }
         }
     };
-    (<T: PortIO, U: PortIOAvailable<T>> $type:ty) => {
-        impl<T: PortIO, U: PortIOAvailable<T>> crate::controller::io::PortIOAvailable<T> for $type {
+    (<T: PortIO, U: ReadStatus<T>> $type:ty) => {
+        impl<T: PortIO, U: crate::controller::driver::status::ReadStatus<T>>
+            crate::controller::io::PortIOAvailable<T> for $type
+        {
             fn port_io_mut(&mut self) -> &mut T {
                 self.1.port_io_mut()
             }
@@ -5,6 +5,8 @@ bitflags! {
         const KEYBOARD_PARITY_ERROR = 0b1000_0000;
         const GENERAL_TIMEOUT = 0b0100_0000;
         const AUXILIARY_DEVICE_OUTPUT_BUFFER_FULL = 0b0010_0000;
+        /// Cleared when the keyboard is inhibited, for example
+        /// by the controller password.
         const INHIBIT_SWITCH = 0b0001_0000;
         const COMMAND_OR_DATA = 0b0000_1000;
         const SYSTEM_FLAG = 0b0000_0100;
@@ -34,6 +36,9 @@ impl Command {
 
     pub const PULSE_OUTPUT_PORT_START: u8 = 0xF0;
     pub const PULSE_OUTPUT_PORT_END: u8 = 0xFF;
+    /// Pulse only output port bit `RESET_MICROPROCESSOR`. Cleared
+    /// bits of the low nibble are pulsed.
+    pub const PULSE_OUTPUT_PORT_RESET: u8 = Self::PULSE_OUTPUT_PORT_START | 0b0000_1110;
 }
 
 #[derive(Debug)]
@@ -71,6 +76,136 @@ impl CommandWaitData {
     pub const WRITE_KEYBOARD_OUTPUT_BUFFER: u8 = 0xD2;
     pub const WRITE_AUXILIARY_DEVICE_OUTPUT_BUFFER: u8 = 0xD3;
     pub const WRITE_TO_AUXILIARY_DEVICE: u8 = 0xD4;
+
+    /// Write to auxiliary device port 0 when active
+    /// multiplexing is enabled.
+    pub const WRITE_TO_MULTIPLEXED_AUXILIARY_DEVICE_START: u8 = 0x90;
+    /// Write to auxiliary device port 3 when active
+    /// multiplexing is enabled.
+    pub const WRITE_TO_MULTIPLEXED_AUXILIARY_DEVICE_END: u8 = 0x93;
+}
+
+/// Names of the controller command constants except command
+/// ranges. See `name_of_controller_command`.
+pub const CONTROLLER_COMMAND_NAMES: &[(u8, &str)] = name_table! {
+    "" Command {
+        ENABLE_PASSWORD,
+        DISABLE_AUXILIARY_DEVICE_INTERFACE,
+        ENABLE_AUXILIARY_DEVICE_INTERFACE,
+        DISABLE_KEYBOARD_INTERFACE,
+        ENABLE_KEYBOARD_INTERFACE,
+        POLL_INPUT_PORT_LOW,
+        POLL_INPUT_PORT_HIGH,
+        PULSE_OUTPUT_PORT_RESET,
+    }
+    "" CommandReturnData {
+        READ_CONTROLLER_COMMAND_BYTE,
+        TEST_PASSWORD_INSTALLED,
+        AUXILIARY_DEVICE_INTERFACE_TEST,
+        SELF_TEST,
+        KEYBOARD_INTERFACE_TEST,
+        READ_INPUT_PORT,
+        READ_OUTPUT_PORT,
+        READ_TEST_INPUTS,
+    }
+    "" CommandWaitData {
+        WRITE_CONTROLLER_COMMAND_BYTE,
+        LOAD_PASSWORD,
+        WRITE_OUTPUT_PORT,
+        WRITE_KEYBOARD_OUTPUT_BUFFER,
+        WRITE_AUXILIARY_DEVICE_OUTPUT_BUFFER,
+        WRITE_TO_AUXILIARY_DEVICE,
+    }
+};
+
+/// Controller command ranges as `(first, last, name)`.
+pub const CONTROLLER_COMMAND_RANGE_NAMES: &[(u8, u8, &str)] = &[
+    (
+        CommandReturnData::READ_RAM_START,
+        CommandReturnData::READ_RAM_END,
+        "READ_RAM",
+    ),
+    (
+        CommandWaitData::WRITE_RAM_START,
+        CommandWaitData::WRITE_RAM_END,
+        "WRITE_RAM",
+    ),
+    (
+        CommandWaitData::WRITE_TO_MULTIPLEXED_AUXILIARY_DEVICE_START,
+        CommandWaitData::WRITE_TO_MULTIPLEXED_AUXILIARY_DEVICE_END,
+        "WRITE_TO_MULTIPLEXED_AUXILIARY_DEVICE",
+    ),
+    (
+        Command::PULSE_OUTPUT_PORT_START,
+        Command::PULSE_OUTPUT_PORT_END,
+        "PULSE_OUTPUT_PORT",
+    ),
+];
+
+/// Constant name of a controller command or `"UNKNOWN"`.
+pub fn name_of_controller_command(command: u8) -> &'static str {
+    if let Some((_, name)) = CONTROLLER_COMMAND_NAMES.iter().find(|(c, _)| *c == command) {
+        return name;
+    }
+
+    CONTROLLER_COMMAND_RANGE_NAMES
+        .iter()
+        .find(|(first, last, _)| (*first..=*last).contains(&command))
+        .map_or("UNKNOWN", |(_, _, name)| name)
+}
+
+#[derive(Debug)]
+/// Return values of command `SELF_TEST`.
+pub struct SelfTestResult;
+
+impl SelfTestResult {
+    pub const PASSED: u8 = 0x55;
+    pub const FAILED: u8 = 0xFC;
+}
+
+#[derive(Debug)]
+/// Return values of commands `KEYBOARD_INTERFACE_TEST` and
+/// `AUXILIARY_DEVICE_INTERFACE_TEST`.
+pub struct InterfaceTestResult;
+
+impl InterfaceTestResult {
+    pub const PASSED: u8 = 0;
+    pub const CLOCK_LINE_LOW: u8 = 1;
+    pub const CLOCK_LINE_HIGH: u8 = 2;
+    pub const DATA_LINE_LOW: u8 = 3;
+    pub const DATA_LINE_HIGH: u8 = 4;
+}
+
+#[derive(Debug)]
+/// Active multiplexing handshake. Bytes are written with
+/// `CommandWaitData::WRITE_AUXILIARY_DEVICE_OUTPUT_BUFFER` and
+/// the controller echoes them back.
+///
+/// Reference: Active PS/2 Multiplexing Specification, version 1.1
+pub struct MultiplexingHandshake;
+
+impl MultiplexingHandshake {
+    /// Response to the last byte is the multiplexing version.
+    pub const ENABLE: [u8; 3] = [0xF0, 0x56, 0xA4];
+    pub const DISABLE: [u8; 3] = [0xF0, 0xF6, 0xA5];
+    /// Controllers without multiplexing support echo the last byte
+    /// of `ENABLE` as the version response.
+    pub const ENABLE_NOT_SUPPORTED_ECHO: u8 = 0xA4;
+    /// Echo of the last byte of `DISABLE`.
+    pub const DISABLE_NOT_SUPPORTED_ECHO: u8 = 0xA5;
+    /// Version response of some BIOS USB legacy emulations, which
+    /// don't support multiplexing. It is not an echo, so it looks
+    /// like a valid version.
+    pub const USB_LEGACY_FALSE_POSITIVE_VERSION: u8 = 0xAC;
+}
+
+#[derive(Debug)]
+/// Return values of command `TEST_PASSWORD_INSTALLED`.
+pub struct PasswordTestResult;
+
+impl PasswordTestResult {
+    pub const INSTALLED: u8 = 0xFA;
+    pub const NOT_INSTALLED: u8 = 0xF1;
 }
 
 bitflags! {
@@ -91,6 +226,15 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// Bit 0 is controller input T0 and bit 1 is
+    /// controller input T1.
+    pub struct TestInputBits: u8 {
+        const AUXILIARY_CLOCK_IN = 0b0000_0010;
+        const KEYBOARD_CLOCK_IN = 0b0000_0001;
+    }
+}
+
 bitflags! {
     pub struct OutputPortBits: u8 {
         const KEYBOARD_DATA_OUT = 0b1000_0000;
@@ -0,0 +1,318 @@
+//! Controller command transactions.
+//!
+//! A transaction writes a controller command and its data byte or
+//! reads its response. Writes wait for an empty input buffer and
+//! responses are read only after stale output buffer data has been
+//! discarded. Transactions don't check that devices and interrupts
+//! are disabled when the response is read, so device data can be
+//! read as a response if they are enabled.
+//!
+//! Some chipsets set the `GENERAL_TIMEOUT` status bit during heavy
+//! bus activity. A transaction which sees the bit is run again at
+//! most `TRANSACTION_RETRIES` times.
+//!
+//! The typestate API in `driver` is built on these functions and
+//! encodes the required controller state to the types. Use
+//! `RawController` to run transactions without the typestate API.
+
+use super::driver::{
+    quirks::Quirks,
+    status::{DataOwner, ReadStatus, StatusInfo},
+    trace, ReadData,
+};
+use super::io::{PortIO, PortIOAvailable};
+use super::raw::CommandReturnData;
+use crate::trace::{TraceEvent, TraceLevel};
+
+/// Default status register poll limit of `RawController`.
+pub const TRANSACTION_POLLS: u32 = 100_000;
+
+/// Number of times a transaction is run again after
+/// `GENERAL_TIMEOUT`.
+pub const TRANSACTION_RETRIES: u32 = 3;
+
+/// Status register poll limit was reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransactionTimeout;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionError {
+    /// Status register poll limit was reached.
+    Timeout,
+    /// Controller set `GENERAL_TIMEOUT` on every retry.
+    GeneralTimeout,
+}
+
+impl From<TransactionTimeout> for TransactionError {
+    fn from(_: TransactionTimeout) -> Self {
+        TransactionError::Timeout
+    }
+}
+
+// TODO: The IBM reference (PDF page 344) says that there
+//       shouldn't be any writes to ports 0x60 and 0x64 when
+//       output buffer bit is set to 1. This is probably unnecessary
+//       when controller command doesn't use the output buffer?
+//       The current code checks that the buffer is empty only when using
+//       commands that return a value.
+
+/// Returns `true` if controller command writes data to the
+/// output buffer.
+pub fn command_returns_data(command: u8) -> bool {
+    matches!(
+        command,
+        CommandReturnData::READ_CONTROLLER_COMMAND_BYTE
+            ..=CommandReturnData::READ_RAM_END
+                | CommandReturnData::TEST_PASSWORD_INSTALLED
+                | CommandReturnData::AUXILIARY_DEVICE_INTERFACE_TEST
+                | CommandReturnData::SELF_TEST
+                | CommandReturnData::KEYBOARD_INTERFACE_TEST
+                | CommandReturnData::READ_INPUT_PORT
+                | CommandReturnData::READ_OUTPUT_PORT
+                | CommandReturnData::READ_TEST_INPUTS
+    )
+}
+
+/// Write to the command register without waiting.
+pub fn write_command<T: PortIO, U: ReadStatus<T>>(controller: &mut U, command: u8) {
+    controller.port_io_mut().write(T::COMMAND_REGISTER, command);
+    controller.set_controller_response_pending(command_returns_data(command));
+    trace(controller, TraceLevel::Verbose, || {
+        TraceEvent::ControllerCommand(command)
+    });
+
+    if controller
+        .quirks()
+        .contains(Quirks::DUMMY_STATUS_READ_AFTER_COMMAND)
+    {
+        controller.port_io_mut().read(T::STATUS_REGISTER);
+    }
+}
+
+/// Write to the data port without waiting.
+pub fn write_data<T: PortIO, U: ReadStatus<T>>(controller: &mut U, data: u8) {
+    controller.port_io_mut().write(T::DATA_PORT, data);
+    trace(controller, TraceLevel::Verbose, || {
+        TraceEvent::DataWritten(data)
+    });
+}
+
+/// Wait until the controller has handled the input buffer. `None`
+/// waits forever.
+pub fn wait_input_buffer_empty<T: PortIO, U: ReadStatus<T>>(
+    controller: &mut U,
+    polls: Option<u32>,
+) -> Result<(), TransactionTimeout> {
+    wait_until_handled(controller, polls).map(|_| ())
+}
+
+/// Send a command and wait until the controller has handled it.
+pub fn send_command<T: PortIO, U: ReadStatus<T>>(
+    controller: &mut U,
+    command: u8,
+    polls: Option<u32>,
+) -> Result<(), TransactionError> {
+    with_retries(controller, |controller| {
+        wait_input_buffer_empty(controller, polls)?;
+        write_command(controller, command);
+        let status = wait_until_handled(controller, polls)?;
+        check_general_timeout(controller, status, command)
+    })
+}
+
+/// Send a command and its data byte. Data is written after the
+/// controller has handled the command.
+pub fn send_command_and_data<T: PortIO, U: ReadStatus<T>>(
+    controller: &mut U,
+    command: u8,
+    data: u8,
+    polls: Option<u32>,
+) -> Result<(), TransactionError> {
+    with_retries(controller, |controller| {
+        wait_input_buffer_empty(controller, polls)?;
+        write_command(controller, command);
+        let status = wait_until_handled(controller, polls)?;
+        check_general_timeout(controller, status, command)?;
+        write_data(controller, data);
+        let status = wait_until_handled(controller, polls)?;
+        check_general_timeout(controller, status, command)
+    })
+}
+
+/// Send a command and read its response. Data in the output buffer
+/// is discarded before the command is sent.
+pub fn send_command_and_read_response<T: PortIO, U: ReadStatus<T>>(
+    controller: &mut U,
+    command: u8,
+    polls: Option<u32>,
+) -> Result<u8, TransactionError> {
+    with_retries(controller, |controller| {
+        if controller.status().data_availability().is_some() {
+            controller.port_io_mut().read(T::DATA_PORT);
+        }
+
+        wait_input_buffer_empty(controller, polls)?;
+        write_command(controller, command);
+        wait_until_handled(controller, polls)?;
+
+        let mut remaining = polls;
+        loop {
+            let status = controller.status();
+            if let Some(DataOwner::KeyboardOrCommandController) = status.data_availability() {
+                let mut response = controller.port_io_mut().read(T::DATA_PORT);
+                if controller.quirks().contains(Quirks::DOUBLE_RESPONSE_READ) {
+                    response = controller.port_io_mut().read(T::DATA_PORT);
+                }
+                controller.set_controller_response_pending(false);
+                trace(controller, TraceLevel::Verbose, || {
+                    TraceEvent::DataRead(response)
+                });
+
+                if status.general_timeout_error() {
+                    trace(controller, TraceLevel::Info, || {
+                        TraceEvent::GeneralTimeout(command)
+                    });
+                    return Err(TransactionError::GeneralTimeout);
+                }
+
+                return Ok(response);
+            }
+            poll(&mut remaining)?;
+        }
+    })
+}
+
+/// Returns the first status where the input buffer is empty.
+fn wait_until_handled<T: PortIO, U: ReadStatus<T>>(
+    controller: &mut U,
+    polls: Option<u32>,
+) -> Result<StatusInfo, TransactionTimeout> {
+    let mut remaining = polls;
+    loop {
+        let status = controller.status();
+        if !status.input_buffer_full() {
+            return Ok(status);
+        }
+        poll(&mut remaining)?;
+    }
+}
+
+/// `GENERAL_TIMEOUT` belongs to the byte in the output buffer if
+/// there is one, so it is checked only when the buffer is empty.
+fn check_general_timeout<T: PortIO, U: ReadStatus<T>>(
+    controller: &mut U,
+    status: StatusInfo,
+    command: u8,
+) -> Result<(), TransactionError> {
+    if status.general_timeout_error() && status.data_availability().is_none() {
+        trace(controller, TraceLevel::Info, || {
+            TraceEvent::GeneralTimeout(command)
+        });
+        Err(TransactionError::GeneralTimeout)
+    } else {
+        Ok(())
+    }
+}
+
+fn with_retries<U, R>(
+    controller: &mut U,
+    mut transaction: impl FnMut(&mut U) -> Result<R, TransactionError>,
+) -> Result<R, TransactionError> {
+    let mut retries = 0;
+    loop {
+        match transaction(controller) {
+            Err(TransactionError::GeneralTimeout) if retries < TRANSACTION_RETRIES => retries += 1,
+            result => return result,
+        }
+    }
+}
+
+fn poll(remaining: &mut Option<u32>) -> Result<(), TransactionTimeout> {
+    match remaining {
+        Some(0) => Err(TransactionTimeout),
+        Some(polls) => {
+            *polls -= 1;
+            Ok(())
+        }
+        None => Ok(()),
+    }
+}
+
+/// Controller transactions without the typestate API.
+///
+/// Controller state is not tracked, so the caller is responsible
+/// for disabling devices and interrupts before reading command
+/// responses. Device data can be read with `ReadData`.
+#[derive(Debug)]
+pub struct RawController<T: PortIO> {
+    port_io: T,
+    quirks: Quirks,
+    polls: Option<u32>,
+    controller_response_pending: bool,
+}
+
+impl<T: PortIO> RawController<T> {
+    pub fn new(port_io: T) -> Self {
+        Self::with_quirks(port_io, Quirks::empty())
+    }
+
+    pub fn with_quirks(port_io: T, quirks: Quirks) -> Self {
+        Self {
+            port_io,
+            quirks,
+            polls: Some(TRANSACTION_POLLS),
+            controller_response_pending: false,
+        }
+    }
+
+    /// Status register poll limit of every wait. `None` waits
+    /// forever.
+    pub fn set_poll_limit(&mut self, polls: Option<u32>) {
+        self.polls = polls;
+    }
+
+    pub fn into_port_io(self) -> T {
+        self.port_io
+    }
+
+    pub fn send_command(&mut self, command: u8) -> Result<(), TransactionError> {
+        send_command(self, command, self.polls)
+    }
+
+    pub fn send_command_and_data(&mut self, command: u8, data: u8) -> Result<(), TransactionError> {
+        send_command_and_data(self, command, data, self.polls)
+    }
+
+    pub fn send_command_and_read_response(&mut self, command: u8) -> Result<u8, TransactionError> {
+        send_command_and_read_response(self, command, self.polls)
+    }
+
+    /// Send a byte to the keyboard.
+    pub fn send_to_keyboard(&mut self, data: u8) -> Result<(), TransactionTimeout> {
+        wait_input_buffer_empty(self, self.polls)?;
+        write_data(self, data);
+        Ok(())
+    }
+}
+
+impl<T: PortIO> PortIOAvailable<T> for RawController<T> {
+    fn port_io_mut(&mut self) -> &mut T {
+        &mut self.port_io
+    }
+}
+
+impl<T: PortIO> ReadStatus<T> for RawController<T> {
+    fn controller_response_pending(&self) -> bool {
+        self.controller_response_pending
+    }
+
+    fn set_controller_response_pending(&mut self, pending: bool) {
+        self.controller_response_pending = pending;
+    }
+
+    fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+}
+
+impl<T: PortIO> ReadData<T> for RawController<T> {}
@@ -0,0 +1,80 @@
+//! `PortIO` implementation for x86 I/O ports.
+
+#![allow(unsafe_code)]
+
+use super::io::{PortIO, DATA_PORT_RAW, STATUS_REGISTER_RAW};
+
+use core::arch::asm;
+
+/// PS/2 controller I/O port.
+#[derive(Debug, Clone, Copy)]
+pub enum X86Port {
+    /// Port 0x60.
+    Data,
+    /// Port 0x64.
+    StatusOrCommand,
+}
+
+impl X86Port {
+    fn address(self) -> u16 {
+        match self {
+            X86Port::Data => DATA_PORT_RAW,
+            X86Port::StatusOrCommand => STATUS_REGISTER_RAW,
+        }
+    }
+}
+
+/// Access to the standard PS/2 controller I/O ports 0x60 and 0x64.
+#[derive(Debug)]
+pub struct X86PortIO {
+    _private: (),
+}
+
+impl X86PortIO {
+    /// # Safety
+    /// * Only one `X86PortIO` may exist at a time and no other code may
+    ///   access I/O ports 0x60 and 0x64 while it exists. The port
+    ///   access of the split port handles counts as one.
+    /// * Current privilege level must allow I/O port access.
+    pub unsafe fn new() -> Self {
+        Self { _private: () }
+    }
+}
+
+#[cfg(feature = "aux-device")]
+impl super::io::SplitPortIO for X86PortIO {
+    fn split_port_io(&mut self) -> Self {
+        Self { _private: () }
+    }
+}
+
+impl PortIO for X86PortIO {
+    type PortID = X86Port;
+
+    const DATA_PORT: Self::PortID = X86Port::Data;
+    const STATUS_REGISTER: Self::PortID = X86Port::StatusOrCommand;
+    const COMMAND_REGISTER: Self::PortID = X86Port::StatusOrCommand;
+
+    fn read(&mut self, port: Self::PortID) -> u8 {
+        let value: u8;
+        let port = port.address();
+
+        // Safety: Requirements of `X86PortIO::new` make the port access
+        //         valid and `X86Port` limits ports to the PS/2
+        //         controller ports.
+        unsafe {
+            asm!("in al, dx", out("al") value, in("dx") port, options(nomem, nostack, preserves_flags));
+        }
+
+        value
+    }
+
+    fn write(&mut self, port: Self::PortID, data: u8) {
+        let port = port.address();
+
+        // Safety: See `read`.
+        unsafe {
+            asm!("out dx, al", in("dx") port, in("al") data, options(nomem, nostack, preserves_flags));
+        }
+    }
+}
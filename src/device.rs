@@ -1,4 +1,8 @@
 pub mod command_queue;
+pub mod health;
 pub mod identify;
 pub mod io;
 pub mod keyboard;
+#[cfg(feature = "aux-device")]
+#[cfg_attr(docsrs, doc(cfg(feature = "aux-device")))]
+pub mod mouse;
@@ -1,28 +1,33 @@
 use super::io::SendToDevice;
 use super::keyboard::driver::{
-    DelayMilliseconds, KeyboardScancodeSetting, RateValue, SetAllKeys, SetKeyType,
+    DelayMilliseconds, KeyboardScancodeSetting, RateValue, SetAllKeys, SetKeyType, TypematicConfig,
 };
-use super::keyboard::raw::{CommandReturnData, FromKeyboard};
+use super::keyboard::raw::{CommandReturnData, FromKeyboard, ResponseTime, ScancodeSetNumber};
 
-use arraydeque::{Array, ArrayDeque, CapacityError, Saturating};
+use crate::ring_buffer::{CapacityError, RingBuffer};
 
+/// Queue with space for `N` commands in addition to the
+/// command in progress.
 #[derive(Debug)]
-pub struct CommandQueue<T: Array<Item = Command>> {
-    commands: ArrayDeque<T, Saturating>,
+pub struct CommandQueue<const N: usize> {
+    commands: RingBuffer<Command, N>,
     command_checker: CommandChecker,
+    /// ID of the first command in `commands`.
+    front_id: u32,
 }
 
-impl<T: Array<Item = Command>> Default for CommandQueue<T> {
+impl<const N: usize> Default for CommandQueue<N> {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl<T: Array<Item = Command>> CommandQueue<T> {
+impl<const N: usize> CommandQueue<N> {
     pub fn new() -> Self {
         Self {
-            commands: ArrayDeque::new(),
+            commands: RingBuffer::new(),
             command_checker: CommandChecker::new(),
+            front_id: 0,
         }
     }
 
@@ -30,20 +35,73 @@ impl<T: Array<Item = Command>> CommandQueue<T> {
         (self.commands.capacity() - self.commands.len()) >= count
     }
 
+    /// Returns ID which identifies the command in `Status` values.
     pub fn add<U: SendToDevice>(
         &mut self,
         command: Command,
         device: &mut U,
-    ) -> Result<(), CapacityError<Command>> {
-        let result = self.commands.push_back(command);
+    ) -> Result<CommandId, CapacityError<Command>> {
+        let id = CommandId(self.front_id.wrapping_add(self.commands.len() as u32));
+        self.commands.push_back(command)?;
 
         if self.command_checker.current_command().is_none() {
-            if let Some(command) = self.commands.pop_front() {
-                self.command_checker.send_new_command(command, device)
+            self.send_next_command(device);
+        }
+
+        Ok(id)
+    }
+
+    /// Replace the last command waiting to be sent if it has the
+    /// same command byte as `command`. Use this for commands where
+    /// only the latest data matters. Returns `command` back if it
+    /// was not replaced.
+    pub fn replace_last_pending(&mut self, command: Command) -> Result<CommandId, Command> {
+        match self.commands.back_mut() {
+            Some(last) if last.command_byte() == command.command_byte() => {
+                *last = command;
+                let id = self.front_id.wrapping_add(self.commands.len() as u32 - 1);
+                Ok(CommandId(id))
             }
+            _ => Err(command),
         }
+    }
 
-        result
+    fn send_next_command<U: SendToDevice>(&mut self, device: &mut U) {
+        if let Some(command) = self.commands.pop_front() {
+            let id = CommandId(self.front_id);
+            self.front_id = self.front_id.wrapping_add(1);
+            self.command_checker.send_new_command(id, command, device);
+        }
+    }
+
+    /// Number of commands in the queue including the command
+    /// in progress.
+    pub fn len(&self) -> usize {
+        self.commands.len() + self.command_checker.current_command().is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.empty()
+    }
+
+    /// Command in progress.
+    pub fn current_command(&self) -> Option<(CommandId, &Command)> {
+        self.command_checker.current()
+    }
+
+    /// Commands waiting to be sent.
+    pub fn pending_commands(&self) -> impl Iterator<Item = (CommandId, &Command)> {
+        let front_id = self.front_id;
+        self.commands
+            .iter()
+            .enumerate()
+            .map(move |(i, command)| (CommandId(front_id.wrapping_add(i as u32)), command))
+    }
+
+    /// Set how many times a command is sent again when the device
+    /// requests resend. Default is `DEFAULT_MAX_RESENDS`.
+    pub fn set_max_resends(&mut self, max_resends: u8) {
+        self.command_checker.set_max_resends(max_resends);
     }
 
     /// Receive data only if command queue is not empty.
@@ -54,10 +112,8 @@ impl<T: Array<Item = Command>> CommandQueue<T> {
     ) -> Option<Status> {
         let result = self.command_checker.receive_data(new_data, device);
 
-        if let Some(Status::CommandFinished(_)) = &result {
-            if let Some(command) = self.commands.pop_front() {
-                self.command_checker.send_new_command(command, device);
-            }
+        if let Some(Status::CommandFinished(..)) | Some(Status::CommandFailed(..)) = &result {
+            self.send_next_command(device);
         }
 
         result
@@ -66,17 +122,156 @@ impl<T: Array<Item = Command>> CommandQueue<T> {
     pub fn empty(&self) -> bool {
         self.commands.is_empty() && self.command_checker.current_command().is_none()
     }
+
+    pub fn set_response_timeouts(&mut self, timeouts: ResponseTimeouts) {
+        self.command_checker.set_response_timeouts(timeouts);
+    }
+
+    /// Continue sending if `SendToDevice::try_send` returned
+    /// `WouldBlock` previously. Call this from the main loop or
+    /// a timer tick.
+    ///
+    /// Returns `true` if there is no unsent data after the call.
+    pub fn pump<U: SendToDevice>(&mut self, device: &mut U) -> bool {
+        self.command_checker.pump(device)
+    }
+
+    /// Request the device to send its last byte again. Use this
+    /// when a received byte was corrupted.
+    pub fn request_resend<U: SendToDevice>(&mut self, device: &mut U) {
+        self.command_checker.request_resend(device)
+    }
+
+    /// Advance command response timer. If the current command times out, it
+    /// is removed from the queue and the next command is sent.
+    pub fn tick<U: SendToDevice>(
+        &mut self,
+        elapsed_milliseconds: u32,
+        device: &mut U,
+    ) -> Option<Status> {
+        let result = self.command_checker.tick(elapsed_milliseconds);
+
+        if let Some(Status::CommandTimeout(..)) = &result {
+            self.send_next_command(device);
+        }
+
+        result
+    }
 }
 
-#[derive(Debug, Default)]
+/// Maximum count of per command timeout overrides.
+pub const MAX_TIMEOUT_OVERRIDES: usize = 4;
+
+#[derive(Debug)]
+pub struct TooManyTimeoutOverrides;
+
+/// Response timeouts for device commands.
+///
+/// Defaults are from `ResponseTime`.
+#[derive(Debug, Clone, Copy)]
+pub struct ResponseTimeouts {
+    command_response_milliseconds: u32,
+    reset_milliseconds: u32,
+    overrides: [Option<(u8, u32)>; MAX_TIMEOUT_OVERRIDES],
+}
+
+impl Default for ResponseTimeouts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ResponseTimeouts {
+    pub const fn new() -> Self {
+        Self {
+            command_response_milliseconds: ResponseTime::COMMAND_RESPONSE_MILLISECONDS,
+            reset_milliseconds: ResponseTime::COMMAND_RESPONSE_MILLISECONDS
+                + ResponseTime::BAT_MILLISECONDS,
+            overrides: [None; MAX_TIMEOUT_OVERRIDES],
+        }
+    }
+
+    /// Timeout for every response byte of a command.
+    pub fn set_command_response(&mut self, milliseconds: u32) {
+        self.command_response_milliseconds = milliseconds;
+    }
+
+    /// Timeout for reset command which includes the BAT.
+    pub fn set_reset(&mut self, milliseconds: u32) {
+        self.reset_milliseconds = milliseconds;
+    }
+
+    /// Set timeout for a specific command byte.
+    pub fn set_override(
+        &mut self,
+        command: u8,
+        milliseconds: u32,
+    ) -> Result<(), TooManyTimeoutOverrides> {
+        let slot = self
+            .overrides
+            .iter_mut()
+            .find(|o| o.map(|(c, _)| c == command).unwrap_or(true))
+            .ok_or(TooManyTimeoutOverrides)?;
+        *slot = Some((command, milliseconds));
+        Ok(())
+    }
+
+    pub fn timeout_for(&self, command: u8) -> u32 {
+        let override_value = self
+            .overrides
+            .iter()
+            .flatten()
+            .find(|(c, _)| *c == command)
+            .map(|(_, milliseconds)| *milliseconds);
+
+        match (override_value, command) {
+            (Some(milliseconds), _) => milliseconds,
+            (None, CommandReturnData::RESET) => self.reset_milliseconds,
+            (None, _) => self.command_response_milliseconds,
+        }
+    }
+}
+
+/// Default value for `CommandQueue::set_max_resends`.
+pub const DEFAULT_MAX_RESENDS: u8 = 3;
+
+/// Identifies a command added to `CommandQueue`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandId(u32);
+
+impl CommandId {
+    pub fn value(&self) -> u32 {
+        self.0
+    }
+}
+
+#[derive(Debug)]
 pub struct CommandChecker {
     current_command: Option<Command>,
+    current_id: CommandId,
+    timeouts: ResponseTimeouts,
+    elapsed_milliseconds: u32,
+    unsent: Option<u8>,
+    resends: u8,
+    max_resends: u8,
+}
+
+impl Default for CommandChecker {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CommandChecker {
     pub fn new() -> Self {
         Self {
             current_command: None,
+            current_id: CommandId(0),
+            timeouts: ResponseTimeouts::new(),
+            elapsed_milliseconds: 0,
+            unsent: None,
+            resends: 0,
+            max_resends: DEFAULT_MAX_RESENDS,
         }
     }
 
@@ -84,19 +279,92 @@ impl CommandChecker {
         &self.current_command
     }
 
-    pub fn send_new_command<T: SendToDevice>(&mut self, command: Command, device: &mut T) {
-        match &command {
-            Command::Echo { command }
-            | Command::AckResponse { command, .. }
-            | Command::AckResponseWithReturnTwoBytes { command, .. }
-            | Command::SendCommandAndData { command, .. }
-            | Command::SendCommandAndDataSingleAck { command, .. }
-            | Command::SendCommandAndDataAndReceiveResponse { command, .. } => {
-                device.send(*command)
+    pub fn current(&self) -> Option<(CommandId, &Command)> {
+        self.current_command
+            .as_ref()
+            .map(|command| (self.current_id, command))
+    }
+
+    pub fn set_max_resends(&mut self, max_resends: u8) {
+        self.max_resends = max_resends;
+    }
+
+    pub fn set_response_timeouts(&mut self, timeouts: ResponseTimeouts) {
+        self.timeouts = timeouts;
+    }
+
+    /// Advance command response timer.
+    ///
+    /// Returns `None` if there is no command in progress.
+    pub fn tick(&mut self, elapsed_milliseconds: u32) -> Option<Status> {
+        let command = self.current_command.as_ref()?;
+
+        if self.unsent.is_some() {
+            return Some(Status::CommandInProgress);
+        }
+
+        self.elapsed_milliseconds = self
+            .elapsed_milliseconds
+            .saturating_add(elapsed_milliseconds);
+
+        if self.elapsed_milliseconds > self.timeouts.timeout_for(command.command_byte()) {
+            let id = self.current_id;
+            self.current_command
+                .take()
+                .map(|command| Status::CommandTimeout(id, command))
+        } else {
+            Some(Status::CommandInProgress)
+        }
+    }
+
+    /// Retry sending a byte which was not sent because
+    /// `SendToDevice::try_send` returned `WouldBlock`.
+    ///
+    /// Returns `true` if there is no unsent byte after the call.
+    pub fn pump<T: SendToDevice>(&mut self, device: &mut T) -> bool {
+        match self.unsent.take() {
+            Some(data) => {
+                self.transmit(data, device);
+                self.unsent.is_none()
             }
+            None => true,
+        }
+    }
+
+    fn transmit<T: SendToDevice>(&mut self, data: u8, device: &mut T) {
+        if device.try_send(data).is_err() {
+            self.unsent = Some(data);
+        }
+    }
+
+    /// Request the device to send its last byte again.
+    ///
+    /// Nothing is sent if there is an unsent byte, because it would
+    /// be overwritten and the command would never complete.
+    pub fn request_resend<T: SendToDevice>(&mut self, device: &mut T) {
+        if self.unsent.is_some() {
+            return;
         }
 
+        self.transmit(CommandReturnData::RESEND, device);
+        self.elapsed_milliseconds = 0;
+    }
+
+    pub fn send_new_command<T: SendToDevice>(
+        &mut self,
+        id: CommandId,
+        command: Command,
+        device: &mut T,
+    ) {
+        self.current_id = id;
+        self.resends = 0;
+        self.resend_command(command, device);
+    }
+
+    fn resend_command<T: SendToDevice>(&mut self, command: Command, device: &mut T) {
+        self.transmit(command.command_byte(), device);
         self.current_command = Some(command);
+        self.elapsed_milliseconds = 0;
     }
 
     pub fn receive_data<U: SendToDevice>(
@@ -105,28 +373,40 @@ impl CommandChecker {
         device: &mut U,
     ) -> Option<Status> {
         if let Some(mut command) = self.current_command.take() {
+            let elapsed_milliseconds = self.elapsed_milliseconds;
+            self.elapsed_milliseconds = 0;
+
+            if new_data == FromKeyboard::RESEND {
+                if self.resends >= self.max_resends {
+                    return Some(Status::CommandFailed(self.current_id, command));
+                }
+
+                self.resends += 1;
+            }
+
             let mut command_finished = false;
             let mut unexpected_data = None;
+            let mut interleaved = None;
 
             match &mut command {
                 Command::Echo { .. } => {
                     if new_data == FromKeyboard::ECHO {
                         command_finished = true;
                     } else if new_data == FromKeyboard::RESEND {
-                        self.send_new_command(command, device);
+                        self.resend_command(command, device);
                         return None;
                     } else {
-                        unexpected_data = Some(new_data);
+                        interleaved = Some(new_data);
                     }
                 }
                 Command::AckResponse { .. } => {
                     if new_data == FromKeyboard::ACK {
                         command_finished = true;
                     } else if new_data == FromKeyboard::RESEND {
-                        self.send_new_command(command, device);
+                        self.resend_command(command, device);
                         return None;
                     } else {
-                        unexpected_data = Some(new_data);
+                        interleaved = Some(new_data);
                     }
                 }
                 Command::AckResponseWithReturnTwoBytes {
@@ -136,10 +416,10 @@ impl CommandChecker {
                     if new_data == FromKeyboard::ACK {
                         *s = AckResponseWithReturnTwoBytesState::WaitFirstByte;
                     } else if new_data == FromKeyboard::RESEND {
-                        self.send_new_command(command, device);
+                        self.resend_command(command, device);
                         return None;
                     } else {
-                        unexpected_data = Some(new_data);
+                        interleaved = Some(new_data);
                     }
                 }
                 Command::AckResponseWithReturnTwoBytes {
@@ -165,12 +445,12 @@ impl CommandChecker {
                 } => {
                     if new_data == FromKeyboard::ACK {
                         *s = SendCommandAndDataState::WaitAck2;
-                        device.send(*data);
+                        self.transmit(*data, device);
                     } else if new_data == FromKeyboard::RESEND {
-                        self.send_new_command(command, device);
+                        self.resend_command(command, device);
                         return None;
                     } else {
-                        unexpected_data = Some(new_data);
+                        interleaved = Some(new_data);
                     }
                 }
                 Command::SendCommandAndData {
@@ -181,7 +461,7 @@ impl CommandChecker {
                     if new_data == FromKeyboard::ACK {
                         command_finished = true;
                     } else if new_data == FromKeyboard::RESEND {
-                        device.send(*data);
+                        self.transmit(*data, device);
                     } else {
                         unexpected_data = Some(new_data);
                     }
@@ -193,12 +473,12 @@ impl CommandChecker {
                 } => {
                     if new_data == FromKeyboard::ACK {
                         *s = SendCommandAndDataState::WaitAck2;
-                        device.send(*data);
+                        self.transmit(*data, device);
                     } else if new_data == FromKeyboard::RESEND {
-                        self.send_new_command(command, device);
+                        self.resend_command(command, device);
                         return None;
                     } else {
-                        unexpected_data = Some(new_data);
+                        interleaved = Some(new_data);
                     }
                 }
                 Command::SendCommandAndDataSingleAck {
@@ -208,7 +488,7 @@ impl CommandChecker {
                     ..
                 } => {
                     if new_data == FromKeyboard::RESEND {
-                        device.send(*data);
+                        self.transmit(*data, device);
                     } else {
                         *scancode_received_after_this_command = new_data;
                         command_finished = true;
@@ -221,12 +501,12 @@ impl CommandChecker {
                 } => {
                     if new_data == FromKeyboard::ACK {
                         *s = SendCommandAndDataAndReceiveResponseState::WaitAck2;
-                        device.send(*data);
+                        self.transmit(*data, device);
                     } else if new_data == FromKeyboard::RESEND {
-                        self.send_new_command(command, device);
+                        self.resend_command(command, device);
                         return None;
                     } else {
-                        unexpected_data = Some(new_data);
+                        interleaved = Some(new_data);
                     }
                 }
                 Command::SendCommandAndDataAndReceiveResponse {
@@ -237,7 +517,9 @@ impl CommandChecker {
                     if new_data == FromKeyboard::ACK {
                         *s = SendCommandAndDataAndReceiveResponseState::WaitResponse;
                     } else if new_data == FromKeyboard::RESEND {
-                        device.send(*data);
+                        self.transmit(*data, device);
+                    } else {
+                        unexpected_data = Some(new_data);
                     }
                 }
                 Command::SendCommandAndDataAndReceiveResponse {
@@ -251,12 +533,16 @@ impl CommandChecker {
             }
 
             if command_finished {
-                Some(Status::CommandFinished(command))
+                Some(Status::CommandFinished(self.current_id, command))
             } else {
                 self.current_command = Some(command);
 
                 if let Some(data) = unexpected_data {
                     Some(Status::UnexpectedData(data))
+                } else if let Some(data) = interleaved {
+                    // Device has not responded to the command yet.
+                    self.elapsed_milliseconds = elapsed_milliseconds;
+                    Some(Status::Interleaved(data))
                 } else {
                     Some(Status::CommandInProgress)
                 }
@@ -301,6 +587,17 @@ pub enum Command {
 }
 
 impl Command {
+    pub fn command_byte(&self) -> u8 {
+        match self {
+            Command::Echo { command }
+            | Command::AckResponse { command, .. }
+            | Command::AckResponseWithReturnTwoBytes { command, .. }
+            | Command::SendCommandAndData { command, .. }
+            | Command::SendCommandAndDataSingleAck { command, .. }
+            | Command::SendCommandAndDataAndReceiveResponse { command, .. } => *command,
+        }
+    }
+
     pub fn default_disable() -> Self {
         Command::AckResponse {
             command: CommandReturnData::DEFAULT_DISABLE,
@@ -313,6 +610,14 @@ impl Command {
         }
     }
 
+    /// Command is finished after ACK. Keyboard sends BAT completion
+    /// code after the reset.
+    pub fn reset() -> Self {
+        Command::AckResponse {
+            command: CommandReturnData::RESET,
+        }
+    }
+
     pub fn read_id() -> Self {
         Command::AckResponseWithReturnTwoBytes {
             command: CommandReturnData::READ_ID,
@@ -354,7 +659,7 @@ impl Command {
     pub fn set_typematic_rate(delay: DelayMilliseconds, rate: RateValue) -> Self {
         Command::SendCommandAndData {
             command: CommandReturnData::SET_TYPEMATIC_RATE,
-            data: delay as u8 | rate.value(),
+            data: TypematicConfig::from_rate_value(delay, rate).typematic_byte(),
             state: SendCommandAndDataState::WaitAck1,
         }
     }
@@ -370,7 +675,7 @@ impl Command {
     pub fn get_current_scancode_set() -> Self {
         Command::SendCommandAndDataAndReceiveResponse {
             command: CommandReturnData::SELECT_ALTERNATE_SCANCODES,
-            data: 0,
+            data: ScancodeSetNumber::GET_CURRENT,
             response: 0,
             state: SendCommandAndDataAndReceiveResponseState::WaitAck1,
         }
@@ -383,11 +688,27 @@ impl Command {
     }
 }
 
+/// Attribution of a byte received while a command is in progress.
+///
+/// Until the device acknowledges the command byte, it may still send
+/// data which it produced before receiving the command, for example
+/// a scancode. These bytes are `Interleaved`. After the command byte
+/// is acknowledged, the device only sends responses, so other bytes
+/// are `UnexpectedData`.
 #[derive(Debug)]
 pub enum Status {
+    /// Byte received after the command byte was acknowledged which was
+    /// not a valid response.
     UnexpectedData(u8),
+    /// Byte which was sent by the device before it received the
+    /// command.
+    Interleaved(u8),
     CommandInProgress,
-    CommandFinished(Command),
+    CommandFinished(CommandId, Command),
+    /// Device did not respond within the timeout.
+    CommandTimeout(CommandId, Command),
+    /// Device requested resend more times than the resend limit allows.
+    CommandFailed(CommandId, Command),
 }
 
 #[derive(Debug)]
@@ -409,3 +730,255 @@ pub enum SendCommandAndDataAndReceiveResponseState {
     WaitAck2,
     WaitResponse,
 }
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    extern crate std;
+
+    use super::*;
+    use crate::device::io::WouldBlock;
+
+    use proptest::prelude::*;
+    use std::vec::Vec;
+
+    /// Records sent bytes. Sending fails while `blocked` is set.
+    #[derive(Debug, Default)]
+    struct Device {
+        sent: Vec<u8>,
+        blocked: bool,
+    }
+
+    impl SendToDevice for Device {
+        fn send(&mut self, data: u8) {
+            self.sent.push(data);
+        }
+
+        fn try_send(&mut self, data: u8) -> Result<(), WouldBlock> {
+            if self.blocked {
+                Err(WouldBlock)
+            } else {
+                self.sent.push(data);
+                Ok(())
+            }
+        }
+    }
+
+    fn commands() -> impl Strategy<Value = Command> {
+        (0..5u8, any::<u8>()).prop_map(|(kind, data)| match kind {
+            0 => Command::echo(),
+            1 => Command::enable(),
+            2 => Command::read_id(),
+            3 => Command::set_status_indicators(data),
+            _ => Command::get_current_scancode_set(),
+        })
+    }
+
+    /// All command kinds which `Keyboard` queues.
+    fn all_commands() -> impl Strategy<Value = Command> {
+        (0..13u8, any::<u8>()).prop_map(|(kind, data)| match kind {
+            0 => Command::echo(),
+            1 => Command::enable(),
+            2 => Command::read_id(),
+            3 => Command::reset(),
+            4 => Command::default_disable(),
+            5 => Command::set_default(),
+            6 => Command::set_status_indicators(data),
+            7 => Command::set_typematic_rate(
+                DelayMilliseconds::ALL[data as usize % DelayMilliseconds::ALL.len()],
+                RateValue::ALL[data as usize % RateValue::ALL.len()],
+            ),
+            8 => Command::set_alternate_scancodes(KeyboardScancodeSetting::Set3),
+            9 => Command::get_current_scancode_set(),
+            10 => Command::scancode_set_3_set_all_keys(SetAllKeys::MakeSlashBreak),
+            11 => Command::scancode_set_3_set_key_type(SetKeyType::Make, data),
+            _ => Command::scancode_set_3_set_key_type(SetKeyType::Typematic, data),
+        })
+    }
+
+    #[derive(Debug)]
+    enum Step {
+        Add(Command),
+        Receive(u8),
+        Tick(u32),
+    }
+
+    fn steps() -> impl Strategy<Value = Step> {
+        prop_oneof![
+            all_commands().prop_map(Step::Add),
+            prop_oneof![
+                Just(FromKeyboard::ACK),
+                Just(FromKeyboard::RESEND),
+                Just(FromKeyboard::ECHO),
+                any::<u8>(),
+            ]
+            .prop_map(Step::Receive),
+            (0..1000u32).prop_map(Step::Tick),
+        ]
+    }
+
+    /// Bytes sent for a command when the device does not request resend.
+    fn bytes_without_resends(command: &Command) -> usize {
+        match command {
+            Command::Echo { .. }
+            | Command::AckResponse { .. }
+            | Command::AckResponseWithReturnTwoBytes { .. } => 1,
+            Command::SendCommandAndData { .. }
+            | Command::SendCommandAndDataSingleAck { .. }
+            | Command::SendCommandAndDataAndReceiveResponse { .. } => 2,
+        }
+    }
+
+    /// Sent byte count and byte limit of every added command.
+    #[derive(Debug, Default)]
+    struct SentBytes(Vec<(CommandId, usize, usize)>);
+
+    impl SentBytes {
+        fn add(&mut self, id: CommandId, limit: usize) {
+            self.0.push((id, limit, 0));
+        }
+
+        /// Bytes sent while the current command changed from `before`
+        /// to `after` belong to `after`. Otherwise they are resends of
+        /// `before`.
+        fn record(&mut self, before: Option<CommandId>, after: Option<CommandId>, count: usize) {
+            if count == 0 {
+                return;
+            }
+            let id = after.unwrap_or_else(|| before.unwrap());
+            let entry = self.0.iter_mut().find(|(added, ..)| *added == id).unwrap();
+            entry.2 += count;
+        }
+    }
+
+    fn current_id<const N: usize>(queue: &CommandQueue<N>) -> Option<CommandId> {
+        queue.current_command().map(|(id, _)| id)
+    }
+
+    fn not_ack_or_resend() -> impl Strategy<Value = u8> {
+        any::<u8>().prop_filter("ACK or RESEND", |&b| {
+            b != FromKeyboard::ACK && b != FromKeyboard::RESEND
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn resend_request_with_unsent_byte_is_no_op(command in commands(), requests in 1..8usize) {
+            let mut device = Device { blocked: true, ..Device::default() };
+            let mut queue = CommandQueue::<4>::new();
+            let command_byte = command.command_byte();
+            queue.add(command, &mut device).unwrap();
+
+            for _ in 0..requests {
+                queue.request_resend(&mut device);
+            }
+            prop_assert!(device.sent.is_empty());
+
+            device.blocked = false;
+            prop_assert!(queue.pump(&mut device));
+            prop_assert_eq!(device.sent, [command_byte]);
+        }
+
+        #[test]
+        fn non_ack_in_wait_ack2_is_unexpected(data: u8, byte in not_ack_or_resend()) {
+            let mut device = Device::default();
+            let mut queue = CommandQueue::<4>::new();
+            queue.add(Command::set_status_indicators(data), &mut device).unwrap();
+
+            prop_assert!(matches!(
+                queue.receive_data(FromKeyboard::ACK, &mut device),
+                Some(Status::CommandInProgress)
+            ));
+            prop_assert!(matches!(
+                queue.receive_data(byte, &mut device),
+                Some(Status::UnexpectedData(b)) if b == byte
+            ));
+            let waits_ack2 = matches!(
+                queue.current_command(),
+                Some((_, Command::SendCommandAndData {
+                    state: SendCommandAndDataState::WaitAck2,
+                    ..
+                }))
+            );
+            prop_assert!(waits_ack2);
+
+            // Command still completes.
+            prop_assert!(matches!(
+                queue.receive_data(FromKeyboard::ACK, &mut device),
+                Some(Status::CommandFinished(..))
+            ));
+        }
+
+        #[test]
+        fn arbitrary_input_respects_resend_limit_and_drains(
+            steps in proptest::collection::vec(steps(), 0..64),
+            max_resends in 0..4u8,
+        ) {
+            let mut device = Device::default();
+            let mut queue = CommandQueue::<4>::new();
+            queue.set_max_resends(max_resends);
+            let mut sent = SentBytes::default();
+
+            for step in steps {
+                let before = current_id(&queue);
+                let sent_before = device.sent.len();
+                match step {
+                    Step::Add(command) => {
+                        let limit = bytes_without_resends(&command) + max_resends as usize;
+                        if let Ok(id) = queue.add(command, &mut device) {
+                            sent.add(id, limit);
+                        }
+                    }
+                    Step::Receive(byte) => {
+                        let _ = queue.receive_data(byte, &mut device);
+                    }
+                    Step::Tick(milliseconds) => {
+                        let _ = queue.tick(milliseconds, &mut device);
+                    }
+                }
+                sent.record(before, current_id(&queue), device.sent.len() - sent_before);
+            }
+
+            // Every command finishes after at most three responses.
+            for _ in 0..(3 * (4 + 1)) {
+                let before = current_id(&queue);
+                let sent_before = device.sent.len();
+                let response = match queue.current_command() {
+                    Some((_, Command::Echo { .. })) => FromKeyboard::ECHO,
+                    Some(_) => FromKeyboard::ACK,
+                    None => break,
+                };
+                let _ = queue.receive_data(response, &mut device);
+                sent.record(before, current_id(&queue), device.sent.len() - sent_before);
+            }
+            prop_assert!(queue.is_empty());
+            prop_assert!(queue.current_command().is_none());
+
+            for (id, limit, count) in sent.0 {
+                prop_assert!(count <= limit, "{:?} sent {} bytes, limit {}", id, count, limit);
+            }
+        }
+
+        #[test]
+        fn resend_limit_fails_command(command in commands(), max_resends in 0..6u8) {
+            let mut device = Device::default();
+            let mut queue = CommandQueue::<4>::new();
+            queue.set_max_resends(max_resends);
+            let command_byte = command.command_byte();
+            let id = queue.add(command, &mut device).unwrap();
+
+            for _ in 0..max_resends {
+                prop_assert!(queue.receive_data(FromKeyboard::RESEND, &mut device).is_none());
+            }
+            prop_assert!(matches!(
+                queue.receive_data(FromKeyboard::RESEND, &mut device),
+                Some(Status::CommandFailed(failed, _)) if failed == id
+            ));
+
+            prop_assert_eq!(device.sent.len(), 1 + max_resends as usize);
+            prop_assert!(device.sent.iter().all(|&b| b == command_byte));
+            prop_assert!(queue.is_empty());
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! Device error statistics and health summary.
+//!
+//! Drivers count errors since they were created. `HealthCounters::health`
+//! compares the error rate to `HealthThresholds`, so a kernel can
+//! warn about a failing keyboard or mouse before it stops working.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DeviceHealth {
+    Good,
+    Degraded,
+    Failing,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HealthCounters {
+    pub bytes_received: u32,
+    /// Finished, failed and timed out commands.
+    pub commands: u32,
+    /// BAT completions, including the one after the initial reset.
+    pub bats: u32,
+    pub bat_failures: u32,
+    /// Resend requests from the device.
+    pub resends: u32,
+    /// Corrupted bytes which the controller discarded.
+    pub transmission_errors: u32,
+    pub command_timeouts: u32,
+    /// Commands which failed after too many resend requests.
+    pub command_failures: u32,
+}
+
+/// Limits for `HealthCounters::health`. Error rate is errors per
+/// 1000 received bytes and commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HealthThresholds {
+    /// Error rate is not used until there are this many received
+    /// bytes and commands.
+    pub min_samples: u32,
+    pub degraded_errors_per_mille: u32,
+    pub failing_errors_per_mille: u32,
+    /// BAT count which is `Degraded`. Devices reset themselves
+    /// after power problems.
+    pub degraded_bats: u32,
+}
+
+impl HealthThresholds {
+    pub const DEFAULT: HealthThresholds = HealthThresholds {
+        min_samples: 100,
+        degraded_errors_per_mille: 5,
+        failing_errors_per_mille: 50,
+        degraded_bats: 4,
+    };
+}
+
+impl Default for HealthThresholds {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl HealthCounters {
+    pub const fn new() -> Self {
+        Self {
+            bytes_received: 0,
+            commands: 0,
+            bats: 0,
+            bat_failures: 0,
+            resends: 0,
+            transmission_errors: 0,
+            command_timeouts: 0,
+            command_failures: 0,
+        }
+    }
+
+    /// Sum of all error counters.
+    pub fn errors(&self) -> u32 {
+        self.bat_failures
+            .saturating_add(self.resends)
+            .saturating_add(self.transmission_errors)
+            .saturating_add(self.command_timeouts)
+            .saturating_add(self.command_failures)
+    }
+
+    /// Errors per 1000 received bytes and commands.
+    pub fn errors_per_mille(&self) -> u32 {
+        let samples = u64::from(self.bytes_received) + u64::from(self.commands);
+        let rate = u64::from(self.errors()) * 1000 / samples.max(1);
+        rate.min(u64::from(u32::MAX)) as u32
+    }
+
+    /// A BAT failure is always `Failing`.
+    pub fn health(&self, thresholds: &HealthThresholds) -> DeviceHealth {
+        if self.bat_failures > 0 {
+            return DeviceHealth::Failing;
+        }
+
+        let samples = self.bytes_received.saturating_add(self.commands);
+        let rate_health = if samples < thresholds.min_samples {
+            DeviceHealth::Good
+        } else {
+            let rate = self.errors_per_mille();
+            if rate >= thresholds.failing_errors_per_mille {
+                DeviceHealth::Failing
+            } else if rate >= thresholds.degraded_errors_per_mille {
+                DeviceHealth::Degraded
+            } else {
+                DeviceHealth::Good
+            }
+        };
+
+        let bat_health = if self.bats >= thresholds.degraded_bats {
+            DeviceHealth::Degraded
+        } else {
+            DeviceHealth::Good
+        };
+
+        rate_health.max(bat_health)
+    }
+}
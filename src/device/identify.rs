@@ -10,7 +10,7 @@ pub struct DeviceIdentifier<T: SendToDevice> {
 
 impl<T: SendToDevice> fmt::Debug for DeviceIdentifier<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "DeviceIdentifier")
+        f.write_str("DeviceIdentifier")
     }
 }
 
@@ -75,6 +75,12 @@ impl<T: SendToDevice> DeviceIdentifier<T> {
         new_data: u8,
         _device: &mut T,
     ) -> Option<Device> {
+        // Mice send only one ID byte.
+        if let Some(kind) = MouseKind::from_id(new_data) {
+            state.state = Self::end;
+            return Some(Device::Mouse(kind));
+        }
+
         state.state = Self::wait_id_byte_2;
         state.byte1 = new_data;
         None
@@ -87,15 +93,7 @@ impl<T: SendToDevice> DeviceIdentifier<T> {
     ) -> Option<Device> {
         state.state = Self::end;
 
-        let device = match (state.byte1, new_data) {
-            (FromKeyboard::ID_FIRST_BYTE, FromKeyboard::ID_SECOND_BYTE) => Device::Keyboard,
-            (first_byte, second_byte) => Device::UnknownID {
-                first_byte,
-                second_byte,
-            },
-        };
-
-        Some(device)
+        Some(Device::from_keyboard_id(state.byte1, new_data))
     }
 
     fn end(_state: &mut DeviceIdentifier<T>, _new_data: u8, _device: &mut T) -> Option<Device> {
@@ -103,9 +101,52 @@ impl<T: SendToDevice> DeviceIdentifier<T> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Device {
     Keyboard,
-    Mouse,
+    Mouse(MouseKind),
     UnknownID { first_byte: u8, second_byte: u8 },
 }
+
+impl Device {
+    /// Device of a two byte ID.
+    pub const fn from_keyboard_id(first_byte: u8, second_byte: u8) -> Self {
+        match (first_byte, second_byte) {
+            (FromKeyboard::ID_FIRST_BYTE, FromKeyboard::ID_SECOND_BYTE) => Device::Keyboard,
+            (first_byte, second_byte) => Device::UnknownID {
+                first_byte,
+                second_byte,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseKind {
+    /// ID 0x00. Mice report this ID after reset.
+    Standard,
+    /// ID 0x03, IntelliMouse with scroll wheel.
+    Wheel,
+    /// ID 0x04, IntelliMouse Explorer with scroll wheel and
+    /// buttons 4 and 5.
+    FiveButton,
+}
+
+impl MouseKind {
+    pub const fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0x00 => Some(MouseKind::Standard),
+            0x03 => Some(MouseKind::Wheel),
+            0x04 => Some(MouseKind::FiveButton),
+            _ => None,
+        }
+    }
+
+    pub const fn id(self) -> u8 {
+        match self {
+            MouseKind::Standard => 0x00,
+            MouseKind::Wheel => 0x03,
+            MouseKind::FiveButton => 0x04,
+        }
+    }
+}
@@ -1,3 +1,21 @@
+#[derive(Debug)]
+pub struct WouldBlock;
+
+/// Byte from the device was corrupted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransmissionError {
+    Parity,
+    Timeout,
+}
+
 pub trait SendToDevice {
     fn send(&mut self, data: u8);
+
+    /// Send without waiting for the controller input buffer.
+    ///
+    /// Default implementation calls `send`.
+    fn try_send(&mut self, data: u8) -> Result<(), WouldBlock> {
+        self.send(data);
+        Ok(())
+    }
 }
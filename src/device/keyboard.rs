@@ -1,2 +1,6 @@
 pub mod driver;
 pub mod raw;
+#[cfg(feature = "set3")]
+#[cfg_attr(docsrs, doc(cfg(feature = "set3")))]
+pub mod reporting;
+pub mod translation;
@@ -1,56 +1,222 @@
-use crate::device::command_queue::{Command, CommandQueue, Status};
-use crate::device::io::SendToDevice;
+use crate::device::command_queue::{Command, CommandId, CommandQueue, ResponseTimeouts, Status};
+use crate::device::health::{DeviceHealth, HealthCounters, HealthThresholds};
+use crate::device::identify::Device;
+use crate::device::io::{SendToDevice, TransmissionError};
+use crate::ring_buffer::{FullPolicy, RingBuffer};
+use crate::trace::{Subsystem, TraceEvent, TraceHook, TraceLevel, Tracer};
 
 use core::fmt;
 
 use super::raw::{
-    CommandReturnData, CommandSetAllKeys, CommandSetKeyType, FromKeyboard, StatusIndicators,
+    self, CommandReturnData, CommandSetAllKeys, CommandSetKeyType, FromKeyboard, ScancodePrefix,
+    ScancodeSetNumber, StatusIndicators, TypematicByte,
 };
-
-use arraydeque::Array;
+#[cfg(feature = "set3")]
+use super::reporting::KeyReporting;
 
 pub use pc_keyboard;
 
 use pc_keyboard::{
-    layouts, Error, HandleControl, KeyEvent, Keyboard as KeyboardScancodeDecoder, ScancodeSet1,
-    ScancodeSet2,
+    layouts, Error, HandleControl, KeyCode, KeyEvent, KeyState,
+    Keyboard as KeyboardScancodeDecoder, KeyboardLayout,
 };
 
-pub struct Keyboard<T: Array<Item = Command>> {
-    commands: CommandQueue<T>,
+#[cfg(feature = "set1")]
+use pc_keyboard::ScancodeSet1;
+#[cfg(feature = "set2")]
+use pc_keyboard::ScancodeSet2;
+
+/// Default maximum number of key events which are buffered when
+/// keyboard is grabbed with `GrabMode::Buffer`.
+pub const GRAB_BUFFER_CAPACITY: usize = 16;
+
+/// Keyboard driver with command queue capacity `N`, grab
+/// buffer capacity `G` and scancode decoder layout `K`.
+///
+/// Only layout `K` is compiled in, so embedded users can pick one
+/// layout instead of `Us104Key`.
+pub struct Keyboard<
+    const N: usize,
+    const G: usize = GRAB_BUFFER_CAPACITY,
+    K: DecoderLayout = layouts::Us104Key,
+> {
+    commands: CommandQueue<N>,
     state: State,
-    scancode_reader: ScancodeDecoder,
+    scancode_reader: ScancodeDecoder<K>,
+    grab: Option<GrabMode>,
+    grabbed_events: RingBuffer<KeyEvent, G>,
+    discarded_grabbed_events: u32,
+    grab_policy: FullPolicy,
+    history: ByteHistory,
+    unexplained_bytes: u32,
+    report_unexplained_bytes: bool,
+    protocol_byte_diagnostics: bool,
+    protocol_bytes: u32,
+    tracer: Tracer,
+    desired: Option<DesiredKeyboardState>,
+    believed: BelievedKeyboardState,
+    kind: Option<Device>,
+    health: HealthCounters,
+    overrun_policy: OverrunPolicy,
+    soft_disabled: bool,
+    discarded_while_disabled: u32,
+    init_command: Option<CommandId>,
+    heartbeat: Option<Heartbeat>,
+    idle_ticks: u32,
+    heartbeat_command: Option<CommandId>,
+    heartbeat_failures: u32,
+    #[cfg(feature = "set3")]
+    key_reporting: KeyReporting,
+    #[cfg(feature = "set3")]
+    key_numbers: Option<KeyNumberDecoder>,
+}
+
+/// Settings for `Keyboard::set_heartbeat`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Heartbeat {
+    /// Number of `tick` calls without received bytes or queued
+    /// commands before an echo command is queued.
+    pub idle_ticks: u32,
+    /// Number of failed echo commands in a row which raise
+    /// `KeyboardError::KeyboardUnresponsive`.
+    pub max_failures: u32,
+}
+
+/// Initial keyboard state for `Keyboard::with_initial_state`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InitialState {
+    /// Set defaults and disable scanning. Scanning is enabled
+    /// with `Keyboard::enable`. This is the `new` default.
+    DefaultsDisabled,
+    /// Set defaults without disabling scanning. Keyboards scan
+    /// after power on, so no enable command is needed at boot.
+    DefaultsEnabled,
+    /// Queue no commands. Keyboard keeps the state which firmware
+    /// left and the driver state is unknown.
+    Unchanged,
 }
 
-impl<T: Array<Item = Command>> fmt::Debug for Keyboard<T> {
+/// `Keyboard` with the default US 104-key layout.
+pub type Us104Keyboard<const N: usize, const G: usize = GRAB_BUFFER_CAPACITY> =
+    Keyboard<N, G, layouts::Us104Key>;
+
+impl<const N: usize, const G: usize, K: DecoderLayout> fmt::Debug for Keyboard<N, G, K> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Keyboard")
+        f.write_str("Keyboard")
     }
 }
 
-impl<T: Array<Item = Command>> Keyboard<T> {
+impl<const N: usize, K: DecoderLayout> Keyboard<N, GRAB_BUFFER_CAPACITY, K> {
     pub fn new<U: SendToDevice>(device: &mut U) -> Result<Self, NotEnoughSpaceInTheCommandQueue> {
+        Self::with_grab_buffer_capacity(device)
+    }
+}
+
+impl<const N: usize, const G: usize, K: DecoderLayout> Keyboard<N, G, K> {
+    /// Same as `new` but grab buffer capacity is `G`.
+    pub fn with_grab_buffer_capacity<U: SendToDevice>(
+        device: &mut U,
+    ) -> Result<Self, NotEnoughSpaceInTheCommandQueue> {
+        Self::with_initial_state(device, InitialState::DefaultsDisabled)
+    }
+
+    /// Same as `with_grab_buffer_capacity` but the queued command
+    /// depends on `state`. Boot code can do other work until
+    /// `init_pending` returns `false`.
+    pub fn with_initial_state<U: SendToDevice>(
+        device: &mut U,
+        state: InitialState,
+    ) -> Result<Self, NotEnoughSpaceInTheCommandQueue> {
         let mut keyboard = Self {
             commands: CommandQueue::new(),
             state: State::ScancodesDisabled,
             scancode_reader: ScancodeDecoder::new(),
+            grab: None,
+            grabbed_events: RingBuffer::new(),
+            discarded_grabbed_events: 0,
+            grab_policy: FullPolicy::Saturate,
+            history: ByteHistory::new(),
+            unexplained_bytes: 0,
+            report_unexplained_bytes: false,
+            protocol_byte_diagnostics: false,
+            protocol_bytes: 0,
+            tracer: Tracer::new(),
+            desired: None,
+            believed: BelievedKeyboardState::UNKNOWN,
+            kind: None,
+            health: HealthCounters::new(),
+            overrun_policy: OverrunPolicy::ReportError,
+            soft_disabled: false,
+            discarded_while_disabled: 0,
+            init_command: None,
+            heartbeat: None,
+            idle_ticks: 0,
+            heartbeat_command: None,
+            heartbeat_failures: 0,
+            #[cfg(feature = "set3")]
+            key_reporting: KeyReporting::new(),
+            #[cfg(feature = "set3")]
+            key_numbers: None,
         };
 
-        keyboard.set_defaults_and_disable(device)?;
+        keyboard.init_command = match state {
+            InitialState::DefaultsDisabled => Some(keyboard.set_defaults_and_disable(device)?),
+            InitialState::DefaultsEnabled => Some(keyboard.set_defaults_and_enable(device)?),
+            InitialState::Unchanged => None,
+        };
 
         Ok(keyboard)
     }
 
+    /// Command which the constructor queued.
+    pub fn init_command(&self) -> Option<CommandId> {
+        self.init_command
+    }
+
+    /// Returns `true` if the command which the constructor queued
+    /// is in progress or waiting to be sent.
+    pub fn init_pending(&self) -> bool {
+        let id = match self.init_command {
+            Some(id) => id,
+            None => return false,
+        };
+
+        self.commands.current_command().map(|(current, _)| current) == Some(id)
+            || self
+                .commands
+                .pending_commands()
+                .any(|(pending, _)| pending == id)
+    }
+
+    /// Returns `Some(true)` if the driver expects that scanning is
+    /// enabled. `None` if the state is unknown.
+    pub fn scanning_enabled(&self) -> Option<bool> {
+        self.believed.enabled
+    }
+
+    /// Scancode set which the driver expects the keyboard to use.
+    /// `None` if the setting is unknown.
+    pub fn scancode_set(&self) -> Option<KeyboardScancodeSetting> {
+        self.believed.scancode_set
+    }
+
+    /// Device from the latest `read_id` response. `None` if the
+    /// keyboard has not been identified.
+    pub fn kind(&self) -> Option<Device> {
+        self.kind
+    }
+
     pub fn set_defaults_and_disable<U: SendToDevice>(
         &mut self,
         device: &mut U,
-    ) -> Result<(), NotEnoughSpaceInTheCommandQueue> {
+    ) -> Result<CommandId, NotEnoughSpaceInTheCommandQueue> {
         if self.commands.space_available(1) {
             self.state = State::ScancodesDisabled;
-            self.commands
-                .add(Command::default_disable(), device)
-                .unwrap();
-            Ok(())
+            self.believed = BelievedKeyboardState {
+                enabled: Some(false),
+                ..self.believed.defaults_restored()
+            };
+            Ok(self.add_command(Command::default_disable(), device))
         } else {
             Err(NotEnoughSpaceInTheCommandQueue)
         }
@@ -59,11 +225,11 @@ impl<T: Array<Item = Command>> Keyboard<T> {
     pub fn set_defaults_and_enable<U: SendToDevice>(
         &mut self,
         device: &mut U,
-    ) -> Result<(), NotEnoughSpaceInTheCommandQueue> {
+    ) -> Result<CommandId, NotEnoughSpaceInTheCommandQueue> {
         if self.commands.space_available(1) {
             self.state = State::ScancodesEnabled;
-            self.commands.add(Command::set_default(), device).unwrap();
-            Ok(())
+            self.believed = self.believed.defaults_restored();
+            Ok(self.add_command(Command::set_default(), device))
         } else {
             Err(NotEnoughSpaceInTheCommandQueue)
         }
@@ -72,92 +238,192 @@ impl<T: Array<Item = Command>> Keyboard<T> {
     pub fn enable<U: SendToDevice>(
         &mut self,
         device: &mut U,
-    ) -> Result<(), NotEnoughSpaceInTheCommandQueue> {
+    ) -> Result<CommandId, NotEnoughSpaceInTheCommandQueue> {
         if self.commands.space_available(1) {
             self.state = State::ScancodesEnabled;
-            self.commands.add(Command::enable(), device).unwrap();
-            Ok(())
+            self.believed.enabled = Some(true);
+            Ok(self.add_command(Command::enable(), device))
         } else {
             Err(NotEnoughSpaceInTheCommandQueue)
         }
     }
 
+    /// If the previous LED update is still waiting in the queue,
+    /// it is replaced and its `CommandId` is returned. Only the
+    /// latest state is sent when the command in progress completes.
     pub fn set_status_indicators<U: SendToDevice>(
         &mut self,
         device: &mut U,
         indicators: StatusIndicators,
-    ) -> Result<(), NotEnoughSpaceInTheCommandQueue> {
+    ) -> Result<CommandId, NotEnoughSpaceInTheCommandQueue> {
+        let command = match self
+            .commands
+            .replace_last_pending(Command::set_status_indicators(indicators.bits()))
+        {
+            Ok(id) => {
+                self.believed.leds = Some(indicators);
+                self.tracer.trace(Subsystem::Queue, TraceLevel::Info, || {
+                    TraceEvent::CommandCoalesced(CommandReturnData::SET_STATUS_INDICATORS)
+                });
+                return Ok(id);
+            }
+            Err(command) => command,
+        };
+
         if self.commands.space_available(1) {
-            self.commands
-                .add(Command::set_status_indicators(indicators.bits()), device)
-                .unwrap();
-            Ok(())
+            self.believed.leds = Some(indicators);
+            Ok(self.add_command(command, device))
         } else {
             Err(NotEnoughSpaceInTheCommandQueue)
         }
     }
 
+    #[cfg(feature = "set3")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "set3")))]
     pub fn scancode_set_3_set_all_keys<U: SendToDevice>(
         &mut self,
         device: &mut U,
         set_all_keys: SetAllKeys,
-    ) -> Result<(), NotEnoughSpaceInTheCommandQueue> {
+    ) -> Result<CommandId, NotEnoughSpaceInTheCommandQueue> {
         if self.commands.space_available(1) {
-            self.commands
-                .add(Command::scancode_set_3_set_all_keys(set_all_keys), device)
-                .unwrap();
-            Ok(())
+            Ok(self.add_command(Command::scancode_set_3_set_all_keys(set_all_keys), device))
         } else {
             Err(NotEnoughSpaceInTheCommandQueue)
         }
     }
 
+    #[cfg(feature = "set3")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "set3")))]
     pub fn scancode_set_3_set_key_type<U: SendToDevice>(
         &mut self,
         device: &mut U,
         set_key_type: SetKeyType,
         scancode: u8,
-    ) -> Result<(), NotEnoughSpaceInTheCommandQueue> {
+    ) -> Result<CommandId, NotEnoughSpaceInTheCommandQueue> {
+        if self.commands.space_available(1) {
+            Ok(self.add_command(
+                Command::scancode_set_3_set_key_type(set_key_type, scancode),
+                device,
+            ))
+        } else {
+            Err(NotEnoughSpaceInTheCommandQueue)
+        }
+    }
+
+    /// Queue `scancode_set_3_set_key_type` command for every scancode.
+    ///
+    /// If there is not enough space in the command queue, no commands
+    /// are queued. Returns ID of the last command.
+    #[cfg(feature = "set3")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "set3")))]
+    pub fn scancode_set_3_set_key_types<U: SendToDevice>(
+        &mut self,
+        device: &mut U,
+        set_key_type: SetKeyType,
+        scancodes: &[u8],
+    ) -> Result<Option<CommandId>, NotEnoughSpaceInTheCommandQueue> {
+        if !self.commands.space_available(scancodes.len()) {
+            return Err(NotEnoughSpaceInTheCommandQueue);
+        }
+
+        let mut id = None;
+        for &scancode in scancodes {
+            id = Some(self.add_command(
+                Command::scancode_set_3_set_key_type(set_key_type, scancode),
+                device,
+            ));
+        }
+
+        Ok(id)
+    }
+
+    /// Reset the keyboard. `KeyboardEvent::BATCompleted` is
+    /// returned when the keyboard has completed the reset.
+    pub fn reset<U: SendToDevice>(
+        &mut self,
+        device: &mut U,
+    ) -> Result<CommandId, NotEnoughSpaceInTheCommandQueue> {
         if self.commands.space_available(1) {
-            self.commands
-                .add(
-                    Command::scancode_set_3_set_key_type(set_key_type, scancode),
-                    device,
-                )
-                .unwrap();
-            Ok(())
+            self.believed = BelievedKeyboardState::UNKNOWN;
+            Ok(self.add_command(Command::reset(), device))
         } else {
             Err(NotEnoughSpaceInTheCommandQueue)
         }
     }
 
-    pub fn set_scancode_decoder(&mut self, setting: ScancodeDecoderSetting) {
+    /// Request the keyboard to send its last byte again. The byte
+    /// is handled like any other received byte.
+    ///
+    /// Resend is not queued, because the keyboard does not
+    /// acknowledge it.
+    pub fn request_resend<U: SendToDevice>(&mut self, device: &mut U) {
+        self.commands.request_resend(device);
+    }
+
+    /// See `ScancodeDecoder::change_decoder`.
+    /// Also disables key number output.
+    pub fn set_scancode_decoder(
+        &mut self,
+        setting: ScancodeDecoderSetting,
+    ) -> Result<(), UnsupportedScancodeSet> {
+        #[cfg(feature = "set3")]
+        {
+            self.key_numbers = None;
+        }
         self.scancode_reader.change_decoder(setting)
     }
 
+    /// Return scancode set 3 key numbers as `KeyboardEvent::KeyNumber`
+    /// instead of decoding scancodes with the layout.
+    ///
+    /// Enabled when the keyboard reports that scancode set 3 was
+    /// selected and disabled when set 1 or 2 is selected or the
+    /// keyboard resets. Controller translation must be disabled,
+    /// because translated bytes are not key numbers.
+    #[cfg(feature = "set3")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "set3")))]
+    pub fn set_key_numbers(&mut self, enabled: bool) {
+        self.key_numbers = if enabled {
+            Some(KeyNumberDecoder { release: false })
+        } else {
+            None
+        };
+    }
+
+    #[cfg(feature = "set3")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "set3")))]
+    pub fn key_numbers(&self) -> bool {
+        self.key_numbers.is_some()
+    }
+
     pub fn set_typematic_rate<U: SendToDevice>(
         &mut self,
         device: &mut U,
         delay: DelayMilliseconds,
         rate: RateValue,
-    ) -> Result<(), NotEnoughSpaceInTheCommandQueue> {
+    ) -> Result<CommandId, NotEnoughSpaceInTheCommandQueue> {
         if self.commands.space_available(1) {
-            self.commands
-                .add(Command::set_typematic_rate(delay, rate), device)
-                .unwrap();
-            Ok(())
+            self.believed.typematic = Some(TypematicConfig::from_rate_value(delay, rate));
+            Ok(self.add_command(Command::set_typematic_rate(delay, rate), device))
         } else {
             Err(NotEnoughSpaceInTheCommandQueue)
         }
     }
 
+    pub fn set_typematic<U: SendToDevice>(
+        &mut self,
+        device: &mut U,
+        config: TypematicConfig,
+    ) -> Result<CommandId, NotEnoughSpaceInTheCommandQueue> {
+        self.set_typematic_rate(device, config.delay(), config.rate())
+    }
+
     pub fn read_id<U: SendToDevice>(
         &mut self,
         device: &mut U,
-    ) -> Result<(), NotEnoughSpaceInTheCommandQueue> {
+    ) -> Result<CommandId, NotEnoughSpaceInTheCommandQueue> {
         if self.commands.space_available(1) {
-            self.commands.add(Command::read_id(), device).unwrap();
-            Ok(())
+            Ok(self.add_command(Command::read_id(), device))
         } else {
             Err(NotEnoughSpaceInTheCommandQueue)
         }
@@ -166,10 +432,9 @@ impl<T: Array<Item = Command>> Keyboard<T> {
     pub fn echo<U: SendToDevice>(
         &mut self,
         device: &mut U,
-    ) -> Result<(), NotEnoughSpaceInTheCommandQueue> {
+    ) -> Result<CommandId, NotEnoughSpaceInTheCommandQueue> {
         if self.commands.space_available(1) {
-            self.commands.add(Command::echo(), device).unwrap();
-            Ok(())
+            Ok(self.add_command(Command::echo(), device))
         } else {
             Err(NotEnoughSpaceInTheCommandQueue)
         }
@@ -179,257 +444,1805 @@ impl<T: Array<Item = Command>> Keyboard<T> {
     ///
     /// PS/2 controller scancode translation
     /// must be disabled when using this command.
+    ///
+    /// If the keyboard rejects the selection, scancode set 2 is used
+    /// and `KeyboardEvent::ScancodeSetFallback` is returned instead
+    /// of an error.
+    ///
+    /// Sets which are disabled with features are not selected.
+    ///
+    /// Two commands are queued: the selection and a query of the
+    /// current set. The selection produces `CommandCompleted` or
+    /// `ScancodeSetFallback` and the query produces `ScancodeSet`.
     pub fn set_alternate_scancode_set<U: SendToDevice>(
         &mut self,
         device: &mut U,
         scancode_setting: KeyboardScancodeSetting,
-    ) -> Result<(), NotEnoughSpaceInTheCommandQueue> {
-        if self.commands.space_available(2) {
-            self.commands
-                .add(Command::set_alternate_scancodes(scancode_setting), device)
-                .unwrap();
-            self.commands
-                .add(Command::get_current_scancode_set(), device)
-                .unwrap();
-            Ok(())
+    ) -> Result<ScancodeSetCommands, ScancodeSetError> {
+        if !scancode_setting.supported() {
+            Err(ScancodeSetError::UnsupportedScancodeSet)
+        } else if self.commands.space_available(2) {
+            self.believed.scancode_set = Some(scancode_setting);
+            let select =
+                self.add_command(Command::set_alternate_scancodes(scancode_setting), device);
+            let query = self.add_command(Command::get_current_scancode_set(), device);
+            Ok(ScancodeSetCommands { select, query })
         } else {
-            Err(NotEnoughSpaceInTheCommandQueue)
+            Err(ScancodeSetError::NotEnoughSpaceInTheCommandQueue)
         }
     }
 
-    pub fn receive_data<U: SendToDevice>(
+    /// Set device configuration which `reconcile` applies. The
+    /// configuration is applied again automatically after keyboard reset.
+    pub fn set_desired_state(&mut self, state: Option<DesiredKeyboardState>) {
+        self.desired = state;
+    }
+
+    pub fn desired_state(&self) -> Option<&DesiredKeyboardState> {
+        self.desired.as_ref()
+    }
+
+    /// Queue commands for the settings which differ from the
+    /// believed keyboard state.
+    ///
+    /// If there is not enough space in the command queue, no commands
+    /// are queued.
+    pub fn reconcile<U: SendToDevice>(
         &mut self,
-        new_data: u8,
         device: &mut U,
-    ) -> Result<Option<KeyboardEvent>, KeyboardError> {
-        match new_data {
-            FromKeyboard::KEY_DETECTION_OVERRUN_SCANCODE_SET_1
-            | FromKeyboard::KEY_DETECTION_OVERRUN_SCANCODE_SET_2_AND_3 => {
-                return Err(KeyboardError::KeyDetectionError);
-            }
-            FromKeyboard::BAT_FAILURE_CODE => return Err(KeyboardError::BATCompletionFailure),
-            FromKeyboard::BAT_COMPLETION_CODE => {
-                self.state = State::ScancodesEnabled;
-                self.set_scancode_decoder(ScancodeDecoderSetting::Set2);
-                return Ok(Some(KeyboardEvent::BATCompleted));
-            }
-            _ => (),
+    ) -> Result<(), NotEnoughSpaceInTheCommandQueue> {
+        let desired = match self.desired {
+            Some(desired) => desired,
+            None => return Ok(()),
+        };
+
+        let disable = !desired.enabled && self.believed.enabled != Some(false);
+        let believed = if disable {
+            self.believed.defaults_restored()
+        } else {
+            self.believed
+        };
+
+        let set_scancode_set =
+            believed.scancode_set != Some(desired.scancode_set) && desired.scancode_set.supported();
+        let set_typematic = believed.typematic != Some(desired.typematic);
+        let set_leds = believed.leds != Some(desired.leds);
+        let enable = desired.enabled && believed.enabled != Some(true);
+
+        let command_count = disable as usize
+            + 2 * set_scancode_set as usize
+            + set_typematic as usize
+            + set_leds as usize
+            + enable as usize;
+
+        if !self.commands.space_available(command_count) {
+            return Err(NotEnoughSpaceInTheCommandQueue);
         }
 
-        if self.commands.empty() {
-            if new_data == FromKeyboard::RESEND {
-                return Ok(None);
-            }
+        if disable {
+            self.set_defaults_and_disable(device)?;
+        }
 
-            self.scancode_reader
-                .decode(new_data)
-                .map(|o| o.map(KeyboardEvent::Key))
-                .map_err(KeyboardError::ScancodeParsingError)
-        } else {
-            match self.commands.receive_data(new_data, device) {
-                Some(Status::CommandFinished(Command::SendCommandAndDataSingleAck {
-                    scancode_received_after_this_command: data,
-                    ..
-                }))
-                | Some(Status::UnexpectedData(data)) => self
-                    .scancode_reader
-                    .decode(data)
-                    .map(|o| o.map(KeyboardEvent::Key))
-                    .map_err(KeyboardError::ScancodeParsingError),
-                Some(Status::CommandFinished(Command::AckResponseWithReturnTwoBytes {
-                    command: CommandReturnData::READ_ID,
-                    byte1,
-                    byte2,
-                    ..
-                })) => Ok(Some(KeyboardEvent::ID { byte1, byte2 })),
-                Some(Status::CommandFinished(Command::SendCommandAndDataAndReceiveResponse {
-                    command: CommandReturnData::SELECT_ALTERNATE_SCANCODES,
-                    response,
-                    ..
-                })) => {
-                    let setting = match response {
-                        1 => {
-                            self.set_scancode_decoder(ScancodeDecoderSetting::Set1);
-                            Ok(KeyboardScancodeSetting::Set1)
-                        }
-                        2 => {
-                            self.set_scancode_decoder(ScancodeDecoderSetting::Set2);
-                            Ok(KeyboardScancodeSetting::Set2)
-                        }
-                        3 => Ok(KeyboardScancodeSetting::Set3), // TODO: ScancodeDecoderSetting::Set3
-                        scancode_set_number => {
-                            Err(KeyboardError::UnknownScancodeSet(scancode_set_number))
-                        }
-                    };
+        if set_scancode_set {
+            // Space and support were checked.
+            let _ = self.set_alternate_scancode_set(device, desired.scancode_set);
+        }
 
-                    setting.map(|scancode_set| Some(KeyboardEvent::ScancodeSet(scancode_set)))
-                }
-                Some(Status::CommandFinished(Command::Echo { .. })) => {
-                    Ok(Some(KeyboardEvent::Echo))
-                }
-                Some(_) | None => Ok(None),
-            }
+        if set_typematic {
+            self.set_typematic(device, desired.typematic)?;
+        }
+
+        if set_leds {
+            self.set_status_indicators(device, desired.leds)?;
+        }
+
+        if enable {
+            self.enable(device)?;
         }
+
+        Ok(())
     }
-}
 
-#[derive(Debug)]
-pub struct ScancodeDecoder {
-    current_decoder: Decoder,
-}
+    /// Set hook for keyboard and command queue trace events.
+    pub fn set_trace_hook(&mut self, hook: Option<TraceHook>) {
+        self.tracer.set_hook(hook);
+    }
 
-impl Default for ScancodeDecoder {
-    fn default() -> Self {
-        Self::new()
+    /// Only `Subsystem::Keyboard` and `Subsystem::Queue` levels
+    /// are used by this driver.
+    pub fn set_trace_level(&mut self, subsystem: Subsystem, level: TraceLevel) {
+        self.tracer.set_level(subsystem, level);
     }
-}
 
-impl ScancodeDecoder {
-    /// Defaults to scancode set 2.
-    pub fn new() -> Self {
-        Self {
-            current_decoder: Decoder::Set2(KeyboardScancodeDecoder::new(
-                layouts::Us104Key,
-                ScancodeSet2,
-                HandleControl::Ignore,
-            )),
+    fn add_command<U: SendToDevice>(&mut self, command: Command, device: &mut U) -> CommandId {
+        self.tracer.trace(Subsystem::Queue, TraceLevel::Info, || {
+            TraceEvent::CommandQueued(command.command_byte())
+        });
+        match self.commands.add(command, device) {
+            Ok(id) => id,
+            // Callers check that there is space.
+            Err(_) => unreachable!(),
         }
     }
 
-    pub fn decode(&mut self, scancode: u8) -> Result<Option<KeyEvent>, Error> {
-        match &mut self.current_decoder {
-            Decoder::Set1(decoder) => decoder.add_byte(scancode),
-            Decoder::Set2(decoder) => decoder.add_byte(scancode),
-        }
+    /// Commands which are in progress or waiting to be sent.
+    pub fn command_queue(&self) -> &CommandQueue<N> {
+        &self.commands
     }
 
-    pub fn change_decoder(&mut self, setting: ScancodeDecoderSetting) {
-        match setting {
-            ScancodeDecoderSetting::Set1 => {
-                self.current_decoder = Decoder::Set1(KeyboardScancodeDecoder::new(
-                    layouts::Us104Key,
-                    ScancodeSet1,
-                    HandleControl::Ignore,
-                ))
+    /// Set how many times a command is sent again when the keyboard
+    /// requests resend.
+    pub fn set_max_resends(&mut self, max_resends: u8) {
+        self.commands.set_max_resends(max_resends);
+    }
+
+    pub fn set_response_timeouts(&mut self, timeouts: ResponseTimeouts) {
+        self.commands.set_response_timeouts(timeouts);
+    }
+
+    /// Advance command response timer and heartbeat. Call this
+    /// periodically with the time elapsed since the previous call.
+    pub fn tick<U: SendToDevice>(
+        &mut self,
+        elapsed_milliseconds: u32,
+        device: &mut U,
+    ) -> Result<Option<KeyboardEvent>, KeyboardError> {
+        match self.commands.tick(elapsed_milliseconds, device) {
+            Some(Status::CommandTimeout(id, command)) => {
+                self.health.commands = self.health.commands.wrapping_add(1);
+                self.health.command_timeouts = self.health.command_timeouts.wrapping_add(1);
+                let command_byte = command.command_byte();
+                self.tracer.trace(Subsystem::Queue, TraceLevel::Error, || {
+                    TraceEvent::CommandTimeout(command_byte)
+                });
+                if self.heartbeat_command == Some(id) {
+                    return self.heartbeat_failed();
+                }
+                if command_byte == CommandReturnData::SELECT_ALTERNATE_SCANCODES {
+                    return Ok(self.scancode_set_fallback(id, &command));
+                }
+                self.believed = BelievedKeyboardState::UNKNOWN;
+                Err(KeyboardError::CommandTimeout {
+                    id,
+                    command: command_byte,
+                })
             }
-            ScancodeDecoderSetting::Set2 => {
-                self.current_decoder = Decoder::Set2(KeyboardScancodeDecoder::new(
-                    layouts::Us104Key,
-                    ScancodeSet2,
-                    HandleControl::Ignore,
-                ))
+            _ => {
+                self.heartbeat_tick(device);
+                Ok(None)
             }
         }
     }
-}
 
-enum Decoder {
-    Set1(KeyboardScancodeDecoder<layouts::Us104Key, ScancodeSet1>),
-    Set2(KeyboardScancodeDecoder<layouts::Us104Key, ScancodeSet2>),
-}
+    /// Queue an echo command when the keyboard has been idle for
+    /// `Heartbeat::idle_ticks` calls of `tick`. Heartbeat echo
+    /// responses are not returned as `KeyboardEvent::Echo`.
+    /// Default is `None`.
+    ///
+    /// Returns an error if `heartbeat` is `Some` and command queue
+    /// capacity `N` is zero.
+    pub fn set_heartbeat(
+        &mut self,
+        heartbeat: Option<Heartbeat>,
+    ) -> Result<(), NotEnoughSpaceInTheCommandQueue> {
+        if heartbeat.is_some() && N == 0 {
+            return Err(NotEnoughSpaceInTheCommandQueue);
+        }
 
-impl fmt::Debug for Decoder {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Decoder")
+        self.heartbeat = heartbeat;
+        self.idle_ticks = 0;
+        self.heartbeat_failures = 0;
+        Ok(())
     }
-}
-
-#[derive(Debug)]
-#[repr(u8)]
-pub enum KeyboardScancodeSetting {
-    Set1 = 1,
-    Set2,
-    Set3,
-}
 
-#[derive(Debug)]
-pub enum ScancodeDecoderSetting {
-    Set1,
-    Set2,
-}
+    /// Scancode set 3 key reporting modes which the driver has set.
+    #[cfg(feature = "set3")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "set3")))]
+    pub fn key_reporting(&self) -> &KeyReporting {
+        &self.key_reporting
+    }
 
-#[derive(Debug)]
-pub enum KeyboardError {
-    KeyDetectionError,
-    BATCompletionFailure,
-    UnknownScancodeSet(u8),
-    ScancodeParsingError(Error),
-}
+    /// Number of failed heartbeat echo commands in a row.
+    pub fn heartbeat_failures(&self) -> u32 {
+        self.heartbeat_failures
+    }
 
-#[derive(Debug)]
-pub enum KeyboardEvent {
-    Key(KeyEvent),
-    BATCompleted,
-    ID { byte1: u8, byte2: u8 },
-    ScancodeSet(KeyboardScancodeSetting),
-    Echo,
-}
+    fn heartbeat_tick<U: SendToDevice>(&mut self, device: &mut U) {
+        let heartbeat = match self.heartbeat {
+            Some(heartbeat) => heartbeat,
+            None => return,
+        };
 
-#[derive(Debug)]
-pub struct NotEnoughSpaceInTheCommandQueue;
+        if !self.commands.empty() || !self.commands.space_available(1) {
+            self.idle_ticks = 0;
+            return;
+        }
 
-#[derive(Debug)]
-enum State {
-    ScancodesDisabled,
-    ScancodesEnabled,
-}
+        self.idle_ticks = self.idle_ticks.saturating_add(1);
+        if self.idle_ticks >= heartbeat.idle_ticks {
+            self.idle_ticks = 0;
+            self.heartbeat_command = Some(self.add_command(Command::echo(), device));
+        }
+    }
 
-#[derive(Debug)]
-#[repr(u8)]
-pub enum SetAllKeys {
-    Typematic = CommandSetAllKeys::TYPEMATIC,
-    MakeSlashBreak = CommandSetAllKeys::MAKE_SLASH_BREAK,
-    Make = CommandSetAllKeys::MAKE,
-    TypematicSlashMakeSlashBreak = CommandSetAllKeys::TYPEMATIC_SLASH_MAKE_SLASH_BREAK,
-}
+    fn heartbeat_failed(&mut self) -> Result<Option<KeyboardEvent>, KeyboardError> {
+        self.heartbeat_command = None;
+        self.heartbeat_failures = self.heartbeat_failures.saturating_add(1);
+        match self.heartbeat {
+            Some(heartbeat) if self.heartbeat_failures == heartbeat.max_failures => {
+                Err(KeyboardError::KeyboardUnresponsive {
+                    failures: self.heartbeat_failures,
+                })
+            }
+            _ => Ok(None),
+        }
+    }
 
-#[derive(Debug)]
-#[repr(u8)]
-pub enum SetKeyType {
-    Typematic = CommandSetKeyType::TYPEMATIC,
-    MakeSlashBreak = CommandSetKeyType::MAKE_SLASH_BREAK,
-    Make = CommandSetKeyType::MAKE,
-}
+    /// Continue sending commands if `SendToDevice::try_send` returned
+    /// `WouldBlock` previously.
+    ///
+    /// Returns `true` if there is no unsent data after the call.
+    pub fn pump<U: SendToDevice>(&mut self, device: &mut U) -> bool {
+        self.commands.pump(device)
+    }
 
-#[derive(Debug)]
-#[repr(u8)]
-pub enum DelayMilliseconds {
-    Delay250 = 0,
-    /// Default value.
-    Delay500 = 0b0010_0000,
-    Delay750 = 0b0100_0000,
-    Delay1000 = 0b0110_0000,
-}
+    /// Set how key detection overrun is handled. Default is
+    /// `OverrunPolicy::ReportError`.
+    pub fn set_overrun_policy(&mut self, policy: OverrunPolicy) {
+        self.overrun_policy = policy;
+    }
 
-#[derive(Debug)]
-pub struct RateValue(u8);
+    pub fn overrun_policy(&self) -> OverrunPolicy {
+        self.overrun_policy
+    }
 
-impl RateValue {
-    /// 30 Hz
-    pub const RATE_MAX: RateValue = RateValue(0);
+    /// Key state is unreliable after overrun. Decoder state is reset
+    /// so that there are no stuck modifier keys.
+    fn recover_from_overrun<U: SendToDevice>(
+        &mut self,
+        device: &mut U,
+        resync_leds: bool,
+    ) -> Result<Option<KeyboardEvent>, KeyboardError> {
+        self.scancode_reader.reset();
+        #[cfg(feature = "set3")]
+        if let Some(decoder) = &mut self.key_numbers {
+            decoder.release = false;
+        }
 
-    /// 2 Hz
-    pub const RATE_MIN: RateValue = RateValue(0b0001_1111);
+        if resync_leds {
+            if let Some(leds) = self.believed.leds {
+                // If the queue is full, LEDs are not updated.
+                let _ = self.set_status_indicators(device, leds);
+            }
+        }
 
-    /// 10,9 Hz
-    pub const RATE_DEFAULT: RateValue = RateValue(0b0000_1011);
+        Ok(Some(KeyboardEvent::AllKeysReleased))
+    }
 
-    /// Create new `RateValue`.
+    /// Stop delivering key events from `receive_data`.
     ///
-    /// # Panics
-    /// If `value & !0b0001_1111 != 0`.
-    pub fn new(value: u8) -> Self {
-        if value & !0b0001_1111 != 0 {
-            panic!(
-                "rate value is out of range. '{} & !0b0001_1111 != 0'",
-                value
-            );
-        }
+    /// Key events are buffered or dropped depending on the mode until
+    /// `ungrab` is called. Buffered events can be read with
+    /// `next_grabbed_event`. No commands are sent to the keyboard, so
+    /// typematic and LED state of the device is not changed.
+    pub fn grab(&mut self, mode: GrabMode) {
+        self.grab = Some(mode);
+    }
 
-        RateValue(value)
+    /// Restore normal key event delivery. Grabbed events which were
+    /// not read are discarded.
+    pub fn ungrab(&mut self) {
+        self.grab = None;
+        self.grabbed_events.clear();
     }
 
-    pub fn value(&self) -> u8 {
-        self.0
+    pub fn grabbed(&self) -> bool {
+        self.grab.is_some()
+    }
+
+    pub fn next_grabbed_event(&mut self) -> Option<KeyEvent> {
+        self.grabbed_events.pop_front()
+    }
+
+    /// Key event to discard when the grab buffer is full. Default is
+    /// `FullPolicy::Saturate`.
+    pub fn set_grab_policy(&mut self, policy: FullPolicy) {
+        self.grab_policy = policy;
+    }
+
+    pub fn grab_policy(&self) -> FullPolicy {
+        self.grab_policy
+    }
+
+    /// Count of key events which were dropped while keyboard was grabbed.
+    pub fn discarded_grabbed_events(&self) -> u32 {
+        self.discarded_grabbed_events
+    }
+
+    /// Send `DEFAULT_DISABLE` and discard scancode bytes until
+    /// `soft_enable` is called. Discarded bytes are counted in
+    /// `discarded_while_disabled`. Command responses are still
+    /// processed. If the keyboard sends BAT completion code, it is
+    /// disabled again.
+    ///
+    /// Intended for secure input, where key presses must not reach
+    /// normal consumers.
+    pub fn soft_disable<U: SendToDevice>(
+        &mut self,
+        device: &mut U,
+    ) -> Result<CommandId, NotEnoughSpaceInTheCommandQueue> {
+        let id = self.set_defaults_and_disable(device)?;
+        self.soft_disabled = true;
+        self.scancode_reader.reset();
+        Ok(id)
+    }
+
+    /// Enable scanning and stop discarding bytes. Discard count is
+    /// not reset.
+    pub fn soft_enable<U: SendToDevice>(
+        &mut self,
+        device: &mut U,
+    ) -> Result<CommandId, NotEnoughSpaceInTheCommandQueue> {
+        let id = self.enable(device)?;
+        self.soft_disabled = false;
+        Ok(id)
+    }
+
+    pub fn soft_disabled(&self) -> bool {
+        self.soft_disabled
+    }
+
+    /// Count of bytes discarded after `soft_disable`.
+    pub fn discarded_while_disabled(&self) -> u32 {
+        self.discarded_while_disabled
+    }
+
+    pub fn receive_data<U: SendToDevice>(
+        &mut self,
+        new_data: u8,
+        device: &mut U,
+    ) -> Result<Option<KeyboardEvent>, KeyboardError> {
+        match self.handle_data(new_data, device) {
+            Ok(Some(KeyboardEvent::Key(key_event))) if self.grab.is_some() => {
+                self.grab_key_event(key_event);
+                Ok(None)
+            }
+            result => result,
+        }
+    }
+
+    /// Handle a corrupted byte which the controller discarded.
+    ///
+    /// Keyboard is requested to send the byte again.
+    pub fn receive_transmission_error<U: SendToDevice>(
+        &mut self,
+        error: TransmissionError,
+        device: &mut U,
+    ) -> Result<Option<KeyboardEvent>, KeyboardError> {
+        self.health.transmission_errors = self.health.transmission_errors.wrapping_add(1);
+        self.commands.request_resend(device);
+        Ok(Some(KeyboardEvent::TransmissionError(error)))
+    }
+
+    /// Count of corrupted bytes.
+    pub fn transmission_errors(&self) -> u32 {
+        self.health.transmission_errors
+    }
+
+    /// Error statistics since the driver was created.
+    pub fn health_counters(&self) -> &HealthCounters {
+        &self.health
+    }
+
+    pub fn health(&self, thresholds: &HealthThresholds) -> DeviceHealth {
+        self.health.health(thresholds)
+    }
+
+    fn grab_key_event(&mut self, key_event: KeyEvent) {
+        let buffered = match &self.grab {
+            Some(GrabMode::Buffer) => self
+                .grabbed_events
+                .push_back_with_policy(key_event, self.grab_policy)
+                .is_none(),
+            Some(GrabMode::Drop) | None => false,
+        };
+
+        if !buffered {
+            self.discarded_grabbed_events = self.discarded_grabbed_events.wrapping_add(1);
+        }
+    }
+
+    /// Count of received bytes which were not expected command
+    /// responses or plausible scancodes. These bytes are not decoded.
+    pub fn unexplained_bytes(&self) -> u32 {
+        self.unexplained_bytes
+    }
+
+    /// If enabled, `receive_data` returns `DiagnosticEvent::UnexplainedByte`
+    /// for bytes which are not decoded. Default is disabled.
+    pub fn report_unexplained_bytes(&mut self, enabled: bool) {
+        self.report_unexplained_bytes = enabled;
+    }
+
+    /// If enabled, `ECHO` and `ACK` bytes which are received when
+    /// there is no command in progress are counted in
+    /// `protocol_bytes` and returned as `DiagnosticEvent::ProtocolByte`
+    /// instead of being decoded. Some firmware and devices send these
+    /// bytes on their own. Default is disabled.
+    pub fn set_protocol_byte_diagnostics(&mut self, enabled: bool) {
+        self.protocol_byte_diagnostics = enabled;
+    }
+
+    /// Count of `DiagnosticEvent::ProtocolByte` events.
+    pub fn protocol_bytes(&self) -> u32 {
+        self.protocol_bytes
+    }
+
+    /// Latest bytes received from the keyboard.
+    pub fn byte_history(&self) -> &ByteHistory {
+        &self.history
+    }
+
+    fn unexplained_byte(&mut self, data: u8) -> Option<KeyboardEvent> {
+        self.unexplained_bytes = self.unexplained_bytes.wrapping_add(1);
+        self.tracer
+            .trace(Subsystem::Keyboard, TraceLevel::Error, || {
+                TraceEvent::UnexplainedByte(data)
+            });
+
+        if self.report_unexplained_bytes {
+            Some(KeyboardEvent::Diagnostic(
+                DiagnosticEvent::UnexplainedByte {
+                    byte: data,
+                    history: self.history,
+                },
+            ))
+        } else {
+            None
+        }
+    }
+
+    fn decode(&mut self, data: u8) -> Result<Option<KeyboardEvent>, KeyboardError> {
+        if self.soft_disabled {
+            self.discarded_while_disabled = self.discarded_while_disabled.wrapping_add(1);
+            return Ok(None);
+        }
+
+        #[cfg(feature = "set3")]
+        if let Some(decoder) = &mut self.key_numbers {
+            if !decoder.plausible_byte(data) {
+                decoder.release = false;
+                return Ok(self.unexplained_byte(data));
+            }
+            return Ok(decoder.decode(data));
+        }
+
+        if !self.scancode_reader.plausible_byte(data) {
+            return Ok(self.unexplained_byte(data));
+        }
+
+        self.scancode_reader
+            .decode(data)
+            .map(|o| o.map(KeyboardEvent::Key))
+            .map_err(KeyboardError::ScancodeParsingError)
+    }
+
+    fn in_scancode(&self) -> bool {
+        #[cfg(feature = "set3")]
+        if let Some(decoder) = &self.key_numbers {
+            return decoder.release;
+        }
+
+        self.scancode_reader.in_sequence()
+    }
+
+    fn handle_data<U: SendToDevice>(
+        &mut self,
+        new_data: u8,
+        device: &mut U,
+    ) -> Result<Option<KeyboardEvent>, KeyboardError> {
+        self.history.push(new_data);
+        self.health.bytes_received = self.health.bytes_received.wrapping_add(1);
+        self.idle_ticks = 0;
+        self.tracer
+            .trace(Subsystem::Keyboard, TraceLevel::Verbose, || {
+                TraceEvent::ByteReceived(new_data)
+            });
+
+        if self.protocol_byte_diagnostics
+            && self.commands.empty()
+            && matches!(new_data, FromKeyboard::ECHO | FromKeyboard::ACK)
+        {
+            self.protocol_bytes = self.protocol_bytes.wrapping_add(1);
+            return Ok(Some(KeyboardEvent::Diagnostic(
+                DiagnosticEvent::ProtocolByte { byte: new_data },
+            )));
+        }
+
+        // Keyboard sends the rest of the scancode after a prefix, so
+        // the byte belongs to the scancode even if a command is in
+        // progress. ACK and RESEND are never part of a scancode.
+        if self.in_scancode() && new_data != FromKeyboard::ACK && new_data != FromKeyboard::RESEND {
+            return self.decode(new_data);
+        }
+
+        match new_data {
+            FromKeyboard::KEY_DETECTION_OVERRUN_SCANCODE_SET_1
+            | FromKeyboard::KEY_DETECTION_OVERRUN_SCANCODE_SET_2_AND_3 => {
+                return match self.overrun_policy {
+                    OverrunPolicy::ReportError => Err(KeyboardError::KeyDetectionError),
+                    OverrunPolicy::ReleaseAllKeys { resync_leds } => {
+                        self.recover_from_overrun(device, resync_leds)
+                    }
+                };
+            }
+            FromKeyboard::BAT_FAILURE_CODE => {
+                self.health.bat_failures = self.health.bat_failures.wrapping_add(1);
+                return Err(KeyboardError::BATCompletionFailure);
+            }
+            FromKeyboard::BAT_COMPLETION_CODE
+                if !self.scancode_reader.bat_completion_code_is_scancode() =>
+            {
+                self.health.bats = self.health.bats.wrapping_add(1);
+                self.state = State::ScancodesEnabled;
+                // Without the `set2` feature the controller is expected
+                // to translate to set 1, so the decoder is kept.
+                let _ = self.set_scancode_decoder(ScancodeDecoderSetting::Set2);
+                self.believed = BelievedKeyboardState::AFTER_RESET;
+                #[cfg(feature = "set3")]
+                self.key_reporting.set_unknown();
+                if self.soft_disabled {
+                    // If the queue is full, bytes are still discarded.
+                    let _ = self.set_defaults_and_disable(device);
+                } else {
+                    // If the queue is full, the user can call `reconcile` later.
+                    let _ = self.reconcile(device);
+                }
+                return Ok(Some(KeyboardEvent::BATCompleted));
+            }
+            _ => (),
+        }
+
+        if self.commands.empty() {
+            if new_data == FromKeyboard::RESEND {
+                return Ok(None);
+            }
+
+            self.decode(new_data)
+        } else {
+            if new_data == FromKeyboard::RESEND {
+                self.health.resends = self.health.resends.wrapping_add(1);
+            }
+
+            let status = self.commands.receive_data(new_data, device);
+
+            if let Some(Status::CommandFinished(..) | Status::CommandFailed(..)) = &status {
+                self.health.commands = self.health.commands.wrapping_add(1);
+            }
+
+            #[cfg(feature = "set3")]
+            if let Some(Status::CommandFinished(_, command)) = &status {
+                self.key_reporting.command_finished(command);
+            }
+
+            if let Some(Status::CommandFinished(_, command)) = &status {
+                let command = command.command_byte();
+                self.tracer
+                    .trace(Subsystem::Queue, TraceLevel::Verbose, || {
+                        TraceEvent::CommandFinished(command)
+                    });
+            }
+
+            match status {
+                Some(Status::CommandFinished(
+                    _,
+                    Command::SendCommandAndDataSingleAck {
+                        scancode_received_after_this_command: data,
+                        ..
+                    },
+                ))
+                | Some(Status::Interleaved(data)) => self.decode(data),
+                Some(Status::UnexpectedData(data)) => Ok(self.unexplained_byte(data)),
+                Some(Status::CommandFinished(
+                    id,
+                    Command::AckResponseWithReturnTwoBytes {
+                        command: CommandReturnData::READ_ID,
+                        byte1,
+                        byte2,
+                        ..
+                    },
+                )) => {
+                    self.kind = Some(Device::from_keyboard_id(byte1, byte2));
+                    Ok(Some(KeyboardEvent::ID { id, byte1, byte2 }))
+                }
+                Some(Status::CommandFinished(
+                    id,
+                    Command::SendCommandAndDataAndReceiveResponse {
+                        command: CommandReturnData::SELECT_ALTERNATE_SCANCODES,
+                        response,
+                        ..
+                    },
+                )) => {
+                    let setting = match response {
+                        ScancodeSetNumber::SET_1 => {
+                            // Only supported sets are selected.
+                            let _ = self.set_scancode_decoder(ScancodeDecoderSetting::Set1);
+                            Ok(KeyboardScancodeSetting::Set1)
+                        }
+                        ScancodeSetNumber::SET_2 => {
+                            let _ = self.set_scancode_decoder(ScancodeDecoderSetting::Set2);
+                            Ok(KeyboardScancodeSetting::Set2)
+                        }
+                        ScancodeSetNumber::SET_3 => {
+                            #[cfg(feature = "set3")]
+                            self.set_key_numbers(true);
+                            Ok(KeyboardScancodeSetting::Set3)
+                        }
+                        scancode_set_number => {
+                            Err(KeyboardError::UnknownScancodeSet(scancode_set_number))
+                        }
+                    };
+
+                    self.believed.scancode_set = setting.as_ref().ok().copied();
+
+                    setting.map(|setting| Some(KeyboardEvent::ScancodeSet { id, setting }))
+                }
+                Some(Status::CommandFinished(
+                    id,
+                    Command::SendCommandAndData {
+                        command: CommandReturnData::SET_TYPEMATIC_RATE,
+                        data,
+                        ..
+                    },
+                )) => {
+                    let config = TypematicConfig::from_typematic_byte(data);
+                    self.believed.typematic = Some(config);
+                    Ok(Some(KeyboardEvent::Typematic { id, config }))
+                }
+                Some(Status::CommandFinished(id, Command::Echo { .. })) => {
+                    if self.heartbeat_command == Some(id) {
+                        self.heartbeat_command = None;
+                        self.heartbeat_failures = 0;
+                        return Ok(None);
+                    }
+                    Ok(Some(KeyboardEvent::Echo { id }))
+                }
+                Some(Status::CommandFinished(id, _)) => {
+                    Ok(Some(KeyboardEvent::CommandCompleted(id)))
+                }
+                Some(Status::CommandFailed(id, command)) => {
+                    self.health.command_failures = self.health.command_failures.wrapping_add(1);
+                    if self.heartbeat_command == Some(id) {
+                        return self.heartbeat_failed();
+                    }
+                    if command.command_byte() == CommandReturnData::SELECT_ALTERNATE_SCANCODES {
+                        return Ok(self.scancode_set_fallback(id, &command));
+                    }
+                    self.believed = BelievedKeyboardState::UNKNOWN;
+                    Err(KeyboardError::CommandFailed {
+                        id,
+                        command: command.command_byte(),
+                    })
+                }
+                Some(_) | None => Ok(None),
+            }
+        }
+    }
+
+    /// Some keyboards don't support scancode set selection. Keyboard
+    /// is assumed to stay in scancode set 2, which is the default set,
+    /// and the decoder is changed to match it. Failed scancode set
+    /// query after a failed selection is not reported.
+    fn scancode_set_fallback(&mut self, id: CommandId, command: &Command) -> Option<KeyboardEvent> {
+        let _ = self.set_scancode_decoder(ScancodeDecoderSetting::Set2);
+        self.believed.scancode_set = Some(KeyboardScancodeSetting::Set2);
+
+        let requested = match command {
+            Command::SendCommandAndData {
+                data: ScancodeSetNumber::SET_1,
+                ..
+            } => KeyboardScancodeSetting::Set1,
+            Command::SendCommandAndData {
+                data: ScancodeSetNumber::SET_2,
+                ..
+            } => KeyboardScancodeSetting::Set2,
+            Command::SendCommandAndData {
+                data: ScancodeSetNumber::SET_3,
+                ..
+            } => KeyboardScancodeSetting::Set3,
+            _ => return None,
+        };
+
+        Some(KeyboardEvent::ScancodeSetFallback {
+            id,
+            requested,
+            active: KeyboardScancodeSetting::Set2,
+        })
+    }
+}
+
+/// Keyboard layouts which `ScancodeDecoder` can create when
+/// the scancode set changes.
+///
+/// Implemented for `pc_keyboard` layouts. The layout does not change
+/// decoded `KeyEvent`s but it is a type parameter of the
+/// `pc_keyboard` decoder.
+pub trait DecoderLayout: KeyboardLayout {
+    fn new() -> Self;
+}
+
+macro_rules! impl_decoder_layout {
+    ($($layout:ident),*) => {
+        $(
+            impl DecoderLayout for layouts::$layout {
+                fn new() -> Self {
+                    layouts::$layout
+                }
+            }
+        )*
+    };
+}
+
+impl_decoder_layout!(Azerty, Dvorak104Key, Jis109Key, Uk105Key, Us104Key);
+
+/// Scancode set 3 key number output.
+#[cfg(feature = "set3")]
+#[derive(Debug, Clone, Copy)]
+struct KeyNumberDecoder {
+    /// Previous byte was the release prefix.
+    release: bool,
+}
+
+#[cfg(feature = "set3")]
+impl KeyNumberDecoder {
+    fn plausible_byte(&self, byte: u8) -> bool {
+        matches!(
+            byte,
+            raw::ScancodeSet3::FIRST_SCANCODE
+                ..=raw::ScancodeSet3::LAST_SCANCODE | ScancodePrefix::RELEASE
+        ) && !(self.release && byte == ScancodePrefix::RELEASE)
+    }
+
+    fn decode(&mut self, byte: u8) -> Option<KeyboardEvent> {
+        if byte == ScancodePrefix::RELEASE {
+            self.release = true;
+            return None;
+        }
+
+        let state = if self.release {
+            KeyState::Up
+        } else {
+            KeyState::Down
+        };
+        self.release = false;
+
+        Some(KeyboardEvent::KeyNumber {
+            number: byte,
+            state,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct ScancodeDecoder<K: DecoderLayout = layouts::Us104Key> {
+    current_decoder: Decoder<K>,
+    sequence: Sequence,
+    set_1_left_shift_down: bool,
+}
+
+/// Position in a multi-byte scancode.
+#[derive(Debug, Clone, Copy)]
+enum Sequence {
+    Start,
+    Extended,
+    /// Scancode set 2 release prefix.
+    #[cfg(feature = "set2")]
+    Release,
+    #[cfg(feature = "set2")]
+    ExtendedRelease,
+    Pause {
+        remaining: u8,
+    },
+}
+
+impl<K: DecoderLayout> Default for ScancodeDecoder<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: DecoderLayout> ScancodeDecoder<K> {
+    /// Defaults to scancode set 2, or set 1 if the `set2` feature
+    /// is disabled.
+    pub fn new() -> Self {
+        #[cfg(feature = "set2")]
+        let current_decoder = Decoder::Set2(KeyboardScancodeDecoder::new(
+            K::new(),
+            ScancodeSet2,
+            HandleControl::Ignore,
+        ));
+        #[cfg(not(feature = "set2"))]
+        let current_decoder = Decoder::Set1(KeyboardScancodeDecoder::new(
+            K::new(),
+            ScancodeSet1,
+            HandleControl::Ignore,
+        ));
+
+        Self {
+            current_decoder,
+            sequence: Sequence::Start,
+            set_1_left_shift_down: false,
+        }
+    }
+
+    /// Decoder state is kept between calls, so bytes of one
+    /// scancode can be decoded with separate calls.
+    pub fn decode(&mut self, scancode: u8) -> Result<Option<KeyEvent>, Error> {
+        match (self.sequence, scancode) {
+            (Sequence::Pause { remaining }, _) => {
+                if remaining > 1 {
+                    self.sequence = Sequence::Pause {
+                        remaining: remaining - 1,
+                    };
+                    return Ok(None);
+                }
+
+                self.sequence = Sequence::Start;
+                return Ok(Some(KeyEvent::new(KeyCode::PauseBreak, KeyState::Down)));
+            }
+            (Sequence::Start, ScancodePrefix::PAUSE) => {
+                let length = match &self.current_decoder {
+                    #[cfg(feature = "set1")]
+                    Decoder::Set1(_) => raw::ScancodeSet1::PAUSE_SEQUENCE_LENGTH,
+                    #[cfg(feature = "set2")]
+                    Decoder::Set2(_) => raw::ScancodeSet2::PAUSE_SEQUENCE_LENGTH,
+                };
+                self.sequence = Sequence::Pause {
+                    remaining: length - 1,
+                };
+                return Ok(None);
+            }
+            (Sequence::Start, raw::ScancodeSet1::LEFT_SHIFT_MAKE) if self.set_1_active() => {
+                self.set_1_left_shift_down = true
+            }
+            (Sequence::Start, raw::ScancodeSet1::LEFT_SHIFT_BREAK) if self.set_1_active() => {
+                self.set_1_left_shift_down = false
+            }
+            _ => (),
+        }
+
+        self.sequence = match (&self.current_decoder, self.sequence, scancode) {
+            (_, Sequence::Start, ScancodePrefix::EXTENDED) => Sequence::Extended,
+            #[cfg(feature = "set2")]
+            (Decoder::Set2(_), Sequence::Start, ScancodePrefix::RELEASE) => Sequence::Release,
+            #[cfg(feature = "set2")]
+            (Decoder::Set2(_), Sequence::Extended, ScancodePrefix::RELEASE) => {
+                Sequence::ExtendedRelease
+            }
+            _ => Sequence::Start,
+        };
+
+        match &mut self.current_decoder {
+            #[cfg(feature = "set1")]
+            Decoder::Set1(decoder) => decoder.add_byte(scancode),
+            #[cfg(feature = "set2")]
+            Decoder::Set2(decoder) => decoder.add_byte(scancode),
+        }
+    }
+
+    /// Returns `true` if a scancode prefix is received and
+    /// the rest of the scancode is not yet received.
+    pub fn in_sequence(&self) -> bool {
+        !matches!(self.sequence, Sequence::Start)
+    }
+
+    /// In scancode set 1 the BAT completion code is also the left shift
+    /// break code. Returns `true` if the code should be decoded as
+    /// a scancode, which is when left shift is pressed down.
+    pub fn bat_completion_code_is_scancode(&self) -> bool {
+        self.set_1_active() && self.set_1_left_shift_down
+    }
+
+    fn set_1_active(&self) -> bool {
+        #[cfg(feature = "set1")]
+        let set_1 = matches!(self.current_decoder, Decoder::Set1(_));
+        #[cfg(not(feature = "set1"))]
+        let set_1 = false;
+
+        set_1
+    }
+
+    /// Returns `false` if byte is not a scancode or scancode prefix
+    /// of the current scancode set.
+    pub fn plausible_byte(&self, byte: u8) -> bool {
+        match &self.current_decoder {
+            #[cfg(feature = "set1")]
+            Decoder::Set1(_) => {
+                matches!(
+                    byte & !raw::ScancodeSet1::BREAK_BIT,
+                    raw::ScancodeSet1::FIRST_MAKE_CODE..=raw::ScancodeSet1::LAST_MAKE_CODE
+                ) || matches!(byte, ScancodePrefix::EXTENDED | ScancodePrefix::PAUSE)
+            }
+            #[cfg(feature = "set2")]
+            Decoder::Set2(_) => matches!(
+                byte,
+                raw::ScancodeSet2::FIRST_SCANCODE
+                    ..=raw::ScancodeSet2::LAST_SCANCODE
+                        | ScancodePrefix::EXTENDED
+                        | ScancodePrefix::PAUSE
+                        | ScancodePrefix::RELEASE
+            ),
+        }
+    }
+
+    /// Reset decoder state including pressed modifier keys.
+    pub fn reset(&mut self) {
+        let setting = match &self.current_decoder {
+            #[cfg(feature = "set1")]
+            Decoder::Set1(_) => ScancodeDecoderSetting::Set1,
+            #[cfg(feature = "set2")]
+            Decoder::Set2(_) => ScancodeDecoderSetting::Set2,
+        };
+        // Current decoder is always supported.
+        let _ = self.change_decoder(setting);
+    }
+
+    /// Returns an error if the feature of the scancode set is
+    /// disabled. The current decoder is not changed then.
+    pub fn change_decoder(
+        &mut self,
+        setting: ScancodeDecoderSetting,
+    ) -> Result<(), UnsupportedScancodeSet> {
+        let decoder = match setting {
+            #[cfg(feature = "set1")]
+            ScancodeDecoderSetting::Set1 => Decoder::Set1(KeyboardScancodeDecoder::new(
+                K::new(),
+                ScancodeSet1,
+                HandleControl::Ignore,
+            )),
+            #[cfg(feature = "set2")]
+            ScancodeDecoderSetting::Set2 => Decoder::Set2(KeyboardScancodeDecoder::new(
+                K::new(),
+                ScancodeSet2,
+                HandleControl::Ignore,
+            )),
+            #[allow(unreachable_patterns)]
+            _ => return Err(UnsupportedScancodeSet),
+        };
+
+        self.current_decoder = decoder;
+        self.sequence = Sequence::Start;
+        self.set_1_left_shift_down = false;
+        Ok(())
+    }
+}
+
+#[cfg(not(any(feature = "set1", feature = "set2")))]
+compile_error!("feature `set1` or `set2` is required");
+
+enum Decoder<K: DecoderLayout> {
+    #[cfg(feature = "set1")]
+    Set1(KeyboardScancodeDecoder<K, ScancodeSet1>),
+    #[cfg(feature = "set2")]
+    Set2(KeyboardScancodeDecoder<K, ScancodeSet2>),
+}
+
+impl<K: DecoderLayout> fmt::Debug for Decoder<K> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Decoder")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum KeyboardScancodeSetting {
+    Set1 = ScancodeSetNumber::SET_1,
+    Set2 = ScancodeSetNumber::SET_2,
+    Set3 = ScancodeSetNumber::SET_3,
+}
+
+impl KeyboardScancodeSetting {
+    /// Returns `false` if the feature of the scancode set is disabled.
+    pub const fn supported(self) -> bool {
+        match self {
+            KeyboardScancodeSetting::Set1 => cfg!(feature = "set1"),
+            KeyboardScancodeSetting::Set2 => cfg!(feature = "set2"),
+            KeyboardScancodeSetting::Set3 => cfg!(feature = "set3"),
+        }
+    }
+}
+
+/// Scancode set feature is disabled.
+#[derive(Debug)]
+pub struct UnsupportedScancodeSet;
+
+/// Commands queued by `Keyboard::set_alternate_scancode_set`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScancodeSetCommands {
+    /// Scancode set selection.
+    pub select: CommandId,
+    /// Scancode set query which follows the selection.
+    pub query: CommandId,
+}
+
+#[derive(Debug)]
+pub enum ScancodeSetError {
+    NotEnoughSpaceInTheCommandQueue,
+    UnsupportedScancodeSet,
+}
+
+impl From<NotEnoughSpaceInTheCommandQueue> for ScancodeSetError {
+    fn from(_: NotEnoughSpaceInTheCommandQueue) -> Self {
+        ScancodeSetError::NotEnoughSpaceInTheCommandQueue
+    }
+}
+
+#[derive(Debug)]
+pub enum ScancodeDecoderSetting {
+    Set1,
+    Set2,
+}
+
+#[derive(Debug)]
+pub enum KeyboardError {
+    KeyDetectionError,
+    BATCompletionFailure,
+    UnknownScancodeSet(u8),
+    ScancodeParsingError(Error),
+    /// Keyboard did not respond to the command.
+    CommandTimeout {
+        id: CommandId,
+        command: u8,
+    },
+    /// Keyboard requested resend too many times.
+    CommandFailed {
+        id: CommandId,
+        command: u8,
+    },
+    /// Heartbeat echo commands failed `Heartbeat::max_failures`
+    /// times in a row.
+    KeyboardUnresponsive {
+        failures: u32,
+    },
+}
+
+#[derive(Debug)]
+pub enum KeyboardEvent {
+    Key(KeyEvent),
+    /// Scancode set 3 key number. See `Keyboard::set_key_numbers`.
+    #[cfg(feature = "set3")]
+    KeyNumber {
+        number: u8,
+        state: KeyState,
+    },
+    BATCompleted,
+    /// Response to read ID command `id`.
+    ID {
+        id: CommandId,
+        byte1: u8,
+        byte2: u8,
+    },
+    /// Response to scancode set query `id`.
+    ScancodeSet {
+        id: CommandId,
+        setting: KeyboardScancodeSetting,
+    },
+    /// Keyboard did not accept the scancode set selection `id`.
+    /// Decoder uses the `active` set instead.
+    ScancodeSetFallback {
+        id: CommandId,
+        requested: KeyboardScancodeSetting,
+        active: KeyboardScancodeSetting,
+    },
+    /// Response to echo command `id`.
+    Echo {
+        id: CommandId,
+    },
+    Diagnostic(DiagnosticEvent),
+    /// Keyboard acknowledged the typematic rate and delay of command
+    /// `id`. `config` is decoded from the acknowledged data byte.
+    ///
+    /// Controller translation doesn't change typematic repeats. In
+    /// scancode set 3 only keys with a repeating `KeyReportingMode`
+    /// use the setting.
+    Typematic {
+        id: CommandId,
+        config: TypematicConfig,
+    },
+    /// Command finished. Commands which return data produce
+    /// `ID`, `ScancodeSet`, `Echo` or `Key` events and typematic
+    /// commands produce `Typematic` events instead.
+    CommandCompleted(CommandId),
+    /// Corrupted byte was discarded and resend requested.
+    TransmissionError(TransmissionError),
+    /// Key state was reset after key detection overrun. Treat all
+    /// keys and modifiers as released.
+    AllKeysReleased,
+}
+
+#[derive(Debug)]
+pub enum DiagnosticEvent {
+    /// Byte was not an expected command response or a plausible scancode.
+    UnexplainedByte { byte: u8, history: ByteHistory },
+    /// `ECHO` or `ACK` byte was received when there was no command
+    /// in progress. See `Keyboard::set_protocol_byte_diagnostics`.
+    ProtocolByte { byte: u8 },
+}
+
+/// Length of `ByteHistory`.
+pub const BYTE_HISTORY_LENGTH: usize = 8;
+
+/// Ring buffer of the latest received bytes.
+#[derive(Debug, Clone, Copy)]
+pub struct ByteHistory {
+    bytes: [u8; BYTE_HISTORY_LENGTH],
+    len: usize,
+    next: usize,
+}
+
+impl Default for ByteHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ByteHistory {
+    pub fn new() -> Self {
+        Self {
+            bytes: [0; BYTE_HISTORY_LENGTH],
+            len: 0,
+            next: 0,
+        }
+    }
+
+    pub fn push(&mut self, byte: u8) {
+        self.bytes[self.next] = byte;
+        self.next = (self.next + 1) % BYTE_HISTORY_LENGTH;
+        self.len = (self.len + 1).min(BYTE_HISTORY_LENGTH);
+    }
+
+    /// Iterate bytes from oldest to newest.
+    pub fn iter(&self) -> impl Iterator<Item = u8> + '_ {
+        let start = (self.next + BYTE_HISTORY_LENGTH - self.len) % BYTE_HISTORY_LENGTH;
+        (0..self.len).map(move |i| self.bytes[(start + i) % BYTE_HISTORY_LENGTH])
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+#[derive(Debug)]
+pub struct NotEnoughSpaceInTheCommandQueue;
+
+/// Keyboard configuration for `Keyboard::reconcile`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DesiredKeyboardState {
+    pub leds: StatusIndicators,
+    pub typematic: TypematicConfig,
+    pub scancode_set: KeyboardScancodeSetting,
+    /// Scanning enabled.
+    pub enabled: bool,
+}
+
+impl Default for DesiredKeyboardState {
+    /// Keyboard state after reset.
+    fn default() -> Self {
+        Self {
+            leds: StatusIndicators::empty(),
+            typematic: TypematicConfig::DEFAULT,
+            scancode_set: KeyboardScancodeSetting::Set2,
+            enabled: true,
+        }
+    }
+}
+
+/// Keyboard state which the driver expects the device to have.
+/// `None` if the setting is unknown.
+#[derive(Debug, Clone, Copy)]
+struct BelievedKeyboardState {
+    leds: Option<StatusIndicators>,
+    typematic: Option<TypematicConfig>,
+    scancode_set: Option<KeyboardScancodeSetting>,
+    enabled: Option<bool>,
+}
+
+impl BelievedKeyboardState {
+    const UNKNOWN: BelievedKeyboardState = BelievedKeyboardState {
+        leds: None,
+        typematic: None,
+        scancode_set: None,
+        enabled: None,
+    };
+
+    const AFTER_RESET: BelievedKeyboardState = BelievedKeyboardState {
+        leds: Some(StatusIndicators::empty()),
+        typematic: Some(TypematicConfig::DEFAULT),
+        scancode_set: Some(KeyboardScancodeSetting::Set2),
+        enabled: Some(true),
+    };
+
+    /// State after set default commands. Scancode set is not
+    /// reset by all keyboards.
+    fn defaults_restored(self) -> Self {
+        Self {
+            typematic: Some(TypematicConfig::DEFAULT),
+            scancode_set: None,
+            ..self
+        }
+    }
+}
+
+/// Handling of key detection overrun.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverrunPolicy {
+    /// Return `KeyboardError::KeyDetectionError`.
+    ReportError,
+    /// Reset key state and return `KeyboardEvent::AllKeysReleased`.
+    /// If `resync_leds` is `true`, believed LED state is sent to
+    /// the keyboard again.
+    ReleaseAllKeys { resync_leds: bool },
+}
+
+#[derive(Debug)]
+pub enum GrabMode {
+    /// Buffer key events up to the grab buffer capacity.
+    Buffer,
+    Drop,
+}
+
+#[derive(Debug)]
+enum State {
+    ScancodesDisabled,
+    ScancodesEnabled,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(u8)]
+pub enum SetAllKeys {
+    Typematic = CommandSetAllKeys::TYPEMATIC,
+    MakeSlashBreak = CommandSetAllKeys::MAKE_SLASH_BREAK,
+    Make = CommandSetAllKeys::MAKE,
+    TypematicSlashMakeSlashBreak = CommandSetAllKeys::TYPEMATIC_SLASH_MAKE_SLASH_BREAK,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(u8)]
+pub enum SetKeyType {
+    Typematic = CommandSetKeyType::TYPEMATIC,
+    MakeSlashBreak = CommandSetKeyType::MAKE_SLASH_BREAK,
+    Make = CommandSetKeyType::MAKE,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum DelayMilliseconds {
+    Delay250 = 0,
+    /// Default value.
+    Delay500 = 0b0010_0000,
+    Delay750 = 0b0100_0000,
+    Delay1000 = 0b0110_0000,
+}
+
+impl DelayMilliseconds {
+    /// All delays from the shortest to the longest.
+    pub const ALL: [DelayMilliseconds; 4] = [
+        DelayMilliseconds::Delay250,
+        DelayMilliseconds::Delay500,
+        DelayMilliseconds::Delay750,
+        DelayMilliseconds::Delay1000,
+    ];
+
+    /// Delay from the typematic byte. Other bits are ignored.
+    pub const fn from_typematic_byte(byte: u8) -> Self {
+        match byte & TypematicByte::DELAY.bits() {
+            0 => DelayMilliseconds::Delay250,
+            0b0010_0000 => DelayMilliseconds::Delay500,
+            0b0100_0000 => DelayMilliseconds::Delay750,
+            _ => DelayMilliseconds::Delay1000,
+        }
+    }
+
+    pub const fn milliseconds(&self) -> u16 {
+        match self {
+            DelayMilliseconds::Delay250 => 250,
+            DelayMilliseconds::Delay500 => 500,
+            DelayMilliseconds::Delay750 => 750,
+            DelayMilliseconds::Delay1000 => 1000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateValue(u8);
+
+impl RateValue {
+    /// 30 Hz
+    pub const RATE_MAX: RateValue = RateValue(0);
+
+    /// 2 Hz
+    pub const RATE_MIN: RateValue = RateValue(0b0001_1111);
+
+    /// 10,9 Hz
+    pub const RATE_DEFAULT: RateValue = RateValue(0b0000_1011);
+
+    /// All rates from the fastest to the slowest.
+    pub const ALL: [RateValue; 32] = {
+        let mut all = [RateValue::RATE_MAX; 32];
+        let mut value = 0;
+        while value < all.len() {
+            all[value] = RateValue(value as u8);
+            value += 1;
+        }
+        all
+    };
+
+    /// Create new `RateValue`.
+    ///
+    /// # Panics
+    /// If `value & !0b0001_1111 != 0`.
+    pub fn new(value: u8) -> Self {
+        if value & !TypematicByte::RATE.bits() != 0 {
+            panic!("rate value is out of range");
+        }
+
+        RateValue(value)
+    }
+
+    pub fn value(&self) -> u8 {
+        self.0
+    }
+
+    /// Create `RateValue` which is closest to `hz`.
+    pub fn from_hz(hz: u16) -> Self {
+        Self::from_centihertz(hz.saturating_mul(100))
+    }
+
+    /// Create `RateValue` which is closest to `centihertz`.
+    pub fn from_centihertz(centihertz: u16) -> Self {
+        let mut closest = Self::RATE_MAX;
+
+        for value in 1..=Self::RATE_MIN.0 {
+            let rate = RateValue(value);
+            if rate.centihertz().abs_diff(centihertz) < closest.centihertz().abs_diff(centihertz) {
+                closest = rate;
+            }
+        }
+
+        closest
+    }
+
+    /// Returns closest `RateValue` for characters per second and
+    /// the actual rate in centihertz.
+    pub fn closest_to_cps(cps: u8) -> (Self, u16) {
+        let rate = Self::from_hz(cps as u16);
+        (rate, rate.centihertz())
+    }
+
+    /// Typematic rate in centihertz.
+    ///
+    /// Period is `(8 + A) * 2^B * 4.17` milliseconds where `A` is bits 0-2
+    /// and `B` is bits 3-4 of the value.
+    pub const fn centihertz(&self) -> u16 {
+        let a = (self.0 & 0b0000_0111) as u32;
+        let b = ((self.0 & 0b0001_1000) >> 3) as u32;
+        let period = ((8 + a) << b) * 417;
+        ((10_000_000 + period / 2) / period) as u16
+    }
+}
+
+/// Typematic delay and rate.
+///
+/// Use `TypematicConfig::new` in a `const` to check
+/// the rate encoding at compile time.
+///
+/// ```
+/// use pc_ps2_controller::device::keyboard::driver::{DelayMilliseconds, TypematicConfig};
+///
+/// const CONFIG: TypematicConfig = TypematicConfig::new(DelayMilliseconds::Delay500, 0b0000_1011);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TypematicConfig {
+    delay: DelayMilliseconds,
+    rate: RateValue,
+}
+
+impl TypematicConfig {
+    /// Default delay and rate of the keyboard.
+    pub const DEFAULT: TypematicConfig = TypematicConfig {
+        delay: DelayMilliseconds::Delay500,
+        rate: RateValue::RATE_DEFAULT,
+    };
+
+    /// # Panics
+    /// If `rate & !0b0001_1111 != 0`.
+    pub const fn new(delay: DelayMilliseconds, rate: u8) -> Self {
+        assert!(
+            rate & !TypematicByte::RATE.bits() == 0,
+            "rate value is out of range"
+        );
+
+        Self {
+            delay,
+            rate: RateValue(rate),
+        }
+    }
+
+    pub fn from_rate_value(delay: DelayMilliseconds, rate: RateValue) -> Self {
+        Self { delay, rate }
+    }
+
+    /// Decode the data byte of the `SET_TYPEMATIC_RATE` command.
+    /// Bit 7 is ignored.
+    pub const fn from_typematic_byte(byte: u8) -> Self {
+        Self {
+            delay: DelayMilliseconds::from_typematic_byte(byte),
+            rate: RateValue(byte & TypematicByte::RATE.bits()),
+        }
+    }
+
+    /// Data byte of the `SET_TYPEMATIC_RATE` command.
+    pub const fn typematic_byte(&self) -> u8 {
+        self.delay as u8 | self.rate.0
+    }
+
+    pub fn delay(&self) -> DelayMilliseconds {
+        self.delay
+    }
+
+    pub fn rate(&self) -> RateValue {
+        self.rate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+
+    #[derive(Debug)]
+    struct NoDevice;
+
+    impl SendToDevice for NoDevice {
+        fn send(&mut self, _: u8) {}
+    }
+
+    #[cfg(feature = "set1")]
+    fn set_1_keyboard() -> Us104Keyboard<1> {
+        let mut keyboard =
+            Us104Keyboard::<1>::with_initial_state(&mut NoDevice, InitialState::Unchanged).unwrap();
+        keyboard
+            .set_scancode_decoder(ScancodeDecoderSetting::Set1)
+            .unwrap();
+        keyboard
+    }
+
+    #[cfg(feature = "set1")]
+    #[test]
+    fn set_1_make_codes_above_0x6d_are_not_quarantined() {
+        let mut keyboard = set_1_keyboard();
+        // Katakana, Ro, Henkan, Muhenkan and Yen.
+        for &byte in &[0x70, 0x73, 0x79, 0x7B, 0x7D] {
+            let _ = keyboard.receive_data(byte, &mut NoDevice);
+            let _ = keyboard.receive_data(byte | raw::ScancodeSet1::BREAK_BIT, &mut NoDevice);
+        }
+        assert_eq!(keyboard.unexplained_bytes(), 0);
+    }
+
+    #[cfg(feature = "set1")]
+    #[test]
+    fn implausible_byte_is_quarantined_with_history() {
+        let mut keyboard = set_1_keyboard();
+        keyboard.report_unexplained_bytes(true);
+
+        let a_make = keyboard.receive_data(0x1E, &mut NoDevice).unwrap();
+        assert!(matches!(a_make, Some(KeyboardEvent::Key(_))));
+        keyboard.receive_data(0x9E, &mut NoDevice).unwrap();
+
+        match keyboard.receive_data(0x80, &mut NoDevice).unwrap() {
+            Some(KeyboardEvent::Diagnostic(DiagnosticEvent::UnexplainedByte { byte, history })) => {
+                assert_eq!(byte, 0x80);
+                let mut bytes = [0; 3];
+                for (slot, byte) in bytes.iter_mut().zip(history.iter()) {
+                    *slot = byte;
+                }
+                assert_eq!(history.iter().count(), 3);
+                assert_eq!(bytes, [0x1E, 0x9E, 0x80]);
+            }
+            other => panic!("expected unexplained byte, got {:?}", other),
+        }
+        assert_eq!(keyboard.unexplained_bytes(), 1);
+
+        keyboard.report_unexplained_bytes(false);
+        assert!(keyboard
+            .receive_data(0x7F, &mut NoDevice)
+            .unwrap()
+            .is_none());
+        assert_eq!(keyboard.unexplained_bytes(), 2);
+    }
+
+    /// Returns the event of the last byte. Other bytes must not
+    /// produce events.
+    fn receive_all(keyboard: &mut Us104Keyboard<2>, bytes: &[u8]) -> Option<KeyboardEvent> {
+        let (last, bytes) = bytes.split_last().unwrap();
+        for &byte in bytes {
+            assert!(keyboard
+                .receive_data(byte, &mut NoDevice)
+                .unwrap()
+                .is_none());
+        }
+        keyboard.receive_data(*last, &mut NoDevice).unwrap()
+    }
+
+    /// Receive `before`, queue a command, receive its ACK, `after`
+    /// and the last ACK. Returns the event of the last byte of
+    /// `after`.
+    fn split_around_ack(
+        setting: ScancodeDecoderSetting,
+        before: &[u8],
+        after: &[u8],
+    ) -> Option<KeyboardEvent> {
+        let mut keyboard =
+            Us104Keyboard::<2>::with_initial_state(&mut NoDevice, InitialState::Unchanged).unwrap();
+        keyboard.set_scancode_decoder(setting).unwrap();
+
+        assert!(receive_all(&mut keyboard, before).is_none());
+        let id = keyboard
+            .set_status_indicators(&mut NoDevice, StatusIndicators::empty())
+            .unwrap();
+        assert!(receive_all(&mut keyboard, &[FromKeyboard::ACK]).is_none());
+        let event = receive_all(&mut keyboard, after);
+        match receive_all(&mut keyboard, &[FromKeyboard::ACK]) {
+            Some(KeyboardEvent::CommandCompleted(completed)) => assert_eq!(completed, id),
+            other => panic!("expected completed command, got {:?}", other),
+        }
+        assert_eq!(keyboard.unexplained_bytes(), 0);
+        event
+    }
+
+    /// Receive `before`, queue a command and receive both ACKs,
+    /// then receive `after`. Returns the event of the last byte.
+    fn split_around_command_finished(
+        setting: ScancodeDecoderSetting,
+        before: &[u8],
+        after: &[u8],
+    ) -> Option<KeyboardEvent> {
+        let mut keyboard =
+            Us104Keyboard::<2>::with_initial_state(&mut NoDevice, InitialState::Unchanged).unwrap();
+        keyboard.set_scancode_decoder(setting).unwrap();
+
+        assert!(receive_all(&mut keyboard, before).is_none());
+        let id = keyboard
+            .set_status_indicators(&mut NoDevice, StatusIndicators::empty())
+            .unwrap();
+        match receive_all(&mut keyboard, &[FromKeyboard::ACK, FromKeyboard::ACK]) {
+            Some(KeyboardEvent::CommandCompleted(completed)) => assert_eq!(completed, id),
+            other => panic!("expected completed command, got {:?}", other),
+        }
+        let event = receive_all(&mut keyboard, after);
+        assert_eq!(keyboard.unexplained_bytes(), 0);
+        event
+    }
+
+    fn assert_key_down(event: Option<KeyboardEvent>, code: KeyCode) {
+        match event {
+            Some(KeyboardEvent::Key(key)) => {
+                assert_eq!(key.code, code);
+                assert_eq!(key.state, KeyState::Down);
+            }
+            other => panic!("expected {:?}, got {:?}", code, other),
+        }
+    }
+
+    #[cfg(feature = "set1")]
+    #[test]
+    fn set_1_sequences_split_around_command_responses() {
+        assert_key_down(
+            split_around_ack(ScancodeDecoderSetting::Set1, &[0xE0], &[0x48]),
+            KeyCode::ArrowUp,
+        );
+        assert_key_down(
+            split_around_ack(
+                ScancodeDecoderSetting::Set1,
+                &[0xE1, 0x1D],
+                &[0x45, 0xE1, 0x9D, 0xC5],
+            ),
+            KeyCode::PauseBreak,
+        );
+        assert_key_down(
+            split_around_command_finished(ScancodeDecoderSetting::Set1, &[0xE0], &[0x48]),
+            KeyCode::ArrowUp,
+        );
+        assert_key_down(
+            split_around_command_finished(
+                ScancodeDecoderSetting::Set1,
+                &[0xE1, 0x1D, 0x45],
+                &[0xE1, 0x9D, 0xC5],
+            ),
+            KeyCode::PauseBreak,
+        );
+    }
+
+    #[cfg(feature = "set2")]
+    #[test]
+    fn set_2_sequences_split_around_command_responses() {
+        assert_key_down(
+            split_around_ack(ScancodeDecoderSetting::Set2, &[0xE0], &[0x75]),
+            KeyCode::ArrowUp,
+        );
+        assert_key_down(
+            split_around_ack(
+                ScancodeDecoderSetting::Set2,
+                &[0xE1, 0x14],
+                &[0x77, 0xE1, 0xF0, 0x14, 0xF0, 0x77],
+            ),
+            KeyCode::PauseBreak,
+        );
+        assert_key_down(
+            split_around_command_finished(ScancodeDecoderSetting::Set2, &[0xE0], &[0x75]),
+            KeyCode::ArrowUp,
+        );
+        assert_key_down(
+            split_around_command_finished(
+                ScancodeDecoderSetting::Set2,
+                &[0xE1, 0x14, 0x77, 0xE1],
+                &[0xF0, 0x14, 0xF0, 0x77],
+            ),
+            KeyCode::PauseBreak,
+        );
+    }
+
+    #[cfg(feature = "set2")]
+    #[test]
+    fn scancode_before_ack_is_decoded_and_keeps_timer() {
+        let mut keyboard =
+            Us104Keyboard::<1>::with_initial_state(&mut NoDevice, InitialState::Unchanged).unwrap();
+        keyboard
+            .set_scancode_decoder(ScancodeDecoderSetting::Set2)
+            .unwrap();
+        let mut timeouts = ResponseTimeouts::new();
+        timeouts.set_command_response(10);
+        keyboard.set_response_timeouts(timeouts);
+
+        let id = keyboard
+            .set_status_indicators(&mut NoDevice, StatusIndicators::empty())
+            .unwrap();
+        assert!(keyboard.tick(6, &mut NoDevice).unwrap().is_none());
+        assert_key_down(
+            keyboard.receive_data(0x1C, &mut NoDevice).unwrap(),
+            KeyCode::A,
+        );
+        assert_eq!(keyboard.command_queue().len(), 1);
+
+        match keyboard.tick(6, &mut NoDevice) {
+            Err(KeyboardError::CommandTimeout { id: timed_out, .. }) => assert_eq!(timed_out, id),
+            other => panic!("expected command timeout, got {:?}", other),
+        }
+        assert_eq!(keyboard.unexplained_bytes(), 0);
+    }
+
+    #[cfg(feature = "set2")]
+    #[test]
+    fn byte_after_ack_is_unexplained_and_not_decoded() {
+        let mut keyboard =
+            Us104Keyboard::<1>::with_initial_state(&mut NoDevice, InitialState::Unchanged).unwrap();
+        keyboard
+            .set_scancode_decoder(ScancodeDecoderSetting::Set2)
+            .unwrap();
+        keyboard.report_unexplained_bytes(true);
+
+        let id = keyboard
+            .set_status_indicators(&mut NoDevice, StatusIndicators::empty())
+            .unwrap();
+        assert!(keyboard
+            .receive_data(FromKeyboard::ACK, &mut NoDevice)
+            .unwrap()
+            .is_none());
+        match keyboard.receive_data(0x1C, &mut NoDevice).unwrap() {
+            Some(KeyboardEvent::Diagnostic(DiagnosticEvent::UnexplainedByte { byte, .. })) => {
+                assert_eq!(byte, 0x1C)
+            }
+            other => panic!("expected unexplained byte, got {:?}", other),
+        }
+        assert_eq!(keyboard.unexplained_bytes(), 1);
+
+        // The decoder did not see 0x1C: the next make code is decoded
+        // on its own.
+        match keyboard
+            .receive_data(FromKeyboard::ACK, &mut NoDevice)
+            .unwrap()
+        {
+            Some(KeyboardEvent::CommandCompleted(completed)) => assert_eq!(completed, id),
+            other => panic!("expected completed command, got {:?}", other),
+        }
+        assert_key_down(
+            keyboard.receive_data(0x32, &mut NoDevice).unwrap(),
+            KeyCode::B,
+        );
+    }
+
+    #[cfg(feature = "set2")]
+    #[test]
+    fn command_responses_contain_command_id() {
+        let mut keyboard =
+            Us104Keyboard::<4>::with_initial_state(&mut NoDevice, InitialState::Unchanged).unwrap();
+
+        let ids = keyboard
+            .set_alternate_scancode_set(&mut NoDevice, KeyboardScancodeSetting::Set2)
+            .unwrap();
+        let read_id = keyboard.read_id(&mut NoDevice).unwrap();
+        let echo = keyboard.echo(&mut NoDevice).unwrap();
+        let ack = FromKeyboard::ACK;
+
+        for &byte in &[
+            ack,
+            ack,
+            ack,
+            ack,
+            ScancodeSetNumber::SET_2,
+            ack,
+            0xAB,
+            0x83,
+        ] {
+            match keyboard.receive_data(byte, &mut NoDevice).unwrap() {
+                None => (),
+                Some(KeyboardEvent::CommandCompleted(id)) => assert_eq!(id, ids.select),
+                Some(KeyboardEvent::ScancodeSet { id, setting }) => {
+                    assert_eq!(id, ids.query);
+                    assert_eq!(setting, KeyboardScancodeSetting::Set2);
+                }
+                Some(KeyboardEvent::ID { id, byte1, byte2 }) => {
+                    assert_eq!(id, read_id);
+                    assert_eq!((byte1, byte2), (0xAB, 0x83));
+                }
+                other => panic!("unexpected event {:?}", other),
+            }
+        }
+        match keyboard
+            .receive_data(FromKeyboard::ECHO, &mut NoDevice)
+            .unwrap()
+        {
+            Some(KeyboardEvent::Echo { id }) => assert_eq!(id, echo),
+            other => panic!("expected echo, got {:?}", other),
+        }
+        assert!(keyboard.command_queue().is_empty());
+    }
+
+    #[test]
+    fn heartbeat_requires_command_queue_space() {
+        let mut keyboard =
+            Us104Keyboard::<0>::with_initial_state(&mut NoDevice, InitialState::Unchanged).unwrap();
+        let heartbeat = Heartbeat {
+            idle_ticks: 1,
+            max_failures: 1,
+        };
+
+        assert!(keyboard.set_heartbeat(Some(heartbeat)).is_err());
+        assert!(keyboard.set_heartbeat(None).is_ok());
+        for _ in 0..4 {
+            assert!(keyboard.tick(1, &mut NoDevice).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn heartbeat_queues_echo_when_idle() {
+        let mut keyboard =
+            Us104Keyboard::<1>::with_initial_state(&mut NoDevice, InitialState::Unchanged).unwrap();
+        keyboard
+            .set_heartbeat(Some(Heartbeat {
+                idle_ticks: 2,
+                max_failures: 1,
+            }))
+            .unwrap();
+
+        keyboard.tick(0, &mut NoDevice).unwrap();
+        assert!(keyboard.command_queue().is_empty());
+        keyboard.tick(0, &mut NoDevice).unwrap();
+        assert_eq!(keyboard.command_queue().len(), 1);
     }
 }
@@ -36,6 +36,42 @@ impl CommandSetKeyType {
     pub const MAKE: u8 = 0xFD;
 }
 
+/// Names of the keyboard command constants. Scancode set 3 key
+/// type commands have prefix `SET_ALL_KEYS_` or `SET_KEY_TYPE_`.
+pub const KEYBOARD_COMMAND_NAMES: &[(u8, &str)] = name_table! {
+    "" CommandReturnData {
+        DEFAULT_DISABLE,
+        ECHO,
+        ENABLE,
+        READ_ID,
+        RESEND,
+        RESET,
+        SELECT_ALTERNATE_SCANCODES,
+        SET_DEFAULT,
+        SET_STATUS_INDICATORS,
+        SET_TYPEMATIC_RATE,
+    }
+    "SET_ALL_KEYS_" CommandSetAllKeys {
+        TYPEMATIC,
+        MAKE_SLASH_BREAK,
+        MAKE,
+        TYPEMATIC_SLASH_MAKE_SLASH_BREAK,
+    }
+    "SET_KEY_TYPE_" CommandSetKeyType {
+        TYPEMATIC,
+        MAKE_SLASH_BREAK,
+        MAKE,
+    }
+};
+
+/// Constant name of a keyboard command or `"UNKNOWN"`.
+pub fn name_of_keyboard_command(command: u8) -> &'static str {
+    KEYBOARD_COMMAND_NAMES
+        .iter()
+        .find(|(c, _)| *c == command)
+        .map_or("UNKNOWN", |(_, name)| name)
+}
+
 bitflags! {
     pub struct StatusIndicators: u8 {
         const SCROLL_LOCK = 0b0000_0001;
@@ -58,3 +94,87 @@ impl FromKeyboard {
     pub const RESEND: u8 = 0xFE;
     pub const KEY_DETECTION_OVERRUN_SCANCODE_SET_1: u8 = 0xFF;
 }
+
+#[derive(Debug)]
+pub struct ScancodePrefix;
+
+impl ScancodePrefix {
+    pub const EXTENDED: u8 = 0xE0;
+    /// Pause key sequence prefix.
+    pub const PAUSE: u8 = 0xE1;
+    /// Break code prefix in scancode sets 2 and 3.
+    pub const RELEASE: u8 = 0xF0;
+}
+
+/// Data bytes of command `SELECT_ALTERNATE_SCANCODES` and
+/// its response values.
+#[derive(Debug)]
+pub struct ScancodeSetNumber;
+
+impl ScancodeSetNumber {
+    /// Keyboard responds with the current scancode set number.
+    pub const GET_CURRENT: u8 = 0;
+    pub const SET_1: u8 = 1;
+    pub const SET_2: u8 = 2;
+    pub const SET_3: u8 = 3;
+}
+
+bitflags! {
+    /// Data byte of command `SET_TYPEMATIC_RATE`.
+    pub struct TypematicByte: u8 {
+        const DELAY = 0b0110_0000;
+        const RATE = 0b0001_1111;
+    }
+}
+
+#[derive(Debug)]
+pub struct ScancodeSet1;
+
+impl ScancodeSet1 {
+    /// Break code is the make code with this bit set.
+    pub const BREAK_BIT: u8 = 0x80;
+    pub const FIRST_MAKE_CODE: u8 = 0x01;
+    /// Includes keys such as the JIS keys from 0x70 to 0x7D, which
+    /// `pc_keyboard` doesn't decode.
+    pub const LAST_MAKE_CODE: u8 = 0x7E;
+    /// Pause key sequence `E1 1D 45 E1 9D C5`.
+    pub const PAUSE_SEQUENCE_LENGTH: u8 = 6;
+    pub const LEFT_SHIFT_MAKE: u8 = 0x2A;
+    /// Same value as `FromKeyboard::BAT_COMPLETION_CODE`.
+    pub const LEFT_SHIFT_BREAK: u8 = 0xAA;
+}
+
+#[derive(Debug)]
+pub struct ScancodeSet2;
+
+impl ScancodeSet2 {
+    /// Pause key sequence `E1 14 77 E1 F0 14 F0 77`.
+    pub const PAUSE_SEQUENCE_LENGTH: u8 = 8;
+    pub const FIRST_SCANCODE: u8 = 0x01;
+    pub const LAST_SCANCODE: u8 = 0x84;
+}
+
+/// Scancode set 3 has one make code for every key, so the make code
+/// is a key number. Break code is `ScancodePrefix::RELEASE` followed
+/// by the make code.
+#[derive(Debug)]
+pub struct ScancodeSet3;
+
+impl ScancodeSet3 {
+    pub const FIRST_SCANCODE: u8 = 0x07;
+    pub const LAST_SCANCODE: u8 = 0x8D;
+}
+
+/// Device response time limits.
+///
+/// Reference: IBM PS/2 Hardware Interface Technical Reference,
+/// keyboard and auxiliary device chapters.
+#[derive(Debug)]
+pub struct ResponseTime;
+
+impl ResponseTime {
+    /// Device must respond to a command within 20 milliseconds.
+    pub const COMMAND_RESPONSE_MILLISECONDS: u32 = 20;
+    /// Basic assurance test (BAT) completes within 750 milliseconds.
+    pub const BAT_MILLISECONDS: u32 = 750;
+}
@@ -0,0 +1,88 @@
+//! Scancode set 3 key reporting modes.
+//!
+//! `Keyboard` updates `KeyReporting` when the set 3 key type commands
+//! complete, so code which tracks key state or generates key repeats
+//! knows which keys don't send break codes or don't repeat.
+
+use super::raw::{CommandReturnData, CommandSetAllKeys, CommandSetKeyType};
+use crate::device::command_queue::Command;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyReportingMode {
+    /// Make code which repeats while the key is down. No break code.
+    Typematic,
+    MakeSlashBreak,
+    /// Make code only.
+    Make,
+    TypematicSlashMakeSlashBreak,
+}
+
+impl KeyReportingMode {
+    pub fn sends_break(self) -> bool {
+        matches!(
+            self,
+            KeyReportingMode::MakeSlashBreak | KeyReportingMode::TypematicSlashMakeSlashBreak
+        )
+    }
+
+    pub fn repeats(self) -> bool {
+        matches!(
+            self,
+            KeyReportingMode::Typematic | KeyReportingMode::TypematicSlashMakeSlashBreak
+        )
+    }
+}
+
+/// Reporting mode of every scancode set 3 scancode.
+#[derive(Debug, Clone)]
+pub struct KeyReporting {
+    modes: [Option<KeyReportingMode>; 256],
+}
+
+impl KeyReporting {
+    pub(crate) const fn new() -> Self {
+        Self { modes: [None; 256] }
+    }
+
+    /// `None` if the mode is unknown. Modes are unknown after reset
+    /// and set default commands, because default modes depend on
+    /// the key.
+    pub fn mode(&self, scancode: u8) -> Option<KeyReportingMode> {
+        self.modes[scancode as usize]
+    }
+
+    pub(crate) fn set_unknown(&mut self) {
+        self.modes = [None; 256];
+    }
+
+    /// Update modes after `command` has finished.
+    pub(crate) fn command_finished(&mut self, command: &Command) {
+        match *command {
+            Command::AckResponse { command } => {
+                let mode = match command {
+                    CommandSetAllKeys::TYPEMATIC => KeyReportingMode::Typematic,
+                    CommandSetAllKeys::MAKE_SLASH_BREAK => KeyReportingMode::MakeSlashBreak,
+                    CommandSetAllKeys::MAKE => KeyReportingMode::Make,
+                    CommandSetAllKeys::TYPEMATIC_SLASH_MAKE_SLASH_BREAK => {
+                        KeyReportingMode::TypematicSlashMakeSlashBreak
+                    }
+                    CommandReturnData::SET_DEFAULT
+                    | CommandReturnData::DEFAULT_DISABLE
+                    | CommandReturnData::RESET => return self.set_unknown(),
+                    _ => return,
+                };
+                self.modes = [Some(mode); 256];
+            }
+            Command::SendCommandAndDataSingleAck { command, data, .. } => {
+                let mode = match command {
+                    CommandSetKeyType::TYPEMATIC => KeyReportingMode::Typematic,
+                    CommandSetKeyType::MAKE_SLASH_BREAK => KeyReportingMode::MakeSlashBreak,
+                    CommandSetKeyType::MAKE => KeyReportingMode::Make,
+                    _ => return,
+                };
+                self.modes[data as usize] = Some(mode);
+            }
+            _ => (),
+        }
+    }
+}
@@ -0,0 +1,86 @@
+//! Scancode set 2 to set 1 translation.
+//!
+//! Controller translates bytes from the keyboard to scancode set 1
+//! when translation is enabled in the controller command byte. Same
+//! translation can be done in software if controller translation
+//! is buggy. Decoding after translation is identical in both cases.
+
+use super::raw::{ScancodePrefix, ScancodeSet1};
+
+/// Translation table of the controller for bytes `0x00..=0x7F`.
+/// Bytes `0x80..=0xFF` are not translated except `0x83` and `0x84`.
+pub const SET_2_TO_SET_1: [u8; 128] = [
+    0xFF, 0x43, 0x41, 0x3F, 0x3D, 0x3B, 0x3C, 0x58, 0x64, 0x44, 0x42, 0x40, 0x3E, 0x0F, 0x29, 0x59,
+    0x65, 0x38, 0x2A, 0x70, 0x1D, 0x10, 0x02, 0x5A, 0x66, 0x71, 0x2C, 0x1F, 0x1E, 0x11, 0x03, 0x5B,
+    0x67, 0x2E, 0x2D, 0x20, 0x12, 0x05, 0x04, 0x5C, 0x68, 0x39, 0x2F, 0x21, 0x14, 0x13, 0x06, 0x5D,
+    0x69, 0x31, 0x30, 0x23, 0x22, 0x15, 0x07, 0x5E, 0x6A, 0x72, 0x32, 0x24, 0x16, 0x08, 0x09, 0x5F,
+    0x6B, 0x33, 0x25, 0x17, 0x18, 0x0B, 0x0A, 0x60, 0x6C, 0x34, 0x35, 0x26, 0x27, 0x19, 0x0C, 0x61,
+    0x6D, 0x73, 0x28, 0x74, 0x1A, 0x0D, 0x62, 0x6E, 0x3A, 0x36, 0x1C, 0x1B, 0x75, 0x2B, 0x63, 0x76,
+    0x55, 0x56, 0x77, 0x78, 0x79, 0x7A, 0x0E, 0x7B, 0x7C, 0x4F, 0x7D, 0x4B, 0x47, 0x7E, 0x7F, 0x6F,
+    0x52, 0x53, 0x50, 0x4C, 0x4D, 0x48, 0x01, 0x45, 0x57, 0x4E, 0x51, 0x4A, 0x37, 0x49, 0x46, 0x54,
+];
+
+/// Translate one byte. Release prefix is not handled, use
+/// `Translator` for translating a byte stream.
+pub fn translate_byte(byte: u8) -> u8 {
+    match byte {
+        0x00..=0x7F => SET_2_TO_SET_1[byte as usize],
+        0x83 => 0x41,
+        0x84 => 0x54,
+        _ => byte,
+    }
+}
+
+/// Where scancode translation is done.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TranslationMode {
+    /// Translation is done by the controller.
+    Hardware,
+    /// Controller translation is disabled and `Translator`
+    /// translates the bytes.
+    Software,
+}
+
+impl TranslationMode {
+    /// Value for the controller command byte translation flag.
+    pub fn controller_translation(self) -> bool {
+        self == TranslationMode::Hardware
+    }
+}
+
+/// Streaming translator which works like the controller.
+///
+/// Release prefix `0xF0` is consumed and the high bit of the
+/// next translated byte is set.
+#[derive(Debug, Default)]
+pub struct Translator {
+    release_pending: bool,
+}
+
+impl Translator {
+    pub const fn new() -> Self {
+        Self {
+            release_pending: false,
+        }
+    }
+
+    /// Returns `None` if the byte was release prefix.
+    pub fn translate(&mut self, byte: u8) -> Option<u8> {
+        if byte == ScancodePrefix::RELEASE {
+            self.release_pending = true;
+            return None;
+        }
+
+        let mut translated = translate_byte(byte);
+        if self.release_pending {
+            self.release_pending = false;
+            translated |= ScancodeSet1::BREAK_BIT;
+        }
+
+        Some(translated)
+    }
+
+    pub fn clear(&mut self) {
+        self.release_pending = false;
+    }
+}
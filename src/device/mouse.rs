@@ -0,0 +1,3 @@
+pub mod cursor;
+pub mod packet;
+pub mod raw;
@@ -0,0 +1,154 @@
+//! Screen cursor position from mouse movement.
+
+use super::packet::MouseEvent;
+
+bitflags::bitflags! {
+    /// Cursor state which `CursorState::update` changed.
+    pub struct CursorDirty: u8 {
+        const POSITION = 1 << 0;
+        const BUTTONS = 1 << 1;
+    }
+}
+
+/// Screen pixels per mouse count is `numerator / denominator`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sensitivity {
+    pub numerator: u16,
+    /// Zero is handled as one.
+    pub denominator: u16,
+}
+
+impl Sensitivity {
+    pub const ONE: Sensitivity = Sensitivity {
+        numerator: 1,
+        denominator: 1,
+    };
+}
+
+impl Default for Sensitivity {
+    fn default() -> Self {
+        Self::ONE
+    }
+}
+
+/// Cursor position which stays inside a `width` x `height` screen.
+/// Screen origin is the top left corner.
+///
+/// Movement smaller than one pixel is accumulated, so slow movement
+/// with a low sensitivity still moves the cursor. Accumulated
+/// movement towards an edge is discarded when the cursor is at the
+/// edge.
+#[derive(Debug, Clone)]
+pub struct CursorState {
+    width: u32,
+    height: u32,
+    x: u32,
+    y: u32,
+    remainder_x: i64,
+    remainder_y: i64,
+    sensitivity: Sensitivity,
+    buttons: [bool; 3],
+}
+
+impl CursorState {
+    /// Cursor starts at the center of the screen.
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            x: width / 2,
+            y: height / 2,
+            remainder_x: 0,
+            remainder_y: 0,
+            sensitivity: Sensitivity::ONE,
+            buttons: [false; 3],
+        }
+    }
+
+    /// Change screen size. Position is clamped to the new size.
+    pub fn set_bounds(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.set_position(self.x, self.y);
+    }
+
+    /// Position is clamped to the screen.
+    pub fn set_position(&mut self, x: u32, y: u32) {
+        self.x = x.min(self.width.saturating_sub(1));
+        self.y = y.min(self.height.saturating_sub(1));
+        self.remainder_x = 0;
+        self.remainder_y = 0;
+    }
+
+    pub fn set_sensitivity(&mut self, sensitivity: Sensitivity) {
+        self.sensitivity = sensitivity;
+        self.remainder_x = 0;
+        self.remainder_y = 0;
+    }
+
+    pub fn x(&self) -> u32 {
+        self.x
+    }
+
+    pub fn y(&self) -> u32 {
+        self.y
+    }
+
+    pub fn left_button(&self) -> bool {
+        self.buttons[0]
+    }
+
+    pub fn right_button(&self) -> bool {
+        self.buttons[1]
+    }
+
+    pub fn middle_button(&self) -> bool {
+        self.buttons[2]
+    }
+
+    /// Movement of events with `MouseEvent::overflow` is ignored,
+    /// because the counts are not valid.
+    pub fn update(&mut self, event: &MouseEvent) -> CursorDirty {
+        let mut dirty = CursorDirty::empty();
+
+        let buttons = [event.left_button, event.right_button, event.middle_button];
+        if buttons != self.buttons {
+            self.buttons = buttons;
+            dirty |= CursorDirty::BUTTONS;
+        }
+
+        if !event.overflow {
+            let (x, remainder_x) =
+                self.move_axis(self.x, self.width, self.remainder_x, event.x.into());
+            // Screen y grows down and mouse y grows up.
+            let (y, remainder_y) =
+                self.move_axis(self.y, self.height, self.remainder_y, -i64::from(event.y));
+            if (x, y) != (self.x, self.y) {
+                dirty |= CursorDirty::POSITION;
+            }
+            self.x = x;
+            self.y = y;
+            self.remainder_x = remainder_x;
+            self.remainder_y = remainder_y;
+        }
+
+        dirty
+    }
+
+    /// Returns new position and remainder.
+    fn move_axis(&self, position: u32, size: u32, remainder: i64, delta: i64) -> (u32, i64) {
+        let denominator = i64::from(self.sensitivity.denominator.max(1));
+        let scaled = delta * i64::from(self.sensitivity.numerator) + remainder;
+        let max = i64::from(size.saturating_sub(1));
+        let target = i64::from(position) + scaled / denominator;
+        let remainder = scaled % denominator;
+
+        if target <= 0 {
+            (0, remainder.max(0))
+        } else if target >= max {
+            (max as u32, remainder.min(0))
+        } else {
+            (target as u32, remainder)
+        }
+    }
+}
@@ -0,0 +1,79 @@
+//! Standard 3 byte mouse packet decoding.
+
+use super::raw::{PacketFlags, PACKET_LENGTH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    /// Movement to the right is positive.
+    pub x: i16,
+    /// Movement up is positive.
+    pub y: i16,
+    pub left_button: bool,
+    pub right_button: bool,
+    pub middle_button: bool,
+    /// Movement was too large to fit in the packet.
+    pub overflow: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct PacketDecoder {
+    bytes: [u8; PACKET_LENGTH],
+    len: usize,
+}
+
+impl PacketDecoder {
+    pub fn new() -> Self {
+        Self {
+            bytes: [0; PACKET_LENGTH],
+            len: 0,
+        }
+    }
+
+    /// Returns `MouseEvent` when a complete packet is received.
+    ///
+    /// First bytes without `PacketFlags::ALWAYS_ONE` are
+    /// discarded to synchronize with the packet stream.
+    pub fn decode(&mut self, data: u8) -> Option<MouseEvent> {
+        if self.len == 0 && !PacketFlags::from_bits_truncate(data).contains(PacketFlags::ALWAYS_ONE)
+        {
+            return None;
+        }
+
+        self.bytes[self.len] = data;
+        self.len += 1;
+
+        if self.len < PACKET_LENGTH {
+            return None;
+        }
+
+        self.len = 0;
+
+        let flags = PacketFlags::from_bits_truncate(self.bytes[0]);
+        let delta = |value: u8, negative: bool| {
+            if negative {
+                value as i16 - 0x100
+            } else {
+                value as i16
+            }
+        };
+
+        Some(MouseEvent {
+            x: delta(self.bytes[1], flags.contains(PacketFlags::X_SIGN)),
+            y: delta(self.bytes[2], flags.contains(PacketFlags::Y_SIGN)),
+            left_button: flags.contains(PacketFlags::LEFT_BUTTON),
+            right_button: flags.contains(PacketFlags::RIGHT_BUTTON),
+            middle_button: flags.contains(PacketFlags::MIDDLE_BUTTON),
+            overflow: flags.intersects(PacketFlags::X_OVERFLOW | PacketFlags::Y_OVERFLOW),
+        })
+    }
+
+    /// Returns `true` if a packet is partially received.
+    pub fn packet_started(&self) -> bool {
+        self.len != 0
+    }
+
+    /// Discard partially received packet.
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+}
@@ -0,0 +1,71 @@
+use bitflags::bitflags;
+
+#[derive(Debug)]
+pub struct MouseCommand;
+
+impl MouseCommand {
+    pub const SET_SCALING_1_1: u8 = 0xE6;
+    pub const SET_SCALING_2_1: u8 = 0xE7;
+    pub const SET_RESOLUTION: u8 = 0xE8;
+    pub const STATUS_REQUEST: u8 = 0xE9;
+    pub const GET_DEVICE_ID: u8 = 0xF2;
+    pub const SET_SAMPLE_RATE: u8 = 0xF3;
+    pub const ENABLE_DATA_REPORTING: u8 = 0xF4;
+    pub const DISABLE_DATA_REPORTING: u8 = 0xF5;
+    pub const SET_DEFAULTS: u8 = 0xF6;
+    pub const RESEND: u8 = 0xFE;
+    pub const RESET: u8 = 0xFF;
+}
+
+#[derive(Debug)]
+pub struct FromMouse;
+
+impl FromMouse {
+    pub const BAT_COMPLETION_CODE: u8 = 0xAA;
+    pub const ACK: u8 = 0xFA;
+    pub const ERROR: u8 = 0xFC;
+    pub const RESEND: u8 = 0xFE;
+}
+
+/// VMware absolute pointing device protocol. Commands are sent
+/// through the hypervisor backdoor I/O port.
+#[derive(Debug)]
+pub struct VmMouse;
+
+impl VmMouse {
+    /// Value of register EAX for backdoor calls.
+    pub const MAGIC: u32 = 0x564D_5868;
+    /// Value of register DX for backdoor calls.
+    pub const PORT: u16 = 0x5658;
+
+    pub const COMMAND_GET_VERSION: u32 = 10;
+    pub const COMMAND_DATA: u32 = 39;
+    pub const COMMAND_STATUS: u32 = 40;
+    pub const COMMAND_COMMAND: u32 = 41;
+
+    /// Arguments of `COMMAND_COMMAND`.
+    pub const READ_ID: u32 = 0x4541_4552;
+    pub const DISABLE: u32 = 0x0000_00F5;
+    pub const REQUEST_RELATIVE: u32 = 0x4C45_5252;
+    pub const REQUEST_ABSOLUTE: u32 = 0x5342_4152;
+
+    /// Response of `READ_ID`.
+    pub const VERSION: u32 = 0x3442_554A;
+}
+
+/// Length of a standard mouse packet.
+pub const PACKET_LENGTH: usize = 3;
+
+bitflags! {
+    /// First byte of a mouse packet.
+    pub struct PacketFlags: u8 {
+        const Y_OVERFLOW = 0b1000_0000;
+        const X_OVERFLOW = 0b0100_0000;
+        const Y_SIGN = 0b0010_0000;
+        const X_SIGN = 0b0001_0000;
+        const ALWAYS_ONE = 0b0000_1000;
+        const MIDDLE_BUTTON = 0b0000_0100;
+        const RIGHT_BUTTON = 0b0000_0010;
+        const LEFT_BUTTON = 0b0000_0001;
+    }
+}
@@ -0,0 +1,443 @@
+//! Controller side of the PS/2 controller protocol.
+//!
+//! `Emulated8042` responds to guest accesses to ports 0x60 and 0x64
+//! like an 8042 compatible controller, so a virtual machine monitor
+//! can forward guest port I/O to it. Controller commands and status
+//! register bits are the same raw constants which the driver uses.
+//!
+//! Devices behind the controller implement `EmulatedDevice`. The
+//! `testing` feature has simulated keyboard and mouse devices.
+//!
+//! ```rust,ignore
+//! let mut controller = Emulated8042::with_devices(keyboard, NoDevice);
+//!
+//! // Guest I/O port exit.
+//! match access {
+//!     Access::In(port) => guest.set_al(controller.read(port)),
+//!     Access::Out(port, data) => controller.write(port, data),
+//! }
+//! if controller.take_keyboard_interrupt() {
+//!     guest.raise_irq(1);
+//! }
+//! ```
+
+use crate::controller::{
+    io::{PortIO, COMMAND_REGISTER_RAW, DATA_PORT_RAW, STATUS_REGISTER_RAW},
+    raw::{
+        Command, CommandReturnData, CommandWaitData, ControllerCommandByte, InterfaceTestResult,
+        OutputPortBits, PasswordTestResult, SelfTestResult, StatusRegister,
+    },
+};
+use crate::device::keyboard::raw::{FromKeyboard, ScancodePrefix};
+use crate::device::keyboard::translation::Translator;
+use crate::ring_buffer::RingBuffer;
+
+/// Capacity of the `Emulated8042` output queue.
+pub const EMULATED_OUTPUT_CAPACITY: usize = 16;
+
+/// Device which is connected to a port of `Emulated8042`.
+pub trait EmulatedDevice {
+    /// Byte from the controller. Return `false` if there is no
+    /// device, so the controller reports a timeout.
+    fn receive(&mut self, data: u8) -> bool;
+
+    /// Remove the next byte which the device sends. The controller
+    /// calls this when the output buffer is empty and the device
+    /// interface is enabled.
+    fn next_byte(&mut self) -> Option<u8>;
+}
+
+/// Empty port.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoDevice;
+
+impl EmulatedDevice for NoDevice {
+    fn receive(&mut self, _data: u8) -> bool {
+        false
+    }
+
+    fn next_byte(&mut self) -> Option<u8> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct OutputByte {
+    data: u8,
+    auxiliary_device: bool,
+}
+
+/// Emulated PS/2 controller.
+///
+/// Controller commands are processed immediately, so the input buffer
+/// is always empty. Device bytes are moved to the output buffer when it
+/// is empty and the device interface is enabled. Keyboard bytes are
+/// translated to scancode set 1 when translation is enabled in the
+/// controller command byte.
+#[derive(Debug)]
+pub struct Emulated8042<K, M> {
+    output: RingBuffer<OutputByte, EMULATED_OUTPUT_CAPACITY>,
+    translator: Translator,
+    ram: [u8; 32],
+    command_waiting_data: Option<u8>,
+    last_write_command: bool,
+    output_port: u8,
+    password: [u8; 8],
+    password_length: usize,
+    password_enabled: bool,
+    password_matched: usize,
+    password_release_pending: bool,
+    timeout: bool,
+    keyboard_interrupt: bool,
+    auxiliary_device_interrupt: bool,
+    cpu_reset_requested: bool,
+    keyboard: K,
+    mouse: M,
+}
+
+impl<K: EmulatedDevice, M: EmulatedDevice> Emulated8042<K, M> {
+    /// Controller command byte has translation, system flag and
+    /// keyboard interrupt enabled like after POST.
+    pub fn with_devices(keyboard: K, mouse: M) -> Self {
+        let mut ram = [0; 32];
+        ram[0] = (ControllerCommandByte::KEYBOARD_TRANSLATE_MODE
+            | ControllerCommandByte::SYSTEM_FLAG
+            | ControllerCommandByte::ENABLE_KEYBOARD_INTERRUPT)
+            .bits();
+
+        Self {
+            output: RingBuffer::new(),
+            translator: Translator::new(),
+            ram,
+            command_waiting_data: None,
+            last_write_command: false,
+            output_port: 0b1100_1111,
+            password: [0; 8],
+            password_length: 0,
+            password_enabled: false,
+            password_matched: 0,
+            password_release_pending: false,
+            timeout: false,
+            keyboard_interrupt: false,
+            auxiliary_device_interrupt: false,
+            cpu_reset_requested: false,
+            keyboard,
+            mouse,
+        }
+    }
+
+    pub fn command_byte(&self) -> ControllerCommandByte {
+        ControllerCommandByte::from_bits_truncate(self.ram[0])
+    }
+
+    pub fn output_port(&self) -> u8 {
+        self.output_port
+    }
+
+    pub fn keyboard(&self) -> &K {
+        &self.keyboard
+    }
+
+    pub fn keyboard_mut(&mut self) -> &mut K {
+        &mut self.keyboard
+    }
+
+    pub fn mouse(&self) -> &M {
+        &self.mouse
+    }
+
+    pub fn mouse_mut(&mut self) -> &mut M {
+        &mut self.mouse
+    }
+
+    /// Returns `true` once for every raised IRQ1.
+    pub fn take_keyboard_interrupt(&mut self) -> bool {
+        core::mem::replace(&mut self.keyboard_interrupt, false)
+    }
+
+    /// Returns `true` once for every raised IRQ12.
+    pub fn take_auxiliary_device_interrupt(&mut self) -> bool {
+        core::mem::replace(&mut self.auxiliary_device_interrupt, false)
+    }
+
+    /// Set when the guest pulses or writes the reset line of the
+    /// output port.
+    pub fn cpu_reset_requested(&self) -> bool {
+        self.cpu_reset_requested
+    }
+
+    pub fn password_enabled(&self) -> bool {
+        self.password_enabled
+    }
+
+    /// Guest read. Ports other than 0x60 read the status register.
+    pub fn read(&mut self, port: u16) -> u8 {
+        self.update_output_buffer();
+
+        if port == DATA_PORT_RAW {
+            self.timeout = false;
+            self.output.pop_front().map(|o| o.data).unwrap_or(0)
+        } else {
+            let mut status = StatusRegister::empty();
+            status.set(StatusRegister::INHIBIT_SWITCH, !self.password_enabled);
+            status.set(
+                StatusRegister::SYSTEM_FLAG,
+                self.command_byte()
+                    .contains(ControllerCommandByte::SYSTEM_FLAG),
+            );
+            status.set(StatusRegister::COMMAND_OR_DATA, self.last_write_command);
+            status.set(StatusRegister::GENERAL_TIMEOUT, self.timeout);
+
+            if let Some(output) = self.output.front() {
+                status.insert(StatusRegister::OUTPUT_BUFFER_FULL);
+                status.set(
+                    StatusRegister::AUXILIARY_DEVICE_OUTPUT_BUFFER_FULL,
+                    output.auxiliary_device,
+                );
+            }
+
+            status.bits()
+        }
+    }
+
+    /// Guest write. Ports other than 0x60 write the command register.
+    pub fn write(&mut self, port: u16, data: u8) {
+        if port == DATA_PORT_RAW {
+            self.last_write_command = false;
+
+            match self.command_waiting_data.take() {
+                Some(command) => self.controller_command_data(command, data),
+                None => self.keyboard_data(data),
+            }
+        } else {
+            self.last_write_command = true;
+            self.command_waiting_data = None;
+            self.controller_command(data);
+        }
+    }
+
+    fn update_output_buffer(&mut self) {
+        if !self.output.is_empty() {
+            return;
+        }
+
+        let command_byte = self.command_byte();
+
+        if self.password_enabled {
+            self.consume_password_bytes();
+            return;
+        }
+
+        if !command_byte.contains(ControllerCommandByte::DISABLE_KEYBOARD) {
+            if let Some(data) = self.next_keyboard_byte() {
+                self.output_device_byte(data, false);
+                return;
+            }
+        }
+
+        if !command_byte.contains(ControllerCommandByte::DISABLE_AUXILIARY_DEVICE) {
+            if let Some(data) = self.mouse.next_byte() {
+                self.output_device_byte(data, true);
+            }
+        }
+    }
+
+    /// Next keyboard byte after translation. Release prefixes which
+    /// the translation consumes are skipped.
+    fn next_keyboard_byte(&mut self) -> Option<u8> {
+        let translate = self
+            .command_byte()
+            .contains(ControllerCommandByte::KEYBOARD_TRANSLATE_MODE);
+
+        loop {
+            let data = self.keyboard.next_byte()?;
+            if !translate {
+                return Some(data);
+            }
+            if let Some(data) = self.translator.translate(data) {
+                return Some(data);
+            }
+        }
+    }
+
+    /// Break codes and prefixes are ignored. Other bytes which don't
+    /// match the next password byte restart the matching.
+    fn consume_password_bytes(&mut self) {
+        while let Some(data) = self.next_keyboard_byte() {
+            if self.password_release_pending {
+                self.password_release_pending = false;
+                continue;
+            }
+            if data == ScancodePrefix::RELEASE {
+                self.password_release_pending = true;
+                continue;
+            }
+            if data & 0x80 != 0 {
+                continue;
+            }
+
+            if data == self.password[self.password_matched] {
+                self.password_matched += 1;
+            } else if data == self.password[0] {
+                self.password_matched = 1;
+            } else {
+                self.password_matched = 0;
+            }
+
+            if self.password_matched == self.password_length {
+                self.password_enabled = false;
+                return;
+            }
+        }
+    }
+
+    fn output_device_byte(&mut self, data: u8, auxiliary_device: bool) {
+        let command_byte = self.command_byte();
+
+        if auxiliary_device {
+            self.auxiliary_device_interrupt |=
+                command_byte.contains(ControllerCommandByte::ENABLE_AUXILIARY_INTERRUPT);
+        } else {
+            self.keyboard_interrupt |=
+                command_byte.contains(ControllerCommandByte::ENABLE_KEYBOARD_INTERRUPT);
+        }
+
+        let _ = self.output.push_back(OutputByte {
+            data,
+            auxiliary_device,
+        });
+    }
+
+    fn output_controller_byte(&mut self, data: u8) {
+        let _ = self.output.push_back(OutputByte {
+            data,
+            auxiliary_device: false,
+        });
+    }
+
+    fn set_command_byte_bit(&mut self, bit: ControllerCommandByte, value: bool) {
+        let mut command_byte = self.command_byte();
+        command_byte.set(bit, value);
+        self.ram[0] = command_byte.bits();
+    }
+
+    fn controller_command(&mut self, command: u8) {
+        match command {
+            CommandReturnData::READ_CONTROLLER_COMMAND_BYTE..=CommandReturnData::READ_RAM_END => {
+                let value =
+                    self.ram[(command - CommandReturnData::READ_CONTROLLER_COMMAND_BYTE) as usize];
+                self.output_controller_byte(value);
+            }
+            CommandWaitData::WRITE_CONTROLLER_COMMAND_BYTE..=CommandWaitData::WRITE_RAM_END
+            | CommandWaitData::WRITE_OUTPUT_PORT
+            | CommandWaitData::WRITE_KEYBOARD_OUTPUT_BUFFER
+            | CommandWaitData::WRITE_AUXILIARY_DEVICE_OUTPUT_BUFFER
+            | CommandWaitData::WRITE_TO_AUXILIARY_DEVICE => {
+                self.command_waiting_data = Some(command);
+            }
+            CommandWaitData::LOAD_PASSWORD => {
+                self.password_length = 0;
+                self.command_waiting_data = Some(command);
+            }
+            CommandReturnData::TEST_PASSWORD_INSTALLED => {
+                let result = if self.password_length == 0 {
+                    PasswordTestResult::NOT_INSTALLED
+                } else {
+                    PasswordTestResult::INSTALLED
+                };
+                self.output_controller_byte(result);
+            }
+            Command::ENABLE_PASSWORD => {
+                self.password_enabled = self.password_length != 0;
+                self.password_matched = 0;
+                self.password_release_pending = false;
+            }
+            Command::DISABLE_AUXILIARY_DEVICE_INTERFACE => {
+                self.set_command_byte_bit(ControllerCommandByte::DISABLE_AUXILIARY_DEVICE, true)
+            }
+            Command::ENABLE_AUXILIARY_DEVICE_INTERFACE => {
+                self.set_command_byte_bit(ControllerCommandByte::DISABLE_AUXILIARY_DEVICE, false)
+            }
+            Command::DISABLE_KEYBOARD_INTERFACE => {
+                self.set_command_byte_bit(ControllerCommandByte::DISABLE_KEYBOARD, true)
+            }
+            Command::ENABLE_KEYBOARD_INTERFACE => {
+                self.set_command_byte_bit(ControllerCommandByte::DISABLE_KEYBOARD, false)
+            }
+            CommandReturnData::AUXILIARY_DEVICE_INTERFACE_TEST
+            | CommandReturnData::KEYBOARD_INTERFACE_TEST => {
+                self.output_controller_byte(InterfaceTestResult::PASSED)
+            }
+            CommandReturnData::SELF_TEST => self.output_controller_byte(SelfTestResult::PASSED),
+            CommandReturnData::READ_INPUT_PORT => self.output_controller_byte(0b1011_0000),
+            CommandReturnData::READ_OUTPUT_PORT => self.output_controller_byte(self.output_port),
+            CommandReturnData::READ_TEST_INPUTS => self.output_controller_byte(0b0000_0011),
+            Command::PULSE_OUTPUT_PORT_START..=Command::PULSE_OUTPUT_PORT_END
+                if command & OutputPortBits::RESET_MICROPROCESSOR.bits() == 0 =>
+            {
+                self.cpu_reset_requested = true;
+            }
+            _ => (),
+        }
+    }
+
+    fn controller_command_data(&mut self, command: u8, data: u8) {
+        match command {
+            CommandWaitData::WRITE_CONTROLLER_COMMAND_BYTE..=CommandWaitData::WRITE_RAM_END => {
+                self.ram[(command - CommandWaitData::WRITE_CONTROLLER_COMMAND_BYTE) as usize] =
+                    data;
+            }
+            CommandWaitData::LOAD_PASSWORD if data != 0 => {
+                if self.password_length < self.password.len() {
+                    self.password[self.password_length] = data;
+                    self.password_length += 1;
+                }
+                self.command_waiting_data = Some(command);
+            }
+            CommandWaitData::WRITE_OUTPUT_PORT => {
+                self.output_port = data;
+                if data & OutputPortBits::RESET_MICROPROCESSOR.bits() == 0 {
+                    self.cpu_reset_requested = true;
+                }
+            }
+            CommandWaitData::WRITE_KEYBOARD_OUTPUT_BUFFER => self.output_device_byte(data, false),
+            CommandWaitData::WRITE_AUXILIARY_DEVICE_OUTPUT_BUFFER => {
+                self.output_device_byte(data, true)
+            }
+            CommandWaitData::WRITE_TO_AUXILIARY_DEVICE => {
+                self.set_command_byte_bit(ControllerCommandByte::DISABLE_AUXILIARY_DEVICE, false);
+                if !self.mouse.receive(data) {
+                    self.timeout = true;
+                    self.output_device_byte(FromKeyboard::RESEND, true);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn keyboard_data(&mut self, data: u8) {
+        self.set_command_byte_bit(ControllerCommandByte::DISABLE_KEYBOARD, false);
+
+        if !self.keyboard.receive(data) {
+            self.timeout = true;
+            self.output_device_byte(FromKeyboard::RESEND, false);
+        }
+    }
+}
+
+/// Driver side access, for testing the driver against the emulator.
+impl<K: EmulatedDevice, M: EmulatedDevice> PortIO for Emulated8042<K, M> {
+    type PortID = u16;
+
+    const DATA_PORT: Self::PortID = DATA_PORT_RAW;
+    const STATUS_REGISTER: Self::PortID = STATUS_REGISTER_RAW;
+    const COMMAND_REGISTER: Self::PortID = COMMAND_REGISTER_RAW;
+
+    fn read(&mut self, port: Self::PortID) -> u8 {
+        Emulated8042::read(self, port)
+    }
+
+    fn write(&mut self, port: Self::PortID, data: u8) {
+        Emulated8042::write(self, port, data)
+    }
+}
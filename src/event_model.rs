@@ -0,0 +1,363 @@
+//! Adapter to a platform independent key event model.
+//!
+//! `KeyEventAdapter` converts key events to `PlatformKeyEvent`, which
+//! follows the W3C UI Events model used by crates like `keyboard-types`:
+//! physical key code, logical key, location, modifiers and repeat flag.
+//! Code and named key values are the W3C strings, so mapping to
+//! a GUI toolkit's own types is a lookup.
+//!
+//! ```rust,ignore
+//! if let Some(KeyboardPollResult::Key { event, decoded }) = driver.poll_keyboard_event() {
+//!     let event = adapter.translate(&event, decoded);
+//!     toolkit.key_event(event.code, event.key, event.modifiers.bits());
+//! }
+//! ```
+
+use crate::keyboard::KeyboardPollResult;
+
+use pc_keyboard::{DecodedKey, KeyCode, KeyEvent, KeyState};
+
+bitflags::bitflags! {
+    /// Modifier keys and lock states after the event.
+    pub struct Modifiers: u8 {
+        const SHIFT = 1 << 0;
+        const CONTROL = 1 << 1;
+        const ALT = 1 << 2;
+        const META = 1 << 3;
+        const CAPS_LOCK = 1 << 4;
+        const NUM_LOCK = 1 << 5;
+        const SCROLL_LOCK = 1 << 6;
+    }
+}
+
+/// Logical key. Named values are W3C key attribute values
+/// like `"Enter"` and `"ArrowLeft"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Character(char),
+    Named(&'static str),
+    Unidentified,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    Standard,
+    Left,
+    Right,
+    Numpad,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlatformKeyEvent {
+    pub state: KeyState,
+    pub key: Key,
+    /// W3C code attribute value like `"KeyA"`.
+    pub code: &'static str,
+    pub location: Location,
+    pub modifiers: Modifiers,
+    /// Key was already pressed.
+    pub repeat: bool,
+}
+
+/// Tracks pressed keys and lock states. Use one adapter per keyboard.
+#[derive(Debug, Clone)]
+pub struct KeyEventAdapter {
+    pressed: [u32; 4],
+    locks: Modifiers,
+}
+
+impl Default for KeyEventAdapter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KeyEventAdapter {
+    pub const fn new() -> Self {
+        Self {
+            pressed: [0; 4],
+            locks: Modifiers::empty(),
+        }
+    }
+
+    /// Lock states which the keyboard LEDs show. Locks are toggled
+    /// when their key is pressed.
+    pub fn set_locks(&mut self, locks: Modifiers) {
+        self.locks = locks & (Modifiers::CAPS_LOCK | Modifiers::NUM_LOCK | Modifiers::SCROLL_LOCK);
+    }
+
+    /// Convert `KeyboardPollResult::Key`. Returns `None` for other results.
+    pub fn translate_poll_result(
+        &mut self,
+        result: &KeyboardPollResult,
+    ) -> Option<PlatformKeyEvent> {
+        match result {
+            KeyboardPollResult::Key { event, decoded } => Some(self.translate(event, *decoded)),
+            _ => None,
+        }
+    }
+
+    pub fn translate(&mut self, event: &KeyEvent, decoded: Option<DecodedKey>) -> PlatformKeyEvent {
+        let code = event.code;
+        let was_pressed = self.is_pressed(code);
+        self.set_pressed(code, event.state == KeyState::Down);
+        let repeat = event.state == KeyState::Down && was_pressed;
+
+        if event.state == KeyState::Down && !repeat {
+            let lock = match code {
+                KeyCode::CapsLock => Modifiers::CAPS_LOCK,
+                KeyCode::NumpadLock => Modifiers::NUM_LOCK,
+                KeyCode::ScrollLock => Modifiers::SCROLL_LOCK,
+                _ => Modifiers::empty(),
+            };
+            self.locks.toggle(lock);
+        }
+
+        let key = match decoded {
+            Some(DecodedKey::Unicode(c)) => match c {
+                '\n' => Key::Named("Enter"),
+                '\x08' => Key::Named("Backspace"),
+                '\t' => Key::Named("Tab"),
+                '\x1B' => Key::Named("Escape"),
+                '\x7F' => Key::Named("Delete"),
+                c if c.is_control() => named_key(code),
+                c => Key::Character(c),
+            },
+            _ => named_key(code),
+        };
+
+        PlatformKeyEvent {
+            state: event.state,
+            key,
+            code: code_name(code),
+            location: location(code),
+            modifiers: self.modifiers(),
+            repeat,
+        }
+    }
+
+    /// Modifiers of currently pressed keys and lock states.
+    pub fn modifiers(&self) -> Modifiers {
+        let mut modifiers = self.locks;
+        let pairs = [
+            (KeyCode::ShiftLeft, KeyCode::ShiftRight, Modifiers::SHIFT),
+            (
+                KeyCode::ControlLeft,
+                KeyCode::ControlRight,
+                Modifiers::CONTROL,
+            ),
+            (KeyCode::AltLeft, KeyCode::AltRight, Modifiers::ALT),
+            (KeyCode::WindowsLeft, KeyCode::WindowsRight, Modifiers::META),
+        ];
+        for (left, right, modifier) in pairs.iter().copied() {
+            modifiers.set(modifier, self.is_pressed(left) || self.is_pressed(right));
+        }
+        modifiers
+    }
+
+    fn is_pressed(&self, code: KeyCode) -> bool {
+        let i = code as usize;
+        self.pressed[i / 32] & (1 << (i % 32)) != 0
+    }
+
+    fn set_pressed(&mut self, code: KeyCode, pressed: bool) {
+        let i = code as usize;
+        if pressed {
+            self.pressed[i / 32] |= 1 << (i % 32);
+        } else {
+            self.pressed[i / 32] &= !(1 << (i % 32));
+        }
+    }
+}
+
+fn location(code: KeyCode) -> Location {
+    use KeyCode::*;
+
+    match code {
+        AltLeft | ControlLeft | ShiftLeft | WindowsLeft => Location::Left,
+        AltRight | ControlRight | ShiftRight | WindowsRight => Location::Right,
+        Numpad0 | Numpad1 | Numpad2 | Numpad3 | Numpad4 | Numpad5 | Numpad6 | Numpad7 | Numpad8
+        | Numpad9 | NumpadEnter | NumpadLock | NumpadSlash | NumpadStar | NumpadMinus
+        | NumpadPeriod | NumpadPlus => Location::Numpad,
+        _ => Location::Standard,
+    }
+}
+
+/// Key for keys which do not produce a character.
+fn named_key(code: KeyCode) -> Key {
+    use KeyCode::*;
+
+    let name = match code {
+        AltLeft | AltRight => "Alt",
+        ArrowDown => "ArrowDown",
+        ArrowLeft => "ArrowLeft",
+        ArrowRight => "ArrowRight",
+        ArrowUp => "ArrowUp",
+        Backspace => "Backspace",
+        CapsLock => "CapsLock",
+        ControlLeft | ControlRight => "Control",
+        Delete => "Delete",
+        End => "End",
+        Enter | NumpadEnter => "Enter",
+        Escape => "Escape",
+        F1 => "F1",
+        F2 => "F2",
+        F3 => "F3",
+        F4 => "F4",
+        F5 => "F5",
+        F6 => "F6",
+        F7 => "F7",
+        F8 => "F8",
+        F9 => "F9",
+        F10 => "F10",
+        F11 => "F11",
+        F12 => "F12",
+        Home => "Home",
+        Insert => "Insert",
+        Menus => "ContextMenu",
+        NumpadLock => "NumLock",
+        PageDown => "PageDown",
+        PageUp => "PageUp",
+        PauseBreak => "Pause",
+        PrintScreen => "PrintScreen",
+        ScrollLock => "ScrollLock",
+        ShiftLeft | ShiftRight => "Shift",
+        Tab => "Tab",
+        WindowsLeft | WindowsRight => "Meta",
+        PrevTrack => "MediaTrackPrevious",
+        NextTrack => "MediaTrackNext",
+        Mute => "AudioVolumeMute",
+        Calculator => "LaunchApplication2",
+        Play => "MediaPlayPause",
+        Stop => "MediaStop",
+        VolumeDown => "AudioVolumeDown",
+        VolumeUp => "AudioVolumeUp",
+        WWWHome => "BrowserHome",
+        _ => return Key::Unidentified,
+    };
+
+    Key::Named(name)
+}
+
+/// W3C code attribute value. Codes are named after the US layout
+/// key at the same position.
+pub fn code_name(code: KeyCode) -> &'static str {
+    use KeyCode::*;
+
+    match code {
+        AltLeft => "AltLeft",
+        AltRight => "AltRight",
+        ArrowDown => "ArrowDown",
+        ArrowLeft => "ArrowLeft",
+        ArrowRight => "ArrowRight",
+        ArrowUp => "ArrowUp",
+        BackSlash | HashTilde => "Backslash",
+        Backspace => "Backspace",
+        BackTick => "Backquote",
+        BracketSquareLeft => "BracketLeft",
+        BracketSquareRight => "BracketRight",
+        CapsLock => "CapsLock",
+        Comma => "Comma",
+        ControlLeft => "ControlLeft",
+        ControlRight => "ControlRight",
+        Delete => "Delete",
+        End => "End",
+        Enter => "Enter",
+        Escape => "Escape",
+        Equals => "Equal",
+        F1 => "F1",
+        F2 => "F2",
+        F3 => "F3",
+        F4 => "F4",
+        F5 => "F5",
+        F6 => "F6",
+        F7 => "F7",
+        F8 => "F8",
+        F9 => "F9",
+        F10 => "F10",
+        F11 => "F11",
+        F12 => "F12",
+        Fullstop => "Period",
+        Home => "Home",
+        Insert => "Insert",
+        Key1 => "Digit1",
+        Key2 => "Digit2",
+        Key3 => "Digit3",
+        Key4 => "Digit4",
+        Key5 => "Digit5",
+        Key6 => "Digit6",
+        Key7 => "Digit7",
+        Key8 => "Digit8",
+        Key9 => "Digit9",
+        Key0 => "Digit0",
+        Menus => "ContextMenu",
+        Minus => "Minus",
+        Numpad0 => "Numpad0",
+        Numpad1 => "Numpad1",
+        Numpad2 => "Numpad2",
+        Numpad3 => "Numpad3",
+        Numpad4 => "Numpad4",
+        Numpad5 => "Numpad5",
+        Numpad6 => "Numpad6",
+        Numpad7 => "Numpad7",
+        Numpad8 => "Numpad8",
+        Numpad9 => "Numpad9",
+        NumpadEnter => "NumpadEnter",
+        NumpadLock => "NumLock",
+        NumpadSlash => "NumpadDivide",
+        NumpadStar => "NumpadMultiply",
+        NumpadMinus => "NumpadSubtract",
+        NumpadPeriod => "NumpadDecimal",
+        NumpadPlus => "NumpadAdd",
+        PageDown => "PageDown",
+        PageUp => "PageUp",
+        PauseBreak => "Pause",
+        PrintScreen => "PrintScreen",
+        ScrollLock => "ScrollLock",
+        SemiColon => "Semicolon",
+        ShiftLeft => "ShiftLeft",
+        ShiftRight => "ShiftRight",
+        Slash => "Slash",
+        Spacebar => "Space",
+        Tab => "Tab",
+        Quote => "Quote",
+        WindowsLeft => "MetaLeft",
+        WindowsRight => "MetaRight",
+        A => "KeyA",
+        B => "KeyB",
+        C => "KeyC",
+        D => "KeyD",
+        E => "KeyE",
+        F => "KeyF",
+        G => "KeyG",
+        H => "KeyH",
+        I => "KeyI",
+        J => "KeyJ",
+        K => "KeyK",
+        L => "KeyL",
+        M => "KeyM",
+        N => "KeyN",
+        O => "KeyO",
+        P => "KeyP",
+        Q => "KeyQ",
+        R => "KeyR",
+        S => "KeyS",
+        T => "KeyT",
+        U => "KeyU",
+        V => "KeyV",
+        W => "KeyW",
+        X => "KeyX",
+        Y => "KeyY",
+        Z => "KeyZ",
+        PrevTrack => "MediaTrackPrevious",
+        NextTrack => "MediaTrackNext",
+        Mute => "AudioVolumeMute",
+        Calculator => "LaunchApp2",
+        Play => "MediaPlayPause",
+        Stop => "MediaStop",
+        VolumeDown => "AudioVolumeDown",
+        VolumeUp => "AudioVolumeUp",
+        WWWHome => "BrowserHome",
+        PowerOnTestOk => "Unidentified",
+    }
+}
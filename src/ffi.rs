@@ -0,0 +1,465 @@
+//! C compatible mirrors of driver events and errors.
+//!
+//! Structs have only fixed size integer fields, larger fields first
+//! and explicit reserved bytes, so they have no padding and the same
+//! layout on every target. `to_bytes` encodes a struct with the byte
+//! order selected at runtime for components which exchange events
+//! as bytes. `C_HEADER` declares the structs and constants for C code.
+//!
+//! Key codes are `pc_keyboard::KeyCode` as `u8`.
+
+use crate::device::io::TransmissionError;
+use crate::device::keyboard::driver::KeyboardError;
+use crate::keyboard::KeyboardPollResult;
+#[cfg(feature = "aux-device")]
+use crate::mouse::MousePollResult;
+
+use pc_keyboard::{DecodedKey, Error, KeyState};
+
+/// Byte order for `to_bytes`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+impl Endian {
+    fn u32(self, value: u32) -> [u8; 4] {
+        match self {
+            Endian::Little => value.to_le_bytes(),
+            Endian::Big => value.to_be_bytes(),
+        }
+    }
+
+    #[cfg(feature = "aux-device")]
+    fn u16(self, value: u16) -> [u8; 2] {
+        match self {
+            Endian::Little => value.to_le_bytes(),
+            Endian::Big => value.to_be_bytes(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Ps2KeyboardEventKind;
+
+impl Ps2KeyboardEventKind {
+    pub const KEY: u8 = 1;
+    pub const BAT_COMPLETED: u8 = 2;
+    pub const BAT_FAILURE: u8 = 3;
+    pub const KEY_DETECTION_ERROR: u8 = 4;
+    /// `value` is a `Ps2DecodingError` constant.
+    pub const DECODING_ERROR: u8 = 5;
+    /// `value` is a `Ps2TransmissionError` constant.
+    pub const TRANSMISSION_ERROR: u8 = 6;
+    pub const PASSWORD_UNLOCK_PENDING: u8 = 7;
+    pub const PASSWORD_UNLOCKED: u8 = 8;
+}
+
+#[derive(Debug)]
+pub struct Ps2Decoded;
+
+impl Ps2Decoded {
+    pub const NONE: u8 = 0;
+    /// `value` is a Unicode scalar value.
+    pub const UNICODE: u8 = 1;
+    /// `value` is a key code.
+    pub const RAW_KEY: u8 = 2;
+}
+
+/// `pc_keyboard::Error` values.
+#[derive(Debug)]
+pub struct Ps2DecodingError;
+
+impl Ps2DecodingError {
+    pub const BAD_START_BIT: u8 = 1;
+    pub const BAD_STOP_BIT: u8 = 2;
+    pub const PARITY_ERROR: u8 = 3;
+    pub const UNKNOWN_KEY_CODE: u8 = 4;
+    pub const INVALID_STATE: u8 = 5;
+}
+
+#[derive(Debug)]
+pub struct Ps2TransmissionError;
+
+impl Ps2TransmissionError {
+    pub const PARITY: u8 = 1;
+    pub const TIMEOUT: u8 = 2;
+}
+
+/// `KeyboardPollResult`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct Ps2KeyboardEvent {
+    pub value: u32,
+    pub kind: u8,
+    pub code: u8,
+    pub pressed: u8,
+    /// `Ps2Decoded` constant.
+    pub decoded: u8,
+}
+
+impl Ps2KeyboardEvent {
+    pub fn to_bytes(&self, endian: Endian) -> [u8; 8] {
+        let value = endian.u32(self.value);
+        [
+            value[0],
+            value[1],
+            value[2],
+            value[3],
+            self.kind,
+            self.code,
+            self.pressed,
+            self.decoded,
+        ]
+    }
+}
+
+impl From<&KeyboardPollResult> for Ps2KeyboardEvent {
+    fn from(result: &KeyboardPollResult) -> Self {
+        let mut event = Ps2KeyboardEvent {
+            value: 0,
+            kind: 0,
+            code: 0,
+            pressed: 0,
+            decoded: Ps2Decoded::NONE,
+        };
+
+        match result {
+            KeyboardPollResult::Key {
+                event: key,
+                decoded,
+            } => {
+                event.kind = Ps2KeyboardEventKind::KEY;
+                event.code = key.code as u8;
+                event.pressed = (key.state == KeyState::Down) as u8;
+                match decoded {
+                    Some(DecodedKey::Unicode(c)) => {
+                        event.decoded = Ps2Decoded::UNICODE;
+                        event.value = *c as u32;
+                    }
+                    Some(DecodedKey::RawKey(code)) => {
+                        event.decoded = Ps2Decoded::RAW_KEY;
+                        event.value = *code as u32;
+                    }
+                    None => (),
+                }
+            }
+            KeyboardPollResult::BATCompleted => event.kind = Ps2KeyboardEventKind::BAT_COMPLETED,
+            KeyboardPollResult::BATFailure => event.kind = Ps2KeyboardEventKind::BAT_FAILURE,
+            KeyboardPollResult::KeyDetectionError => {
+                event.kind = Ps2KeyboardEventKind::KEY_DETECTION_ERROR
+            }
+            KeyboardPollResult::Error(e) => {
+                event.kind = Ps2KeyboardEventKind::DECODING_ERROR;
+                event.value = decoding_error(e).into();
+            }
+            KeyboardPollResult::TransmissionError(e) => {
+                event.kind = Ps2KeyboardEventKind::TRANSMISSION_ERROR;
+                event.value = transmission_error(*e).into();
+            }
+            KeyboardPollResult::PasswordUnlockPending => {
+                event.kind = Ps2KeyboardEventKind::PASSWORD_UNLOCK_PENDING
+            }
+            KeyboardPollResult::PasswordUnlocked => {
+                event.kind = Ps2KeyboardEventKind::PASSWORD_UNLOCKED
+            }
+        }
+
+        event
+    }
+}
+
+#[derive(Debug)]
+pub struct Ps2KeyboardErrorKind;
+
+impl Ps2KeyboardErrorKind {
+    pub const KEY_DETECTION_ERROR: u8 = 1;
+    pub const BAT_COMPLETION_FAILURE: u8 = 2;
+    /// `value` is the scancode set byte.
+    pub const UNKNOWN_SCANCODE_SET: u8 = 3;
+    /// `value` is a `Ps2DecodingError` constant.
+    pub const SCANCODE_PARSING_ERROR: u8 = 4;
+    /// `command_id` and `command` are set.
+    pub const COMMAND_TIMEOUT: u8 = 5;
+    /// `command_id` and `command` are set.
+    pub const COMMAND_FAILED: u8 = 6;
+    /// `value` is the failure count.
+    pub const KEYBOARD_UNRESPONSIVE: u8 = 7;
+}
+
+/// `device::keyboard::driver::KeyboardError`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct Ps2KeyboardError {
+    pub value: u32,
+    pub command_id: u32,
+    pub kind: u8,
+    pub command: u8,
+    pub reserved: [u8; 2],
+}
+
+impl Ps2KeyboardError {
+    pub fn to_bytes(&self, endian: Endian) -> [u8; 12] {
+        let value = endian.u32(self.value);
+        let id = endian.u32(self.command_id);
+        [
+            value[0],
+            value[1],
+            value[2],
+            value[3],
+            id[0],
+            id[1],
+            id[2],
+            id[3],
+            self.kind,
+            self.command,
+            0,
+            0,
+        ]
+    }
+}
+
+impl From<&KeyboardError> for Ps2KeyboardError {
+    fn from(error: &KeyboardError) -> Self {
+        let mut ffi = Ps2KeyboardError {
+            value: 0,
+            command_id: 0,
+            kind: 0,
+            command: 0,
+            reserved: [0; 2],
+        };
+
+        match error {
+            KeyboardError::KeyDetectionError => {
+                ffi.kind = Ps2KeyboardErrorKind::KEY_DETECTION_ERROR
+            }
+            KeyboardError::BATCompletionFailure => {
+                ffi.kind = Ps2KeyboardErrorKind::BAT_COMPLETION_FAILURE
+            }
+            KeyboardError::UnknownScancodeSet(set) => {
+                ffi.kind = Ps2KeyboardErrorKind::UNKNOWN_SCANCODE_SET;
+                ffi.value = (*set).into();
+            }
+            KeyboardError::ScancodeParsingError(e) => {
+                ffi.kind = Ps2KeyboardErrorKind::SCANCODE_PARSING_ERROR;
+                ffi.value = decoding_error(e).into();
+            }
+            KeyboardError::CommandTimeout { id, command } => {
+                ffi.kind = Ps2KeyboardErrorKind::COMMAND_TIMEOUT;
+                ffi.command_id = id.value();
+                ffi.command = *command;
+            }
+            KeyboardError::CommandFailed { id, command } => {
+                ffi.kind = Ps2KeyboardErrorKind::COMMAND_FAILED;
+                ffi.command_id = id.value();
+                ffi.command = *command;
+            }
+            KeyboardError::KeyboardUnresponsive { failures } => {
+                ffi.kind = Ps2KeyboardErrorKind::KEYBOARD_UNRESPONSIVE;
+                ffi.value = *failures;
+            }
+        }
+
+        ffi
+    }
+}
+
+#[cfg(feature = "aux-device")]
+#[cfg_attr(docsrs, doc(cfg(feature = "aux-device")))]
+#[derive(Debug)]
+pub struct Ps2MouseEventKind;
+
+#[cfg(feature = "aux-device")]
+impl Ps2MouseEventKind {
+    pub const MOUSE: u8 = 1;
+    pub const RECONNECTED: u8 = 2;
+    pub const AUX_STORM_SUPPRESSED: u8 = 3;
+    /// `error` is a `Ps2TransmissionError` constant.
+    pub const TRANSMISSION_ERROR: u8 = 4;
+}
+
+#[cfg(feature = "aux-device")]
+#[cfg_attr(docsrs, doc(cfg(feature = "aux-device")))]
+#[derive(Debug)]
+pub struct Ps2MouseButtons;
+
+#[cfg(feature = "aux-device")]
+impl Ps2MouseButtons {
+    pub const LEFT: u8 = 1 << 0;
+    pub const RIGHT: u8 = 1 << 1;
+    pub const MIDDLE: u8 = 1 << 2;
+}
+
+/// `MousePollResult`.
+#[cfg(feature = "aux-device")]
+#[cfg_attr(docsrs, doc(cfg(feature = "aux-device")))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct Ps2MouseEvent {
+    pub x: i16,
+    pub y: i16,
+    pub kind: u8,
+    /// `Ps2MouseButtons` flags.
+    pub buttons: u8,
+    pub overflow: u8,
+    pub error: u8,
+}
+
+#[cfg(feature = "aux-device")]
+impl Ps2MouseEvent {
+    pub fn to_bytes(&self, endian: Endian) -> [u8; 8] {
+        let x = endian.u16(self.x as u16);
+        let y = endian.u16(self.y as u16);
+        [
+            x[0],
+            x[1],
+            y[0],
+            y[1],
+            self.kind,
+            self.buttons,
+            self.overflow,
+            self.error,
+        ]
+    }
+}
+
+#[cfg(feature = "aux-device")]
+impl From<&MousePollResult> for Ps2MouseEvent {
+    fn from(result: &MousePollResult) -> Self {
+        let mut ffi = Ps2MouseEvent {
+            x: 0,
+            y: 0,
+            kind: 0,
+            buttons: 0,
+            overflow: 0,
+            error: 0,
+        };
+
+        match result {
+            MousePollResult::Mouse(event) => {
+                ffi.kind = Ps2MouseEventKind::MOUSE;
+                ffi.x = event.x;
+                ffi.y = event.y;
+                ffi.overflow = event.overflow as u8;
+                if event.left_button {
+                    ffi.buttons |= Ps2MouseButtons::LEFT;
+                }
+                if event.right_button {
+                    ffi.buttons |= Ps2MouseButtons::RIGHT;
+                }
+                if event.middle_button {
+                    ffi.buttons |= Ps2MouseButtons::MIDDLE;
+                }
+            }
+            MousePollResult::Reconnected => ffi.kind = Ps2MouseEventKind::RECONNECTED,
+            MousePollResult::AuxStormSuppressed => {
+                ffi.kind = Ps2MouseEventKind::AUX_STORM_SUPPRESSED
+            }
+            MousePollResult::TransmissionError(e) => {
+                ffi.kind = Ps2MouseEventKind::TRANSMISSION_ERROR;
+                ffi.error = transmission_error(*e);
+            }
+        }
+
+        ffi
+    }
+}
+
+// Layouts must match `C_HEADER`.
+const _: () = assert!(core::mem::size_of::<Ps2KeyboardEvent>() == 8);
+const _: () = assert!(core::mem::size_of::<Ps2KeyboardError>() == 12);
+#[cfg(feature = "aux-device")]
+const _: () = assert!(core::mem::size_of::<Ps2MouseEvent>() == 8);
+
+fn decoding_error(error: &Error) -> u8 {
+    match error {
+        Error::BadStartBit => Ps2DecodingError::BAD_START_BIT,
+        Error::BadStopBit => Ps2DecodingError::BAD_STOP_BIT,
+        Error::ParityError => Ps2DecodingError::PARITY_ERROR,
+        Error::UnknownKeyCode => Ps2DecodingError::UNKNOWN_KEY_CODE,
+        Error::InvalidState => Ps2DecodingError::INVALID_STATE,
+    }
+}
+
+fn transmission_error(error: TransmissionError) -> u8 {
+    match error {
+        TransmissionError::Parity => Ps2TransmissionError::PARITY,
+        TransmissionError::Timeout => Ps2TransmissionError::TIMEOUT,
+    }
+}
+
+/// C header for the types and constants of this module.
+pub const C_HEADER: &str = r#"/* Generated from pc-ps2-controller ffi module. */
+#ifndef PC_PS2_CONTROLLER_FFI_H
+#define PC_PS2_CONTROLLER_FFI_H
+
+#include <stdint.h>
+
+#define PS2_KEYBOARD_EVENT_KIND_KEY 1
+#define PS2_KEYBOARD_EVENT_KIND_BAT_COMPLETED 2
+#define PS2_KEYBOARD_EVENT_KIND_BAT_FAILURE 3
+#define PS2_KEYBOARD_EVENT_KIND_KEY_DETECTION_ERROR 4
+#define PS2_KEYBOARD_EVENT_KIND_DECODING_ERROR 5
+#define PS2_KEYBOARD_EVENT_KIND_TRANSMISSION_ERROR 6
+#define PS2_KEYBOARD_EVENT_KIND_PASSWORD_UNLOCK_PENDING 7
+#define PS2_KEYBOARD_EVENT_KIND_PASSWORD_UNLOCKED 8
+
+#define PS2_DECODED_NONE 0
+#define PS2_DECODED_UNICODE 1
+#define PS2_DECODED_RAW_KEY 2
+
+#define PS2_DECODING_ERROR_BAD_START_BIT 1
+#define PS2_DECODING_ERROR_BAD_STOP_BIT 2
+#define PS2_DECODING_ERROR_PARITY_ERROR 3
+#define PS2_DECODING_ERROR_UNKNOWN_KEY_CODE 4
+#define PS2_DECODING_ERROR_INVALID_STATE 5
+
+#define PS2_TRANSMISSION_ERROR_PARITY 1
+#define PS2_TRANSMISSION_ERROR_TIMEOUT 2
+
+#define PS2_KEYBOARD_ERROR_KIND_KEY_DETECTION_ERROR 1
+#define PS2_KEYBOARD_ERROR_KIND_BAT_COMPLETION_FAILURE 2
+#define PS2_KEYBOARD_ERROR_KIND_UNKNOWN_SCANCODE_SET 3
+#define PS2_KEYBOARD_ERROR_KIND_SCANCODE_PARSING_ERROR 4
+#define PS2_KEYBOARD_ERROR_KIND_COMMAND_TIMEOUT 5
+#define PS2_KEYBOARD_ERROR_KIND_COMMAND_FAILED 6
+#define PS2_KEYBOARD_ERROR_KIND_KEYBOARD_UNRESPONSIVE 7
+
+#define PS2_MOUSE_EVENT_KIND_MOUSE 1
+#define PS2_MOUSE_EVENT_KIND_RECONNECTED 2
+#define PS2_MOUSE_EVENT_KIND_AUX_STORM_SUPPRESSED 3
+#define PS2_MOUSE_EVENT_KIND_TRANSMISSION_ERROR 4
+
+#define PS2_MOUSE_BUTTONS_LEFT 1
+#define PS2_MOUSE_BUTTONS_RIGHT 2
+#define PS2_MOUSE_BUTTONS_MIDDLE 4
+
+typedef struct Ps2KeyboardEvent {
+    uint32_t value;
+    uint8_t kind;
+    uint8_t code;
+    uint8_t pressed;
+    uint8_t decoded;
+} Ps2KeyboardEvent;
+
+typedef struct Ps2KeyboardError {
+    uint32_t value;
+    uint32_t command_id;
+    uint8_t kind;
+    uint8_t command;
+    uint8_t reserved[2];
+} Ps2KeyboardError;
+
+typedef struct Ps2MouseEvent {
+    int16_t x;
+    int16_t y;
+    uint8_t kind;
+    uint8_t buttons;
+    uint8_t overflow;
+    uint8_t error;
+} Ps2MouseEvent;
+
+#endif
+"#;
@@ -0,0 +1,226 @@
+//! Static memory footprint of the driver types.
+//!
+//! Sizes are measured with a zero sized `PortIO`, interrupts
+//! enabled, `Us104Key`, `ScancodeSet2` and the default buffer
+//! capacities. `QueuedKeyboardDriver` is measured with command queue
+//! capacity `FOOTPRINT_COMMAND_QUEUE_CAPACITY`. Add the size of your
+//! `PortIO` to every size.
+//!
+//! Only type sizes are measured. Code size, for example formatting
+//! code which is linked from `core::fmt`, is not covered.
+//!
+//! Every preset feature has a budget. `FEATURE_BUDGET` is the budget
+//! of the enabled features, so it is the preset budget when only
+//! preset features are enabled and grows with features which are
+//! enabled on top of a preset. Tests and the `footprint` example
+//! check `FOOTPRINT` against it.
+//!
+//! | Preset | Features |
+//! |--------|----------|
+//! | `preset-tiny` | `set1`. Keyboard only, with controller translation. |
+//! | `preset-default` | Default features. |
+//! | `preset-full` | Default features, `event-model`, `global` and `render`. |
+//!
+//! Use `preset-tiny` with `default-features = false`.
+//!
+//! `COMPONENT_SIZES` documents the buffers and tables inside the
+//! driver types. Their sizes are the same with every preset. Tests
+//! check `COMPONENT_FOOTPRINT` against these x86_64 sizes:
+//!
+//! | Component | Bytes | Used by |
+//! |-----------|-------|---------|
+//! | Translation table | 128 | Software translation and the emulator. Constant data. |
+//! | Grab buffer | 48 | `Keyboard` |
+//! | Deferred buffer | 48 | `KeyboardDriver` and `MouseDriver` |
+//! | Transition log | 88 | Controller types |
+//! | Capture writer | 40 | `Capture`, without the caller provided buffer |
+//! | Capture reader | 24 | `CaptureReader` |
+
+use crate::capture::{Capture, CaptureReader};
+use crate::controller::driver::{
+    marker::InterruptsEnabled, transitions::TransitionLog, DeviceData, DevicesDisabled,
+    EnabledDevices,
+};
+use crate::controller::io::PortIO;
+use crate::device::keyboard::driver::GRAB_BUFFER_CAPACITY;
+use crate::device::keyboard::translation::SET_2_TO_SET_1;
+use crate::keyboard::{KeyboardDriver, QueuedKeyboardDriver, DEFERRED_BUFFER_CAPACITY};
+use crate::ring_buffer::RingBuffer;
+
+use core::mem::size_of;
+
+use pc_keyboard::{layouts::Us104Key, KeyEvent, ScancodeSet2};
+
+/// Command queue capacity of the measured `QueuedKeyboardDriver`.
+pub const FOOTPRINT_COMMAND_QUEUE_CAPACITY: usize = 8;
+
+/// Sizes in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryBudget {
+    pub devices_disabled: usize,
+    pub enabled_devices: usize,
+    pub keyboard_driver: usize,
+    pub queued_keyboard_driver: usize,
+    /// Zero without the `aux-device` feature.
+    pub mouse_driver: usize,
+}
+
+impl MemoryBudget {
+    pub const fn fits(&self, budget: &MemoryBudget) -> bool {
+        self.devices_disabled <= budget.devices_disabled
+            && self.enabled_devices <= budget.enabled_devices
+            && self.keyboard_driver <= budget.keyboard_driver
+            && self.queued_keyboard_driver <= budget.queued_keyboard_driver
+            && self.mouse_driver <= budget.mouse_driver
+    }
+}
+
+// Budgets are the x86_64 sizes rounded up. Sizes are smaller on
+// 32-bit targets.
+
+pub const TINY_BUDGET: MemoryBudget = MemoryBudget {
+    devices_disabled: 144,
+    enabled_devices: 144,
+    keyboard_driver: 384,
+    queued_keyboard_driver: 512,
+    mouse_driver: 0,
+};
+
+pub const DEFAULT_BUDGET: MemoryBudget = MemoryBudget {
+    devices_disabled: 144,
+    enabled_devices: 144,
+    keyboard_driver: 384,
+    queued_keyboard_driver: 832,
+    mouse_driver: 320,
+};
+
+pub const FULL_BUDGET: MemoryBudget = DEFAULT_BUDGET;
+
+/// Sizes with the enabled features.
+pub const FOOTPRINT: MemoryBudget = MemoryBudget {
+    devices_disabled: size_of::<DevicesDisabled<NoPortIO>>(),
+    enabled_devices: size_of::<EnabledDevices<NoPortIO, InterruptsEnabled>>(),
+    keyboard_driver: size_of::<KeyboardDriver<NoPortIO, InterruptsEnabled, Us104Key, ScancodeSet2>>(
+    ),
+    queued_keyboard_driver: size_of::<
+        QueuedKeyboardDriver<NoPortIO, InterruptsEnabled, FOOTPRINT_COMMAND_QUEUE_CAPACITY>,
+    >(),
+    #[cfg(feature = "aux-device")]
+    mouse_driver: size_of::<crate::mouse::MouseDriver<NoPortIO, InterruptsEnabled>>(),
+    #[cfg(not(feature = "aux-device"))]
+    mouse_driver: 0,
+};
+
+/// Budget of the enabled features. `set2` and `set3` add the
+/// scancode set decoders to `QueuedKeyboardDriver` and `aux-device`
+/// adds `MouseDriver`. Other features don't change the sizes.
+pub const FEATURE_BUDGET: MemoryBudget = MemoryBudget {
+    queued_keyboard_driver: if cfg!(any(feature = "set2", feature = "set3")) {
+        DEFAULT_BUDGET.queued_keyboard_driver
+    } else {
+        TINY_BUDGET.queued_keyboard_driver
+    },
+    mouse_driver: if cfg!(feature = "aux-device") {
+        DEFAULT_BUDGET.mouse_driver
+    } else {
+        TINY_BUDGET.mouse_driver
+    },
+    ..TINY_BUDGET
+};
+
+/// Sizes in bytes of the buffers and tables inside the driver types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ComponentSizes {
+    /// `SET_2_TO_SET_1`.
+    pub translation_table: usize,
+    /// Grab buffer with `GRAB_BUFFER_CAPACITY`.
+    pub grab_buffer: usize,
+    /// Deferred buffer with `DEFERRED_BUFFER_CAPACITY`.
+    pub deferred_buffer: usize,
+    pub transition_log: usize,
+    pub capture_writer: usize,
+    pub capture_reader: usize,
+}
+
+/// Documented x86_64 component sizes.
+pub const COMPONENT_SIZES: ComponentSizes = ComponentSizes {
+    translation_table: 128,
+    grab_buffer: 48,
+    deferred_buffer: 48,
+    transition_log: 88,
+    capture_writer: 40,
+    capture_reader: 24,
+};
+
+/// Component sizes with the enabled features.
+pub const COMPONENT_FOOTPRINT: ComponentSizes = ComponentSizes {
+    translation_table: size_of::<[u8; SET_2_TO_SET_1.len()]>(),
+    grab_buffer: size_of::<RingBuffer<KeyEvent, GRAB_BUFFER_CAPACITY>>(),
+    deferred_buffer: size_of::<RingBuffer<DeviceData, DEFERRED_BUFFER_CAPACITY>>(),
+    transition_log: size_of::<TransitionLog>(),
+    capture_writer: size_of::<Capture>(),
+    capture_reader: size_of::<CaptureReader>(),
+};
+
+/// Zero sized `PortIO` for the measurements.
+#[derive(Debug)]
+struct NoPortIO;
+
+impl PortIO for NoPortIO {
+    type PortID = u16;
+
+    const DATA_PORT: Self::PortID = 0;
+    const STATUS_REGISTER: Self::PortID = 0;
+    const COMMAND_REGISTER: Self::PortID = 0;
+
+    fn read(&mut self, _: Self::PortID) -> u8 {
+        0
+    }
+
+    fn write(&mut self, _: Self::PortID, _: u8) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn footprint_fits_feature_budget() {
+        assert!(
+            FOOTPRINT.fits(&FEATURE_BUDGET),
+            "{:?} exceeds {:?}",
+            FOOTPRINT,
+            FEATURE_BUDGET
+        );
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn component_sizes_match_documented_sizes() {
+        let measured = COMPONENT_FOOTPRINT;
+        let documented = COMPONENT_SIZES;
+
+        assert_eq!(measured.translation_table, documented.translation_table);
+        assert_eq!(measured.grab_buffer, documented.grab_buffer);
+        assert_eq!(measured.deferred_buffer, documented.deferred_buffer);
+        assert_eq!(measured.transition_log, documented.transition_log);
+        assert_eq!(measured.capture_writer, documented.capture_writer);
+        assert_eq!(measured.capture_reader, documented.capture_reader);
+    }
+
+    #[test]
+    fn driver_types_contain_their_components() {
+        let components = COMPONENT_FOOTPRINT;
+
+        assert!(FOOTPRINT.devices_disabled >= components.transition_log);
+        assert!(FOOTPRINT.enabled_devices >= components.transition_log);
+        assert!(
+            FOOTPRINT.keyboard_driver >= FOOTPRINT.enabled_devices + components.deferred_buffer
+        );
+        assert!(
+            FOOTPRINT.queued_keyboard_driver >= FOOTPRINT.enabled_devices + components.grab_buffer
+        );
+        #[cfg(feature = "aux-device")]
+        assert!(FOOTPRINT.mouse_driver >= FOOTPRINT.enabled_devices + components.deferred_buffer);
+    }
+}
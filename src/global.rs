@@ -0,0 +1,115 @@
+//! Driver storage for `static` items.
+//!
+//! Kernels usually store the driver in a `static` item so that the
+//! interrupt handler can access it. `StaticPs2` wraps a `lock_api`
+//! mutex and separates the normal code path from the interrupt
+//! handler path. The interrupt handler path never spins on the lock,
+//! so interrupting code which holds the lock will not deadlock the
+//! interrupt handler.
+//!
+//! ```rust,ignore
+//! static PS2: StaticPs2<spin::Mutex<()>, EnabledDevices<Io, InterruptsEnabled>> =
+//!     StaticPs2::new();
+//!
+//! // Initialization code
+//! PS2.init(controller).unwrap();
+//!
+//! // IRQ1 handler
+//! PS2.with_irq_reader(|controller| {
+//!     while let Some(data) = controller.read_data() {
+//!         // Handle data.
+//!     }
+//! });
+//! ```
+
+use lock_api::{Mutex, RawMutex};
+
+use core::fmt;
+
+pub struct StaticPs2<R: RawMutex, T> {
+    driver: Mutex<R, Option<T>>,
+}
+
+impl<R: RawMutex, T> fmt::Debug for StaticPs2<R, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("StaticPs2")
+    }
+}
+
+impl<R: RawMutex, T> Default for StaticPs2<R, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R: RawMutex, T> StaticPs2<R, T> {
+    pub const fn new() -> Self {
+        Self {
+            driver: Mutex::const_new(R::INIT, None),
+        }
+    }
+
+    /// Store the driver. If there is already a driver stored, the new
+    /// driver is returned back.
+    pub fn init(&self, driver: T) -> Result<(), T> {
+        let mut stored = self.driver.lock();
+
+        if stored.is_some() {
+            Err(driver)
+        } else {
+            *stored = Some(driver);
+            Ok(())
+        }
+    }
+
+    /// Remove the stored driver.
+    ///
+    /// You should disable the interrupts before calling this method,
+    /// so that the interrupt handler will not run with missing driver.
+    pub fn take(&self) -> Option<T> {
+        self.driver.lock().take()
+    }
+
+    /// Access the driver from normal code. Spins until the lock is
+    /// available.
+    ///
+    /// Returns `None` if the driver is not initialized.
+    ///
+    /// Don't use this method from the interrupt handler. If the interrupt
+    /// handler runs on the same CPU while the lock is held, the system
+    /// will deadlock.
+    pub fn with<U, F: FnOnce(&mut T) -> U>(&self, f: F) -> Option<U> {
+        self.driver.lock().as_mut().map(f)
+    }
+
+    /// Access the driver from the interrupt handler.
+    ///
+    /// Returns `None` if the driver is not initialized or if the lock
+    /// is held by the interrupted code. In that case the data
+    /// will stay in the controller output buffer and the interrupted
+    /// code can read it.
+    pub fn with_irq_reader<U, F: FnOnce(&mut T) -> U>(&self, f: F) -> Option<U> {
+        self.driver.try_lock()?.as_mut().map(f)
+    }
+
+    /// Read the driver from a panic handler even if the lock is held.
+    ///
+    /// Use with `panic_dump::dump_input_history` to print the latest
+    /// input. Returns `None` if the driver is not initialized.
+    ///
+    /// # Safety
+    /// No other code may access the driver while `f` runs. For example,
+    /// the panic handler has halted other CPUs and disabled interrupts.
+    /// If the lock was held, the driver state may be partially updated.
+    #[allow(unsafe_code)]
+    pub unsafe fn panic_access<U, F: FnOnce(&T) -> U>(&self, f: F) -> Option<U> {
+        if let Some(driver) = self.driver.try_lock() {
+            return driver.as_ref().map(f);
+        }
+
+        // Safety: Caller guarantees that the lock holder does not run
+        //         anymore, so there is no concurrent mutable access.
+        let driver = unsafe { &*self.driver.data_ptr() };
+        driver.as_ref().map(f)
+    }
+}
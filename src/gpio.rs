@@ -0,0 +1,271 @@
+//! PS/2 port implemented with GPIO pins.
+//!
+//! For systems where the device is connected to GPIO pins instead of
+//! a 8042 controller. `GpioPort` implements the line protocol and
+//! `SendToDevice`, so device drivers like
+//! `device::keyboard::driver::Keyboard` work on top of it.
+//!
+//! Received bytes are produced by `GpioPort::clock_falling_edge`,
+//! which should be called from the clock pin falling edge interrupt.
+//! Without interrupts, call `GpioPort::poll` often enough to see
+//! every clock pulse. Device clock is 10-16.7 kHz.
+//!
+//! ```rust,ignore
+//! let mut port = GpioPort::new(pins);
+//! let mut keyboard: Keyboard<8> = Keyboard::new(&mut port)?;
+//!
+//! // Clock pin interrupt handler
+//! match port.clock_falling_edge() {
+//!     Some(Ok(byte)) => keyboard.receive_data(byte, &mut port),
+//!     Some(Err(e)) => keyboard.receive_transmission_error(e, &mut port),
+//!     None => Ok(None),
+//! }
+//! ```
+
+use crate::device::io::{SendToDevice, TransmissionError, WouldBlock};
+
+/// Minimum time the host holds clock low before sending.
+pub const REQUEST_TO_SEND_MICROSECONDS: u32 = 100;
+
+/// Maximum time for device to clock in a byte sent by the host.
+pub const SEND_TIMEOUT_MICROSECONDS: u32 = 15_000;
+
+/// Clock line is polled with this interval when `SendToDevice::send`
+/// waits for the transmission to complete.
+pub const SEND_POLL_INTERVAL_MICROSECONDS: u32 = 5;
+
+/// Clock and data lines of a PS/2 port.
+///
+/// Lines are open collector. Releasing a line lets the pull-up
+/// resistor drive it high.
+pub trait Ps2LineDriver {
+    /// Returns `true` if clock line is high.
+    fn clock(&mut self) -> bool;
+    /// Returns `true` if data line is high.
+    fn data(&mut self) -> bool;
+    /// Pull clock line low or release it.
+    fn set_clock(&mut self, release: bool);
+    /// Pull data line low or release it.
+    fn set_data(&mut self, release: bool);
+    fn delay_microseconds(&mut self, microseconds: u32);
+}
+
+/// Number of clock pulses in a frame. Start bit, 8 data bits,
+/// parity bit and stop bit for receiving. When sending, the last
+/// pulse is the line acknowledge bit from the device.
+const FRAME_CLOCK_PULSES: u8 = 11;
+
+#[derive(Debug, Clone, Copy)]
+enum Transfer {
+    Receive { bits: u16, count: u8 },
+    Send { data: u8, count: u8 },
+}
+
+impl Transfer {
+    const IDLE: Transfer = Transfer::Receive { bits: 0, count: 0 };
+}
+
+#[derive(Debug)]
+pub struct GpioPort<L: Ps2LineDriver> {
+    line: L,
+    transfer: Transfer,
+    previous_clock: bool,
+    /// Byte received while `send` was waiting.
+    received: Option<Result<u8, TransmissionError>>,
+    missing_line_acks: u32,
+}
+
+impl<L: Ps2LineDriver> GpioPort<L> {
+    /// Releases both lines.
+    pub fn new(mut line: L) -> Self {
+        line.set_clock(true);
+        line.set_data(true);
+
+        Self {
+            line,
+            transfer: Transfer::IDLE,
+            previous_clock: true,
+            received: None,
+            missing_line_acks: 0,
+        }
+    }
+
+    pub fn line_mut(&mut self) -> &mut L {
+        &mut self.line
+    }
+
+    pub fn into_line(self) -> L {
+        self.line
+    }
+
+    /// Returns `true` if a frame is being received or sent.
+    pub fn busy(&self) -> bool {
+        !matches!(self.transfer, Transfer::Receive { count: 0, .. })
+    }
+
+    /// Count of sent bytes which the device did not acknowledge on
+    /// the line level.
+    pub fn missing_line_acks(&self) -> u32 {
+        self.missing_line_acks
+    }
+
+    /// Sample the clock line and handle a falling edge if there
+    /// was one since the previous call. Also returns a byte which
+    /// was received while `SendToDevice::send` was waiting.
+    pub fn poll(&mut self) -> Option<Result<u8, TransmissionError>> {
+        if let Some(received) = self.received.take() {
+            return Some(received);
+        }
+
+        self.sample_clock()
+    }
+
+    fn sample_clock(&mut self) -> Option<Result<u8, TransmissionError>> {
+        let clock = self.line.clock();
+        let falling_edge = self.previous_clock && !clock;
+        self.previous_clock = clock;
+
+        if falling_edge {
+            self.clock_falling_edge()
+        } else {
+            None
+        }
+    }
+
+    /// Handle a falling edge of the clock line. Returns the byte when
+    /// a frame is received. Frames with invalid start or stop bit are
+    /// reported as `TransmissionError::Parity`.
+    pub fn clock_falling_edge(&mut self) -> Option<Result<u8, TransmissionError>> {
+        match self.transfer {
+            Transfer::Receive { bits, count } => {
+                let bits = bits | (u16::from(self.line.data()) << count);
+                let count = count + 1;
+
+                if count < FRAME_CLOCK_PULSES {
+                    self.transfer = Transfer::Receive { bits, count };
+                    return None;
+                }
+
+                self.transfer = Transfer::IDLE;
+                Some(decode_frame(bits))
+            }
+            Transfer::Send { data, count } => {
+                let count = count + 1;
+
+                match count {
+                    1..=8 => self.line.set_data(data & (1 << (count - 1)) != 0),
+                    9 => self.line.set_data(odd_parity(data)),
+                    10 => self.line.set_data(true),
+                    _ => {
+                        if self.line.data() {
+                            self.missing_line_acks = self.missing_line_acks.wrapping_add(1);
+                        }
+
+                        self.transfer = Transfer::IDLE;
+                        return None;
+                    }
+                }
+
+                self.transfer = Transfer::Send { data, count };
+                None
+            }
+        }
+    }
+
+    /// Abort the current frame. Call this if the clock line has
+    /// been idle too long in the middle of a frame. Returns
+    /// `TransmissionError::Timeout` if a frame was being received.
+    pub fn frame_timeout(&mut self) -> Option<TransmissionError> {
+        let receiving = matches!(self.transfer, Transfer::Receive { count, .. } if count > 0);
+
+        self.line.set_data(true);
+        self.transfer = Transfer::IDLE;
+
+        if receiving {
+            Some(TransmissionError::Timeout)
+        } else {
+            None
+        }
+    }
+
+    /// Request to send and start sending `data`. Device clocks the
+    /// bits in, so `clock_falling_edge` or `poll` must be called
+    /// until `busy` returns `false`.
+    pub fn start_send(&mut self, data: u8) -> Result<(), WouldBlock> {
+        if self.busy() {
+            return Err(WouldBlock);
+        }
+
+        self.line.set_clock(false);
+        self.line.delay_microseconds(REQUEST_TO_SEND_MICROSECONDS);
+        // Start bit.
+        self.line.set_data(false);
+        self.line.set_clock(true);
+        self.previous_clock = true;
+
+        self.transfer = Transfer::Send { data, count: 0 };
+        Ok(())
+    }
+}
+
+impl<L: Ps2LineDriver> SendToDevice for GpioPort<L> {
+    /// Polls the clock line until the device has clocked in the
+    /// byte. Frame is aborted after `SEND_TIMEOUT_MICROSECONDS`.
+    /// Don't use from the clock interrupt handler.
+    fn send(&mut self, data: u8) {
+        // Wait until the device has sent the current frame.
+        let mut waited = 0;
+        while self.start_send(data).is_err() {
+            if waited >= SEND_TIMEOUT_MICROSECONDS {
+                if let Some(e) = self.frame_timeout() {
+                    self.received = Some(Err(e));
+                }
+                continue;
+            }
+
+            self.line
+                .delay_microseconds(SEND_POLL_INTERVAL_MICROSECONDS);
+            waited += SEND_POLL_INTERVAL_MICROSECONDS;
+            if let Some(received) = self.sample_clock() {
+                self.received = Some(received);
+            }
+        }
+
+        let mut waited = 0;
+        while self.busy() {
+            if waited >= SEND_TIMEOUT_MICROSECONDS {
+                self.frame_timeout();
+                return;
+            }
+
+            self.line
+                .delay_microseconds(SEND_POLL_INTERVAL_MICROSECONDS);
+            waited += SEND_POLL_INTERVAL_MICROSECONDS;
+            let _ = self.sample_clock();
+        }
+    }
+
+    /// Start sending without waiting. Returns `WouldBlock` if a
+    /// frame is in progress.
+    fn try_send(&mut self, data: u8) -> Result<(), WouldBlock> {
+        self.start_send(data)
+    }
+}
+
+/// Returns the parity bit which makes the count of set bits odd.
+fn odd_parity(data: u8) -> bool {
+    data.count_ones() & 1 == 0
+}
+
+fn decode_frame(bits: u16) -> Result<u8, TransmissionError> {
+    let start = bits & 1 != 0;
+    let data = (bits >> 1) as u8;
+    let parity = bits & (1 << 9) != 0;
+    let stop = bits & (1 << 10) != 0;
+
+    if start || !stop || parity != odd_parity(data) {
+        Err(TransmissionError::Parity)
+    } else {
+        Ok(data)
+    }
+}
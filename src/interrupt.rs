@@ -0,0 +1,108 @@
+//! End of interrupt ordering for driver interrupt handlers.
+//!
+//! Interrupt controller must receive end of interrupt (EOI) at the
+//! right point relative to the data port read. If EOI is sent too
+//! early or not at all, interrupts from the PS/2 controller can be
+//! lost. Drivers call the hook from their interrupt handler methods,
+//! so the order is the same every time.
+//!
+//! `IRQ_KEYBOARD` and `IRQ_AUX` are the interrupt lines of the
+//! controller. Use them when setting up interrupt handlers, so the
+//! handler of each line calls the right driver.
+
+use crate::controller::driver::status::DataOwner;
+
+/// Interrupt line of the legacy interrupt controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Irq(u8);
+
+/// Keyboard interrupt line.
+pub const IRQ_KEYBOARD: Irq = Irq(1);
+/// Auxiliary device interrupt line.
+pub const IRQ_AUX: Irq = Irq(12);
+
+impl Irq {
+    pub const fn number(self) -> u8 {
+        self.0
+    }
+
+    /// Interrupt vector when the interrupt lines are remapped to
+    /// consecutive vectors starting from `offset`, like I/O APIC
+    /// redirection entries usually are. `None` if the vector would
+    /// be larger than 255.
+    pub const fn vector(self, offset: u8) -> Option<u8> {
+        offset.checked_add(self.0)
+    }
+
+    /// Interrupt vector with cascaded 8259 PICs. Lines 8-15 are
+    /// connected to the secondary PIC.
+    pub const fn pic_vector(self, primary_offset: u8, secondary_offset: u8) -> Option<u8> {
+        if self.0 < 8 {
+            primary_offset.checked_add(self.0)
+        } else {
+            secondary_offset.checked_add(self.0 - 8)
+        }
+    }
+}
+
+/// Interrupt line which is raised when the controller has data from
+/// `owner`.
+pub const fn irq_for(owner: DataOwner) -> Irq {
+    match owner {
+        DataOwner::KeyboardOrCommandController => IRQ_KEYBOARD,
+        DataOwner::AuxiliaryDevice => IRQ_AUX,
+    }
+}
+
+/// Send EOI to the interrupt controller.
+pub type EndOfInterrupt = fn();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EoiOrder {
+    /// Send EOI before reading the data port.
+    BeforeRead,
+    /// Send EOI after reading the data port. If the controller has
+    /// another byte, the interrupt for it is raised after the read
+    /// and the interrupt controller keeps it pending until EOI.
+    #[default]
+    AfterRead,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EndOfInterruptHook {
+    hook: Option<EndOfInterrupt>,
+    order: EoiOrder,
+}
+
+impl EndOfInterruptHook {
+    pub const fn new() -> Self {
+        Self {
+            hook: None,
+            order: EoiOrder::AfterRead,
+        }
+    }
+
+    pub fn set_hook(&mut self, hook: Option<EndOfInterrupt>, order: EoiOrder) {
+        self.hook = hook;
+        self.order = order;
+    }
+
+    pub fn order(&self) -> EoiOrder {
+        self.order
+    }
+
+    /// Run `read` and call the hook before or after it. Hook is
+    /// called even if there was no data.
+    pub fn around_read<R, F: FnOnce() -> R>(&self, read: F) -> R {
+        let hook = self.hook.unwrap_or(|| ());
+
+        if self.order == EoiOrder::BeforeRead {
+            hook();
+            read()
+        } else {
+            let result = read();
+            hook();
+            result
+        }
+    }
+}
@@ -0,0 +1,788 @@
+//! Polling keyboard drivers.
+//!
+//! `KeyboardDriver` reads scancodes from the controller and decodes
+//! them. No commands are sent to the keyboard. Use `QueuedKeyboardDriver`
+//! if keyboard configuration is needed.
+
+use crate::capture::Capture;
+#[cfg(feature = "unstable")]
+use crate::controller::{driver::handoff::Handoff, transaction::TransactionError};
+use crate::controller::{
+    driver::{
+        status::{DataOrigin, PasswordState, ReadStatus},
+        DeviceData, DeviceNotEnabled, EnabledDevices, KeyboardSender, ReadData,
+    },
+    io::PortIO,
+};
+use crate::device::io::TransmissionError;
+use crate::device::keyboard::driver::{
+    ByteHistory, DecoderLayout, InitialState, Keyboard as KeyboardDevice, KeyboardError,
+    KeyboardEvent, NotEnoughSpaceInTheCommandQueue, GRAB_BUFFER_CAPACITY,
+};
+use crate::device::keyboard::raw::{CommandReturnData, FromKeyboard};
+use crate::device::keyboard::translation::{TranslationMode, Translator};
+use crate::interrupt::{EndOfInterrupt, EndOfInterruptHook, EoiOrder};
+#[cfg(feature = "unstable")]
+use crate::readiness::{Notify, Readiness, ReadinessHook};
+use crate::ring_buffer::{FullPolicy, RingBuffer};
+use crate::sequence::{EventSequence, Sequenced};
+
+use core::fmt;
+#[cfg(feature = "unstable")]
+use core::task::Waker;
+
+use pc_keyboard::{
+    layouts, DecodedKey, Error, HandleControl, KeyCode, KeyEvent, KeyState, Keyboard,
+    KeyboardLayout, ScancodeSet,
+};
+
+/// Default capacity of the buffer between `irq_fast_path` and
+/// `process_deferred`.
+pub const DEFERRED_BUFFER_CAPACITY: usize = 16;
+
+/// Maximum number of hotkeys in `KeyboardDriver`.
+pub const HOTKEY_TABLE_CAPACITY: usize = 4;
+
+/// Called from interrupt handler methods with the raw scancode byte.
+pub type HotkeyCallback = fn(u8);
+
+#[derive(Debug)]
+pub struct HotkeyTableFull;
+
+/// Called with every key event and the key which the layout decoded
+/// from it. Composition state of the input method is kept by the
+/// caller, for example in a static.
+pub type InputMethodHook = fn(&KeyEvent, Option<DecodedKey>) -> Composition;
+
+/// Result of `InputMethodHook`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Composition {
+    /// Deliver the decoded key unchanged.
+    PassThrough,
+    /// Key was added to the composition and is delivered with
+    /// `decoded` set to `None`.
+    Composing,
+    /// Composition finished. Key is delivered with `decoded` set to
+    /// the committed key. Input methods which commit more than one
+    /// character deliver the rest themselves.
+    Commit(DecodedKey),
+}
+
+/// Keyboard driver with deferred buffer capacity `D`.
+pub struct KeyboardDriver<
+    T: PortIO,
+    IRQ,
+    K: KeyboardLayout,
+    S: ScancodeSet,
+    const D: usize = DEFERRED_BUFFER_CAPACITY,
+> {
+    controller: EnabledDevices<T, IRQ>,
+    decoder: Keyboard<K, S>,
+    translator: Option<Translator>,
+    deferred: RingBuffer<DeviceData, D>,
+    deferred_overflows: u32,
+    deferred_policy: FullPolicy,
+    sequence: EventSequence,
+    end_of_interrupt: EndOfInterruptHook,
+    #[cfg(feature = "unstable")]
+    readiness: ReadinessHook,
+    hotkeys: [Option<(u8, HotkeyCallback)>; HOTKEY_TABLE_CAPACITY],
+    history: ByteHistory,
+    input_method: Option<InputMethodHook>,
+    password_unlock_pending: bool,
+}
+
+impl<T: PortIO, IRQ, K: KeyboardLayout, S: ScancodeSet, const D: usize> fmt::Debug
+    for KeyboardDriver<T, IRQ, K, S, D>
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("KeyboardDriver")
+    }
+}
+
+impl<T: PortIO, IRQ, K: KeyboardLayout, S: ScancodeSet> KeyboardDriver<T, IRQ, K, S> {
+    pub fn new(
+        controller: EnabledDevices<T, IRQ>,
+        layout: K,
+        scancode_set: S,
+        handle_ctrl: HandleControl,
+    ) -> Self {
+        Self::with_deferred_capacity(controller, layout, scancode_set, handle_ctrl)
+    }
+}
+
+impl<T: PortIO, IRQ, K: KeyboardLayout, S: ScancodeSet, const D: usize>
+    KeyboardDriver<T, IRQ, K, S, D>
+{
+    /// Same as `new` but deferred buffer capacity is `D`.
+    pub fn with_deferred_capacity(
+        controller: EnabledDevices<T, IRQ>,
+        layout: K,
+        scancode_set: S,
+        handle_ctrl: HandleControl,
+    ) -> Self {
+        Self {
+            controller,
+            decoder: Keyboard::new(layout, scancode_set, handle_ctrl),
+            translator: None,
+            deferred: RingBuffer::new(),
+            deferred_overflows: 0,
+            deferred_policy: FullPolicy::Saturate,
+            sequence: EventSequence::new(),
+            end_of_interrupt: EndOfInterruptHook::new(),
+            #[cfg(feature = "unstable")]
+            readiness: ReadinessHook::new(),
+            hotkeys: [None; HOTKEY_TABLE_CAPACITY],
+            history: ByteHistory::new(),
+            input_method: None,
+            password_unlock_pending: false,
+        }
+    }
+
+    /// Default is `TranslationMode::Hardware`. Use the same mode
+    /// with `DevicesDisabled::set_translation_mode`. With both modes
+    /// the decoder receives scancode set 1.
+    pub fn set_translation_mode(&mut self, mode: TranslationMode) {
+        self.translator = match mode {
+            TranslationMode::Hardware => None,
+            TranslationMode::Software => Some(Translator::new()),
+        };
+    }
+
+    pub fn controller_mut(&mut self) -> &mut EnabledDevices<T, IRQ> {
+        &mut self.controller
+    }
+
+    pub fn into_controller(self) -> EnabledDevices<T, IRQ> {
+        self.controller
+    }
+
+    /// Read one byte from the controller if it is available and return
+    /// the decoded key. Key releases, BAT codes and errors are
+    /// discarded. Use `poll_keyboard_event` to receive those.
+    pub fn poll_keyboard(&mut self) -> Option<DecodedKey> {
+        match self.poll_keyboard_event()? {
+            KeyboardPollResult::Key {
+                decoded: Some(key), ..
+            } => Some(key),
+            _ => None,
+        }
+    }
+
+    /// Read one byte from the controller if it is available and return
+    /// the result of processing it.
+    pub fn poll_keyboard_event(&mut self) -> Option<KeyboardPollResult> {
+        self.poll_sequenced().map(|e| e.event)
+    }
+
+    /// Same as `poll_keyboard_event` but the received byte and the
+    /// result are written to `capture`.
+    pub fn poll_captured(
+        &mut self,
+        capture: &mut Capture,
+        timestamp: u32,
+    ) -> Option<KeyboardPollResult> {
+        let data = match self.controller.read_data() {
+            Some(data) => data,
+            None => {
+                let result = self.password_state_change()?;
+                capture.poll_result(timestamp, &result);
+                return Some(result);
+            }
+        };
+        if let DeviceData::Keyboard(byte) = data {
+            capture.byte(timestamp, byte);
+        }
+
+        let result = self.handle_data(data)?.event;
+        capture.poll_result(timestamp, &result);
+        Some(result)
+    }
+
+    /// Same as `poll_keyboard_event` but the result has a sequence number.
+    pub fn poll_sequenced(&mut self) -> Option<Sequenced<KeyboardPollResult>> {
+        match self.controller.read_data() {
+            Some(data) => self.handle_data(data),
+            None => {
+                let result = self.password_state_change()?;
+                Some(self.sequence.stamp(result))
+            }
+        }
+    }
+
+    /// Returns `true` if a keyboard byte is waiting in the controller
+    /// output buffer or in the deferred buffer of `irq_fast_path`.
+    /// No data is read.
+    pub fn has_pending_input(&mut self) -> bool {
+        !self.deferred.is_empty()
+            || matches!(
+                self.controller.status().data_origin(),
+                Some(DataOrigin::Keyboard)
+            )
+    }
+
+    /// Controller password is active and the controller consumes
+    /// keyboard bytes until the user types the password. Keyboard
+    /// doesn't send events, but it is not broken.
+    ///
+    /// Polling methods check the password state when there is no
+    /// data and return `KeyboardPollResult::PasswordUnlockPending` and
+    /// `KeyboardPollResult::PasswordUnlocked` when it changes. Bytes
+    /// received while the password is active are discarded.
+    pub fn password_unlock_pending(&self) -> bool {
+        self.password_unlock_pending
+    }
+
+    /// Call this from keyboard interrupt handler.
+    pub fn handle_keyboard_interrupt(&mut self) -> Option<KeyboardPollResult> {
+        let data = self.read_in_interrupt()?;
+        self.handle_data(data).map(|e| e.event)
+    }
+
+    /// Set hook which `handle_keyboard_interrupt` and `irq_fast_path` call
+    /// to send end of interrupt. Default order is `EoiOrder::AfterRead`.
+    pub fn set_end_of_interrupt(&mut self, hook: Option<EndOfInterrupt>, order: EoiOrder) {
+        self.end_of_interrupt.set_hook(hook, order);
+    }
+
+    /// Call `callback` when `scancode` is received in
+    /// `handle_keyboard_interrupt` or `irq_fast_path`. The byte is
+    /// compared before translation and decoding, and it is still
+    /// processed normally after the callback returns. Replaces
+    /// the callback if `scancode` already has one.
+    ///
+    /// Keep callbacks short, they run in interrupt context.
+    pub fn add_hotkey(
+        &mut self,
+        scancode: u8,
+        callback: HotkeyCallback,
+    ) -> Result<(), HotkeyTableFull> {
+        let slot = match self
+            .hotkeys
+            .iter()
+            .position(|hotkey| matches!(hotkey, Some((code, _)) if *code == scancode))
+        {
+            Some(i) => i,
+            None => self
+                .hotkeys
+                .iter()
+                .position(Option::is_none)
+                .ok_or(HotkeyTableFull)?,
+        };
+
+        self.hotkeys[slot] = Some((scancode, callback));
+        Ok(())
+    }
+
+    pub fn remove_hotkey(&mut self, scancode: u8) {
+        for hotkey in &mut self.hotkeys {
+            if matches!(hotkey, Some((code, _)) if *code == scancode) {
+                *hotkey = None;
+            }
+        }
+    }
+
+    pub fn clear_hotkeys(&mut self) {
+        self.hotkeys = [None; HOTKEY_TABLE_CAPACITY];
+    }
+
+    /// Set hook which receives decoded keys before they are returned.
+    /// Key events are still returned while composing, so key state
+    /// tracking is not affected.
+    pub fn set_input_method(&mut self, hook: Option<InputMethodHook>) {
+        self.input_method = hook;
+    }
+
+    fn read_in_interrupt(&mut self) -> Option<DeviceData> {
+        let controller = &mut self.controller;
+        let data = self
+            .end_of_interrupt
+            .around_read(|| controller.read_data())?;
+
+        if let DeviceData::Keyboard(byte) = data {
+            for (code, callback) in self.hotkeys.iter().flatten() {
+                if *code == byte {
+                    callback(byte);
+                }
+            }
+        }
+
+        Some(data)
+    }
+
+    /// Alternative to `handle_keyboard_interrupt` which only reads
+    /// the byte and stores it for `process_deferred`. Returns `true`
+    /// if a byte was stored.
+    ///
+    /// If the buffer is full, a byte is discarded according to
+    /// `set_deferred_policy` and counted in `deferred_overflows`.
+    pub fn irq_fast_path(&mut self) -> bool {
+        let data = match self.read_in_interrupt() {
+            Some(data) => data,
+            None => return false,
+        };
+
+        let stored = match self
+            .deferred
+            .push_back_with_policy(data, self.deferred_policy)
+        {
+            None => true,
+            Some(_) => {
+                self.deferred_overflows = self.deferred_overflows.wrapping_add(1);
+                self.deferred_policy == FullPolicy::OverwriteOldest && D > 0
+            }
+        };
+
+        #[cfg(feature = "unstable")]
+        if stored {
+            self.readiness.notify();
+        }
+        stored
+    }
+
+    /// Byte to discard when the deferred buffer is full. Default is
+    /// `FullPolicy::Saturate`. `FullPolicy::OverwriteOldest` keeps
+    /// the latest input, but the first event after an overflow may be
+    /// decoded from a partial byte sequence.
+    pub fn set_deferred_policy(&mut self, policy: FullPolicy) {
+        self.deferred_policy = policy;
+    }
+
+    pub fn deferred_policy(&self) -> FullPolicy {
+        self.deferred_policy
+    }
+
+    /// Process bytes stored by `irq_fast_path` until there is a result
+    /// or the buffer is empty. Call this from task context.
+    ///
+    /// Don't mix with `poll_keyboard_event`, because bytes would be
+    /// processed out of order.
+    pub fn process_deferred(&mut self) -> Option<KeyboardPollResult> {
+        self.process_deferred_sequenced().map(|e| e.event)
+    }
+
+    /// Same as `process_deferred` but the result has a sequence number.
+    pub fn process_deferred_sequenced(&mut self) -> Option<Sequenced<KeyboardPollResult>> {
+        while let Some(data) = self.deferred.pop_front() {
+            if let Some(result) = self.handle_data(data) {
+                return Some(result);
+            }
+        }
+
+        None
+    }
+
+    /// Number of bytes discarded because the deferred buffer was full.
+    /// Events of these bytes are lost without a gap in the
+    /// sequence numbers.
+    pub fn deferred_overflows(&self) -> u32 {
+        self.deferred_overflows
+    }
+
+    /// Sequence number of the next event.
+    pub fn next_sequence(&self) -> u32 {
+        self.sequence.next()
+    }
+
+    /// Latest bytes received from the keyboard before translation.
+    pub fn byte_history(&self) -> &ByteHistory {
+        &self.history
+    }
+
+    fn password_state_change(&mut self) -> Option<KeyboardPollResult> {
+        let active = matches!(
+            self.controller.status().password_state(),
+            PasswordState::Active
+        );
+        if active == self.password_unlock_pending {
+            return None;
+        }
+
+        self.password_unlock_pending = active;
+        if active {
+            self.decoder.clear();
+            Some(KeyboardPollResult::PasswordUnlockPending)
+        } else {
+            Some(KeyboardPollResult::PasswordUnlocked)
+        }
+    }
+
+    fn handle_data(&mut self, data: DeviceData) -> Option<Sequenced<KeyboardPollResult>> {
+        let result = self.process_data(data)?;
+        Some(self.sequence.stamp(result))
+    }
+
+    fn process_data(&mut self, data: DeviceData) -> Option<KeyboardPollResult> {
+        if self.password_unlock_pending {
+            return None;
+        }
+
+        if let DeviceData::Keyboard(data) = data {
+            self.history.push(data);
+        }
+
+        match data {
+            DeviceData::Keyboard(data) => match &mut self.translator {
+                Some(translator) => {
+                    let data = translator.translate(data)?;
+                    self.handle_byte(data)
+                }
+                None => self.handle_byte(data),
+            },
+            DeviceData::KeyboardTransmissionError(e) => {
+                // Keyboard is enabled, so sending can't fail.
+                let _ = self.controller.send_to_keyboard(CommandReturnData::RESEND);
+                if let Some(translator) = &mut self.translator {
+                    translator.clear();
+                }
+                Some(KeyboardPollResult::TransmissionError(e))
+            }
+            _ => None,
+        }
+    }
+
+    fn handle_byte(&mut self, data: u8) -> Option<KeyboardPollResult> {
+        match data {
+            FromKeyboard::BAT_FAILURE_CODE => {
+                self.decoder.clear();
+                return Some(KeyboardPollResult::BATFailure);
+            }
+            FromKeyboard::KEY_DETECTION_OVERRUN_SCANCODE_SET_1
+            | FromKeyboard::KEY_DETECTION_OVERRUN_SCANCODE_SET_2_AND_3 => {
+                self.decoder.clear();
+                return Some(KeyboardPollResult::KeyDetectionError);
+            }
+            _ => (),
+        }
+
+        match self.decoder.add_byte(data) {
+            Ok(Some(KeyEvent {
+                code: KeyCode::PowerOnTestOk,
+                state: KeyState::Down,
+            })) => Some(KeyboardPollResult::BATCompleted),
+            Ok(Some(event)) => {
+                let mut decoded = self.decoder.process_keyevent(event.clone());
+                if let Some(hook) = self.input_method {
+                    decoded = match hook(&event, decoded) {
+                        Composition::PassThrough => decoded,
+                        Composition::Composing => None,
+                        Composition::Commit(key) => Some(key),
+                    };
+                }
+                Some(KeyboardPollResult::Key { event, decoded })
+            }
+            Ok(None) => None,
+            Err(e) => Some(KeyboardPollResult::Error(e)),
+        }
+    }
+}
+
+#[cfg(feature = "unstable")]
+#[cfg_attr(docsrs, doc(cfg(feature = "unstable")))]
+impl<T: PortIO, IRQ, K: KeyboardLayout, S: ScancodeSet, const D: usize> Readiness
+    for KeyboardDriver<T, IRQ, K, S, D>
+{
+    fn set_readiness(&mut self, notify: Option<Notify>) {
+        self.readiness.set(notify);
+    }
+
+    fn events_pending(&self) -> bool {
+        !self.deferred.is_empty()
+    }
+
+    fn register_waker(&mut self, waker: &Waker) {
+        self.readiness.register_waker(waker);
+    }
+}
+
+#[derive(Debug)]
+pub enum KeyboardPollResult {
+    /// Key press or release. `decoded` is `None` for releases and
+    /// modifier keys.
+    Key {
+        event: KeyEvent,
+        decoded: Option<DecodedKey>,
+    },
+    BATCompleted,
+    BATFailure,
+    KeyDetectionError,
+    /// Scancode decoding error.
+    Error(Error),
+    /// Corrupted byte was discarded and resend requested.
+    TransmissionError(TransmissionError),
+    /// Controller password became active. See
+    /// `KeyboardDriver::password_unlock_pending`.
+    PasswordUnlockPending,
+    /// User typed the controller password and key events resume.
+    PasswordUnlocked,
+}
+
+/// `device::keyboard::driver::Keyboard` combined with the controller.
+/// Capacities `N` and `G` and layout `K` are passed to the keyboard
+/// driver.
+///
+/// All bytes to the keyboard go through the command queue, so ACK
+/// and resend tracking stays in sync. `send_raw` bypasses the queue.
+#[derive(Debug)]
+pub struct QueuedKeyboardDriver<
+    T: PortIO,
+    IRQ,
+    const N: usize,
+    const G: usize = GRAB_BUFFER_CAPACITY,
+    K: DecoderLayout = layouts::Us104Key,
+> {
+    controller: EnabledDevices<T, IRQ>,
+    keyboard: KeyboardDevice<N, G, K>,
+}
+
+#[derive(Debug)]
+pub enum QueuedKeyboardError {
+    DeviceNotEnabled,
+    NotEnoughSpaceInTheCommandQueue,
+}
+
+impl<T: PortIO, IRQ, const N: usize, K: DecoderLayout>
+    QueuedKeyboardDriver<T, IRQ, N, GRAB_BUFFER_CAPACITY, K>
+{
+    /// Queues commands which set keyboard defaults and disable scanning.
+    pub fn new(
+        controller: EnabledDevices<T, IRQ>,
+    ) -> Result<Self, (EnabledDevices<T, IRQ>, QueuedKeyboardError)> {
+        Self::with_grab_buffer_capacity(controller)
+    }
+}
+
+impl<T: PortIO, IRQ, const N: usize, const G: usize, K: DecoderLayout>
+    QueuedKeyboardDriver<T, IRQ, N, G, K>
+{
+    /// Same as `new` but grab buffer capacity is `G`.
+    pub fn with_grab_buffer_capacity(
+        controller: EnabledDevices<T, IRQ>,
+    ) -> Result<Self, (EnabledDevices<T, IRQ>, QueuedKeyboardError)> {
+        Self::with_initial_state(controller, InitialState::DefaultsDisabled)
+    }
+
+    /// See `Keyboard::with_initial_state`.
+    pub fn with_initial_state(
+        mut controller: EnabledDevices<T, IRQ>,
+        state: InitialState,
+    ) -> Result<Self, (EnabledDevices<T, IRQ>, QueuedKeyboardError)> {
+        let mut sender = match controller.keyboard_sender() {
+            Ok(sender) => sender,
+            Err(DeviceNotEnabled) => {
+                return Err((controller, QueuedKeyboardError::DeviceNotEnabled))
+            }
+        };
+
+        match KeyboardDevice::with_initial_state(&mut sender, state) {
+            Ok(keyboard) => Ok(Self {
+                controller,
+                keyboard,
+            }),
+            Err(NotEnoughSpaceInTheCommandQueue) => Err((
+                controller,
+                QueuedKeyboardError::NotEnoughSpaceInTheCommandQueue,
+            )),
+        }
+    }
+
+    pub fn keyboard(&self) -> &KeyboardDevice<N, G, K> {
+        &self.keyboard
+    }
+
+    /// Run keyboard driver methods which send commands.
+    ///
+    /// ```rust,ignore
+    /// driver.command(|keyboard, sender| keyboard.set_status_indicators(sender, leds))?;
+    /// ```
+    pub fn command<R, F: FnOnce(&mut KeyboardDevice<N, G, K>, &mut KeyboardSender<T, IRQ>) -> R>(
+        &mut self,
+        f: F,
+    ) -> R {
+        f(
+            &mut self.keyboard,
+            &mut keyboard_sender(&mut self.controller),
+        )
+    }
+
+    /// Read one byte from the controller if it is available and
+    /// give it to the keyboard driver.
+    pub fn poll(&mut self) -> Option<Result<Option<KeyboardEvent>, KeyboardError>> {
+        let data = self.controller.read_data()?;
+        let mut sender = keyboard_sender(&mut self.controller);
+
+        match data {
+            DeviceData::Keyboard(data) => Some(self.keyboard.receive_data(data, &mut sender)),
+            DeviceData::KeyboardTransmissionError(e) => {
+                Some(self.keyboard.receive_transmission_error(e, &mut sender))
+            }
+            _ => None,
+        }
+    }
+
+    /// See `Keyboard::tick`.
+    pub fn tick(
+        &mut self,
+        elapsed_milliseconds: u32,
+    ) -> Result<Option<KeyboardEvent>, KeyboardError> {
+        let mut sender = keyboard_sender(&mut self.controller);
+        self.keyboard.tick(elapsed_milliseconds, &mut sender)
+    }
+
+    /// See `Keyboard::pump`.
+    pub fn pump(&mut self) -> bool {
+        let mut sender = keyboard_sender(&mut self.controller);
+        self.keyboard.pump(&mut sender)
+    }
+
+    /// Send byte without the command queue. Responses to the byte
+    /// are handled as unexpected data by the keyboard driver.
+    pub fn send_raw(&mut self, data: u8) -> Result<(), DeviceNotEnabled> {
+        self.controller.send_to_keyboard(data)
+    }
+
+    pub fn into_parts(self) -> (EnabledDevices<T, IRQ>, KeyboardDevice<N, G, K>) {
+        (self.controller, self.keyboard)
+    }
+
+    /// Same as `EnabledDevices::handoff` but the scancode set and
+    /// the keyboard kind are set from the keyboard driver.
+    #[cfg(feature = "unstable")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "unstable")))]
+    pub fn handoff(self) -> (T, Result<Handoff, TransactionError>) {
+        let (port_io, handoff) = self.controller.handoff();
+        let keyboard = self.keyboard;
+        let handoff = handoff.map(|handoff| Handoff {
+            scancode_set: keyboard.scancode_set(),
+            keyboard_kind: keyboard.kind(),
+            ..handoff
+        });
+        (port_io, handoff)
+    }
+}
+
+fn keyboard_sender<T: PortIO, IRQ>(
+    controller: &mut EnabledDevices<T, IRQ>,
+) -> KeyboardSender<'_, T, IRQ> {
+    // Keyboard was enabled when the driver was created.
+    match controller.keyboard_sender() {
+        Ok(sender) => sender,
+        Err(DeviceNotEnabled) => unreachable!(),
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use crate::controller::driver::{marker::Disabled, EnableDevice, InitController};
+    use crate::controller::io::PortIOAvailable;
+    use crate::controller::raw::StatusRegister;
+    use crate::testing::Simulator;
+
+    use pc_keyboard::ScancodeSet1;
+
+    /// Status register value with the keyboard not inhibited which
+    /// real controllers commonly return.
+    const IDLE_STATUS: u8 = 0x1C;
+
+    /// Simulator with fixed status register bits other than the
+    /// buffer bits.
+    #[derive(Debug)]
+    struct FixedStatus {
+        simulator: Simulator,
+        status: u8,
+    }
+
+    impl PortIO for FixedStatus {
+        type PortID = u16;
+
+        const DATA_PORT: Self::PortID = Simulator::DATA_PORT;
+        const STATUS_REGISTER: Self::PortID = Simulator::STATUS_REGISTER;
+        const COMMAND_REGISTER: Self::PortID = Simulator::COMMAND_REGISTER;
+
+        fn read(&mut self, port: Self::PortID) -> u8 {
+            let value = self.simulator.read(port);
+            if port == Self::STATUS_REGISTER {
+                let buffers = StatusRegister::OUTPUT_BUFFER_FULL
+                    | StatusRegister::AUXILIARY_DEVICE_OUTPUT_BUFFER_FULL
+                    | StatusRegister::INPUT_BUFFER_FULL;
+                (value & buffers.bits()) | (self.status & !buffers.bits())
+            } else {
+                value
+            }
+        }
+
+        fn write(&mut self, port: Self::PortID, data: u8) {
+            self.simulator.write(port, data)
+        }
+    }
+
+    type Driver = KeyboardDriver<FixedStatus, Disabled, layouts::Us104Key, ScancodeSet1>;
+
+    fn driver() -> Driver {
+        let port_io = FixedStatus {
+            simulator: Simulator::new(),
+            status: IDLE_STATUS,
+        };
+        let mut controller = InitController::start_init(port_io);
+        controller.scancode_translation(false).unwrap();
+        let controller = controller.enable_devices(EnableDevice::Keyboard).unwrap();
+
+        KeyboardDriver::new(
+            controller,
+            layouts::Us104Key,
+            ScancodeSet1,
+            HandleControl::Ignore,
+        )
+    }
+
+    fn port_io(driver: &mut Driver) -> &mut FixedStatus {
+        driver.controller_mut().port_io_mut()
+    }
+
+    fn key_a(result: Option<KeyboardPollResult>) -> bool {
+        matches!(
+            result,
+            Some(KeyboardPollResult::Key {
+                event: KeyEvent {
+                    code: KeyCode::A,
+                    state: KeyState::Down
+                },
+                ..
+            })
+        )
+    }
+
+    #[test]
+    fn keys_are_delivered_when_keyboard_is_not_inhibited() {
+        let mut driver = driver();
+
+        assert!(driver.poll_keyboard_event().is_none());
+        port_io(&mut driver).simulator.press_keyboard_bytes(&[0x1E]);
+        assert!(key_a(driver.poll_keyboard_event()));
+        assert!(!driver.password_unlock_pending());
+    }
+
+    #[test]
+    fn keys_are_discarded_while_password_is_active() {
+        let mut driver = driver();
+        port_io(&mut driver).status = IDLE_STATUS & !StatusRegister::INHIBIT_SWITCH.bits();
+
+        assert!(matches!(
+            driver.poll_keyboard_event(),
+            Some(KeyboardPollResult::PasswordUnlockPending)
+        ));
+        port_io(&mut driver).simulator.press_keyboard_bytes(&[0x1E]);
+        assert!(driver.poll_keyboard_event().is_none());
+
+        port_io(&mut driver).status = IDLE_STATUS;
+        assert!(matches!(
+            driver.poll_keyboard_event(),
+            Some(KeyboardPollResult::PasswordUnlocked)
+        ));
+        port_io(&mut driver).simulator.press_keyboard_bytes(&[0x1E]);
+        assert!(key_a(driver.poll_keyboard_event()));
+    }
+}
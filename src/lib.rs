@@ -6,11 +6,145 @@
 //! * <http://classiccomputers.info/down/IBM_PS2/documents/PS2_Hardware_Interface_Technical_Reference_May88.pdf>
 //!     * PDF page 332
 //! * <https://wiki.osdev.org/%228042%22_PS/2_Controller>
+//!
+//! # Features
+//! * `aux-device` (default) - Auxiliary device support. Disable to
+//!   reduce code size on keyboard only systems.
+//! * `conformance` - Run commands against real hardware and report
+//!   which commands are supported.
+//! * `emulation` - Controller side of the protocol for emulating an
+//!   8042 in a virtual machine monitor.
+//! * `event-model` - Adapter to a W3C style key event model for
+//!   GUI toolkits.
+//! * `ffi` - `#[repr(C)]` mirrors of events and errors and a C
+//!   header for them.
+//! * `global` - `StaticPs2` wrapper for `static` driver storage.
+//! * `gpio` - PS/2 port which uses GPIO pins instead of a controller.
+//! * `machine-reset` (default) - `ResetCPU`. Disable to make sure
+//!   that the driver can't reset the machine.
+//! * `preset-tiny`, `preset-default` (default) and `preset-full` -
+//!   Feature sets with a memory budget which is checked by tests.
+//!   See `footprint`.
+//! * `qemu-ci` - Scripted QEMU input tests for kernels which use
+//!   this crate.
+//! * `render` - Single line text rendering of events for logs.
+//! * `set1`, `set2` and `set3` (default) - Scancode sets which the
+//!   keyboard driver supports. One of `set1` and `set2` is required.
+//!   Use only `set1` with controller translation to reduce code size.
+//! * `testing` - Mock `PortIO` and controller simulator.
+//! * `unstable` - Experimental subsystems. See API stability.
+//! * `x86-io` - `PortIO` implementation for x86 I/O ports.
+//!
+//! # API stability
+//! * Stable - Items re-exported from a versioned module like `v1`.
+//!   Breaking changes require a new versioned module. `v1` contains
+//!   the core typestate, controller and keyboard API.
+//! * Unstable - Items which require the `unstable` feature: the
+//!   transition graph, `readiness`, `MergedSources`, controller
+//!   handoff and breadcrumbs. These may change or be removed in
+//!   any release.
+//!
+//! Other public items, for example the capture, health, hotkey and
+//! interrupt helpers, are not covered by semantic versioning yet.
+//! They may change in a minor release.
+//!
+//! # Thread safety
+//! Drivers are `Send` and `Sync` when the `PortIO` implementation is,
+//! so a driver can be moved to another core or stored behind a lock.
+//! Drivers have no interior mutability and every operation which
+//! touches the hardware takes `&mut self`, so there is no re-entrancy
+//! inside the driver. Accessing the driver from an interrupt handler
+//! requires a lock which the handler can't deadlock on. See `global`.
+//!
+//! # Machine state
+//! Some controller operations affect the whole machine:
+//! * `ResetCPU::reset_cpu` and `ResetCPU::try_reset_cpu` reset the
+//!   machine. These require the `machine-reset` feature.
+//! * `OutputPort::set_address_line_20` changes the A20 gate. It can't
+//!   reset the machine, but disabling the gate breaks memory access
+//!   above 1 MiB.
+//! * `Password::enable_password` blocks keyboard and auxiliary device
+//!   input until the user types the password.
+//! * Methods which wait for the controller without a `try_` prefix
+//!   don't return if the controller stops responding.
+//!   `ResetCPU::try_reset_cpu` has bounded waits.
+//!
+//! `PortIOAvailable::port_io_mut` gives unrestricted port access.
+//!
+//! # Formatting
+//! Driver code doesn't use `core::fmt` outside of `Debug`
+//! implementations, the `render` and `qemu-ci` features and
+//! `panic_dump`, which are linked only if they are used. Panic
+//! messages have no arguments, so builds with `panic_immediate_abort`
+//! contain no formatting code from this crate. `unwrap` and `expect`
+//! are denied, because they format the error value.
+//!
+//! These rules are checked only by the lints and by review. No test
+//! inspects a release build for `core::fmt` symbols, and `footprint`
+//! measures type sizes, not code size.
 
 #![no_std]
-#![forbid(missing_debug_implementations, unsafe_code)]
+#![cfg_attr(docsrs, feature(doc_cfg))]
+#![forbid(missing_debug_implementations)]
+#![deny(clippy::unwrap_used, clippy::expect_used)]
+#![cfg_attr(not(any(feature = "global", feature = "x86-io")), forbid(unsafe_code))]
+#![cfg_attr(any(feature = "global", feature = "x86-io"), deny(unsafe_code))]
 
+/// `&[(u8, &str)]` of raw constants and their names. Names
+/// start with the prefix literal of the group.
+macro_rules! name_table {
+    ($($prefix:literal $type:ident { $($name:ident),* $(,)? })*) => {
+        &[$($(($type::$name, concat!($prefix, stringify!($name))),)*)*]
+    };
+}
+
+mod auto_traits;
+pub mod capture;
 pub mod controller;
 pub mod device;
+pub mod footprint;
+pub mod interrupt;
+pub mod keyboard;
+#[cfg(feature = "aux-device")]
+#[cfg_attr(docsrs, doc(cfg(feature = "aux-device")))]
+pub mod mouse;
+pub mod panic_dump;
+pub mod passphrase;
+#[cfg(feature = "aux-device")]
+#[cfg_attr(docsrs, doc(cfg(feature = "aux-device")))]
+pub mod passthrough;
+#[cfg(feature = "unstable")]
+#[cfg_attr(docsrs, doc(cfg(feature = "unstable")))]
+pub mod readiness;
+pub mod ring_buffer;
+pub mod sequence;
+pub mod stream;
+pub mod trace;
+pub mod v1;
+
+#[cfg(feature = "emulation")]
+#[cfg_attr(docsrs, doc(cfg(feature = "emulation")))]
+pub mod emulation;
+#[cfg(feature = "event-model")]
+#[cfg_attr(docsrs, doc(cfg(feature = "event-model")))]
+pub mod event_model;
+#[cfg(feature = "ffi")]
+#[cfg_attr(docsrs, doc(cfg(feature = "ffi")))]
+pub mod ffi;
+#[cfg(feature = "global")]
+#[cfg_attr(docsrs, doc(cfg(feature = "global")))]
+pub mod global;
+#[cfg(feature = "gpio")]
+#[cfg_attr(docsrs, doc(cfg(feature = "gpio")))]
+pub mod gpio;
+#[cfg(feature = "qemu-ci")]
+#[cfg_attr(docsrs, doc(cfg(feature = "qemu-ci")))]
+pub mod qemu_ci;
+#[cfg(feature = "render")]
+#[cfg_attr(docsrs, doc(cfg(feature = "render")))]
+pub mod render;
+#[cfg(feature = "testing")]
+#[cfg_attr(docsrs, doc(cfg(feature = "testing")))]
+pub mod testing;
 
 pub use pc_keyboard;
@@ -0,0 +1,428 @@
+//! Simple polling mouse driver.
+//!
+//! `MouseDriver` enables data reporting and decodes standard
+//! 3 byte packets from the auxiliary device.
+
+use crate::controller::{
+    driver::{DeviceData, DeviceNotEnabled, EnabledDevices, ReadData},
+    io::PortIO,
+};
+use crate::device::health::{DeviceHealth, HealthCounters, HealthThresholds};
+use crate::device::identify::{Device, MouseKind};
+use crate::device::io::TransmissionError;
+use crate::device::mouse::{
+    packet::{MouseEvent, PacketDecoder},
+    raw::{FromMouse, MouseCommand},
+};
+use crate::interrupt::{EndOfInterrupt, EndOfInterruptHook, EoiOrder};
+use crate::keyboard::DEFERRED_BUFFER_CAPACITY;
+#[cfg(feature = "unstable")]
+use crate::readiness::{Notify, Readiness, ReadinessHook};
+use crate::ring_buffer::{FullPolicy, RingBuffer};
+use crate::sequence::{EventSequence, Sequenced};
+
+#[cfg(feature = "unstable")]
+use core::task::Waker;
+
+/// Mouse driver with deferred buffer capacity `D`.
+#[derive(Debug)]
+pub struct MouseDriver<T: PortIO, IRQ, const D: usize = DEFERRED_BUFFER_CAPACITY> {
+    controller: EnabledDevices<T, IRQ>,
+    decoder: PacketDecoder,
+    ack_pending: bool,
+    reconnect_pending: bool,
+    health: HealthCounters,
+    deferred: RingBuffer<DeviceData, D>,
+    deferred_overflows: u32,
+    deferred_policy: FullPolicy,
+    sequence: EventSequence,
+    end_of_interrupt: EndOfInterruptHook,
+    #[cfg(feature = "unstable")]
+    readiness: ReadinessHook,
+    storm_guard: Option<StormGuard>,
+    interrupt_bytes: u32,
+    storm: Option<StormAction>,
+    storm_report_pending: bool,
+    storm_discarded: u32,
+}
+
+/// Interrupt storm protection. See `MouseDriver::set_storm_guard`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StormGuard {
+    /// Bytes allowed in interrupt handlers between
+    /// `MouseDriver::tick` calls.
+    pub bytes_per_tick: u32,
+    pub action: StormAction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StormAction {
+    /// Send disable data reporting command to the device.
+    DisableReporting,
+    /// Disable the auxiliary device interface of the controller.
+    /// Use this if the device ignores commands.
+    DisableInterface,
+}
+
+impl<T: PortIO, IRQ> MouseDriver<T, IRQ> {
+    /// Enables data reporting of the auxiliary device.
+    pub fn new(
+        controller: EnabledDevices<T, IRQ>,
+    ) -> Result<Self, (EnabledDevices<T, IRQ>, DeviceNotEnabled)> {
+        Self::with_deferred_capacity(controller)
+    }
+}
+
+impl<T: PortIO, IRQ, const D: usize> MouseDriver<T, IRQ, D> {
+    /// Same as `new` but deferred buffer capacity is `D`.
+    pub fn with_deferred_capacity(
+        mut controller: EnabledDevices<T, IRQ>,
+    ) -> Result<Self, (EnabledDevices<T, IRQ>, DeviceNotEnabled)> {
+        if let Err(e) = controller.send_to_auxiliary_device(MouseCommand::ENABLE_DATA_REPORTING) {
+            return Err((controller, e));
+        }
+
+        Ok(Self {
+            controller,
+            decoder: PacketDecoder::new(),
+            ack_pending: true,
+            reconnect_pending: false,
+            health: HealthCounters::new(),
+            deferred: RingBuffer::new(),
+            deferred_overflows: 0,
+            deferred_policy: FullPolicy::Saturate,
+            sequence: EventSequence::new(),
+            end_of_interrupt: EndOfInterruptHook::new(),
+            #[cfg(feature = "unstable")]
+            readiness: ReadinessHook::new(),
+            storm_guard: None,
+            interrupt_bytes: 0,
+            storm: None,
+            storm_report_pending: false,
+            storm_discarded: 0,
+        })
+    }
+
+    pub fn controller_mut(&mut self) -> &mut EnabledDevices<T, IRQ> {
+        &mut self.controller
+    }
+
+    pub fn into_controller(self) -> EnabledDevices<T, IRQ> {
+        self.controller
+    }
+
+    /// Read one byte from the controller if it is available and return
+    /// the mouse event if a packet was completed.
+    pub fn poll_mouse(&mut self) -> Option<MouseEvent> {
+        match self.poll_mouse_event()? {
+            MousePollResult::Mouse(event) => Some(event),
+            _ => None,
+        }
+    }
+
+    /// Read one byte from the controller if it is available and return
+    /// the result of processing it.
+    pub fn poll_mouse_event(&mut self) -> Option<MousePollResult> {
+        self.poll_sequenced().map(|e| e.event)
+    }
+
+    /// Same as `poll_mouse_event` but the result has a sequence number.
+    pub fn poll_sequenced(&mut self) -> Option<Sequenced<MousePollResult>> {
+        let data = self.controller.read_data()?;
+        self.handle_data(data)
+    }
+
+    /// Call this from auxiliary device interrupt handler.
+    pub fn handle_mouse_interrupt(&mut self) -> Option<MousePollResult> {
+        let data = self.read_in_interrupt()?;
+        if !self.count_interrupt_byte() {
+            return self.take_storm_report().map(|e| e.event);
+        }
+        self.handle_data(data).map(|e| e.event)
+    }
+
+    /// Set hook which `handle_mouse_interrupt` and `irq_fast_path` call
+    /// to send end of interrupt. Default order is `EoiOrder::AfterRead`.
+    pub fn set_end_of_interrupt(&mut self, hook: Option<EndOfInterrupt>, order: EoiOrder) {
+        self.end_of_interrupt.set_hook(hook, order);
+    }
+
+    fn read_in_interrupt(&mut self) -> Option<DeviceData> {
+        let controller = &mut self.controller;
+        self.end_of_interrupt.around_read(|| controller.read_data())
+    }
+
+    /// Alternative to `handle_mouse_interrupt` which only reads
+    /// the byte and stores it for `process_deferred`. Returns `true`
+    /// if a byte was stored.
+    ///
+    /// If the buffer is full, a byte is discarded according to
+    /// `set_deferred_policy` and counted in `deferred_overflows`.
+    pub fn irq_fast_path(&mut self) -> bool {
+        let data = match self.read_in_interrupt() {
+            Some(data) => data,
+            None => return false,
+        };
+
+        if !self.count_interrupt_byte() {
+            return false;
+        }
+
+        let stored = match self
+            .deferred
+            .push_back_with_policy(data, self.deferred_policy)
+        {
+            None => true,
+            Some(_) => {
+                self.deferred_overflows = self.deferred_overflows.wrapping_add(1);
+                self.deferred_policy == FullPolicy::OverwriteOldest && D > 0
+            }
+        };
+
+        #[cfg(feature = "unstable")]
+        if stored {
+            self.readiness.notify();
+        }
+        stored
+    }
+
+    /// Byte to discard when the deferred buffer is full. Default is
+    /// `FullPolicy::Saturate`. `FullPolicy::OverwriteOldest` keeps
+    /// the latest input, but the first event after an overflow may be
+    /// decoded from a partial byte sequence.
+    pub fn set_deferred_policy(&mut self, policy: FullPolicy) {
+        self.deferred_policy = policy;
+    }
+
+    pub fn deferred_policy(&self) -> FullPolicy {
+        self.deferred_policy
+    }
+
+    /// Process bytes stored by `irq_fast_path` until there is a result
+    /// or the buffer is empty. Call this from task context.
+    pub fn process_deferred(&mut self) -> Option<MousePollResult> {
+        self.process_deferred_sequenced().map(|e| e.event)
+    }
+
+    /// Same as `process_deferred` but the result has a sequence number.
+    pub fn process_deferred_sequenced(&mut self) -> Option<Sequenced<MousePollResult>> {
+        if let Some(result) = self.take_storm_report() {
+            return Some(result);
+        }
+
+        while let Some(data) = self.deferred.pop_front() {
+            if let Some(result) = self.handle_data(data) {
+                return Some(result);
+            }
+        }
+
+        None
+    }
+
+    /// Number of bytes discarded because the deferred buffer was full.
+    /// Events of these bytes are lost without a gap in the
+    /// sequence numbers.
+    pub fn deferred_overflows(&self) -> u32 {
+        self.deferred_overflows
+    }
+
+    /// Sequence number of the next event.
+    pub fn next_sequence(&self) -> u32 {
+        self.sequence.next()
+    }
+
+    /// Error statistics since the driver was created. Reconnects
+    /// are counted as BATs.
+    pub fn health_counters(&self) -> &HealthCounters {
+        &self.health
+    }
+
+    pub fn health(&self, thresholds: &HealthThresholds) -> DeviceHealth {
+        self.health.health(thresholds)
+    }
+
+    /// Enable interrupt storm protection. If interrupt handlers read
+    /// more than `bytes_per_tick` bytes between `tick` calls, the guard
+    /// runs `action`, discards bytes from interrupt handlers and
+    /// returns `MousePollResult::AuxStormSuppressed` once.
+    /// `None` disables the guard but does not resume the device.
+    pub fn set_storm_guard(&mut self, guard: Option<StormGuard>) {
+        self.storm_guard = guard;
+        self.interrupt_bytes = 0;
+    }
+
+    /// Call this periodically, for example from a timer interrupt.
+    pub fn tick(&mut self) {
+        self.interrupt_bytes = 0;
+    }
+
+    /// Returns `true` if the storm guard has suppressed the device.
+    pub fn storm_suppressed(&self) -> bool {
+        self.storm.is_some()
+    }
+
+    /// Number of bytes which the storm guard discarded.
+    pub fn storm_discarded_bytes(&self) -> u32 {
+        self.storm_discarded
+    }
+
+    /// Enable the interface and data reporting again after
+    /// `MousePollResult::AuxStormSuppressed`. Returns `false` if the
+    /// device was not suppressed.
+    pub fn resume_after_storm(&mut self) -> bool {
+        let action = match self.storm.take() {
+            Some(action) => action,
+            None => return false,
+        };
+
+        if action == StormAction::DisableInterface {
+            self.controller.set_auxiliary_device_interface(true);
+        }
+
+        self.interrupt_bytes = 0;
+        self.storm_report_pending = false;
+        self.decoder.clear();
+        self.reconnect_pending = false;
+        // Auxiliary device is enabled, so sending can't fail.
+        let _ = self
+            .controller
+            .send_to_auxiliary_device(MouseCommand::ENABLE_DATA_REPORTING);
+        self.ack_pending = true;
+        true
+    }
+
+    /// Returns `false` if the byte should be discarded.
+    fn count_interrupt_byte(&mut self) -> bool {
+        if self.storm.is_some() {
+            self.storm_discarded = self.storm_discarded.wrapping_add(1);
+            return false;
+        }
+
+        let guard = match self.storm_guard {
+            Some(guard) => guard,
+            None => return true,
+        };
+
+        self.interrupt_bytes = self.interrupt_bytes.saturating_add(1);
+        if self.interrupt_bytes <= guard.bytes_per_tick {
+            return true;
+        }
+
+        match guard.action {
+            StormAction::DisableReporting => {
+                // Auxiliary device is enabled, so sending can't fail.
+                let _ = self
+                    .controller
+                    .send_to_auxiliary_device(MouseCommand::DISABLE_DATA_REPORTING);
+            }
+            StormAction::DisableInterface => self.controller.set_auxiliary_device_interface(false),
+        }
+
+        self.storm = Some(guard.action);
+        self.storm_report_pending = true;
+        self.storm_discarded = self.storm_discarded.wrapping_add(1);
+        self.decoder.clear();
+        self.reconnect_pending = false;
+        self.deferred.clear();
+        false
+    }
+
+    fn take_storm_report(&mut self) -> Option<Sequenced<MousePollResult>> {
+        if !self.storm_report_pending {
+            return None;
+        }
+
+        self.storm_report_pending = false;
+        Some(self.sequence.stamp(MousePollResult::AuxStormSuppressed))
+    }
+
+    fn handle_data(&mut self, data: DeviceData) -> Option<Sequenced<MousePollResult>> {
+        let result = self.process_data(data)?;
+        Some(self.sequence.stamp(result))
+    }
+
+    fn process_data(&mut self, data: DeviceData) -> Option<MousePollResult> {
+        match data {
+            DeviceData::AuxiliaryDevice(data) => {
+                self.health.bytes_received = self.health.bytes_received.wrapping_add(1);
+                self.handle_byte(data)
+            }
+            DeviceData::AuxiliaryDeviceTransmissionError(e) => {
+                self.health.transmission_errors = self.health.transmission_errors.wrapping_add(1);
+                self.decoder.clear();
+                // Auxiliary device is enabled, so sending can't fail.
+                let _ = self
+                    .controller
+                    .send_to_auxiliary_device(MouseCommand::RESEND);
+                Some(MousePollResult::TransmissionError(e))
+            }
+            _ => None,
+        }
+    }
+
+    fn handle_byte(&mut self, data: u8) -> Option<MousePollResult> {
+        if self.ack_pending && data == FromMouse::ACK {
+            self.ack_pending = false;
+            return None;
+        }
+
+        // Device sends BAT completion and ID 0x00 when it is
+        // connected. Same bytes at a packet start are a packet with
+        // Y overflow, which is rare enough to be ignored.
+        if self.reconnect_pending {
+            self.reconnect_pending = false;
+            if data == MouseKind::Standard.id() {
+                return Some(self.reconnected());
+            }
+            let _ = self.decoder.decode(FromMouse::BAT_COMPLETION_CODE);
+        } else if data == FromMouse::BAT_COMPLETION_CODE && !self.decoder.packet_started() {
+            self.reconnect_pending = true;
+            return None;
+        }
+
+        self.decoder.decode(data).map(MousePollResult::Mouse)
+    }
+
+    fn reconnected(&mut self) -> MousePollResult {
+        self.health.bats = self.health.bats.wrapping_add(1);
+        self.decoder.clear();
+        self.controller
+            .set_auxiliary_device_kind(Some(Device::Mouse(MouseKind::Standard)));
+        // Reset disabled data reporting.
+        // Auxiliary device is enabled, so sending can't fail.
+        let _ = self
+            .controller
+            .send_to_auxiliary_device(MouseCommand::ENABLE_DATA_REPORTING);
+        self.ack_pending = true;
+        MousePollResult::Reconnected
+    }
+}
+
+#[cfg(feature = "unstable")]
+#[cfg_attr(docsrs, doc(cfg(feature = "unstable")))]
+impl<T: PortIO, IRQ, const D: usize> Readiness for MouseDriver<T, IRQ, D> {
+    fn set_readiness(&mut self, notify: Option<Notify>) {
+        self.readiness.set(notify);
+    }
+
+    fn events_pending(&self) -> bool {
+        !self.deferred.is_empty()
+    }
+
+    fn register_waker(&mut self, waker: &Waker) {
+        self.readiness.register_waker(waker);
+    }
+}
+
+#[derive(Debug)]
+pub enum MousePollResult {
+    Mouse(MouseEvent),
+    /// Device was connected again. Data reporting is enabled and
+    /// device kind is set to `MouseKind::Standard`.
+    Reconnected,
+    /// Too many bytes in interrupt handlers. Bytes are discarded
+    /// until `MouseDriver::resume_after_storm`.
+    AuxStormSuppressed,
+    /// Corrupted byte was discarded and resend requested.
+    TransmissionError(TransmissionError),
+}
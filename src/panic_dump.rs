@@ -0,0 +1,91 @@
+//! Print latest input from a panic handler.
+//!
+//! Crashes which are triggered by a specific key sequence are easier
+//! to diagnose if the panic message includes the last received bytes.
+//! Functions in this module only read the driver state. They don't
+//! lock, allocate or access the hardware, so they can be called
+//! from a panic handler even if the panic happened inside the driver.
+//!
+//! ```rust,ignore
+//! #[panic_handler]
+//! fn panic(info: &PanicInfo) -> ! {
+//!     // Safety: Other CPUs are halted.
+//!     unsafe {
+//!         PS2.panic_access(|driver| dump_input_history(driver, &mut serial))
+//!     };
+//!     loop {}
+//! }
+//! ```
+
+use crate::controller::io::PortIO;
+use crate::device::keyboard::driver::{ByteHistory, DecoderLayout, Keyboard};
+use crate::keyboard::{KeyboardDriver, QueuedKeyboardDriver};
+
+use core::fmt::{self, Write};
+
+use pc_keyboard::{KeyboardLayout, ScancodeSet};
+
+/// Driver which records latest received bytes.
+pub trait InputHistory {
+    fn byte_history(&self) -> &ByteHistory;
+
+    /// Count of bytes which were not decoded.
+    fn unexplained_bytes(&self) -> Option<u32> {
+        None
+    }
+}
+
+impl<const N: usize, const G: usize, K: DecoderLayout> InputHistory for Keyboard<N, G, K> {
+    fn byte_history(&self) -> &ByteHistory {
+        Keyboard::byte_history(self)
+    }
+
+    fn unexplained_bytes(&self) -> Option<u32> {
+        Some(Keyboard::unexplained_bytes(self))
+    }
+}
+
+impl<T: PortIO, IRQ, const N: usize, const G: usize> InputHistory
+    for QueuedKeyboardDriver<T, IRQ, N, G>
+{
+    fn byte_history(&self) -> &ByteHistory {
+        self.keyboard().byte_history()
+    }
+
+    fn unexplained_bytes(&self) -> Option<u32> {
+        Some(self.keyboard().unexplained_bytes())
+    }
+}
+
+impl<T: PortIO, IRQ, K: KeyboardLayout, S: ScancodeSet, const D: usize> InputHistory
+    for KeyboardDriver<T, IRQ, K, S, D>
+{
+    fn byte_history(&self) -> &ByteHistory {
+        KeyboardDriver::byte_history(self)
+    }
+}
+
+/// Write one line with the latest bytes from oldest to newest.
+///
+/// ```text
+/// ps2 history bytes=1C,F0,1C unexplained=0
+/// ```
+pub fn dump_input_history<H: InputHistory + ?Sized, W: Write>(
+    source: &H,
+    out: &mut W,
+) -> fmt::Result {
+    out.write_str("ps2 history bytes=")?;
+
+    for (i, byte) in source.byte_history().iter().enumerate() {
+        if i > 0 {
+            out.write_char(',')?;
+        }
+        write!(out, "{:02X}", byte)?;
+    }
+
+    if let Some(unexplained) = source.unexplained_bytes() {
+        write!(out, " unexplained={}", unexplained)?;
+    }
+
+    out.write_char('\n')
+}
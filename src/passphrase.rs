@@ -0,0 +1,147 @@
+//! Key press input for early boot passphrase prompts.
+//!
+//! `PassphraseInput` delivers only key presses of printable keys,
+//! Enter and Backspace, so a prompt doesn't need to track key state
+//! or handle key releases. Every key press is delivered once.
+//! `PassphraseInput::configure` sets the slowest typematic rate and
+//! the longest delay, and if the keyboard rejects the command, held
+//! keys are still delivered only once because the decoder discards
+//! the repeats.
+//!
+//! ```rust,ignore
+//! let mut input = PassphraseInput::new();
+//! input.set_mask_hook(Some(echo_mask));
+//! input.configure(&mut driver)?;
+//! loop {
+//!     match input.poll(&mut driver) {
+//!         Some(PassphraseKey::Char(c)) => passphrase.push(c),
+//!         Some(PassphraseKey::Backspace) => passphrase.pop(),
+//!         Some(PassphraseKey::Enter) => break,
+//!         None => (),
+//!     }
+//! }
+//! ```
+
+use crate::controller::io::PortIO;
+use crate::device::command_queue::CommandId;
+use crate::device::keyboard::driver::{
+    DecoderLayout, DelayMilliseconds, KeyboardEvent, NotEnoughSpaceInTheCommandQueue, RateValue,
+    TypematicConfig,
+};
+use crate::keyboard::QueuedKeyboardDriver;
+
+use core::fmt;
+
+use pc_keyboard::{DecodedKey, HandleControl, KeyCode, KeyEvent, KeyState, Keyboard, ScancodeSet2};
+
+/// Called with every delivered key, so the prompt can draw a mask
+/// character or erase one.
+pub type MaskHook = fn(PassphraseKey);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PassphraseKey {
+    /// Printable character from the keyboard layout.
+    Char(char),
+    Enter,
+    Backspace,
+}
+
+/// Passphrase key decoder with layout `K`.
+pub struct PassphraseInput<K: DecoderLayout = pc_keyboard::layouts::Us104Key> {
+    decoder: Keyboard<K, ScancodeSet2>,
+    pressed: Option<KeyCode>,
+    mask: Option<MaskHook>,
+}
+
+impl<K: DecoderLayout> fmt::Debug for PassphraseInput<K> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("PassphraseInput")
+    }
+}
+
+impl<K: DecoderLayout> Default for PassphraseInput<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: DecoderLayout> PassphraseInput<K> {
+    pub fn new() -> Self {
+        Self {
+            decoder: Keyboard::new(K::new(), ScancodeSet2, HandleControl::Ignore),
+            pressed: None,
+            mask: None,
+        }
+    }
+
+    pub fn set_mask_hook(&mut self, hook: Option<MaskHook>) {
+        self.mask = hook;
+    }
+
+    /// Queue the typematic command. Command failure is reported by
+    /// the keyboard driver and doesn't affect the decoder.
+    pub fn configure<T: PortIO, IRQ, const N: usize, const G: usize>(
+        &mut self,
+        driver: &mut QueuedKeyboardDriver<T, IRQ, N, G, K>,
+    ) -> Result<CommandId, NotEnoughSpaceInTheCommandQueue> {
+        let config =
+            TypematicConfig::from_rate_value(DelayMilliseconds::Delay1000, RateValue::RATE_MIN);
+        driver.command(|keyboard, sender| keyboard.set_typematic(sender, config))
+    }
+
+    /// Read one byte with `QueuedKeyboardDriver::poll` and decode it.
+    /// Keyboard driver errors are discarded.
+    pub fn poll<T: PortIO, IRQ, const N: usize, const G: usize>(
+        &mut self,
+        driver: &mut QueuedKeyboardDriver<T, IRQ, N, G, K>,
+    ) -> Option<PassphraseKey> {
+        match driver.poll()? {
+            Ok(Some(KeyboardEvent::Key(event))) => self.key_event(event),
+            Ok(Some(KeyboardEvent::AllKeysReleased)) => {
+                self.clear();
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Decode a key event from any keyboard driver. Modifier events
+    /// must be given to the decoder too.
+    pub fn key_event(&mut self, event: KeyEvent) -> Option<PassphraseKey> {
+        let code = event.code;
+        let state = event.state;
+        let decoded = self.decoder.process_keyevent(event);
+
+        if state == KeyState::Up {
+            if self.pressed == Some(code) {
+                self.pressed = None;
+            }
+            return None;
+        }
+
+        // Typematic repeats only repeat the latest key press.
+        if self.pressed == Some(code) {
+            return None;
+        }
+        self.pressed = Some(code);
+
+        let key = match (code, decoded) {
+            (KeyCode::Enter | KeyCode::NumpadEnter, _) => PassphraseKey::Enter,
+            (KeyCode::Backspace, _) => PassphraseKey::Backspace,
+            (_, Some(DecodedKey::Unicode(c))) if !c.is_control() => PassphraseKey::Char(c),
+            _ => return None,
+        };
+
+        if let Some(mask) = self.mask {
+            mask(key);
+        }
+
+        Some(key)
+    }
+
+    /// Forget the held key. Call this when the keyboard driver
+    /// reports that all keys were released.
+    pub fn clear(&mut self) {
+        self.pressed = None;
+    }
+}
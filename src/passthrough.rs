@@ -0,0 +1,181 @@
+//! Raw auxiliary device passthrough.
+//!
+//! Use `AuxPassthrough` instead of `MouseDriver` when the pointing
+//! device driver runs in userspace. Auxiliary device bytes are not
+//! decoded. They are numbered and given to an `AuxSink`, which
+//! forwards them to userspace. Commands from userspace are sent with
+//! the `WRITE_TO_AUXILIARY_DEVICE` (0xD4) controller command.
+//!
+//! Only one client at a time can send commands, so command bytes and
+//! their responses from different clients are not interleaved. The
+//! first client which sends becomes the owner of the command channel
+//! until it calls `AuxPassthrough::release`.
+
+use crate::controller::{
+    driver::{DeviceData, EnabledDevices, ReadData, SendError},
+    io::PortIO,
+};
+use crate::device::io::TransmissionError;
+use crate::interrupt::{EndOfInterrupt, EndOfInterruptHook, EoiOrder};
+use crate::sequence::{EventSequence, Sequenced};
+
+/// Receives auxiliary device data. Called from `AuxPassthrough::poll`
+/// and `AuxPassthrough::handle_interrupt`.
+pub type AuxSink = fn(Sequenced<AuxData>);
+
+/// Userspace client which sends commands.
+pub type ClientId = u32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuxData {
+    Byte(u8),
+    /// Byte was corrupted. Resend is not requested, so the client
+    /// decides how to recover.
+    TransmissionError(TransmissionError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PassthroughError {
+    /// Another client owns the command channel.
+    Busy,
+    DeviceNotEnabled,
+    /// Controller input buffer is full. Try again later.
+    WouldBlock,
+}
+
+impl From<SendError> for PassthroughError {
+    fn from(e: SendError) -> Self {
+        match e {
+            SendError::DeviceNotEnabled => PassthroughError::DeviceNotEnabled,
+            SendError::WouldBlock => PassthroughError::WouldBlock,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct AuxPassthrough<T: PortIO, IRQ> {
+    controller: EnabledDevices<T, IRQ>,
+    sink: AuxSink,
+    sequence: EventSequence,
+    owner: Option<ClientId>,
+    end_of_interrupt: EndOfInterruptHook,
+    discarded: u32,
+}
+
+impl<T: PortIO, IRQ> AuxPassthrough<T, IRQ> {
+    /// No commands are sent to the auxiliary device, so the client
+    /// enables data reporting.
+    pub fn new(controller: EnabledDevices<T, IRQ>, sink: AuxSink) -> Self {
+        Self {
+            controller,
+            sink,
+            sequence: EventSequence::new(),
+            owner: None,
+            end_of_interrupt: EndOfInterruptHook::new(),
+            discarded: 0,
+        }
+    }
+
+    pub fn controller_mut(&mut self) -> &mut EnabledDevices<T, IRQ> {
+        &mut self.controller
+    }
+
+    pub fn into_controller(self) -> EnabledDevices<T, IRQ> {
+        self.controller
+    }
+
+    /// Read one byte from the controller if it is available. Returns
+    /// `true` if a byte was read. Bytes from other sources than the
+    /// auxiliary device are discarded.
+    pub fn poll(&mut self) -> bool {
+        match self.controller.read_data() {
+            Some(data) => {
+                self.deliver(data);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Call this from auxiliary device interrupt handler.
+    pub fn handle_interrupt(&mut self) -> bool {
+        let controller = &mut self.controller;
+        match self.end_of_interrupt.around_read(|| controller.read_data()) {
+            Some(data) => {
+                self.deliver(data);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Set hook which `handle_interrupt` calls to send end of
+    /// interrupt. Default order is `EoiOrder::AfterRead`.
+    pub fn set_end_of_interrupt(&mut self, hook: Option<EndOfInterrupt>, order: EoiOrder) {
+        self.end_of_interrupt.set_hook(hook, order);
+    }
+
+    /// Sequence number of the next byte given to the sink.
+    pub fn next_sequence(&self) -> u32 {
+        self.sequence.next()
+    }
+
+    /// Number of keyboard bytes and controller command responses
+    /// which were read and discarded.
+    pub fn discarded_bytes(&self) -> u32 {
+        self.discarded
+    }
+
+    /// Current owner of the command channel.
+    pub fn owner(&self) -> Option<ClientId> {
+        self.owner
+    }
+
+    /// Make `client` the owner of the command channel. Owner can
+    /// acquire the channel again.
+    pub fn acquire(&mut self, client: ClientId) -> Result<(), PassthroughError> {
+        match self.owner {
+            Some(owner) if owner != client => Err(PassthroughError::Busy),
+            _ => {
+                self.owner = Some(client);
+                Ok(())
+            }
+        }
+    }
+
+    /// Release the command channel if `client` owns it.
+    pub fn release(&mut self, client: ClientId) {
+        if self.owner == Some(client) {
+            self.owner = None;
+        }
+    }
+
+    /// Send a byte to the auxiliary device without waiting for the
+    /// controller input buffer. `client` acquires the command channel
+    /// if it has no owner.
+    pub fn send(&mut self, client: ClientId, data: u8) -> Result<(), PassthroughError> {
+        self.acquire(client)?;
+        self.controller.try_send_to_auxiliary_device(data)?;
+        Ok(())
+    }
+
+    /// Write the data byte of a previous `send` if the controller
+    /// was still processing the command.
+    pub fn flush(&mut self) -> Result<(), PassthroughError> {
+        self.controller.flush()?;
+        Ok(())
+    }
+
+    fn deliver(&mut self, data: DeviceData) {
+        let data = match data {
+            DeviceData::AuxiliaryDevice(data) => AuxData::Byte(data),
+            DeviceData::AuxiliaryDeviceTransmissionError(e) => AuxData::TransmissionError(e),
+            _ => {
+                self.discarded = self.discarded.wrapping_add(1);
+                return;
+            }
+        };
+
+        (self.sink)(self.sequence.stamp(data));
+    }
+}
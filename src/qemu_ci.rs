@@ -0,0 +1,451 @@
+//! Scripted input tests for kernels running under QEMU.
+//!
+//! The test kernel and the CI runner share a list of `Step`s. The
+//! runner writes `Step::monitor_command` lines to the QEMU monitor
+//! and the kernel passes driver events to `ScriptChecker`. When the
+//! script has finished, the kernel writes `ScriptStatus::exit_code`
+//! to the `isa-debug-exit` device, so the QEMU exit status is the
+//! test result.
+//!
+//! ```text
+//! qemu-system-x86_64 -device isa-debug-exit,iobase=0xf4,iosize=0x04 \
+//!     -monitor unix:monitor.sock,server,nowait ...
+//! ```
+//!
+//! Kernel side:
+//!
+//! ```rust,ignore
+//! const SCRIPT: &[Step] = &[Step::Key(KeyCode::A), Step::MouseMove { dx: 10, dy: -5 }];
+//!
+//! let mut checker = ScriptChecker::new(SCRIPT);
+//! while checker.status() == ScriptStatus::Running {
+//!     if let Some(result) = driver.poll_keyboard_event() {
+//!         checker.keyboard_event(&result);
+//!     }
+//!     if let Some(result) = driver.poll_mouse_event() {
+//!         checker.mouse_event(&result);
+//!     }
+//! }
+//! unsafe { exit_qemu(checker.status().exit_code().unwrap_or(0xFF)) };
+//! ```
+//!
+//! Runner side, after the kernel has initialized the controller:
+//!
+//! ```rust,ignore
+//! for step in SCRIPT {
+//!     writeln!(monitor, "{}", step.monitor_command())?;
+//! }
+//! ```
+//!
+//! The crate doesn't ship a bootable test kernel, because boot code
+//! and the target configuration depend on the kernel. The kernel
+//! loop above is the test kernel part which is specific to this
+//! crate. The `qemu_ci_runner` example is a runner which writes
+//! `SMOKE_SCRIPT` or the script of a test kernel to the monitor:
+//!
+//! ```text
+//! cargo run --example qemu_ci_runner --features qemu-ci -- monitor.sock
+//! ```
+
+use crate::keyboard::KeyboardPollResult;
+#[cfg(feature = "aux-device")]
+use crate::mouse::MousePollResult;
+
+use pc_keyboard::{KeyCode, KeyState};
+
+/// I/O port of the `isa-debug-exit` device in the QEMU command line
+/// above.
+pub const ISA_DEBUG_EXIT_PORT: u16 = 0xF4;
+
+/// QEMU exit status is `(code << 1) | 1`, so these are 33 and 35.
+pub const EXIT_PASSED: u8 = 0x10;
+pub const EXIT_FAILED: u8 = 0x11;
+
+/// Script which uses every step type. Test kernels can use it to
+/// check the keyboard and mouse input paths.
+pub const SMOKE_SCRIPT: &[Step] = &[
+    Step::Key(KeyCode::A),
+    Step::Key(KeyCode::Enter),
+    Step::MouseMove { dx: 10, dy: -5 },
+    Step::MouseButtons {
+        left: true,
+        right: false,
+        middle: false,
+    },
+    Step::MouseButtons {
+        left: false,
+        right: false,
+        middle: false,
+    },
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// Press and release a key with `sendkey`.
+    Key(KeyCode),
+    /// `mouse_move`. Down is positive `dy` like in QEMU, so the
+    /// mouse reports `-dy`. QEMU may split the movement into several
+    /// packets.
+    MouseMove { dx: i16, dy: i16 },
+    /// `mouse_button`. Buttons which are not set are released.
+    MouseButtons {
+        left: bool,
+        right: bool,
+        middle: bool,
+    },
+}
+
+impl Step {
+    /// QEMU monitor command for this step. `Display` writes the
+    /// command line without a line break.
+    pub fn monitor_command(&self) -> MonitorCommand {
+        MonitorCommand(*self)
+    }
+}
+
+/// Returned from `Step::monitor_command`.
+#[derive(Debug, Clone, Copy)]
+pub struct MonitorCommand(Step);
+
+impl core::fmt::Display for MonitorCommand {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self.0 {
+            Step::Key(code) => match qcode(code) {
+                Some(name) => write!(f, "sendkey {}", name),
+                None => Err(core::fmt::Error),
+            },
+            Step::MouseMove { dx, dy } => write!(f, "mouse_move {} {}", dx, dy),
+            Step::MouseButtons {
+                left,
+                right,
+                middle,
+            } => {
+                let state = left as u8 | (right as u8) << 1 | (middle as u8) << 2;
+                write!(f, "mouse_button {}", state)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptStatus {
+    Running,
+    Passed,
+    /// Index of the step which received an unexpected event.
+    Failed {
+        step: usize,
+    },
+}
+
+impl ScriptStatus {
+    /// Value for `ISA_DEBUG_EXIT_PORT`. `None` if the script is
+    /// still running.
+    pub fn exit_code(&self) -> Option<u8> {
+        match self {
+            ScriptStatus::Running => None,
+            ScriptStatus::Passed => Some(EXIT_PASSED),
+            ScriptStatus::Failed { .. } => Some(EXIT_FAILED),
+        }
+    }
+}
+
+/// Compares driver events to a script.
+///
+/// `BATCompleted` and password state changes are ignored, so the
+/// checker can be created before the keyboard has finished its reset. Other events which the
+/// current step doesn't expect fail the script.
+#[derive(Debug, Clone)]
+pub struct ScriptChecker<'a> {
+    steps: &'a [Step],
+    step: usize,
+    key_pressed: bool,
+    mouse_x: i32,
+    mouse_y: i32,
+    buttons: (bool, bool, bool),
+    failed: bool,
+}
+
+impl<'a> ScriptChecker<'a> {
+    pub fn new(steps: &'a [Step]) -> Self {
+        Self {
+            steps,
+            step: 0,
+            key_pressed: false,
+            mouse_x: 0,
+            mouse_y: 0,
+            buttons: (false, false, false),
+            failed: false,
+        }
+    }
+
+    pub fn status(&self) -> ScriptStatus {
+        if self.failed {
+            ScriptStatus::Failed { step: self.step }
+        } else if self.step >= self.steps.len() {
+            ScriptStatus::Passed
+        } else {
+            ScriptStatus::Running
+        }
+    }
+
+    pub fn keyboard_event(&mut self, result: &KeyboardPollResult) {
+        let event = match result {
+            KeyboardPollResult::BATCompleted
+            | KeyboardPollResult::PasswordUnlockPending
+            | KeyboardPollResult::PasswordUnlocked => return,
+            KeyboardPollResult::Key { event, .. } => event,
+            _ => return self.fail(),
+        };
+
+        match self.current() {
+            Some(Step::Key(code)) if code == event.code => match (self.key_pressed, event.state) {
+                (false, KeyState::Down) => self.key_pressed = true,
+                (true, KeyState::Down) => (),
+                (true, KeyState::Up) => {
+                    self.key_pressed = false;
+                    self.next();
+                }
+                (false, KeyState::Up) => self.fail(),
+            },
+            Some(_) => self.fail(),
+            None => (),
+        }
+    }
+
+    #[cfg(feature = "aux-device")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "aux-device")))]
+    pub fn mouse_event(&mut self, result: &MousePollResult) {
+        let event = match result {
+            MousePollResult::Mouse(event) => event,
+            _ => return self.fail(),
+        };
+        let buttons = (event.left_button, event.right_button, event.middle_button);
+
+        match self.current() {
+            Some(Step::MouseMove { dx, dy }) => {
+                let (x, y) = (
+                    self.mouse_x + i32::from(event.x),
+                    self.mouse_y + i32::from(event.y),
+                );
+                let (dx, dy) = (i32::from(dx), -i32::from(dy));
+                if event.overflow || buttons != self.buttons || !within(x, dx) || !within(y, dy) {
+                    return self.fail();
+                }
+                self.mouse_x = x;
+                self.mouse_y = y;
+                if (x, y) == (dx, dy) {
+                    self.next();
+                }
+            }
+            Some(Step::MouseButtons {
+                left,
+                right,
+                middle,
+            }) => {
+                if event.x != 0 || event.y != 0 || buttons != (left, right, middle) {
+                    return self.fail();
+                }
+                self.buttons = buttons;
+                self.next();
+            }
+            Some(_) => self.fail(),
+            None => (),
+        }
+    }
+
+    fn current(&self) -> Option<Step> {
+        if self.failed {
+            None
+        } else {
+            self.steps.get(self.step).copied()
+        }
+    }
+
+    fn next(&mut self) {
+        self.step += 1;
+        self.mouse_x = 0;
+        self.mouse_y = 0;
+    }
+
+    fn fail(&mut self) {
+        if self.step < self.steps.len() {
+            self.failed = true;
+        }
+    }
+}
+
+/// `value` is between zero and `target`.
+#[cfg(feature = "aux-device")]
+fn within(value: i32, target: i32) -> bool {
+    if target < 0 {
+        (target..=0).contains(&value)
+    } else {
+        (0..=target).contains(&value)
+    }
+}
+
+/// Write `code` to `ISA_DEBUG_EXIT_PORT`. QEMU exits immediately.
+///
+/// # Safety
+/// QEMU must have been started with the `isa-debug-exit` device at
+/// `ISA_DEBUG_EXIT_PORT` and the current privilege level must allow
+/// I/O port access.
+#[cfg(all(feature = "x86-io", any(target_arch = "x86", target_arch = "x86_64")))]
+#[cfg_attr(docsrs, doc(cfg(feature = "x86-io")))]
+#[allow(unsafe_code)]
+pub unsafe fn exit_qemu(code: u8) {
+    core::arch::asm!("out dx, al", in("dx") ISA_DEBUG_EXIT_PORT, in("al") code, options(nomem, nostack, preserves_flags));
+}
+
+/// QEMU `QKeyCode` name for `sendkey`. Codes are named after the US
+/// layout key at the same position.
+pub fn qcode(code: KeyCode) -> Option<&'static str> {
+    use KeyCode::*;
+
+    let name = match code {
+        AltLeft => "alt",
+        AltRight => "alt_r",
+        ArrowDown => "down",
+        ArrowLeft => "left",
+        ArrowRight => "right",
+        ArrowUp => "up",
+        BackSlash | HashTilde => "backslash",
+        Backspace => "backspace",
+        BackTick => "grave_accent",
+        BracketSquareLeft => "bracket_left",
+        BracketSquareRight => "bracket_right",
+        CapsLock => "caps_lock",
+        Comma => "comma",
+        ControlLeft => "ctrl",
+        ControlRight => "ctrl_r",
+        Delete => "delete",
+        End => "end",
+        Enter => "ret",
+        Escape => "esc",
+        Equals => "equal",
+        F1 => "f1",
+        F2 => "f2",
+        F3 => "f3",
+        F4 => "f4",
+        F5 => "f5",
+        F6 => "f6",
+        F7 => "f7",
+        F8 => "f8",
+        F9 => "f9",
+        F10 => "f10",
+        F11 => "f11",
+        F12 => "f12",
+        Fullstop => "dot",
+        Home => "home",
+        Insert => "insert",
+        Key1 => "1",
+        Key2 => "2",
+        Key3 => "3",
+        Key4 => "4",
+        Key5 => "5",
+        Key6 => "6",
+        Key7 => "7",
+        Key8 => "8",
+        Key9 => "9",
+        Key0 => "0",
+        Menus => "menu",
+        Minus => "minus",
+        Numpad0 => "kp_0",
+        Numpad1 => "kp_1",
+        Numpad2 => "kp_2",
+        Numpad3 => "kp_3",
+        Numpad4 => "kp_4",
+        Numpad5 => "kp_5",
+        Numpad6 => "kp_6",
+        Numpad7 => "kp_7",
+        Numpad8 => "kp_8",
+        Numpad9 => "kp_9",
+        NumpadEnter => "kp_enter",
+        NumpadLock => "num_lock",
+        NumpadSlash => "kp_divide",
+        NumpadStar => "kp_multiply",
+        NumpadMinus => "kp_subtract",
+        NumpadPeriod => "kp_decimal",
+        NumpadPlus => "kp_add",
+        PageDown => "pgdn",
+        PageUp => "pgup",
+        PauseBreak => "pause",
+        PrintScreen => "print",
+        ScrollLock => "scroll_lock",
+        SemiColon => "semicolon",
+        ShiftLeft => "shift",
+        ShiftRight => "shift_r",
+        Slash => "slash",
+        Spacebar => "spc",
+        Tab => "tab",
+        Quote => "apostrophe",
+        WindowsLeft => "meta_l",
+        WindowsRight => "meta_r",
+        A => "a",
+        B => "b",
+        C => "c",
+        D => "d",
+        E => "e",
+        F => "f",
+        G => "g",
+        H => "h",
+        I => "i",
+        J => "j",
+        K => "k",
+        L => "l",
+        M => "m",
+        N => "n",
+        O => "o",
+        P => "p",
+        Q => "q",
+        R => "r",
+        S => "s",
+        T => "t",
+        U => "u",
+        V => "v",
+        W => "w",
+        X => "x",
+        Y => "y",
+        Z => "z",
+        PrevTrack => "audioprev",
+        NextTrack => "audionext",
+        Mute => "audiomute",
+        Calculator => "calculator",
+        Play => "audioplay",
+        Stop => "audiostop",
+        VolumeDown => "volumedown",
+        VolumeUp => "volumeup",
+        WWWHome => "homepage",
+        PowerOnTestOk => return None,
+    };
+
+    Some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+
+    use std::string::ToString;
+
+    #[test]
+    fn smoke_script_monitor_commands() {
+        let commands: std::vec::Vec<_> = SMOKE_SCRIPT
+            .iter()
+            .map(|step| step.monitor_command().to_string())
+            .collect();
+
+        assert_eq!(
+            commands,
+            [
+                "sendkey a",
+                "sendkey ret",
+                "mouse_move 10 -5",
+                "mouse_button 1",
+                "mouse_button 0",
+            ]
+        );
+    }
+}
@@ -0,0 +1,81 @@
+//! Readiness notifications for event loops.
+//!
+//! `irq_fast_path` of the polling drivers stores bytes for
+//! `process_deferred`. A driver with a `Notify` notifies it from the
+//! interrupt handler when a byte was stored, so an async executor or
+//! a message loop can process the bytes without polling.
+//!
+//! ```rust,ignore
+//! fn poll_next(&mut self, cx: &mut Context) -> Poll<Option<KeyboardPollResult>> {
+//!     self.driver.register_waker(cx.waker());
+//!     match self.driver.process_deferred() {
+//!         Some(result) => Poll::Ready(Some(result)),
+//!         None => Poll::Pending,
+//!     }
+//! }
+//! ```
+
+use core::task::Waker;
+
+#[derive(Debug, Clone)]
+pub enum Notify {
+    /// Called from the interrupt handler.
+    Callback(fn()),
+    /// Woken from the interrupt handler. Waker is not consumed, so it
+    /// is woken for every stored byte.
+    Waker(Waker),
+}
+
+impl Notify {
+    pub fn notify(&self) {
+        match self {
+            Notify::Callback(callback) => callback(),
+            Notify::Waker(waker) => waker.wake_by_ref(),
+        }
+    }
+}
+
+/// Drivers which notify when events are pending.
+pub trait Readiness {
+    /// Replaces the previous notification.
+    fn set_readiness(&mut self, notify: Option<Notify>);
+
+    /// Returns `true` if stored bytes are waiting for
+    /// `process_deferred`. Bytes may not produce events.
+    fn events_pending(&self) -> bool;
+
+    /// Set `waker` as the notification. Waker is cloned only if it
+    /// would not wake the same task as the current one.
+    fn register_waker(&mut self, waker: &Waker);
+}
+
+/// Notification storage of the drivers.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ReadinessHook {
+    notify: Option<Notify>,
+}
+
+impl ReadinessHook {
+    pub(crate) const fn new() -> Self {
+        Self { notify: None }
+    }
+
+    pub(crate) fn set(&mut self, notify: Option<Notify>) {
+        self.notify = notify;
+    }
+
+    pub(crate) fn register_waker(&mut self, waker: &Waker) {
+        if let Some(Notify::Waker(current)) = &self.notify {
+            if current.will_wake(waker) {
+                return;
+            }
+        }
+        self.notify = Some(Notify::Waker(waker.clone()));
+    }
+
+    pub(crate) fn notify(&self) {
+        if let Some(notify) = &self.notify {
+            notify.notify();
+        }
+    }
+}
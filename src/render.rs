@@ -0,0 +1,256 @@
+//! Single line text rendering of driver events for logs.
+//!
+//! Every line starts with a source tag and an event name, followed by
+//! `key=value` fields. Values contain no spaces, so lines are easy
+//! to grep and parse. The format is stable.
+//!
+//! ```text
+//! kbd key code=A state=down decoded=char:U+0061
+//! kbd-dev command-completed id=3
+//! kbd-err command-timeout id=4 command=0xED
+//! mouse move x=-3 y=12 buttons=l-- overflow=0
+//! ```
+
+use crate::device::io::TransmissionError;
+use crate::device::keyboard::driver::{
+    DiagnosticEvent, KeyboardError, KeyboardEvent, KeyboardScancodeSetting,
+};
+use crate::keyboard::KeyboardPollResult;
+
+use core::fmt::{self, Write};
+
+use pc_keyboard::{DecodedKey, Error, KeyEvent, KeyState};
+
+/// Event or error which can be rendered.
+#[derive(Debug, Clone, Copy)]
+pub enum Ps2Event<'a> {
+    /// Tag `kbd`.
+    Keyboard(&'a KeyboardPollResult),
+    /// Tag `kbd-dev`.
+    KeyboardDevice(&'a KeyboardEvent),
+    /// Tag `kbd-err`.
+    KeyboardError(&'a KeyboardError),
+    /// Tag `mouse`.
+    #[cfg(feature = "aux-device")]
+    Mouse(&'a crate::mouse::MousePollResult),
+}
+
+impl<'a> From<&'a KeyboardPollResult> for Ps2Event<'a> {
+    fn from(event: &'a KeyboardPollResult) -> Self {
+        Ps2Event::Keyboard(event)
+    }
+}
+
+impl<'a> From<&'a KeyboardEvent> for Ps2Event<'a> {
+    fn from(event: &'a KeyboardEvent) -> Self {
+        Ps2Event::KeyboardDevice(event)
+    }
+}
+
+impl<'a> From<&'a KeyboardError> for Ps2Event<'a> {
+    fn from(error: &'a KeyboardError) -> Self {
+        Ps2Event::KeyboardError(error)
+    }
+}
+
+#[cfg(feature = "aux-device")]
+impl<'a> From<&'a crate::mouse::MousePollResult> for Ps2Event<'a> {
+    fn from(event: &'a crate::mouse::MousePollResult) -> Self {
+        Ps2Event::Mouse(event)
+    }
+}
+
+/// Write `event` as a single line without line terminator.
+pub fn render_event<W: Write>(event: &Ps2Event, out: &mut W) -> fmt::Result {
+    match *event {
+        Ps2Event::Keyboard(event) => render_keyboard(event, out),
+        Ps2Event::KeyboardDevice(event) => render_keyboard_device(event, out),
+        Ps2Event::KeyboardError(error) => render_keyboard_error(error, out),
+        #[cfg(feature = "aux-device")]
+        Ps2Event::Mouse(event) => render_mouse(event, out),
+    }
+}
+
+fn render_keyboard<W: Write>(event: &KeyboardPollResult, out: &mut W) -> fmt::Result {
+    out.write_str("kbd ")?;
+
+    match event {
+        KeyboardPollResult::Key { event, decoded } => {
+            render_key(event, out)?;
+            match decoded {
+                Some(DecodedKey::Unicode(c)) => write!(out, " decoded=char:U+{:04X}", *c as u32),
+                Some(DecodedKey::RawKey(code)) => write!(out, " decoded=raw:{:?}", code),
+                None => out.write_str(" decoded=none"),
+            }
+        }
+        KeyboardPollResult::BATCompleted => out.write_str("bat-completed"),
+        KeyboardPollResult::BATFailure => out.write_str("bat-failure"),
+        KeyboardPollResult::KeyDetectionError => out.write_str("key-detection-error"),
+        KeyboardPollResult::PasswordUnlockPending => out.write_str("password-unlock-pending"),
+        KeyboardPollResult::PasswordUnlocked => out.write_str("password-unlocked"),
+        KeyboardPollResult::Error(e) => write!(out, "decode-error kind={}", decode_error(e)),
+        KeyboardPollResult::TransmissionError(e) => render_transmission_error(e, out),
+    }
+}
+
+fn render_keyboard_device<W: Write>(event: &KeyboardEvent, out: &mut W) -> fmt::Result {
+    out.write_str("kbd-dev ")?;
+
+    match event {
+        KeyboardEvent::Key(event) => render_key(event, out),
+        #[cfg(feature = "set3")]
+        KeyboardEvent::KeyNumber { number, state } => write!(
+            out,
+            "key-number number=0x{:02X} state={}",
+            number,
+            key_state(state)
+        ),
+        KeyboardEvent::BATCompleted => out.write_str("bat-completed"),
+        KeyboardEvent::ID { id, byte1, byte2 } => write!(
+            out,
+            "id id={} byte1=0x{:02X} byte2=0x{:02X}",
+            id.value(),
+            byte1,
+            byte2
+        ),
+        KeyboardEvent::ScancodeSet { id, setting } => write!(
+            out,
+            "scancode-set id={} set={}",
+            id.value(),
+            scancode_set(setting)
+        ),
+        KeyboardEvent::ScancodeSetFallback {
+            id,
+            requested,
+            active,
+        } => write!(
+            out,
+            "scancode-set-fallback id={} requested={} active={}",
+            id.value(),
+            scancode_set(requested),
+            scancode_set(active)
+        ),
+        KeyboardEvent::Echo { id } => write!(out, "echo id={}", id.value()),
+        KeyboardEvent::Diagnostic(DiagnosticEvent::UnexplainedByte { byte, history }) => {
+            write!(out, "unexplained-byte byte=0x{:02X} history=", byte)?;
+            for (i, byte) in history.iter().enumerate() {
+                if i > 0 {
+                    out.write_char(',')?;
+                }
+                write!(out, "{:02X}", byte)?;
+            }
+            Ok(())
+        }
+        KeyboardEvent::Diagnostic(DiagnosticEvent::ProtocolByte { byte }) => {
+            write!(out, "protocol-byte byte=0x{:02X}", byte)
+        }
+        KeyboardEvent::Typematic { id, config } => write!(
+            out,
+            "typematic id={} delay-ms={} rate-centihertz={}",
+            id.value(),
+            config.delay().milliseconds(),
+            config.rate().centihertz()
+        ),
+        KeyboardEvent::CommandCompleted(id) => write!(out, "command-completed id={}", id.value()),
+        KeyboardEvent::TransmissionError(e) => render_transmission_error(e, out),
+        KeyboardEvent::AllKeysReleased => out.write_str("all-keys-released"),
+    }
+}
+
+fn render_keyboard_error<W: Write>(error: &KeyboardError, out: &mut W) -> fmt::Result {
+    out.write_str("kbd-err ")?;
+
+    match error {
+        KeyboardError::KeyDetectionError => out.write_str("key-detection-error"),
+        KeyboardError::BATCompletionFailure => out.write_str("bat-completion-failure"),
+        KeyboardError::UnknownScancodeSet(set) => {
+            write!(out, "unknown-scancode-set set=0x{:02X}", set)
+        }
+        KeyboardError::ScancodeParsingError(e) => {
+            write!(out, "decode-error kind={}", decode_error(e))
+        }
+        KeyboardError::CommandTimeout { id, command } => write!(
+            out,
+            "command-timeout id={} command=0x{:02X}",
+            id.value(),
+            command
+        ),
+        KeyboardError::CommandFailed { id, command } => write!(
+            out,
+            "command-failed id={} command=0x{:02X}",
+            id.value(),
+            command
+        ),
+        KeyboardError::KeyboardUnresponsive { failures } => {
+            write!(out, "keyboard-unresponsive failures={}", failures)
+        }
+    }
+}
+
+#[cfg(feature = "aux-device")]
+fn render_mouse<W: Write>(event: &crate::mouse::MousePollResult, out: &mut W) -> fmt::Result {
+    use crate::mouse::MousePollResult;
+
+    out.write_str("mouse ")?;
+
+    match event {
+        MousePollResult::Mouse(event) => {
+            let button = |pressed, name| if pressed { name } else { '-' };
+            write!(
+                out,
+                "move x={} y={} buttons={}{}{} overflow={}",
+                event.x,
+                event.y,
+                button(event.left_button, 'l'),
+                button(event.middle_button, 'm'),
+                button(event.right_button, 'r'),
+                event.overflow as u8,
+            )
+        }
+        MousePollResult::Reconnected => out.write_str("reconnected"),
+        MousePollResult::AuxStormSuppressed => out.write_str("aux-storm-suppressed"),
+        MousePollResult::TransmissionError(e) => render_transmission_error(e, out),
+    }
+}
+
+fn render_key<W: Write>(event: &KeyEvent, out: &mut W) -> fmt::Result {
+    write!(
+        out,
+        "key code={:?} state={}",
+        event.code,
+        key_state(&event.state)
+    )
+}
+
+fn key_state(state: &KeyState) -> &'static str {
+    match state {
+        KeyState::Up => "up",
+        KeyState::Down => "down",
+    }
+}
+
+fn scancode_set(setting: &KeyboardScancodeSetting) -> u8 {
+    match setting {
+        KeyboardScancodeSetting::Set1 => 1,
+        KeyboardScancodeSetting::Set2 => 2,
+        KeyboardScancodeSetting::Set3 => 3,
+    }
+}
+
+fn render_transmission_error<W: Write>(error: &TransmissionError, out: &mut W) -> fmt::Result {
+    let kind = match error {
+        TransmissionError::Parity => "parity",
+        TransmissionError::Timeout => "timeout",
+    };
+    write!(out, "transmission-error kind={}", kind)
+}
+
+fn decode_error(error: &Error) -> &'static str {
+    match error {
+        Error::BadStartBit => "bad-start-bit",
+        Error::BadStopBit => "bad-stop-bit",
+        Error::ParityError => "parity",
+        Error::UnknownKeyCode => "unknown-key-code",
+        Error::InvalidState => "invalid-state",
+    }
+}
@@ -0,0 +1,234 @@
+//! Fixed capacity FIFO queue.
+
+/// Element which did not fit in the `RingBuffer`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CapacityError<T> {
+    pub element: T,
+}
+
+/// Element to discard when adding to a full buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FullPolicy {
+    /// Discard the new element.
+    Saturate,
+    /// Discard the oldest element.
+    OverwriteOldest,
+}
+
+/// FIFO queue with capacity `N`. Adding to a full
+/// queue fails and the queue is not modified.
+#[derive(Debug)]
+pub struct RingBuffer<T, const N: usize> {
+    items: [Option<T>; N],
+    start: usize,
+    len: usize,
+}
+
+impl<T, const N: usize> Default for RingBuffer<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> RingBuffer<T, N> {
+    pub fn new() -> Self {
+        Self {
+            items: core::array::from_fn(|_| None),
+            start: 0,
+            len: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        N
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    pub fn push_back(&mut self, element: T) -> Result<(), CapacityError<T>> {
+        if self.is_full() {
+            return Err(CapacityError { element });
+        }
+
+        self.items[(self.start + self.len) % N] = Some(element);
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Add to a full queue according to `policy`. Returns the
+    /// discarded element.
+    pub fn push_back_with_policy(&mut self, element: T, policy: FullPolicy) -> Option<T> {
+        match self.push_back(element) {
+            Ok(()) => None,
+            Err(CapacityError { element }) => match policy {
+                FullPolicy::OverwriteOldest if N > 0 => {
+                    let oldest = self.pop_front();
+                    // There is space, because the oldest element was removed.
+                    let _ = self.push_back(element);
+                    oldest
+                }
+                _ => Some(element),
+            },
+        }
+    }
+
+    pub fn pop_front(&mut self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let element = self.items[self.start].take();
+        self.start = (self.start + 1) % N;
+        self.len -= 1;
+        element
+    }
+
+    /// Element which was added first.
+    pub fn front(&self) -> Option<&T> {
+        if self.is_empty() {
+            return None;
+        }
+
+        self.items[self.start].as_ref()
+    }
+
+    /// Element which was added last.
+    pub fn back_mut(&mut self) -> Option<&mut T> {
+        if self.is_empty() {
+            return None;
+        }
+
+        self.items[(self.start + self.len - 1) % N].as_mut()
+    }
+
+    pub fn clear(&mut self) {
+        while self.pop_front().is_some() {}
+    }
+
+    /// Iterate elements from front to back.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        (0..self.len).filter_map(move |i| self.items[(self.start + i) % N].as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+
+    fn contents<const N: usize>(buffer: &RingBuffer<u8, N>) -> [Option<u8>; 4] {
+        let mut items = [None; 4];
+        for (slot, item) in items.iter_mut().zip(buffer.iter()) {
+            *slot = Some(*item);
+        }
+        items
+    }
+
+    #[test]
+    fn elements_wrap_around() {
+        let mut buffer = RingBuffer::<u8, 3>::new();
+        for i in 0..10 {
+            assert_eq!(buffer.push_back(i), Ok(()));
+            assert_eq!(buffer.pop_front(), Some(i));
+            assert!(buffer.is_empty());
+        }
+        assert_eq!(buffer.pop_front(), None);
+    }
+
+    #[test]
+    fn push_back_to_full_buffer_saturates() {
+        let mut buffer = RingBuffer::<u8, 2>::new();
+        buffer.push_back(1).unwrap();
+        buffer.push_back(2).unwrap();
+        assert!(buffer.is_full());
+
+        assert_eq!(buffer.push_back(3), Err(CapacityError { element: 3 }));
+        assert_eq!(
+            buffer.push_back_with_policy(3, FullPolicy::Saturate),
+            Some(3)
+        );
+        assert_eq!(contents(&buffer), [Some(1), Some(2), None, None]);
+    }
+
+    #[test]
+    fn overwrite_oldest_discards_front() {
+        let mut buffer = RingBuffer::<u8, 3>::new();
+        for i in 0..3 {
+            assert_eq!(
+                buffer.push_back_with_policy(i, FullPolicy::OverwriteOldest),
+                None
+            );
+        }
+
+        assert_eq!(
+            buffer.push_back_with_policy(3, FullPolicy::OverwriteOldest),
+            Some(0)
+        );
+        assert_eq!(
+            buffer.push_back_with_policy(4, FullPolicy::OverwriteOldest),
+            Some(1)
+        );
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(contents(&buffer), [Some(2), Some(3), Some(4), None]);
+    }
+
+    #[test]
+    fn overwrite_oldest_without_capacity_discards_new_element() {
+        let mut buffer = RingBuffer::<u8, 0>::new();
+        assert_eq!(
+            buffer.push_back_with_policy(1, FullPolicy::OverwriteOldest),
+            Some(1)
+        );
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn front_and_back_after_wrap() {
+        let mut buffer = RingBuffer::<u8, 3>::new();
+        assert_eq!(buffer.front(), None);
+        assert_eq!(buffer.back_mut(), None);
+
+        for i in 0..3 {
+            buffer.push_back(i).unwrap();
+        }
+        buffer.pop_front();
+        buffer.pop_front();
+        buffer.push_back(3).unwrap();
+        buffer.push_back(4).unwrap();
+
+        assert_eq!(buffer.front(), Some(&2));
+        *buffer.back_mut().unwrap() = 5;
+        assert_eq!(contents(&buffer), [Some(2), Some(3), Some(5), None]);
+    }
+
+    #[test]
+    fn iter_is_in_insertion_order_after_wrap() {
+        let mut buffer = RingBuffer::<u8, 4>::new();
+        for i in 0..4 {
+            buffer.push_back(i).unwrap();
+        }
+        for _ in 0..3 {
+            buffer.pop_front();
+        }
+        for i in 4..7 {
+            buffer.push_back(i).unwrap();
+        }
+
+        assert_eq!(contents(&buffer), [Some(3), Some(4), Some(5), Some(6)]);
+
+        buffer.clear();
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.iter().next(), None);
+    }
+}
@@ -0,0 +1,37 @@
+//! Event sequence numbers.
+//!
+//! Drivers number every emitted event, so a consumer which receives
+//! events through several paths can detect missed events from gaps
+//! in the sequence numbers. Bytes which are dropped before decoding
+//! don't produce events, so drivers count those separately, for
+//! example `KeyboardDriver::deferred_overflows`.
+
+/// Event with a sequence number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sequenced<E> {
+    /// Increases by one for every event of a driver. Wraps on overflow.
+    pub sequence: u32,
+    pub event: E,
+}
+
+#[derive(Debug, Default)]
+pub struct EventSequence {
+    next: u32,
+}
+
+impl EventSequence {
+    pub const fn new() -> Self {
+        Self { next: 0 }
+    }
+
+    /// Sequence number of the next event.
+    pub fn next(&self) -> u32 {
+        self.next
+    }
+
+    pub fn stamp<E>(&mut self, event: E) -> Sequenced<E> {
+        let sequence = self.next;
+        self.next = self.next.wrapping_add(1);
+        Sequenced { sequence, event }
+    }
+}
@@ -0,0 +1,269 @@
+//! Non-blocking event stream adapters.
+//!
+//! `EventSource` is implemented by the polling drivers, so input can
+//! be consumed through a common trait. `Utf8Reader` turns keyboard
+//! events into a byte stream with a `read` method like the
+//! non-blocking read traits of embedded I/O crates. In shortcut mode
+//! it returns Ctrl and Alt key combinations as `Shortcut`s instead of
+//! text, so console applications can implement key bindings. `MergedSources`
+//! combines drivers of several devices, for example keyboards
+//! on two controllers, into one stream. It requires the `unstable`
+//! feature.
+
+use crate::controller::driver::status::ReadStatus;
+use crate::controller::io::PortIO;
+use crate::device::io::WouldBlock;
+use crate::keyboard::{KeyboardDriver, KeyboardPollResult};
+use crate::sequence::Sequenced;
+
+use pc_keyboard::{DecodedKey, KeyCode, KeyEvent, KeyState, KeyboardLayout, ScancodeSet};
+
+pub trait EventSource {
+    type Event;
+
+    /// Process available data until there is an event. Returns
+    /// `None` if there is no event available.
+    fn next_event(&mut self) -> Option<Self::Event>;
+}
+
+impl<T: PortIO, IRQ, K: KeyboardLayout, S: ScancodeSet, const D: usize> EventSource
+    for KeyboardDriver<T, IRQ, K, S, D>
+{
+    type Event = Sequenced<KeyboardPollResult>;
+
+    fn next_event(&mut self) -> Option<Self::Event> {
+        loop {
+            if let Some(event) = self.poll_sequenced() {
+                return Some(event);
+            }
+
+            self.controller_mut().status().data_availability()?;
+        }
+    }
+}
+
+#[cfg(feature = "aux-device")]
+impl<T: PortIO, IRQ, const D: usize> EventSource for crate::mouse::MouseDriver<T, IRQ, D> {
+    type Event = Sequenced<crate::mouse::MousePollResult>;
+
+    fn next_event(&mut self) -> Option<Self::Event> {
+        loop {
+            if let Some(event) = self.poll_sequenced() {
+                return Some(event);
+            }
+
+            self.controller_mut().status().data_availability()?;
+        }
+    }
+}
+
+#[cfg(feature = "unstable")]
+#[cfg_attr(docsrs, doc(cfg(feature = "unstable")))]
+/// Event from one of the `MergedSources`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceEvent<E> {
+    /// Index of the source in `MergedSources`.
+    pub source: usize,
+    pub event: E,
+}
+
+#[cfg(feature = "unstable")]
+#[cfg_attr(docsrs, doc(cfg(feature = "unstable")))]
+/// Events of `N` sources in one stream. Sources are polled in turns,
+/// so a busy device can't starve the others.
+#[derive(Debug)]
+pub struct MergedSources<S, const N: usize> {
+    sources: [S; N],
+    next: usize,
+}
+
+#[cfg(feature = "unstable")]
+#[cfg_attr(docsrs, doc(cfg(feature = "unstable")))]
+impl<S: EventSource, const N: usize> MergedSources<S, N> {
+    pub fn new(sources: [S; N]) -> Self {
+        Self { sources, next: 0 }
+    }
+
+    pub fn source_mut(&mut self, source: usize) -> Option<&mut S> {
+        self.sources.get_mut(source)
+    }
+
+    pub fn into_inner(self) -> [S; N] {
+        self.sources
+    }
+}
+
+#[cfg(feature = "unstable")]
+#[cfg_attr(docsrs, doc(cfg(feature = "unstable")))]
+impl<S: EventSource, const N: usize> EventSource for MergedSources<S, N> {
+    type Event = SourceEvent<S::Event>;
+
+    fn next_event(&mut self) -> Option<Self::Event> {
+        for _ in 0..N {
+            let source = self.next;
+            self.next = (self.next + 1) % N;
+
+            if let Some(event) = self.sources[source].next_event() {
+                return Some(SourceEvent { source, event });
+            }
+        }
+
+        None
+    }
+}
+
+/// Non-blocking byte reader.
+pub trait NonBlockingRead {
+    /// Read at least one byte. Returns `WouldBlock` if there
+    /// are no bytes available.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, WouldBlock>;
+}
+
+bitflags::bitflags! {
+    struct HeldModifiers: u8 {
+        const SHIFT_LEFT = 1 << 0;
+        const SHIFT_RIGHT = 1 << 1;
+        const CONTROL_LEFT = 1 << 2;
+        const CONTROL_RIGHT = 1 << 3;
+        const ALT = 1 << 4;
+    }
+}
+
+/// Key press with Ctrl or Alt held down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shortcut {
+    pub code: KeyCode,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+}
+
+/// UTF-8 encoded characters of the decoded key presses. Other
+/// events are discarded.
+#[derive(Debug)]
+pub struct Utf8Reader<E> {
+    source: E,
+    pending: [u8; 4],
+    pending_start: usize,
+    pending_end: usize,
+    shortcut_mode: bool,
+    shortcut: Option<Shortcut>,
+    held: HeldModifiers,
+}
+
+impl<E: EventSource<Event = Sequenced<KeyboardPollResult>>> Utf8Reader<E> {
+    pub fn new(source: E) -> Self {
+        Self {
+            source,
+            pending: [0; 4],
+            pending_start: 0,
+            pending_end: 0,
+            shortcut_mode: false,
+            shortcut: None,
+            held: HeldModifiers::empty(),
+        }
+    }
+
+    /// If enabled, key presses with Ctrl or left Alt held down are
+    /// returned from `next_shortcut` instead of as text. Right Alt
+    /// is AltGr on many layouts, so it doesn't make a shortcut.
+    /// Disabled by default.
+    pub fn set_shortcut_mode(&mut self, enabled: bool) {
+        self.shortcut_mode = enabled;
+    }
+
+    /// Take the pending shortcut. `read` doesn't return text typed
+    /// after a shortcut until the shortcut is taken, so text and
+    /// shortcuts stay in order.
+    pub fn next_shortcut(&mut self) -> Option<Shortcut> {
+        if self.shortcut.is_none() && self.pending_start == self.pending_end {
+            if let Some(c) = self.next_character() {
+                self.pending_start = 0;
+                self.pending_end = c.encode_utf8(&mut self.pending).len();
+            }
+        }
+        self.shortcut.take()
+    }
+
+    pub fn source_mut(&mut self) -> &mut E {
+        &mut self.source
+    }
+
+    /// Bytes of a partially read character are lost.
+    pub fn into_inner(self) -> E {
+        self.source
+    }
+
+    /// Returns `None` if there is a pending shortcut.
+    fn next_character(&mut self) -> Option<char> {
+        while self.shortcut.is_none() {
+            if let KeyboardPollResult::Key { event, decoded } = self.source.next_event()?.event {
+                if self.update_held(&event) {
+                    continue;
+                }
+
+                let ctrl = self
+                    .held
+                    .intersects(HeldModifiers::CONTROL_LEFT | HeldModifiers::CONTROL_RIGHT);
+                let alt = self.held.contains(HeldModifiers::ALT);
+
+                if self.shortcut_mode && event.state == KeyState::Down && (ctrl || alt) {
+                    self.shortcut = Some(Shortcut {
+                        code: event.code,
+                        ctrl,
+                        alt,
+                        shift: self
+                            .held
+                            .intersects(HeldModifiers::SHIFT_LEFT | HeldModifiers::SHIFT_RIGHT),
+                    });
+                } else if let Some(DecodedKey::Unicode(c)) = decoded {
+                    return Some(c);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Returns `true` if `event` is a Shift, Ctrl or left Alt event.
+    fn update_held(&mut self, event: &KeyEvent) -> bool {
+        let modifier = match event.code {
+            KeyCode::ShiftLeft => HeldModifiers::SHIFT_LEFT,
+            KeyCode::ShiftRight => HeldModifiers::SHIFT_RIGHT,
+            KeyCode::ControlLeft => HeldModifiers::CONTROL_LEFT,
+            KeyCode::ControlRight => HeldModifiers::CONTROL_RIGHT,
+            KeyCode::AltLeft => HeldModifiers::ALT,
+            _ => return false,
+        };
+        self.held.set(modifier, event.state == KeyState::Down);
+        true
+    }
+}
+
+impl<E: EventSource<Event = Sequenced<KeyboardPollResult>>> NonBlockingRead for Utf8Reader<E> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, WouldBlock> {
+        let mut len = 0;
+
+        while len < buf.len() {
+            if self.pending_start == self.pending_end {
+                match self.next_character() {
+                    Some(c) => {
+                        self.pending_start = 0;
+                        self.pending_end = c.encode_utf8(&mut self.pending).len();
+                    }
+                    None => break,
+                }
+            }
+
+            buf[len] = self.pending[self.pending_start];
+            self.pending_start += 1;
+            len += 1;
+        }
+
+        if len == 0 && !buf.is_empty() {
+            Err(WouldBlock)
+        } else {
+            Ok(len)
+        }
+    }
+}
@@ -0,0 +1,632 @@
+//! Host-side testing helpers.
+//!
+//! `MockPortIO` returns scripted data and records writes.
+//! `Simulator` is an `Emulated8042` with a simulated keyboard and
+//! mouse, so that the driver can be exercised without real hardware.
+//! `fault::FaultyPortIO` injects hardware faults into both.
+
+use crate::controller::{
+    io::{PortIO, COMMAND_REGISTER_RAW, DATA_PORT_RAW, STATUS_REGISTER_RAW},
+    raw::StatusRegister,
+};
+use crate::device::keyboard::raw::{
+    CommandReturnData as KeyboardCommand, CommandSetAllKeys, CommandSetKeyType, FromKeyboard,
+    ScancodeSetNumber,
+};
+use crate::emulation::{Emulated8042, EmulatedDevice};
+use crate::ring_buffer::{CapacityError, FullPolicy, RingBuffer};
+
+pub mod fault;
+
+/// Capacity of `MockPortIO` data and write queues.
+pub const MOCK_QUEUE_CAPACITY: usize = 64;
+
+/// Capacity of `SimulatedKeyboard` and `SimulatedMouse` output
+/// queues.
+pub const SIMULATOR_QUEUE_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortWrite {
+    pub port: u16,
+    pub data: u8,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct MockData {
+    data: u8,
+    auxiliary_device: bool,
+}
+
+/// Scripted `PortIO` implementation.
+///
+/// Status register reads return the idle status combined with
+/// output buffer bits which are set if there is queued data.
+/// Input buffer is always empty.
+#[derive(Debug)]
+pub struct MockPortIO {
+    data: RingBuffer<MockData, MOCK_QUEUE_CAPACITY>,
+    writes: RingBuffer<PortWrite, MOCK_QUEUE_CAPACITY>,
+    idle_status: StatusRegister,
+}
+
+impl Default for MockPortIO {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockPortIO {
+    pub fn new() -> Self {
+        Self {
+            data: RingBuffer::new(),
+            writes: RingBuffer::new(),
+            idle_status: StatusRegister::INHIBIT_SWITCH,
+        }
+    }
+
+    /// Status register bits which are returned in addition to
+    /// output buffer bits. Default is `INHIBIT_SWITCH`, so the
+    /// keyboard is not inhibited.
+    pub fn set_idle_status(&mut self, status: StatusRegister) {
+        self.idle_status = status;
+    }
+
+    pub fn push_keyboard_data(&mut self, data: u8) -> Result<(), CapacityError<u8>> {
+        self.push(data, false)
+    }
+
+    pub fn push_auxiliary_device_data(&mut self, data: u8) -> Result<(), CapacityError<u8>> {
+        self.push(data, true)
+    }
+
+    fn push(&mut self, data: u8, auxiliary_device: bool) -> Result<(), CapacityError<u8>> {
+        self.data
+            .push_back(MockData {
+                data,
+                auxiliary_device,
+            })
+            .map_err(|e| CapacityError {
+                element: e.element.data,
+            })
+    }
+
+    /// Remove the oldest recorded write.
+    pub fn next_write(&mut self) -> Option<PortWrite> {
+        self.writes.pop_front()
+    }
+
+    /// Recorded writes. Only the latest `MOCK_QUEUE_CAPACITY`
+    /// writes are stored.
+    pub fn writes(&self) -> impl Iterator<Item = &PortWrite> {
+        self.writes.iter()
+    }
+
+    pub fn clear_writes(&mut self) {
+        self.writes.clear();
+    }
+}
+
+impl PortIO for MockPortIO {
+    type PortID = u16;
+
+    const DATA_PORT: Self::PortID = DATA_PORT_RAW;
+    const STATUS_REGISTER: Self::PortID = STATUS_REGISTER_RAW;
+    const COMMAND_REGISTER: Self::PortID = COMMAND_REGISTER_RAW;
+
+    fn read(&mut self, port: Self::PortID) -> u8 {
+        if port == Self::DATA_PORT {
+            self.data.pop_front().map(|d| d.data).unwrap_or(0)
+        } else {
+            let mut status = self.idle_status;
+            status.remove(StatusRegister::INPUT_BUFFER_FULL);
+
+            if let Some(data) = self.data.front() {
+                status.insert(StatusRegister::OUTPUT_BUFFER_FULL);
+                status.set(
+                    StatusRegister::AUXILIARY_DEVICE_OUTPUT_BUFFER_FULL,
+                    data.auxiliary_device,
+                );
+            }
+
+            status.bits()
+        }
+    }
+
+    fn write(&mut self, port: Self::PortID, data: u8) {
+        self.writes
+            .push_back_with_policy(PortWrite { port, data }, FullPolicy::OverwriteOldest);
+    }
+}
+
+type DeviceQueue = RingBuffer<u8, SIMULATOR_QUEUE_CAPACITY>;
+
+/// Simulated keyboard which uses scancode set 2 by default.
+#[derive(Debug)]
+pub struct SimulatedKeyboard {
+    connected: bool,
+    scanning: bool,
+    leds: u8,
+    typematic: u8,
+    scancode_set: u8,
+    command_waiting_data: Option<u8>,
+    last_sent: u8,
+    bytes: DeviceQueue,
+}
+
+impl SimulatedKeyboard {
+    fn new() -> Self {
+        Self {
+            connected: true,
+            scanning: true,
+            leds: 0,
+            typematic: 0b0010_1011,
+            scancode_set: 2,
+            command_waiting_data: None,
+            last_sent: 0,
+            bytes: RingBuffer::new(),
+        }
+    }
+
+    pub fn leds(&self) -> u8 {
+        self.leds
+    }
+
+    pub fn typematic(&self) -> u8 {
+        self.typematic
+    }
+
+    pub fn scancode_set(&self) -> u8 {
+        self.scancode_set
+    }
+
+    pub fn scanning(&self) -> bool {
+        self.scanning
+    }
+
+    fn set_defaults(&mut self) {
+        self.leds = 0;
+        self.typematic = 0b0010_1011;
+        self.scancode_set = 2;
+    }
+
+    fn respond(&mut self, byte: u8) {
+        self.last_sent = byte;
+        let _ = self.bytes.push_back(byte);
+    }
+
+    fn command(&mut self, data: u8) {
+        if let Some(command) = self.command_waiting_data.take() {
+            match command {
+                KeyboardCommand::SET_STATUS_INDICATORS => self.leds = data,
+                KeyboardCommand::SET_TYPEMATIC_RATE => self.typematic = data,
+                KeyboardCommand::SELECT_ALTERNATE_SCANCODES => {
+                    self.respond(FromKeyboard::ACK);
+                    match data {
+                        ScancodeSetNumber::GET_CURRENT => self.respond(self.scancode_set),
+                        ScancodeSetNumber::SET_1..=ScancodeSetNumber::SET_3 => {
+                            self.scancode_set = data
+                        }
+                        _ => (),
+                    }
+                    return;
+                }
+                _ => (),
+            }
+            self.respond(FromKeyboard::ACK);
+            return;
+        }
+
+        match data {
+            KeyboardCommand::ECHO => self.respond(FromKeyboard::ECHO),
+            KeyboardCommand::RESEND => {
+                let last_sent = self.last_sent;
+                self.respond(last_sent);
+            }
+            KeyboardCommand::RESET => {
+                self.set_defaults();
+                self.scanning = true;
+                self.respond(FromKeyboard::ACK);
+                self.respond(FromKeyboard::BAT_COMPLETION_CODE);
+            }
+            KeyboardCommand::READ_ID => {
+                self.scanning = false;
+                self.respond(FromKeyboard::ACK);
+                self.respond(FromKeyboard::ID_FIRST_BYTE);
+                self.respond(FromKeyboard::ID_SECOND_BYTE);
+                self.scanning = true;
+            }
+            KeyboardCommand::ENABLE => {
+                self.scanning = true;
+                self.respond(FromKeyboard::ACK);
+            }
+            KeyboardCommand::DEFAULT_DISABLE => {
+                self.set_defaults();
+                self.scanning = false;
+                self.respond(FromKeyboard::ACK);
+            }
+            KeyboardCommand::SET_DEFAULT => {
+                self.set_defaults();
+                self.scanning = true;
+                self.respond(FromKeyboard::ACK);
+            }
+            KeyboardCommand::SET_STATUS_INDICATORS
+            | KeyboardCommand::SET_TYPEMATIC_RATE
+            | KeyboardCommand::SELECT_ALTERNATE_SCANCODES
+            | CommandSetKeyType::TYPEMATIC
+            | CommandSetKeyType::MAKE_SLASH_BREAK
+            | CommandSetKeyType::MAKE => {
+                self.command_waiting_data = Some(data);
+                self.respond(FromKeyboard::ACK);
+            }
+            CommandSetAllKeys::TYPEMATIC
+            | CommandSetAllKeys::MAKE_SLASH_BREAK
+            | CommandSetAllKeys::MAKE
+            | CommandSetAllKeys::TYPEMATIC_SLASH_MAKE_SLASH_BREAK => {
+                self.respond(FromKeyboard::ACK)
+            }
+            _ => self.respond(FromKeyboard::RESEND),
+        }
+    }
+}
+
+/// Simulated mouse which acknowledges every byte.
+#[derive(Debug)]
+pub struct SimulatedMouse {
+    connected: bool,
+    reporting: bool,
+    command_waiting_data: bool,
+    bytes: DeviceQueue,
+}
+
+impl SimulatedMouse {
+    const RESET: u8 = 0xFF;
+    const READ_ID: u8 = 0xF2;
+    const SET_SAMPLE_RATE: u8 = 0xF3;
+    const ENABLE_DATA_REPORTING: u8 = 0xF4;
+    const DISABLE_DATA_REPORTING: u8 = 0xF5;
+    const SET_RESOLUTION: u8 = 0xE8;
+    const STATUS_REQUEST: u8 = 0xE9;
+
+    fn new() -> Self {
+        Self {
+            connected: true,
+            reporting: false,
+            command_waiting_data: false,
+            bytes: RingBuffer::new(),
+        }
+    }
+
+    pub fn reporting(&self) -> bool {
+        self.reporting
+    }
+
+    fn command(&mut self, data: u8) {
+        let out = &mut self.bytes;
+        let _ = out.push_back(FromKeyboard::ACK);
+
+        if self.command_waiting_data {
+            self.command_waiting_data = false;
+            return;
+        }
+
+        match data {
+            Self::RESET => {
+                self.reporting = false;
+                let _ = out.push_back(FromKeyboard::BAT_COMPLETION_CODE);
+                let _ = out.push_back(0);
+            }
+            Self::READ_ID => {
+                let _ = out.push_back(0);
+            }
+            Self::STATUS_REQUEST => {
+                for _ in 0..3 {
+                    let _ = out.push_back(0);
+                }
+            }
+            Self::ENABLE_DATA_REPORTING => self.reporting = true,
+            Self::DISABLE_DATA_REPORTING => self.reporting = false,
+            Self::SET_SAMPLE_RATE | Self::SET_RESOLUTION => self.command_waiting_data = true,
+            _ => (),
+        }
+    }
+}
+
+impl EmulatedDevice for SimulatedKeyboard {
+    fn receive(&mut self, data: u8) -> bool {
+        if self.connected {
+            self.command(data);
+        }
+        self.connected
+    }
+
+    fn next_byte(&mut self) -> Option<u8> {
+        self.bytes.pop_front()
+    }
+}
+
+impl EmulatedDevice for SimulatedMouse {
+    fn receive(&mut self, data: u8) -> bool {
+        if self.connected {
+            self.command(data);
+        }
+        self.connected
+    }
+
+    fn next_byte(&mut self) -> Option<u8> {
+        self.bytes.pop_front()
+    }
+}
+
+/// PS/2 controller simulator.
+///
+/// See `Emulated8042` for the controller behavior.
+pub type Simulator = Emulated8042<SimulatedKeyboard, SimulatedMouse>;
+
+impl Default for Simulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Simulator {
+    pub fn new() -> Self {
+        Self::with_devices(SimulatedKeyboard::new(), SimulatedMouse::new())
+    }
+
+    pub fn set_keyboard_connected(&mut self, connected: bool) {
+        self.keyboard_mut().connected = connected;
+    }
+
+    pub fn set_mouse_connected(&mut self, connected: bool) {
+        self.mouse_mut().connected = connected;
+    }
+
+    /// Send bytes from the keyboard if scanning is enabled.
+    pub fn press_keyboard_bytes(&mut self, bytes: &[u8]) {
+        let keyboard = self.keyboard_mut();
+        if keyboard.connected && keyboard.scanning {
+            for byte in bytes {
+                let _ = keyboard.bytes.push_back(*byte);
+            }
+        }
+    }
+
+    /// Send bytes from the mouse if data reporting is enabled.
+    pub fn move_mouse_bytes(&mut self, bytes: &[u8]) {
+        let mouse = self.mouse_mut();
+        if mouse.connected && mouse.reporting {
+            for byte in bytes {
+                let _ = mouse.bytes.push_back(*byte);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::unwrap_used)]
+
+    use super::*;
+    use crate::controller::driver::{
+        marker::Disabled, EnableDevice, EnabledDevices, InitController,
+    };
+    use crate::controller::driver::{
+        DeviceData, DeviceInterfaceError, InterfaceError, ReadData, ReadRAM, Testing,
+    };
+    use crate::controller::io::PortIOAvailable;
+    use crate::controller::raw::{Command, CommandWaitData, ControllerCommandByte, StatusRegister};
+    use crate::controller::transaction::{TransactionError, TRANSACTION_RETRIES};
+    use crate::device::keyboard::driver::{KeyboardError, KeyboardEvent};
+    use crate::keyboard::QueuedKeyboardDriver;
+    use fault::{Fault, FaultyPortIO};
+
+    fn init() -> EnabledDevices<Simulator, Disabled> {
+        InitController::start_init(Simulator::new())
+            .enable_devices(EnableDevice::Keyboard)
+            .unwrap()
+    }
+
+    fn poll_all(driver: &mut QueuedKeyboardDriver<Simulator, Disabled, 8>) -> (u32, bool) {
+        let mut completed = 0;
+        let mut echo = false;
+        while let Some(result) = driver.poll() {
+            match result.unwrap() {
+                Some(KeyboardEvent::CommandCompleted(_)) => completed += 1,
+                Some(KeyboardEvent::Echo { .. }) => echo = true,
+                _ => (),
+            }
+        }
+        (completed, echo)
+    }
+
+    #[test]
+    fn init_enables_keyboard_with_interrupts_disabled() {
+        let mut controller = init();
+        let command_byte = controller.port_io_mut().command_byte();
+
+        assert!(!command_byte.contains(ControllerCommandByte::DISABLE_KEYBOARD));
+        assert!(command_byte.contains(ControllerCommandByte::DISABLE_AUXILIARY_DEVICE));
+        assert!(!command_byte.intersects(
+            ControllerCommandByte::ENABLE_KEYBOARD_INTERRUPT
+                | ControllerCommandByte::ENABLE_AUXILIARY_INTERRUPT
+        ));
+    }
+
+    #[test]
+    fn keyboard_commands_are_acknowledged() {
+        let mut driver = QueuedKeyboardDriver::<_, _, 8>::new(init()).unwrap();
+        let (completed, _) = poll_all(&mut driver);
+        assert!(completed > 0);
+        assert!(driver.keyboard().command_queue().is_empty());
+
+        driver
+            .command(|keyboard, sender| keyboard.echo(sender))
+            .unwrap();
+        assert_eq!(poll_all(&mut driver), (0, true));
+        assert!(driver.keyboard().command_queue().is_empty());
+
+        let (mut controller, _) = driver.into_parts();
+        assert!(!controller.port_io_mut().keyboard().scanning());
+    }
+
+    #[cfg(all(feature = "set2", feature = "unstable"))]
+    #[test]
+    fn handoff_contains_keyboard_state() {
+        use crate::device::identify::Device;
+        use crate::device::keyboard::driver::KeyboardScancodeSetting;
+
+        // Scancode set selection requires controller translation
+        // to be disabled.
+        let mut controller = InitController::start_init(Simulator::new());
+        controller.scancode_translation(false).unwrap();
+        let controller = controller.enable_devices(EnableDevice::Keyboard).unwrap();
+
+        let mut driver = QueuedKeyboardDriver::<_, _, 8>::new(controller).unwrap();
+        poll_all(&mut driver);
+        driver
+            .command(|keyboard, sender| {
+                keyboard.set_alternate_scancode_set(sender, KeyboardScancodeSetting::Set2)
+            })
+            .unwrap();
+        driver
+            .command(|keyboard, sender| keyboard.read_id(sender))
+            .unwrap();
+        poll_all(&mut driver);
+
+        let (_, handoff) = driver.handoff();
+        let handoff = handoff.unwrap();
+        assert_eq!(handoff.scancode_set, Some(KeyboardScancodeSetting::Set2));
+        assert_eq!(handoff.keyboard_kind, Some(Device::Keyboard));
+    }
+
+    #[cfg(all(feature = "set1", feature = "set2"))]
+    #[test]
+    fn jis_layout_decodes_set_1_and_set_2() {
+        use crate::device::keyboard::driver::{
+            InitialState, ScancodeDecoderSetting, GRAB_BUFFER_CAPACITY,
+        };
+        use pc_keyboard::layouts::Jis109Key;
+        use pc_keyboard::{DecodedKey, HandleControl, ScancodeSet2};
+
+        // Set 2 make and break codes of Shift+2, BackTick, SemiColon
+        // and Quote. JIS layout maps them to characters which differ
+        // from the US layout.
+        let bytes = [
+            0x12, 0x1E, 0xF0, 0x1E, 0xF0, 0x12, 0x0E, 0xF0, 0x0E, 0x4C, 0xF0, 0x4C, 0x52, 0xF0,
+            0x52,
+        ];
+
+        // With translation the controller converts the bytes to set 1.
+        for &translation in &[true, false] {
+            let mut controller = InitController::start_init(Simulator::new());
+            controller.scancode_translation(translation).unwrap();
+            let mut controller = controller.enable_devices(EnableDevice::Keyboard).unwrap();
+            controller.port_io_mut().press_keyboard_bytes(&bytes);
+
+            let mut driver =
+                QueuedKeyboardDriver::<_, _, 8, GRAB_BUFFER_CAPACITY, Jis109Key>::with_initial_state(
+                    controller,
+                    InitialState::Unchanged,
+                )
+                .unwrap();
+            driver
+                .command(|keyboard, _| {
+                    keyboard.set_scancode_decoder(if translation {
+                        ScancodeDecoderSetting::Set1
+                    } else {
+                        ScancodeDecoderSetting::Set2
+                    })
+                })
+                .unwrap();
+
+            let mut layout =
+                pc_keyboard::Keyboard::new(Jis109Key, ScancodeSet2, HandleControl::Ignore);
+            let mut decoded = ['\0'; 4];
+            let mut count = 0;
+            while let Some(result) = driver.poll() {
+                if let Some(KeyboardEvent::Key(event)) = result.unwrap() {
+                    if let Some(DecodedKey::Unicode(c)) = layout.process_keyevent(event) {
+                        decoded[count] = c;
+                        count += 1;
+                    }
+                }
+            }
+            assert_eq!(decoded, ['"', '@', ':', '^']);
+            assert_eq!(count, decoded.len());
+        }
+    }
+
+    #[test]
+    fn keyboard_bytes_are_translated() {
+        let mut controller = init();
+        // Set 2 make and break codes of A.
+        controller
+            .port_io_mut()
+            .press_keyboard_bytes(&[0x1C, 0xF0, 0x1C]);
+
+        let mut read = || match controller.read_data() {
+            Some(DeviceData::Keyboard(data)) => Some(data),
+            _ => None,
+        };
+        assert_eq!(read(), Some(0x1E));
+        assert_eq!(read(), Some(0x9E));
+        assert_eq!(read(), None);
+    }
+
+    #[test]
+    fn password_matching_restarts_after_wrong_key() {
+        let mut simulator = Simulator::new();
+        simulator.write(Simulator::COMMAND_REGISTER, CommandWaitData::LOAD_PASSWORD);
+        for &byte in &[0x1E, 0x30, 0] {
+            simulator.write(Simulator::DATA_PORT, byte);
+        }
+        simulator.write(Simulator::COMMAND_REGISTER, Command::ENABLE_PASSWORD);
+        let inhibited = |simulator: &mut Simulator| {
+            simulator.read(Simulator::STATUS_REGISTER) & StatusRegister::INHIBIT_SWITCH.bits() == 0
+        };
+        assert!(inhibited(&mut simulator));
+
+        // Set 2 A, wrong key C, B, then A and B with break codes.
+        simulator.press_keyboard_bytes(&[0x1C, 0x21, 0x32]);
+        assert!(inhibited(&mut simulator));
+        simulator.press_keyboard_bytes(&[0x1C, 0xF0, 0x1C, 0x32, 0xF0, 0x32]);
+        assert!(!inhibited(&mut simulator));
+        assert!(!simulator.password_enabled());
+    }
+
+    #[test]
+    fn unanswered_command_times_out() {
+        let mut driver = QueuedKeyboardDriver::<_, _, 8>::new(init()).unwrap();
+
+        match driver.tick(u32::MAX) {
+            Err(KeyboardError::CommandTimeout { .. }) => (),
+            other => panic!("expected timeout, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn general_timeout_is_returned_from_controller_commands() {
+        let mut controller = InitController::start_init(FaultyPortIO::new(Simulator::new()));
+        let general_timeout = Fault::Timeout {
+            bytes: TRANSACTION_RETRIES + 1,
+        };
+
+        controller.port_io_mut().inject(general_timeout);
+        match controller.keyboard_interface_test() {
+            Err(DeviceInterfaceError::Transaction(TransactionError::GeneralTimeout)) => (),
+            other => panic!("expected general timeout, got {:?}", other),
+        }
+
+        controller.port_io_mut().inject(general_timeout);
+        assert_eq!(
+            controller.controller_config(),
+            Err(TransactionError::GeneralTimeout)
+        );
+
+        controller.port_io_mut().inject(general_timeout);
+        match controller.enable_devices_and_interrupts(EnableDevice::Keyboard) {
+            Err((_, InterfaceError::Keyboard(DeviceInterfaceError::Transaction(_)))) => (),
+            other => panic!("expected general timeout, got {:?}", other.map(|_| ())),
+        }
+    }
+}
@@ -0,0 +1,357 @@
+//! Fault injection for `PortIO` implementations.
+//!
+//! `FaultyPortIO` wraps `MockPortIO`, `Simulator` or a real
+//! controller and makes it misbehave like broken hardware. Faults are
+//! injected immediately with `FaultyPortIO::inject` or after a number
+//! of port writes with `FaultyPortIO::schedule`, so a scenario can
+//! break a specific command of an initialization sequence.
+//!
+//! ```rust,ignore
+//! let scenario = [
+//!     ScheduledFault { after_writes: 0, fault: Fault::StuckInputBuffer { polls: 100 } },
+//!     ScheduledFault { after_writes: 4, fault: Fault::DropAck { count: 1 } },
+//! ];
+//! let port_io = FaultyPortIO::with_scenario(Simulator::new(), &scenario)?;
+//! ```
+
+use crate::controller::{
+    io::{PortIO, COMMAND_REGISTER_RAW, DATA_PORT_RAW, STATUS_REGISTER_RAW},
+    raw::{CommandWaitData, StatusRegister},
+};
+use crate::device::keyboard::raw::FromKeyboard;
+use crate::ring_buffer::{CapacityError, RingBuffer};
+
+/// Capacity of the `FaultyPortIO` schedule and injected byte queues.
+pub const FAULT_QUEUE_CAPACITY: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Status register reports a full input buffer for the next
+    /// `polls` reads.
+    StuckInputBuffer { polls: u32 },
+    /// Next `bytes` bytes from the output buffer have the parity
+    /// error bit set.
+    ParityError { bytes: u32 },
+    /// Next `bytes` bytes from the output buffer have the general
+    /// timeout bit set.
+    Timeout { bytes: u32 },
+    /// Each of the next `writes` bytes to a device is answered with
+    /// RESEND instead of being sent, with probability `1 / one_in`.
+    /// Zero `one_in` is handled as one.
+    ResendStorm { writes: u32, one_in: u32 },
+    /// Next `count` ACK bytes from the devices are discarded.
+    DropAck { count: u32 },
+    /// Next byte in the output buffer is hidden for `polls` status
+    /// register reads.
+    DelayResponse { polls: u32 },
+}
+
+/// `fault` is injected when `after_writes` port writes have been
+/// made through `FaultyPortIO`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScheduledFault {
+    pub after_writes: u32,
+    pub fault: Fault,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PendingByte {
+    data: u8,
+    auxiliary_device: bool,
+    errors: StatusRegister,
+    delay: u32,
+}
+
+/// `PortIO` wrapper which injects faults.
+///
+/// Output buffer bytes are read from the wrapped `PortIO` when the
+/// status register is read, so the bytes can be delayed, dropped or
+/// marked with error bits.
+#[derive(Debug)]
+pub struct FaultyPortIO<T: PortIO<PortID = u16>> {
+    port_io: T,
+    pending: Option<PendingByte>,
+    injected: RingBuffer<(u8, bool), FAULT_QUEUE_CAPACITY>,
+    schedule: RingBuffer<ScheduledFault, FAULT_QUEUE_CAPACITY>,
+    writes: u32,
+    previous_command: Option<u8>,
+    random: u32,
+    stuck_polls: u32,
+    parity_errors: u32,
+    timeouts: u32,
+    resend_writes: u32,
+    resend_one_in: u32,
+    dropped_acks: u32,
+    delay_polls: u32,
+}
+
+impl<T: PortIO<PortID = u16>> FaultyPortIO<T> {
+    pub fn new(port_io: T) -> Self {
+        Self {
+            port_io,
+            pending: None,
+            injected: RingBuffer::new(),
+            schedule: RingBuffer::new(),
+            writes: 0,
+            previous_command: None,
+            random: 0x2545_F491,
+            stuck_polls: 0,
+            parity_errors: 0,
+            timeouts: 0,
+            resend_writes: 0,
+            resend_one_in: 1,
+            dropped_acks: 0,
+            delay_polls: 0,
+        }
+    }
+
+    pub fn with_scenario(
+        port_io: T,
+        scenario: &[ScheduledFault],
+    ) -> Result<Self, CapacityError<ScheduledFault>> {
+        let mut faulty = Self::new(port_io);
+        for fault in scenario {
+            faulty.schedule(*fault)?;
+        }
+        Ok(faulty)
+    }
+
+    /// Seed of the `Fault::ResendStorm` random numbers. Zero is
+    /// handled as one.
+    pub fn set_seed(&mut self, seed: u32) {
+        self.random = seed.max(1);
+    }
+
+    /// Replaces the active fault of the same kind.
+    pub fn inject(&mut self, fault: Fault) {
+        match fault {
+            Fault::StuckInputBuffer { polls } => self.stuck_polls = polls,
+            Fault::ParityError { bytes } => self.parity_errors = bytes,
+            Fault::Timeout { bytes } => self.timeouts = bytes,
+            Fault::ResendStorm { writes, one_in } => {
+                self.resend_writes = writes;
+                self.resend_one_in = one_in.max(1);
+            }
+            Fault::DropAck { count } => self.dropped_acks = count,
+            Fault::DelayResponse { polls } => self.delay_polls = polls,
+        }
+    }
+
+    /// Faults which are already due are injected immediately.
+    pub fn schedule(&mut self, fault: ScheduledFault) -> Result<(), CapacityError<ScheduledFault>> {
+        self.schedule.push_back(fault)?;
+        self.inject_due_faults();
+        Ok(())
+    }
+
+    /// Number of port writes made through this wrapper.
+    pub fn writes(&self) -> u32 {
+        self.writes
+    }
+
+    /// Clear active and scheduled faults.
+    pub fn clear_faults(&mut self) {
+        self.schedule.clear();
+        self.stuck_polls = 0;
+        self.parity_errors = 0;
+        self.timeouts = 0;
+        self.resend_writes = 0;
+        self.dropped_acks = 0;
+        self.delay_polls = 0;
+    }
+
+    pub fn port_io(&self) -> &T {
+        &self.port_io
+    }
+
+    pub fn port_io_mut(&mut self) -> &mut T {
+        &mut self.port_io
+    }
+
+    pub fn into_inner(self) -> T {
+        self.port_io
+    }
+
+    fn inject_due_faults(&mut self) {
+        let mut remaining = self.schedule.len();
+        while remaining > 0 {
+            remaining -= 1;
+            if let Some(fault) = self.schedule.pop_front() {
+                if fault.after_writes <= self.writes {
+                    self.inject(fault.fault);
+                } else {
+                    let _ = self.schedule.push_back(fault);
+                }
+            }
+        }
+    }
+
+    /// Xorshift.
+    fn next_random(&mut self) -> u32 {
+        let mut x = self.random;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.random = x;
+        x
+    }
+
+    /// Returns status register of the wrapped `PortIO`.
+    fn fill_pending(&mut self) -> StatusRegister {
+        let mut status = StatusRegister::from_bits_truncate(self.port_io.read(STATUS_REGISTER_RAW));
+
+        if self.pending.is_some() {
+            return status;
+        }
+
+        let (data, auxiliary_device, mut errors) = if let Some((data, auxiliary_device)) =
+            self.injected.pop_front()
+        {
+            (data, auxiliary_device, StatusRegister::empty())
+        } else {
+            loop {
+                if !status.contains(StatusRegister::OUTPUT_BUFFER_FULL) {
+                    return status;
+                }
+
+                let data = self.port_io.read(DATA_PORT_RAW);
+                let errors = status
+                    & (StatusRegister::KEYBOARD_PARITY_ERROR | StatusRegister::GENERAL_TIMEOUT);
+                let auxiliary_device =
+                    status.contains(StatusRegister::AUXILIARY_DEVICE_OUTPUT_BUFFER_FULL);
+
+                if data == FromKeyboard::ACK && self.dropped_acks > 0 {
+                    self.dropped_acks -= 1;
+                    status =
+                        StatusRegister::from_bits_truncate(self.port_io.read(STATUS_REGISTER_RAW));
+                    continue;
+                }
+
+                break (data, auxiliary_device, errors);
+            }
+        };
+
+        if self.parity_errors > 0 {
+            self.parity_errors -= 1;
+            errors.insert(StatusRegister::KEYBOARD_PARITY_ERROR);
+        }
+        if self.timeouts > 0 {
+            self.timeouts -= 1;
+            errors.insert(StatusRegister::GENERAL_TIMEOUT);
+        }
+
+        self.pending = Some(PendingByte {
+            data,
+            auxiliary_device,
+            errors,
+            delay: core::mem::replace(&mut self.delay_polls, 0),
+        });
+
+        status
+    }
+
+    fn write_device_data(&mut self, data: u8, auxiliary_device: bool) {
+        if self.resend_writes > 0 {
+            self.resend_writes -= 1;
+            if self.next_random().is_multiple_of(self.resend_one_in) {
+                let _ = self
+                    .injected
+                    .push_back((FromKeyboard::RESEND, auxiliary_device));
+                return;
+            }
+        }
+
+        self.port_io.write(DATA_PORT_RAW, data);
+    }
+}
+
+impl<T: PortIO<PortID = u16>> PortIO for FaultyPortIO<T> {
+    type PortID = u16;
+
+    const DATA_PORT: Self::PortID = DATA_PORT_RAW;
+    const STATUS_REGISTER: Self::PortID = STATUS_REGISTER_RAW;
+    const COMMAND_REGISTER: Self::PortID = COMMAND_REGISTER_RAW;
+
+    fn read(&mut self, port: Self::PortID) -> u8 {
+        if port == Self::DATA_PORT {
+            if self.pending.is_none() {
+                self.fill_pending();
+            }
+
+            return match self.pending.take() {
+                Some(pending) => pending.data,
+                None => self.port_io.read(DATA_PORT_RAW),
+            };
+        }
+
+        let mut status = self.fill_pending();
+        status.remove(
+            StatusRegister::OUTPUT_BUFFER_FULL
+                | StatusRegister::AUXILIARY_DEVICE_OUTPUT_BUFFER_FULL
+                | StatusRegister::KEYBOARD_PARITY_ERROR
+                | StatusRegister::GENERAL_TIMEOUT,
+        );
+
+        if self.stuck_polls > 0 {
+            self.stuck_polls -= 1;
+            status.insert(StatusRegister::INPUT_BUFFER_FULL);
+        }
+
+        if let Some(pending) = &mut self.pending {
+            if pending.delay > 0 {
+                pending.delay -= 1;
+            } else {
+                status.insert(StatusRegister::OUTPUT_BUFFER_FULL | pending.errors);
+                status.set(
+                    StatusRegister::AUXILIARY_DEVICE_OUTPUT_BUFFER_FULL,
+                    pending.auxiliary_device,
+                );
+            }
+        }
+
+        status.bits()
+    }
+
+    fn write(&mut self, port: Self::PortID, data: u8) {
+        if port == Self::DATA_PORT {
+            match self.previous_command.take() {
+                None => self.write_device_data(data, false),
+                Some(CommandWaitData::WRITE_TO_AUXILIARY_DEVICE) => {
+                    self.write_device_data(data, true)
+                }
+                Some(command) => {
+                    // Password bytes are written until the null terminator.
+                    if command == CommandWaitData::LOAD_PASSWORD && data != 0 {
+                        self.previous_command = Some(command);
+                    }
+                    self.port_io.write(DATA_PORT_RAW, data)
+                }
+            }
+        } else {
+            self.previous_command = if command_waits_data(data) {
+                Some(data)
+            } else {
+                None
+            };
+            self.port_io.write(port, data);
+        }
+
+        self.writes = self.writes.wrapping_add(1);
+        self.inject_due_faults();
+    }
+}
+
+/// Returns `true` if the next data port write is controller
+/// command data instead of a byte to the keyboard.
+fn command_waits_data(command: u8) -> bool {
+    matches!(
+        command,
+        CommandWaitData::WRITE_CONTROLLER_COMMAND_BYTE
+            ..=CommandWaitData::WRITE_RAM_END
+                | CommandWaitData::LOAD_PASSWORD
+                | CommandWaitData::WRITE_OUTPUT_PORT
+                | CommandWaitData::WRITE_KEYBOARD_OUTPUT_BUFFER
+                | CommandWaitData::WRITE_AUXILIARY_DEVICE_OUTPUT_BUFFER
+                | CommandWaitData::WRITE_TO_AUXILIARY_DEVICE
+    )
+}
@@ -0,0 +1,187 @@
+//! Runtime switchable tracing.
+//!
+//! Drivers store a `Tracer` which forwards structured `TraceEvent`s
+//! to a user provided hook. Each subsystem has its own `TraceLevel`,
+//! so detailed tracing can be enabled only for the layer which
+//! misbehaves. Level check is a single comparison, and events are not
+//! constructed when the level is not enabled.
+
+use crate::controller::raw::name_of_controller_command;
+use crate::device::keyboard::raw::name_of_keyboard_command;
+
+use core::fmt;
+
+/// Hook which receives trace events.
+pub type TraceHook = fn(Subsystem, TraceLevel, TraceEvent);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Subsystem {
+    Controller,
+    Keyboard,
+    Mouse,
+    Queue,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TraceLevel {
+    Off,
+    Error,
+    Info,
+    Verbose,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceEvent {
+    /// Controller command byte was written.
+    ControllerCommand(u8),
+    /// Data byte was written to the controller data port.
+    DataWritten(u8),
+    /// Data byte was read from the controller data port.
+    DataRead(u8),
+    /// Byte was received by a device driver.
+    ByteReceived(u8),
+    /// Byte was not an expected command response or
+    /// a plausible scancode.
+    UnexplainedByte(u8),
+    /// Command was added to the command queue.
+    CommandQueued(u8),
+    /// Command waiting in the command queue was replaced with
+    /// newer data.
+    CommandCoalesced(u8),
+    /// Command finished successfully.
+    CommandFinished(u8),
+    /// Device did not respond to the command in time.
+    CommandTimeout(u8),
+    /// Controller set `GENERAL_TIMEOUT` during a controller
+    /// command transaction.
+    GeneralTimeout(u8),
+}
+
+/// Commands are written like `controller-command SELF_TEST (0xAA)`. Command
+/// queue events use keyboard command names, because only the
+/// keyboard driver has a command queue.
+impl fmt::Display for TraceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (event, byte, name) = match *self {
+            TraceEvent::ControllerCommand(c) => {
+                ("controller-command", c, Some(name_of_controller_command(c)))
+            }
+            TraceEvent::DataWritten(b) => ("data-written", b, None),
+            TraceEvent::DataRead(b) => ("data-read", b, None),
+            TraceEvent::ByteReceived(b) => ("byte-received", b, None),
+            TraceEvent::UnexplainedByte(b) => ("unexplained-byte", b, None),
+            TraceEvent::CommandQueued(c) => {
+                ("command-queued", c, Some(name_of_keyboard_command(c)))
+            }
+            TraceEvent::CommandCoalesced(c) => {
+                ("command-coalesced", c, Some(name_of_keyboard_command(c)))
+            }
+            TraceEvent::CommandFinished(c) => {
+                ("command-finished", c, Some(name_of_keyboard_command(c)))
+            }
+            TraceEvent::CommandTimeout(c) => {
+                ("command-timeout", c, Some(name_of_keyboard_command(c)))
+            }
+            TraceEvent::GeneralTimeout(c) => {
+                ("general-timeout", c, Some(name_of_controller_command(c)))
+            }
+        };
+
+        match name {
+            Some(name) => write!(f, "{} {} (0x{:02X})", event, name, byte),
+            None => write!(f, "{} 0x{:02X}", event, byte),
+        }
+    }
+}
+
+/// Trace levels of all subsystems. Default level is `TraceLevel::Off`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceLevels {
+    controller: TraceLevel,
+    keyboard: TraceLevel,
+    mouse: TraceLevel,
+    queue: TraceLevel,
+}
+
+impl Default for TraceLevels {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TraceLevels {
+    pub const fn new() -> Self {
+        Self {
+            controller: TraceLevel::Off,
+            keyboard: TraceLevel::Off,
+            mouse: TraceLevel::Off,
+            queue: TraceLevel::Off,
+        }
+    }
+
+    pub fn level(&self, subsystem: Subsystem) -> TraceLevel {
+        match subsystem {
+            Subsystem::Controller => self.controller,
+            Subsystem::Keyboard => self.keyboard,
+            Subsystem::Mouse => self.mouse,
+            Subsystem::Queue => self.queue,
+        }
+    }
+
+    pub fn set_level(&mut self, subsystem: Subsystem, level: TraceLevel) {
+        match subsystem {
+            Subsystem::Controller => self.controller = level,
+            Subsystem::Keyboard => self.keyboard = level,
+            Subsystem::Mouse => self.mouse = level,
+            Subsystem::Queue => self.queue = level,
+        }
+    }
+
+    #[inline]
+    pub fn enabled(&self, subsystem: Subsystem, level: TraceLevel) -> bool {
+        level != TraceLevel::Off && level <= self.level(subsystem)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Tracer {
+    levels: TraceLevels,
+    hook: Option<TraceHook>,
+}
+
+impl Tracer {
+    pub const fn new() -> Self {
+        Self {
+            levels: TraceLevels::new(),
+            hook: None,
+        }
+    }
+
+    pub fn set_hook(&mut self, hook: Option<TraceHook>) {
+        self.hook = hook;
+    }
+
+    pub fn levels(&self) -> &TraceLevels {
+        &self.levels
+    }
+
+    pub fn set_level(&mut self, subsystem: Subsystem, level: TraceLevel) {
+        self.levels.set_level(subsystem, level);
+    }
+
+    /// Send event to the hook if there is a hook and
+    /// the level is enabled for the subsystem.
+    #[inline]
+    pub fn trace<F: FnOnce() -> TraceEvent>(
+        &self,
+        subsystem: Subsystem,
+        level: TraceLevel,
+        event: F,
+    ) {
+        if let Some(hook) = self.hook {
+            if self.levels.enabled(subsystem, level) {
+                hook(subsystem, level, event())
+            }
+        }
+    }
+}
@@ -0,0 +1,48 @@
+//! Stable API, version 1.
+//!
+//! Core typestate, controller and keyboard API. Items re-exported
+//! here follow semantic versioning. Depend on these paths if the
+//! crate is used long-term.
+
+pub use crate::controller::driver::builder::InitControllerBuilder;
+pub use crate::controller::driver::config::{ConfigConflict, ControllerConfig};
+pub use crate::controller::driver::marker::{
+    AuxiliaryDeviceDisabled, Disabled, InterruptsDisabled, InterruptsEnabled, KeyboardDisabled,
+};
+pub use crate::controller::driver::output_port::OutputPort;
+pub use crate::controller::driver::quirks::Quirks;
+pub use crate::controller::driver::ram::{ControllerRam, RamAddressOutOfRange, RamReadError};
+pub use crate::controller::driver::status::{
+    DataOrigin, DataOwner, InputBufferWrite, PasswordState, ReadStatus, StatusInfo,
+};
+pub use crate::controller::driver::transitions::{
+    Transition, TransitionClock, TransitionLog, TransitionRecord,
+};
+pub use crate::controller::driver::unknown_bits::ParsingMode;
+#[cfg(feature = "machine-reset")]
+pub use crate::controller::driver::ResetCPU;
+pub use crate::controller::driver::{
+    AvailableDevices, DeviceData, DeviceInterfaceError, DeviceNotEnabled, DevicesDisabled,
+    EnableDevice, EnabledDevices, InitController, InterfaceError, KeyboardSender, Password,
+    PasswordError, ReadData, ReadInputs, ReadRAM, RoutedByte, SelfTestError, SendError, Testing,
+    WriteOutputBuffer, WriteRAM,
+};
+pub use crate::controller::io::{PortIO, PortIOAvailable};
+pub use crate::controller::transaction::{TransactionError, TransactionTimeout};
+
+pub use crate::device::command_queue::{Command, CommandId, CommandQueue, ResponseTimeouts};
+pub use crate::device::identify::{Device, MouseKind};
+pub use crate::device::io::{SendToDevice, TransmissionError, WouldBlock};
+pub use crate::device::keyboard::driver::{
+    DecoderLayout, DelayMilliseconds, DesiredKeyboardState, Heartbeat, InitialState, Keyboard,
+    KeyboardError, KeyboardEvent, KeyboardScancodeSetting, NotEnoughSpaceInTheCommandQueue,
+    OverrunPolicy, RateValue, ScancodeSetCommands, ScancodeSetError, TypematicConfig,
+    UnsupportedScancodeSet, Us104Keyboard,
+};
+#[cfg(feature = "set3")]
+pub use crate::device::keyboard::reporting::{KeyReporting, KeyReportingMode};
+pub use crate::device::keyboard::translation::{translate_byte, TranslationMode, Translator};
+pub use crate::keyboard::{
+    KeyboardDriver, KeyboardPollResult, QueuedKeyboardDriver, QueuedKeyboardError,
+};
+pub use crate::ring_buffer::FullPolicy;